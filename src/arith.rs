@@ -0,0 +1,563 @@
+//! Arithmetic evaluation for `$((...))` and `let`, on signed 64-bit integers with the operators
+//! and precedence of C.
+
+use std::ffi::CString;
+
+use crate::shell::Shell;
+
+/// How many variables whose values are themselves expressions can be followed before giving up,
+/// which stops `a=b b=a` from recursing forever.
+const MAX_DEPTH: usize = 64;
+
+/// The operators, longest first so that `<<=` isn't read as `<` followed by `<=`.
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+/// The assignment operators, with the binary operator each compound one applies.
+const ASSIGNMENTS: [(&str, Option<&str>); 11] = [
+    ("=", None),
+    ("+=", Some("+")),
+    ("-=", Some("-")),
+    ("*=", Some("*")),
+    ("/=", Some("/")),
+    ("%=", Some("%")),
+    ("<<=", Some("<<")),
+    (">>=", Some(">>")),
+    ("&=", Some("&")),
+    ("^=", Some("^")),
+    ("|=", Some("|")),
+];
+
+/// A token of an arithmetic expression.
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// A parsed arithmetic expression.
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    /// A unary operator: `-`, `+`, `!` or `~`.
+    Unary(&'static str, Box<Expr>),
+    /// A binary operator, including `&&` and `||` which only evaluate their right side when it
+    /// is needed.
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `name = value` or a compound assignment such as `name += value`, which holds the binary
+    /// operator it applies.
+    Assign(String, Option<&'static str>, Box<Expr>),
+    /// `++name`, `--name`, `name++` or `name--`, as the name, the amount to add and whether the
+    /// new value is the result.
+    Increment(String, i64, bool),
+    /// `condition ? then : otherwise`.
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `first, second`, which evaluates both and gives the second.
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+/// Parse an integer constant, which is decimal, octal with a leading `0`, hexadecimal with a
+/// leading `0x`, or in any base from 2 to 64 as `base#digits`.
+fn parse_number(text: &str) -> Option<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        (
+            base.parse::<u32>()
+                .ok()
+                .filter(|base| (2..=64).contains(base))?,
+            digits,
+        )
+    } else if let Some(digits) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            // Up to base 36 letters of either case are the same digit
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        if digit >= base {
+            return None;
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Some(value)
+}
+
+/// Split an expression into numbers, names and operators.
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "#@_".contains(c)))
+                .unwrap_or(rest.len());
+            let number = parse_number(&rest[..len])
+                .ok_or_else(|| format!("{}: value too great for base", &rest[..len]))?;
+            tokens.push(Token::Number(number));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("{}: syntax error: invalid arithmetic operator", rest))?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// The precedence of a binary operator that associates to the left, higher binding tighter.
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "|" => Some(3),
+        "^" => Some(4),
+        "&" => Some(5),
+        "==" | "!=" => Some(6),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "<<" | ">>" => Some(8),
+        "+" | "-" => Some(9),
+        "*" | "/" | "%" => Some(10),
+        _ => None,
+    }
+}
+
+/// A recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    fn syntax_error(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some(Token::Number(number)) => format!("syntax error near `{}'", number),
+            Some(Token::Name(name)) => format!("syntax error near `{}'", name),
+            Some(Token::Op(op)) => format!("syntax error near `{}'", op),
+            None => String::from("syntax error: operand expected"),
+        }
+    }
+
+    /// `expression := assignment (',' assignment)*`
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Comma(Box::new(expr), Box::new(self.parse_assignment()?));
+        }
+        Ok(expr)
+    }
+
+    /// `assignment := name ('=' | '+=' | ...) assignment | conditional`
+    fn parse_assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if let Some(&(_, base)) = ASSIGNMENTS.iter().find(|(assign, _)| assign == op) {
+                let name = name.clone();
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(name, base, Box::new(value)));
+            }
+        }
+
+        self.parse_conditional()
+    }
+
+    /// `conditional := binary ('?' expression ':' conditional)?`
+    fn parse_conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.parse_binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.pos += 1;
+        let then = self.parse_comma()?;
+        self.expect(":")?;
+        let otherwise = self.parse_conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Parse the binary operators binding at least as tightly as `min`.
+    fn parse_binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.parse_power()?;
+        while let Some(op) = self.peek_op() {
+            match precedence(op) {
+                Some(level) if level >= min => {
+                    self.pos += 1;
+                    let right = self.parse_binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                }
+                _ => break,
+            }
+        }
+        Ok(left)
+    }
+
+    /// `power := unary ('**' power)?`
+    fn parse_power(&mut self) -> Result<Expr, String> {
+        let base = self.parse_unary()?;
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            let exponent = self.parse_power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    /// `unary := ('-' | '+' | '!' | '~') unary | ('++' | '--') name | postfix`
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some(Token::Name(name)) = self.tokens.get(self.pos) else {
+                    return Err(self.syntax_error());
+                };
+                let name = name.clone();
+                self.pos += 1;
+                Ok(Expr::Increment(name, if op == "++" { 1 } else { -1 }, true))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    /// `postfix := number | name ('++' | '--')? | '(' expression ')'`
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(Expr::Number(number))
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.pos += 1;
+                        Ok(Expr::Increment(
+                            name,
+                            if op == "++" { 1 } else { -1 },
+                            false,
+                        ))
+                    }
+                    _ => Ok(Expr::Variable(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+}
+
+/// Get the expression of a `$((...))` arithmetic expansion from the text between `$(` and the
+/// final `)`, or `None` if it is a command substitution that starts with a subshell, as in
+/// `$((cd /tmp) && ls)`.
+pub fn arithmetic_body(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+
+    Some(inner)
+}
+
+/// Parse an expression that has already been expanded.
+fn parse(expression: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+    };
+    let expr = parser.parse_comma()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.syntax_error());
+    }
+
+    Ok(expr)
+}
+
+/// Apply a binary operator other than `&&` and `||`. Overflow wraps around.
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(String::from("division by 0")),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err(String::from("exponent less than 0")),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "&" => left & right,
+        "^" => left ^ right,
+        _ => left | right,
+    })
+}
+
+impl Shell {
+    /// Evaluate the arithmetic expression of `$((...))` or `let`. Parameters and command
+    /// substitutions in it are expanded first, then variables named in it are read as numbers,
+    /// with an unset or empty variable being 0 and one holding an expression evaluating it.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `expression: &str` The unexpanded expression.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(i64)` The value of the expression, after making any assignments in it.
+    /// - `Err(String)` if the expression isn't valid or divides by zero.
+    pub fn arithmetic(&mut self, expression: &str) -> Result<i64, String> {
+        let expanded = self.expand_string(expression)?;
+        self.arithmetic_value(&expanded)
+    }
+
+    /// Evaluate an arithmetic expression that has already been expanded, as the arguments of
+    /// `let` and the values assigned to integer variables are.
+    pub fn arithmetic_value(&mut self, expression: &str) -> Result<i64, String> {
+        self.evaluate_arithmetic(expression, 0)
+            .map_err(|err| format!("{}: {}", expression.trim(), err))
+    }
+
+    /// Parse and evaluate an expanded expression.
+    fn evaluate_arithmetic(&mut self, expression: &str, depth: usize) -> Result<i64, String> {
+        if expression.trim().is_empty() {
+            return Ok(0);
+        }
+        let expr = parse(expression)?;
+        self.evaluate_expr(&expr, depth)
+    }
+
+    /// Get the value of a variable used in an expression.
+    fn arithmetic_var(&mut self, name: &str, depth: usize) -> Result<i64, String> {
+        let value = self.get_var(name).unwrap_or_default();
+        if let Some(number) = parse_number(value.trim()) {
+            return Ok(number);
+        }
+        if depth >= MAX_DEPTH {
+            return Err(String::from("expression recursion level exceeded"));
+        }
+        self.evaluate_arithmetic(&value, depth + 1)
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr, depth: usize) -> Result<i64, String> {
+        match expr {
+            Expr::Number(number) => Ok(*number),
+            Expr::Variable(name) => self.arithmetic_var(name, depth),
+            Expr::Unary(op, operand) => {
+                let value = self.evaluate_expr(operand, depth)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Binary("&&", left, right) => Ok((self.evaluate_expr(left, depth)? != 0
+                && self.evaluate_expr(right, depth)? != 0)
+                as i64),
+            Expr::Binary("||", left, right) => Ok((self.evaluate_expr(left, depth)? != 0
+                || self.evaluate_expr(right, depth)? != 0)
+                as i64),
+            Expr::Binary(op, left, right) => {
+                let left = self.evaluate_expr(left, depth)?;
+                let right = self.evaluate_expr(right, depth)?;
+                apply(op, left, right)
+            }
+            Expr::Assign(name, op, value) => {
+                self.check_readonly(name)?;
+                let mut value = self.evaluate_expr(value, depth)?;
+                if let Some(op) = op {
+                    value = apply(op, self.arithmetic_var(name, depth)?, value)?;
+                }
+                self.set_var(name, &value.to_string());
+                Ok(value)
+            }
+            Expr::Increment(name, delta, prefix) => {
+                self.check_readonly(name)?;
+                let old = self.arithmetic_var(name, depth)?;
+                let new = old.wrapping_add(*delta);
+                self.set_var(name, &new.to_string());
+                Ok(if *prefix { new } else { old })
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.evaluate_expr(condition, depth)? != 0 {
+                    self.evaluate_expr(then, depth)
+                } else {
+                    self.evaluate_expr(otherwise, depth)
+                }
+            }
+            Expr::Comma(first, second) => {
+                self.evaluate_expr(first, depth)?;
+                self.evaluate_expr(second, depth)
+            }
+        }
+    }
+
+    /// The `let` builtin, as in `let expression...`, which evaluates each argument as an
+    /// arithmetic expression, e.g. `let "x = x + 1" y++`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `let`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(true)` if the last expression is not 0.
+    /// - `Ok(false)` if it is 0.
+    /// - `Err(String)` if there are no expressions or one isn't valid.
+    pub fn let_expressions(&mut self, args: &[CString]) -> Result<bool, String> {
+        if args.is_empty() {
+            return Err(String::from("expression expected"));
+        }
+
+        let mut value = 0;
+        for arg in args {
+            value = self.arithmetic_value(&arg.to_string_lossy())?;
+        }
+
+        Ok(value != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(Some(42), parse_number("42"));
+        assert_eq!(Some(8), parse_number("010"));
+        assert_eq!(Some(255), parse_number("0xff"));
+        assert_eq!(Some(5), parse_number("2#101"));
+        assert_eq!(Some(35), parse_number("36#z"));
+        assert_eq!(Some(63), parse_number("64#_"));
+        assert_eq!(None, parse_number("08"));
+        assert_eq!(None, parse_number("1#1"));
+        assert_eq!(None, parse_number("12a"));
+    }
+
+    #[test]
+    fn test_arithmetic_body() {
+        assert_eq!(Some("1 + (2)"), arithmetic_body("(1 + (2))"));
+        assert_eq!(None, arithmetic_body("(cd /tmp) && (ls)"));
+        assert_eq!(None, arithmetic_body(" (ls)"));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut shell = Shell::new();
+        let mut eval = |expression: &str| shell.arithmetic(expression);
+
+        assert_eq!(Ok(7), eval("1 + 2 * 3"));
+        assert_eq!(Ok(9), eval("(1 + 2) * 3"));
+        assert_eq!(Ok(-3), eval("-7 / 2"));
+        assert_eq!(Ok(512), eval("2 ** 3 ** 2"));
+        assert_eq!(Ok(4), eval("-2 ** 2"));
+        assert_eq!(Ok(1), eval("1 < 2 && 2 <= 2 || 0"));
+        assert_eq!(Ok(6), eval("~-7 ^ 0"));
+        assert_eq!(Ok(20), eval("0 ? 10 : 20"));
+        assert_eq!(Ok(3), eval("1, 2, 3"));
+        assert_eq!(Ok(0), eval(""));
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 $ 2").is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_variables() {
+        let mut shell = Shell::new();
+
+        assert_eq!(Ok(5), shell.arithmetic("x = 5"));
+        assert_eq!(Ok(15), shell.arithmetic("x += 10"));
+        assert_eq!(Ok(15), shell.arithmetic("x++"));
+        assert_eq!(Ok(17), shell.arithmetic("++x"));
+        assert_eq!(Some(String::from("17")), shell.get_var("x"));
+        assert_eq!(Ok(1), shell.arithmetic("x == $x"));
+        assert_eq!(Ok(0), shell.arithmetic("arith_unset"));
+
+        // A variable holding an expression is evaluated, but not forever
+        shell.set_var("y", "x - 7");
+        assert_eq!(Ok(20), shell.arithmetic("y * 2"));
+        shell.set_var("a", "b");
+        shell.set_var("b", "a");
+        assert!(shell.arithmetic("a").is_err());
+
+        // The side that isn't needed isn't evaluated
+        assert_eq!(Ok(0), shell.arithmetic("0 && (z = 1)"));
+        assert_eq!(Ok(1), shell.arithmetic("1 ? 1 : (z = 1)"));
+        assert_eq!(None, shell.get_var("z"));
+    }
+}
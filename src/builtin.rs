@@ -0,0 +1,756 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::rc::Rc;
+
+use crate::shell::{Jump, Shell};
+
+/// A command run by the shell itself rather than as a program. Builtins are looked up by name in
+/// the shell's registry, which starts with `BUILTINS` and can be added to with
+/// `Shell::add_builtin`.
+pub trait Builtin {
+    /// The name the builtin is run by.
+    fn name(&self) -> &str;
+
+    /// How the builtin is used, as in `cd [-L | -P] [dir]`.
+    fn usage(&self) -> &str;
+
+    /// A one-line description of what the builtin does.
+    fn summary(&self) -> &str;
+
+    /// The lines `help` shows to describe the builtin and its options.
+    fn details(&self) -> &[&str];
+
+    /// Run the builtin with the arguments after its name, once any redirections have been
+    /// applied. Errors are printed to standard error.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the builtin.
+    fn run(&self, shell: &mut Shell, args: &[CString]) -> i32;
+}
+
+/// One of the builtins the shell starts with, described by its help and the function that runs
+/// it.
+#[derive(Clone, Copy)]
+pub struct ShellBuiltin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub details: &'static [&'static str],
+    pub run: fn(&mut Shell, &[CString]) -> i32,
+}
+
+impl Builtin for ShellBuiltin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn usage(&self) -> &str {
+        self.usage
+    }
+
+    fn summary(&self) -> &str {
+        self.summary
+    }
+
+    fn details(&self) -> &[&str] {
+        self.details
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[CString]) -> i32 {
+        (self.run)(shell, args)
+    }
+}
+
+/// Get the exit status of a builtin that fails with status 1, printing its error.
+fn status(name: &str, result: Result<(), String>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}: {}", name, err);
+            1
+        }
+    }
+}
+
+/// Get the exit status of a builtin that tests something, such as `test`, which is 1 if the test
+/// failed and `error_status` if it couldn't be done, printing the error.
+fn test_status(name: &str, result: Result<bool, String>, error_status: i32) -> i32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("{}: {}", name, err);
+            error_status
+        }
+    }
+}
+
+/// The builtins the shell starts with, in order of their names.
+pub const BUILTINS: [ShellBuiltin; 52] = [
+    ShellBuiltin {
+        name: ".",
+        usage: ". file [args]",
+        summary: "Run the commands in a file in the current shell.",
+        details: &[
+            "The file is looked up on $PATH if its name has no slash. Any args become the",
+            "positional parameters while it runs.",
+        ],
+        run: |shell, args| shell.source(args),
+    },
+    ShellBuiltin {
+        name: ":",
+        usage: ": [arg ...]",
+        summary: "Do nothing, successfully.",
+        details: &[
+            "The arguments are expanded and any redirections applied, with no other effect.",
+        ],
+        run: |_, _| 0,
+    },
+    ShellBuiltin {
+        name: "[",
+        usage: "[ expression ]",
+        summary: "Evaluate a conditional expression.",
+        details: &["The same as `test`, except that the last argument must be `]`."],
+        run: |_, args| test_status("[", Shell::test(args, true), 2),
+    },
+    ShellBuiltin {
+        name: "alias",
+        usage: "alias [name[=value] ...]",
+        summary: "Define or print aliases.",
+        details: &[
+            "Without arguments every alias is printed in a form that can be reused as",
+            "input. name=value defines an alias and name on its own prints one.",
+        ],
+        run: |shell, args| status("alias", shell.alias(args)),
+    },
+    ShellBuiltin {
+        name: "bg",
+        usage: "bg [%job ...]",
+        summary: "Continue jobs in the background.",
+        details: &[
+            "Each stopped job is continued as if it had been started with `&`, the current",
+            "job by default.",
+        ],
+        run: |shell, args| status("bg", shell.bg(args)),
+    },
+    ShellBuiltin {
+        name: "break",
+        usage: "break [n]",
+        summary: "Exit from loops.",
+        details: &["Ends the innermost enclosing for, while or until loop, or the n'th one out."],
+        run: |shell, args| status("break", shell.loop_jump(false, args)),
+    },
+    ShellBuiltin {
+        name: "builtin",
+        usage: "builtin name [args]",
+        summary: "Run a builtin.",
+        details: &["Runs the builtin name even when a function of the same name is defined."],
+        run: |shell, args| {
+            // `builtin name` runs the builtin even if a function has taken its name
+            let Some(name) = args.first().map(|name| name.to_string_lossy()) else {
+                return 0;
+            };
+            match shell.builtin(&name) {
+                Some(builtin) => builtin.run(shell, &args[1..]),
+                None => {
+                    eprintln!("builtin: {}: not a shell builtin", name);
+                    1
+                }
+            }
+        },
+    },
+    ShellBuiltin {
+        name: "cd",
+        usage: "cd [-L | -P] [dir | -]",
+        summary: "Change the working directory.",
+        details: &[
+            "Changes to dir, or to $HOME without one, and updates $PWD and $OLDPWD.",
+            "cd - changes back to $OLDPWD and prints it. A relative dir that isn't in the",
+            "current directory is looked for in each directory in $CDPATH and printed if found.",
+            "",
+            "  -L  follow dir from $PWD so that .. undoes symlinks, the default",
+            "  -P  follow the physical directory structure and resolve symlinks in $PWD",
+        ],
+        run: |shell, args| status("cd", shell.cd(args)),
+    },
+    ShellBuiltin {
+        name: "command",
+        usage: "command [-Vv] name [args]",
+        summary: "Run a command without looking up functions.",
+        details: &[
+            "  -v  print how name would be run",
+            "  -V  describe how name would be run",
+        ],
+        run: |shell, args| {
+            let mut argv = vec![CString::new("command").unwrap()];
+            argv.extend_from_slice(args);
+            shell.command_builtin(argv, &[], false)
+        },
+    },
+    ShellBuiltin {
+        name: "continue",
+        usage: "continue [n]",
+        summary: "Resume the next iteration of loops.",
+        details: &[
+            "Skips to the next iteration of the innermost enclosing for, while or until",
+            "loop, or of the n'th one out.",
+        ],
+        run: |shell, args| status("continue", shell.loop_jump(true, args)),
+    },
+    ShellBuiltin {
+        name: "declare",
+        usage: "declare [-aAirxp] [name[=value] ...]",
+        summary: "Set variables and their attributes.",
+        details: &[
+            "  -a  make each name an indexed array",
+            "  -A  make each name an associative array",
+            "  -i  evaluate values assigned to each name as arithmetic expressions",
+            "  -r  make each name readonly",
+            "  -x  export each name",
+            "  -p  print each name as the declare command that recreates it",
+            "A + instead of a - turns -i or -x off. Without names every variable with the",
+            "attributes given is printed.",
+        ],
+        run: |shell, args| status("declare", shell.declare(args)),
+    },
+    ShellBuiltin {
+        name: "dirs",
+        usage: "dirs [-clpv] [+N | -N]",
+        summary: "Display the directory stack.",
+        details: &[
+            "  -c  clear the directory stack",
+            "  -l  don't shorten directories within $HOME to ~",
+            "  -p  print one directory per line",
+            "  -v  print one numbered directory per line",
+            "  +N  print the N'th entry counting from the left",
+            "  -N  print the N'th entry counting from the right",
+        ],
+        run: |shell, args| status("dirs", shell.dirs(args)),
+    },
+    ShellBuiltin {
+        name: "disown",
+        usage: "disown [-a] [%job ...]",
+        summary: "Remove jobs from the job table.",
+        details: &["Forgets each job, the current job by default, or all of them with -a."],
+        run: |shell, args| status("disown", shell.disown(args)),
+    },
+    ShellBuiltin {
+        name: "echo",
+        usage: "echo [-neE] [arg ...]",
+        summary: "Write arguments to standard output.",
+        details: &[
+            "  -n  don't print a newline after the arguments",
+            "  -e  interpret backslash escapes",
+            "  -E  don't interpret backslash escapes",
+        ],
+        run: |_, args| status("echo", Shell::echo(args)),
+    },
+    ShellBuiltin {
+        name: "enable",
+        usage: "enable [-anp] [name ...]",
+        summary: "Turn builtins on and off.",
+        details: &[
+            "  -n  turn each name off, so a program of the same name is run instead",
+            "  -a  list every builtin, whether it is on or off",
+            "  -p  list the builtins in a form that can be reused as input",
+        ],
+        run: |shell, args| status("enable", shell.enable(args)),
+    },
+    ShellBuiltin {
+        name: "eval",
+        usage: "eval [arg ...]",
+        summary: "Run arguments as a command.",
+        details: &["Joins the arguments with spaces and runs the result in the current shell."],
+        run: |shell, args| shell.eval(args),
+    },
+    ShellBuiltin {
+        name: "exec",
+        usage: "exec [command [args]]",
+        summary: "Replace the shell with a command.",
+        details: &["Without a command, any redirections apply to the shell itself."],
+        run: |shell, args| {
+            let mut argv = vec![CString::new("exec").unwrap()];
+            argv.extend_from_slice(args);
+            shell.exec_builtin(argv, &[])
+        },
+    },
+    ShellBuiltin {
+        name: "exit",
+        usage: "exit [n]",
+        summary: "Exit the shell.",
+        details: &[
+            "Exits with status n, or the status of the last command without one, after running",
+            "the EXIT trap.",
+            "An interactive shell sends SIGHUP to its remaining jobs first, unless the huponexit",
+            "option is turned off with `set +o huponexit`.",
+        ],
+        run: |shell, args| {
+            let status = match args.first().map(|arg| arg.to_string_lossy()) {
+                Some(arg) => arg.parse::<i32>().unwrap_or_else(|_| {
+                    eprintln!("exit: {}: numeric argument required", arg);
+                    2
+                }),
+                None => shell.last_status,
+            };
+            shell.exit_shell(status)
+        },
+    },
+    ShellBuiltin {
+        name: "export",
+        usage: "export [-p] [name[=value] ...]",
+        summary: "Pass variables on to the commands the shell runs.",
+        details: &["  -p  print the exported variables, as happens without names"],
+        run: |shell, args| status("export", shell.export(args)),
+    },
+    ShellBuiltin {
+        name: "false",
+        usage: "false",
+        summary: "Exit with status 1.",
+        details: &[],
+        run: |_, _| 1,
+    },
+    ShellBuiltin {
+        name: "fc",
+        usage: "fc [-e editor] [-lnr] [first [last]] or fc -s [old=new] [command]",
+        summary: "Fix and re-run commands from the history.",
+        details: &[
+            "Opens the commands from FIRST to LAST, the previous command by default, in EDITOR,",
+            "$FCEDIT, $EDITOR or vi, then prints and runs the edited commands. FIRST and LAST",
+            "may be numbers, negative offsets from the previous command, or the text a command",
+            "starts with.",
+            "",
+            "  -e  edit with EDITOR, or run the commands unedited if it is -",
+            "  -l  list the commands, the last 16 by default, instead of running them",
+            "  -n  don't number the commands listed",
+            "  -r  reverse the order of the commands",
+            "  -s  run the command again after replacing each OLD in it with NEW",
+        ],
+        run: |shell, args| shell.fc(args),
+    },
+    ShellBuiltin {
+        name: "fg",
+        usage: "fg [%job]",
+        summary: "Move a job to the foreground.",
+        details: &["Continues the job, the current job by default, and waits for it."],
+        run: |shell, args| match shell.fg(args) {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("fg: {}", err);
+                1
+            }
+        },
+    },
+    ShellBuiltin {
+        name: "getopts",
+        usage: "getopts optstring name [arg ...]",
+        summary: "Parse option arguments.",
+        details: &[
+            "Sets name to the next option in the positional parameters, or in the args,",
+            "and $OPTARG to its argument if it is followed by a colon in optstring.",
+        ],
+        run: |shell, args| test_status("getopts", shell.getopts(args), 2),
+    },
+    ShellBuiltin {
+        name: "help",
+        usage: "help [-ds] [pattern ...]",
+        summary: "Display information about builtins.",
+        details: &[
+            "  -d  print only the summary of each builtin",
+            "  -s  print only the usage of each builtin",
+            "Without a pattern every builtin is listed with its summary.",
+        ],
+        run: |shell, args| status("help", shell.help(args)),
+    },
+    ShellBuiltin {
+        name: "history",
+        usage: "history [-c] [-d offset] [n] or history -rw [file]",
+        summary: "Display or manipulate the command history.",
+        details: &[
+            "Lists the commands in the history with their numbers, or only the last N of them.",
+            "",
+            "  -c  clear the history",
+            "  -d  delete the entry at OFFSET, counting back from the end if it is negative",
+            "  -r  read the commands in FILE, or the history file, into the history",
+            "  -w  write the history to FILE, or the history file",
+        ],
+        run: |shell, args| status("history", shell.history(args)),
+    },
+    ShellBuiltin {
+        name: "jobs",
+        usage: "jobs [-lp] [%job ...]",
+        summary: "Display the status of jobs.",
+        details: &[
+            "  -l  add the process ID of each job",
+            "  -p  print only the process IDs",
+        ],
+        run: |shell, args| status("jobs", shell.jobs(args)),
+    },
+    ShellBuiltin {
+        name: "kill",
+        usage: "kill [-s sig | -sig] pid | %job ...",
+        summary: "Send a signal to processes or jobs.",
+        details: &[
+            "  -s sig  send the signal named sig rather than SIGTERM",
+            "  -n num  send the signal numbered num",
+            "  -l      list the signal names, or the one for an exit status",
+        ],
+        run: |shell, args| test_status("kill", shell.kill(args), 2),
+    },
+    ShellBuiltin {
+        name: "let",
+        usage: "let expression ...",
+        summary: "Evaluate arithmetic expressions.",
+        details: &[
+            "Each expression is evaluated as in $((...)), assigning to any variables in it.",
+            "Exits with status 0 if the last one is not 0 and 1 otherwise.",
+        ],
+        run: |shell, args| test_status("let", shell.let_expressions(args), 1),
+    },
+    ShellBuiltin {
+        name: "local",
+        usage: "local name[=value] ...",
+        summary: "Define variables local to a function.",
+        details: &["Each name goes back to its previous value once the function returns."],
+        run: |shell, args| status("local", shell.declare_local(args)),
+    },
+    ShellBuiltin {
+        name: "popd",
+        usage: "popd [-n] [+N | -N]",
+        summary: "Remove directories from the directory stack.",
+        details: &[
+            "  -n  don't change directory",
+            "  +N  remove the N'th entry counting from the left",
+            "  -N  remove the N'th entry counting from the right",
+        ],
+        run: |shell, args| status("popd", shell.popd(args)),
+    },
+    ShellBuiltin {
+        name: "printf",
+        usage: "printf format [arg ...]",
+        summary: "Write formatted output.",
+        details: &["The format is reused until every arg has been printed."],
+        run: |_, args| status("printf", Shell::printf(args)),
+    },
+    ShellBuiltin {
+        name: "pushd",
+        usage: "pushd [-n] [dir | +N | -N]",
+        summary: "Add directories to the directory stack.",
+        details: &[
+            "  -n  add dir without changing to it",
+            "  +N  rotate the N'th entry counting from the left to the top",
+            "  -N  rotate the N'th entry counting from the right to the top",
+            "Without arguments the top two directories are swapped.",
+        ],
+        run: |shell, args| status("pushd", shell.pushd(args)),
+    },
+    ShellBuiltin {
+        name: "pwd",
+        usage: "pwd [-LP]",
+        summary: "Print the working directory.",
+        details: &[
+            "  -L  print the directory kept in $PWD",
+            "  -P  print the directory with every symlink resolved",
+        ],
+        run: |shell, args| status("pwd", shell.pwd(args)),
+    },
+    ShellBuiltin {
+        name: "read",
+        usage: "read [-r] [-p prompt] [name ...]",
+        summary: "Read a line from standard input.",
+        details: &[
+            "The line is split into fields which are assigned to each name in turn, the",
+            "last taking the rest of the line, or to $REPLY without names.",
+            "  -p prompt  print prompt first",
+            "  -r         don't treat backslashes as escapes",
+        ],
+        run: |shell, args| test_status("read", shell.read(args), 2),
+    },
+    ShellBuiltin {
+        name: "readonly",
+        usage: "readonly [-p] [name[=value] ...]",
+        summary: "Make variables readonly.",
+        details: &[
+            "Each name is given its value, if there is one, and can't be assigned or unset",
+            "afterwards. Without names the readonly variables are printed.",
+        ],
+        run: |shell, args| status("readonly", shell.readonly(args)),
+    },
+    ShellBuiltin {
+        name: "return",
+        usage: "return [n]",
+        summary: "Return from a function or sourced file.",
+        details: &["Returns with status n, or that of the last command run."],
+        run: |shell, args| match shell.return_from(args) {
+            Ok(()) => match shell.jump {
+                Some(Jump::Return(status)) => status,
+                _ => 0,
+            },
+            Err(err) => {
+                eprintln!("return: {}", err);
+                1
+            }
+        },
+    },
+    ShellBuiltin {
+        name: "set",
+        usage: "set [-efmux] [-o option] [--] [arg ...]",
+        summary: "Set shell options and positional parameters.",
+        details: &[
+            "A - turns an option on and a + turns it off. Any args replace the positional",
+            "parameters. Without arguments every variable and function is printed.",
+        ],
+        run: |shell, args| status("set", shell.set_options(args)),
+    },
+    ShellBuiltin {
+        name: "shift",
+        usage: "shift [n]",
+        summary: "Shift the positional parameters.",
+        details: &["Removes the first n positional parameters, or the first one without n."],
+        run: |shell, args| status("shift", shell.shift(args)),
+    },
+    ShellBuiltin {
+        name: "source",
+        usage: "source file [args]",
+        summary: "Run the commands in a file in the current shell.",
+        details: &["The same as `.`."],
+        run: |shell, args| shell.source(args),
+    },
+    ShellBuiltin {
+        name: "test",
+        usage: "test [expression]",
+        summary: "Evaluate a conditional expression.",
+        details: &[
+            "Exits with status 0 if the expression is true and 1 if it is false, such as",
+            "-f file, -n string, a = b or n -lt m.",
+        ],
+        run: |_, args| test_status("test", Shell::test(args, false), 2),
+    },
+    ShellBuiltin {
+        name: "theme",
+        usage: "theme [-u] [name] or theme -d name [-s separator] segment ...",
+        summary: "Define and pick prompt themes.",
+        details: &[
+            "A theme draws the prompt in place of $PS1 as a row of segments, each a prompt",
+            "string that can start with a color such as blue: or bold-red:. Without",
+            "arguments the themes are printed.",
+            "",
+            "  -d  define the theme NAME from its segments",
+            "  -s  put SEPARATOR between the segments instead of a space",
+            "  -u  go back to drawing the prompt from $PS1",
+        ],
+        run: |shell, args| status("theme", shell.theme_builtin(args)),
+    },
+    ShellBuiltin {
+        name: "times",
+        usage: "times",
+        summary: "Display process times.",
+        details: &["Prints the user and system time used by the shell and by its children."],
+        run: |_, _| status("times", Shell::times()),
+    },
+    ShellBuiltin {
+        name: "trap",
+        usage: "trap [action signal ...]",
+        summary: "Run commands when the shell receives signals.",
+        details: &[
+            "An empty action ignores each signal and - resets it. Without arguments the",
+            "traps are printed.",
+        ],
+        run: |shell, args| status("trap", shell.trap(args)),
+    },
+    ShellBuiltin {
+        name: "true",
+        usage: "true",
+        summary: "Exit with status 0.",
+        details: &[],
+        run: |_, _| 0,
+    },
+    ShellBuiltin {
+        name: "type",
+        usage: "type name ...",
+        summary: "Describe how names would be run.",
+        details: &["Prints whether each name is an alias, function, builtin or program."],
+        run: |shell, args| status("type", shell.command_type(args)),
+    },
+    ShellBuiltin {
+        name: "typeset",
+        usage: "typeset [-aAirxp] [name[=value] ...]",
+        summary: "Set variables and their attributes.",
+        details: &["The same as `declare`."],
+        run: |shell, args| status("typeset", shell.declare(args)),
+    },
+    ShellBuiltin {
+        name: "ulimit",
+        usage: "ulimit [-HSa] [-cdfnstuv] [limit]",
+        summary: "Display or set resource limits.",
+        details: &[
+            "  -H  use the hard limit",
+            "  -S  use the soft limit",
+            "  -a  print every limit",
+            "The maximum file size is used without a limit option.",
+        ],
+        run: |_, args| status("ulimit", Shell::ulimit(args)),
+    },
+    ShellBuiltin {
+        name: "umask",
+        usage: "umask [-S] [mask]",
+        summary: "Display or set the file mode creation mask.",
+        details: &[
+            "  -S  print the mask symbolically",
+            "The mask is given in octal or symbolically, as in u=rwx,g=rx,o=.",
+        ],
+        run: |_, args| status("umask", Shell::umask(args)),
+    },
+    ShellBuiltin {
+        name: "unalias",
+        usage: "unalias [-a] name ...",
+        summary: "Remove aliases.",
+        details: &["  -a  remove every alias"],
+        run: |shell, args| status("unalias", shell.unalias(args)),
+    },
+    ShellBuiltin {
+        name: "unset",
+        usage: "unset [-fv] name ...",
+        summary: "Remove variables or functions.",
+        details: &["  -f  remove functions", "  -v  remove variables"],
+        run: |shell, args| status("unset", shell.unset(args)),
+    },
+    ShellBuiltin {
+        name: "wait",
+        usage: "wait [-n] [pid | %job ...]",
+        summary: "Wait for jobs to finish.",
+        details: &[
+            "Waits for each job, or for all of them without arguments, and exits with the",
+            "status of the last one. -n waits only for the first of them to finish and exits",
+            "with its status.",
+        ],
+        run: |shell, args| match shell.wait(args) {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("wait: {}", err);
+                2
+            }
+        },
+    },
+    ShellBuiltin {
+        name: "which",
+        usage: "which [-a] name ...",
+        summary: "Show what names would run as commands.",
+        details: &[
+            "Looks each name up the way the shell does when running it, as an alias, keyword,",
+            "function, builtin or program on $PATH, and prints the first match.",
+            "",
+            "  -a  print every match in the order they are looked up",
+        ],
+        run: |shell, args| status("which", shell.which(args)),
+    },
+];
+
+/// Create the registry of builtins the shell starts with, keyed by name.
+pub fn registry() -> BTreeMap<String, Rc<dyn Builtin>> {
+    BUILTINS
+        .into_iter()
+        .map(|builtin| {
+            (
+                builtin.name.to_string(),
+                Rc::new(builtin) as Rc<dyn Builtin>,
+            )
+        })
+        .collect()
+}
+
+impl Shell {
+    /// Get the builtin `name` refers to, unless it has been turned off with `enable -n`.
+    pub fn builtin(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        if self.disabled_builtins.contains(name) {
+            return None;
+        }
+        self.builtins.get(name).cloned()
+    }
+
+    /// Check whether `name` is a builtin that hasn't been turned off with `enable -n`.
+    pub fn is_builtin(&self, name: &str) -> bool {
+        self.builtins.contains_key(name) && !self.disabled_builtins.contains(name)
+    }
+
+    /// Add a builtin to the registry, replacing any builtin of the same name.
+    pub fn add_builtin(&mut self, builtin: Rc<dyn Builtin>) {
+        self.builtins.insert(builtin.name().to_string(), builtin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// A builtin that sets `GREETED` to its arguments and fails with their count.
+    struct Greet;
+
+    impl Builtin for Greet {
+        fn name(&self) -> &str {
+            "greet"
+        }
+
+        fn usage(&self) -> &str {
+            "greet [name ...]"
+        }
+
+        fn summary(&self) -> &str {
+            "Greet each name."
+        }
+
+        fn details(&self) -> &[&str] {
+            &[]
+        }
+
+        fn run(&self, shell: &mut Shell, args: &[CString]) -> i32 {
+            let names: Vec<String> = args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+            shell.set_var("GREETED", &names.join(" "));
+            args.len() as i32
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let shell = Shell::new();
+
+        // Every builtin has its own name
+        assert_eq!(BUILTINS.len(), shell.builtins.len());
+        let names: Vec<&str> = BUILTINS.iter().map(|builtin| builtin.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(sorted, names);
+        assert!(shell.is_builtin("cd"));
+        assert!(shell.builtin("greet").is_none());
+    }
+
+    #[test]
+    fn test_run_builtin() {
+        let mut shell = Shell::new();
+        let args = [CString::new("-n").unwrap(), CString::new("x").unwrap()];
+
+        let test = shell.builtin("test").unwrap();
+        assert_eq!(0, test.run(&mut shell, &args));
+        assert_eq!(1, test.run(&mut shell, &[]));
+        let set = shell.builtin("set").unwrap();
+        assert_eq!(1, set.run(&mut shell, &[CString::new("-Q").unwrap()]));
+    }
+
+    #[test]
+    fn test_add_builtin() {
+        let mut shell = Shell::new();
+
+        shell.add_builtin(Rc::new(Greet));
+        assert!(shell.is_builtin("greet"));
+        let status = shell.execute_list(&parser::parse("greet a b").unwrap());
+        assert_eq!(2, status);
+        assert_eq!(Some(String::from("a b")), shell.get_var("GREETED"));
+
+        shell.disabled_builtins.insert(String::from("greet"));
+        assert!(shell.builtin("greet").is_none());
+    }
+}
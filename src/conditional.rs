@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs::Metadata;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use libc::{
+    access, isatty, regcomp, regex_t, regexec, regfree, regmatch_t, REG_EXTENDED, R_OK, W_OK, X_OK,
+};
+
+use crate::parser::Conditional;
+use crate::pattern;
+use crate::shell::Shell;
+
+/// The operators that test a single argument, such as `-f file`.
+pub const UNARY_OPS: [&str; 19] = [
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-p", "-r", "-s", "-S", "-t", "-u",
+    "-w", "-x", "-z",
+];
+
+/// The operators that compare two arguments, such as `a = b`.
+pub const BINARY_OPS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// A parser for the expression given to `test`, reading its arguments from left to right.
+struct Expression<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl Expression<'_> {
+    /// Parse `expr -o expr`, the loosest binding form.
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.next_if("-o") {
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    /// Parse `expr -a expr`.
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.next_if("-a") {
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    /// Parse `! expr`.
+    fn not(&mut self) -> Result<bool, String> {
+        if self.next_if("!") {
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    /// Parse a parenthesised expression, a unary or binary test, or a lone string.
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(&first) = self.args.get(self.pos) else {
+            return Err(String::from("argument expected"));
+        };
+        let rest = &self.args[self.pos + 1..];
+
+        if first == "(" {
+            self.pos += 1;
+            let result = self.or()?;
+            if !self.next_if(")") {
+                return Err(String::from("`)' expected"));
+            }
+            return Ok(result);
+        }
+        if let [op, right, ..] = rest {
+            if BINARY_OPS.contains(op) {
+                self.pos += 3;
+                return binary(first, op, right);
+            }
+        }
+        if let [arg, ..] = rest {
+            if UNARY_OPS.contains(&first) {
+                self.pos += 2;
+                return unary(first, arg);
+            }
+        }
+
+        self.pos += 1;
+        Ok(!first.is_empty())
+    }
+
+    /// Move past the next argument if it is `word`.
+    fn next_if(&mut self, word: &str) -> bool {
+        if self.args.get(self.pos) == Some(&word) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+}
+
+/// Evaluate a test of a single argument, such as `-d dir`.
+fn unary(op: &str, arg: &str) -> Result<bool, String> {
+    let metadata = || std::fs::metadata(arg).ok();
+    let has_mode = |bits: u32| metadata().is_some_and(|meta| meta.mode() & bits != 0);
+    let accessible =
+        |mode: i32| CString::new(arg).is_ok_and(|path| unsafe { access(path.as_ptr(), mode) } == 0);
+
+    Ok(match op {
+        "-b" => metadata().is_some_and(|meta| meta.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|meta| meta.file_type().is_char_device()),
+        "-d" => metadata().is_some_and(|meta| meta.is_dir()),
+        "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|meta| meta.is_file()),
+        "-g" => has_mode(0o2000),
+        "-h" | "-L" => std::fs::symlink_metadata(arg).is_ok_and(|meta| meta.is_symlink()),
+        "-k" => has_mode(0o1000),
+        "-n" => !arg.is_empty(),
+        "-p" => metadata().is_some_and(|meta| meta.file_type().is_fifo()),
+        "-r" => accessible(R_OK),
+        "-s" => metadata().is_some_and(|meta| meta.len() > 0),
+        "-S" => metadata().is_some_and(|meta| meta.file_type().is_socket()),
+        "-t" => unsafe { isatty(integer(arg)? as i32) == 1 },
+        "-u" => has_mode(0o4000),
+        "-w" => accessible(W_OK),
+        "-x" => accessible(X_OK),
+        _ => arg.is_empty(),
+    })
+}
+
+/// Evaluate a comparison of two arguments, such as `a -lt b`.
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let modified = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let same_file =
+        |left: &Metadata, right: &Metadata| left.dev() == right.dev() && left.ino() == right.ino();
+
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // A file that doesn't exist is older than one that does
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, right) => left.is_some() && right.is_none(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (left, right) => left.is_none() && right.is_some(),
+        },
+        _ => match (std::fs::metadata(left), std::fs::metadata(right)) {
+            (Ok(left), Ok(right)) => same_file(&left, &right),
+            _ => false,
+        },
+    })
+}
+
+/// Parse an argument of an integer comparison, allowing whitespace around it.
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+impl Shell {
+    /// The `test` and `[` builtins, which evaluate a conditional expression of string, integer
+    /// and file tests joined with `!`, `-a`, `-o` and parentheses. Like other shells, up to four
+    /// arguments are read by how many there are, so that `test -n` is true and `test ! = x`
+    /// compares `!` as a string.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after the command name.
+    /// - `bracket: bool` Whether this is `[`, whose last argument must be `]`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(bool)` Whether the expression is true.
+    /// - `Err(String)` if the expression isn't valid or an integer test was given something
+    ///   other than a number.
+    pub fn test(args: &[CString], bracket: bool) -> Result<bool, String> {
+        let mut args: Vec<&str> = args
+            .iter()
+            .map(|arg| CStr::to_str(arg).unwrap_or_default())
+            .collect();
+        if bracket && args.pop() != Some("]") {
+            return Err(String::from("missing `]'"));
+        }
+
+        Shell::evaluate(&args)
+    }
+
+    /// Evaluate the arguments of `test`, choosing how to read them by how many there are.
+    fn evaluate(args: &[&str]) -> Result<bool, String> {
+        match args {
+            [] => Ok(false),
+            [arg] => Ok(!arg.is_empty()),
+            ["!", arg] => Ok(arg.is_empty()),
+            [op, arg] if UNARY_OPS.contains(op) => unary(op, arg),
+            [_, _] => Err(format!("{}: unary operator expected", args[0])),
+            [left, op, right] if BINARY_OPS.contains(op) => binary(left, op, right),
+            [left, "-a", right] => Ok(!left.is_empty() && !right.is_empty()),
+            [left, "-o", right] => Ok(!left.is_empty() || !right.is_empty()),
+            ["!", rest @ ..] if args.len() <= 4 => Ok(!Shell::evaluate(rest)?),
+            ["(", inner @ .., ")"] if args.len() <= 4 => Shell::evaluate(inner),
+            _ => {
+                let mut expression = Expression { args, pos: 0 };
+                let result = expression.or()?;
+                match args.get(expression.pos) {
+                    Some(extra) => Err(format!("{}: unexpected argument", extra)),
+                    None => Ok(result),
+                }
+            }
+        }
+    }
+
+    /// Evaluate the expression of a `[[ ... ]]` command. Its words are expanded without field
+    /// splitting or pathname expansion, so an unquoted `$x` is always one argument. The right of
+    /// `==` and `!=` is matched as a pattern and the right of `=~` as an extended regular
+    /// expression, whose match and groups are stored in the array `BASH_REMATCH`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(bool)` Whether the expression is true.
+    /// - `Err(String)` if a word couldn't be expanded, an integer test was given something other
+    ///   than a number or the regular expression isn't valid.
+    pub fn evaluate_conditional(&mut self, expression: &Conditional) -> Result<bool, String> {
+        match expression {
+            Conditional::Word(word) => Ok(!self.expand_string(word)?.is_empty()),
+            Conditional::Unary { op, word } => unary(op, &self.expand_string(word)?),
+            Conditional::Binary { left, op, right } => {
+                let left = self.expand_string(left)?;
+                match op.as_str() {
+                    "=" | "==" => Ok(pattern::matches(&self.expand_pattern(right)?, &left)),
+                    "!=" => Ok(!pattern::matches(&self.expand_pattern(right)?, &left)),
+                    "=~" => {
+                        let regex = self.expand_regex(right)?;
+                        self.match_regex(&regex, &left)
+                    }
+                    op => binary(&left, op, &self.expand_string(right)?),
+                }
+            }
+            Conditional::Not(expression) => Ok(!self.evaluate_conditional(expression)?),
+            Conditional::And(left, right) => {
+                Ok(self.evaluate_conditional(left)? && self.evaluate_conditional(right)?)
+            }
+            Conditional::Or(left, right) => {
+                Ok(self.evaluate_conditional(left)? || self.evaluate_conditional(right)?)
+            }
+            Conditional::Group(expression) => self.evaluate_conditional(expression),
+        }
+    }
+
+    /// Match `text` against the extended regular expression `regex`, setting `BASH_REMATCH` to
+    /// the matched text followed by the text of each group. It is unset if nothing matched.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(bool)` Whether the regular expression matched.
+    /// - `Err(String)` if the regular expression isn't valid.
+    fn match_regex(&mut self, regex: &str, text: &str) -> Result<bool, String> {
+        let invalid = || format!("{}: invalid regular expression", regex);
+        let pattern = CString::new(regex).map_err(|_| invalid())?;
+        let subject = CString::new(text).map_err(|_| invalid())?;
+
+        // There is a group for each `(`, which may count a few that aren't groups
+        let empty = regmatch_t {
+            rm_so: -1,
+            rm_eo: -1,
+        };
+        let mut groups = vec![empty; regex.matches('(').count() + 1];
+        let matched = unsafe {
+            let mut compiled: regex_t = std::mem::zeroed();
+            if regcomp(&mut compiled, pattern.as_ptr(), REG_EXTENDED) != 0 {
+                return Err(invalid());
+            }
+            let status = regexec(
+                &compiled,
+                subject.as_ptr(),
+                groups.len(),
+                groups.as_mut_ptr(),
+                0,
+            );
+            regfree(&mut compiled);
+            status == 0
+        };
+
+        self.arrays.remove("BASH_REMATCH");
+        if !matched {
+            return Ok(false);
+        }
+        while groups.last().is_some_and(|group| group.rm_so < 0) {
+            groups.pop();
+        }
+        let elements: BTreeMap<usize, String> = groups
+            .iter()
+            .map(
+                |group| match (usize::try_from(group.rm_so), usize::try_from(group.rm_eo)) {
+                    (Ok(start), Ok(end)) => text[start..end].to_string(),
+                    _ => String::new(),
+                },
+            )
+            .enumerate()
+            .collect();
+        self.vars.remove("BASH_REMATCH");
+        self.arrays.insert(String::from("BASH_REMATCH"), elements);
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &[&str]) -> Result<bool, String> {
+        let args = crate::test_support::args(args);
+        Shell::test(&args, false)
+    }
+
+    #[test]
+    fn test_strings_and_integers() {
+        assert_eq!(Ok(false), test(&[]));
+        assert_eq!(Ok(true), test(&["-n"]));
+        assert_eq!(Ok(false), test(&[""]));
+        assert_eq!(Ok(true), test(&["-z", ""]));
+        assert_eq!(Ok(true), test(&["!", ""]));
+        assert_eq!(Ok(true), test(&["abc", "=", "abc"]));
+        assert_eq!(Ok(true), test(&["abc", "!=", "abd"]));
+        assert_eq!(Ok(false), test(&["!", "=", "x"]));
+        assert_eq!(Ok(true), test(&[" 10", "-gt", "9"]));
+        assert_eq!(Ok(false), test(&["-3", "-ge", "2"]));
+        assert!(test(&["a", "-eq", "1"]).is_err());
+        assert!(test(&["a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_files_and_connectives() {
+        assert_eq!(Ok(true), test(&["-d", "/"]));
+        assert_eq!(Ok(false), test(&["-f", "/"]));
+        assert_eq!(Ok(true), test(&["-e", "/bin/sh", "-a", "-x", "/bin/sh"]));
+        assert_eq!(Ok(false), test(&["-e", "/nonexistent-file"]));
+        assert_eq!(Ok(true), test(&["/", "-ef", "/."]));
+        assert_eq!(
+            Ok(true),
+            test(&["(", "a", "=", "b", ")", "-o", "!", "-z", "x"])
+        );
+        assert_eq!(
+            Ok(false),
+            test(&["1", "-eq", "1", "-a", "(", "x", "-o", "", ")", "-a", ""])
+        );
+        assert!(test(&["(", "a", "=", "a", "-o", "b"]).is_err());
+
+        let args = [CString::new("x").unwrap(), CString::new("]").unwrap()];
+        assert_eq!(Ok(true), Shell::test(&args, true));
+        assert!(Shell::test(&args[..1], true).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Runs a corpus of POSIX sh snippets through the shell in `--posix` mode and through `/bin/sh`,
+//! checking that both print the same standard output and finish with the same exit status.
+//!
+//! Each snippet is run by a fresh `Shell` in a child process with its output redirected to a
+//! file, so nothing it does can leak into the test process.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::process::{exit, Command};
+
+use libc::{c_int, dup2, fork, waitpid, STDERR_FILENO, STDOUT_FILENO};
+
+use crate::shell::Shell;
+
+/// Snippets covering the grammar and expansions the shell supports. Anything added here must
+/// behave the same in every POSIX shell, so avoid `echo` with backslashes and other
+/// implementation-defined behaviour.
+const CORPUS: &[&str] = &[
+    "echo hello world",
+    "true && echo yes || echo no; false && echo yes || echo no",
+    "! false; echo $?; ! true; echo $?",
+    "false | true; echo $?; true | false; echo $?",
+    "printf '%s\\n' a b c | tr a-z A-Z | sort -r",
+    "x=1; (x=2; echo $x); echo $x",
+    "{ echo a; echo b; } | wc -l",
+    "(exit 7); echo $?",
+    "exit 3",
+    "echo a # a comment",
+    "echo -n a; echo -n; echo b; echo -- -n -x",
+    "x=1; sh -c 'echo ${x-unset}'; export x; sh -c 'echo $x'; export y=2 z; z=3; sh -c 'echo $y $z'",
+    "X=1 sh -c 'echo $X'; echo ${X-unset}; printf 'a:b\\n' | { IFS=: read a b; echo \"$a $b\"; }; Z=0; Z=5 env | grep '^Z='; echo $Z",
+    "x=1; export x; unset x; echo ${x-gone}; sh -c 'echo ${x-gone}'; f() { echo f; }; unset -f f; f 2>/dev/null || echo nofunc",
+    "set -- a 'b c'; echo $# \"$2\"; set x; echo $# $1; set -f -; echo $# $1; set --; echo $#",
+    "command -v sh > /dev/null && echo found; command -v exec_test_missing || echo missing; echo() { :; }; echo hidden; command echo real",
+    "f=/tmp/conformance.e.$$; exec 3> $f; echo x >&3; exec 3>&-; cat $f; rm $f; (exec sh -c 'exit 5'); echo $?; exec echo done; echo never",
+    "printf 'a b  c d\\n x\\\\ y\\\\\\nz\\n' | { read p q r; echo \"$p|$q|$r\"; read -r s; echo \"$s\"; read t; echo \"$t\"; read u; echo $?; }",
+    "printf '1:2::4\\nlast' | { IFS=:; read a b c d; echo \"$a-$b-$c-$d\"; read e; echo \"$? $e\"; }",
+    "[ a = a ] && echo eq; test 3 -lt 10; echo $?; [ -d / -a ! -f / ]; echo $?; test -n; echo $?; test; echo $?; [ x = ]; echo $?",
+    "[ \\( a = b \\) -o 1 -ge 1 ]; echo $?; test 1 -eq x 2>/dev/null; echo $?; [ a 2>/dev/null; echo $?",
+    "printf '%5s|%-3d|%03x|%c|%.2s\\n' ab 7 255 xyz abc; printf '%s-' a b c; printf '\\n%d\\n' 1x; echo $?",
+    "echo one \\\n two",
+    "echo first\necho second",
+    "echo 'single $x' \"double ${HOME+set}\" a\\ b",
+    "x='a  b'; printf '<%s>' $x \"$x\"; echo",
+    "IFS=:; x=a:b::c; printf '<%s>' $x; echo",
+    "x=' lead trail '; printf '<%s>' [$x]; echo",
+    "x=file.tar.gz; echo ${x%.*} ${x%%.*} ${x#*.} ${x##*.} ${#x}",
+    "echo ${undefined_var:-default} ${undefined_var-unset} ${undefined_var+alt}.",
+    "echo ${y:=assigned}; echo $y",
+    "x=''; echo ${x:-colon} ${x-plain}.",
+    "x='*.c'; y=${x#\\*}; echo \"[$y]\"",
+    "echo $(echo nested $(echo deep)) `echo back`",
+    "printf '<%s>\\n' \"$(printf 'a\\n\\n\\n')\"",
+    "echo \"$(echo ')')\"",
+    "x=$(false); echo $?; x=$(exit 4); echo $?",
+    "set -f; echo /*; set +f",
+    "f=/tmp/conformance.$$; echo a > $f; echo b >> $f; cat < $f; rm $f",
+    "echo to stderr 1>&2; echo to stdout",
+    "{ echo grouped; } > /dev/null; echo after",
+    "nonexistent_command_for_conformance 2>/dev/null; echo $?",
+    "if true; then echo yes; else echo no; fi",
+    "if false; then echo 1; elif false; then echo 2; else echo 3; fi; echo $?",
+    "if false; then echo never; fi; echo $?",
+    "if (exit 4); then :; fi; if ! (exit 4); then echo negated; fi",
+    "if true\nthen\n  echo multi\nfi > /dev/null; echo done",
+    "echo if then fi",
+    "x=; while [ \"$x\" != aaa ]; do x=a$x; echo $x; done; echo $?",
+    "while false; do :; done; echo $?",
+    "x=; while [ -z \"$x\" ]; do x=1; (exit 5); done; echo $?",
+    "f=/tmp/conformance.w.$$; x=; while [ -z \"$x\" ]; do x=1; echo in; done > $f; x=; while [ -z \"$x\" ]; do x=1; cat; done < $f; rm $f",
+    "x=; while [ -z \"$x\" ]; do x=1; echo piped; done | tr a-z A-Z",
+    "x=; until [ \"$x\" = aaa ]; do x=a$x; echo $x; done; echo $?",
+    "until true; do :; done; echo $?",
+    "x=; until false; do [ -n \"$x\" ] && exit 2; x=1; done",
+    "for x in a 'b c' d; do echo \"<$x>\"; done; echo $x $?",
+    "v='1 2'; for x in $v \"$v\" $unset; do echo $x; done",
+    "for x in; do echo never; done; echo $?",
+    "for x in a b; do (exit 6); done; echo $?",
+    "for x\nin a\ndo\n  echo $x\ndone | tr a A",
+    "set -f; for x in *; do echo \"$x\"; done",
+    "for x in abc b c '*' d; do case $x in a*) echo a;; b | c) echo bc;; \"*\") echo star;; *) echo other; esac; done",
+    "case x in\n  (y) echo y\n    ;;\n  x)\n    echo x\n    ;;\nesac",
+    "false; case a in b) ;; esac; echo $?; false; case a in a) ;; esac; echo $?",
+    "p='?'; case x in $p) echo glob;; esac; case x in \"$p\") echo literal;; esac",
+    "case a in a) (exit 3);; esac; echo $?",
+    "f() { echo \"$# <$1> <$2>\"; }; f a 'b c'; f; echo $#",
+    "f() (exit $1); f 3; echo $?; f 0; echo $?",
+    "f()\n{\n  echo in f\n} > /dev/null; f; echo after",
+    "f() { echo $1; }; f one | tr a-z A-Z; for x in 1 2; do f $x; done",
+    "f() { g() { echo g; }; }; g 2>/dev/null; f; g",
+    "f() { echo in; return 3; echo never; }; f; echo $?",
+    "f() { for x in 1 2 3; do [ $x = 2 ] && return $x; echo $x; done; }; f; echo $?",
+    "f() { false; return; }; f; echo $?; g() { (return 4); echo $?; }; g",
+    "f() { return 1; }; f && echo yes || echo no; if f; then echo t; else echo e; fi",
+    "f() { while getopts ab:c o; do echo \"$o ${OPTARG:-} $OPTIND\"; done; echo $OPTIND; }; f -a -b x -c -- -a",
+    "x='echo $y; y=2'; y=1; eval \"$x\"; eval echo \\$y; eval 'false'; echo $?; eval; echo $?",
+    "for x in 1 2 3; do eval '[ $x = 2 ] && break'; echo $x; done",
+    "for x in 1 2 3; do [ $x = 2 ] && continue; echo $x; done; echo $?",
+    "for x in 1 2; do for y in a b c; do [ $y = b ] && continue 2; echo $x$y; done; done",
+    "for x in 1 2; do while true; do echo $x; break 2; done; done; echo $?",
+    "while break; do echo never; done; echo after; for x in 1 2; do (break; echo sub); echo $x; done",
+    "set -e; false && true; ! true; if false; then :; fi; echo alive; true && false; echo dead",
+    "set -e; f() { false; echo in f; }; f || echo failed; f; echo dead",
+    "set -e; for x in 1 2; do (exit $x); echo dead; done",
+    "f=/tmp/conformance-source-$$; printf 'x=1\\nreturn 3\\nx=2\\n' > $f; . $f; echo $? $x; rm $f",
+    "f() { for x in \"$@\"; do echo \"<$x>\"; done; echo \"$*\"; echo $@; }; f 'a  b' c; f",
+    "f() { IFS=,; echo \"$*\"; IFS=; echo \"$*\"; }; f 1 2 3",
+    "f() { shift; echo \"$# $1\"; shift 2; echo \"$# $1\"; shift 0; echo $1; }; f a b c d",
+    "f() { while getopts :a: o; do echo \"$o $OPTARG\"; done; }; f -z -a",
+    "(exit 3) & (exit 5) & wait %1; echo $?; wait %2; echo $?; (exit 4) & wait; echo $?; wait 1; echo $?",
+    "kill -l 15; kill -l 137; kill -0 $$; echo $?",
+    "umask 027; umask; umask -S; umask a+w; umask; umask g-w,o=r; umask; umask u=rwx,g=,o=x; umask -S",
+    "x=3; echo $((x * 2 + 1)) $((7 % 3)) $((1 << 4)) $((x += 2)) $x $((x > 4 ? 010 : 0x10)) $(((x) - -1))",
+    "ulimit -Sn 512; ulimit -n; ulimit -Hn; ulimit -c 0; ulimit -c; ulimit -f 100; ulimit; ulimit -Sf 50; ulimit -Hf",
+    "trap 'echo bye $?' EXIT; echo hi; false",
+    "(trap 'echo inner' EXIT; echo a); trap 'echo outer' EXIT; trap - EXIT; echo b",
+    "trap 'echo trapped' EXIT; exit 3",
+    "trap '' HUP; trap - HUP; trap x NOPE; echo $?",
+    "while :; done",
+    "until :; do done",
+    "for x in a b do echo; done",
+    "case a in a) b c) d;; esac",
+    "if true; then fi",
+    "echo a;; echo b",
+    "echo a )",
+];
+
+/// Run a snippet with `/bin/sh`.
+///
+/// ## Returns
+///
+/// What the snippet printed to standard output and its exit status.
+fn run_sh(snippet: &str) -> (String, i32) {
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(snippet)
+        .output()
+        .expect("failed to run /bin/sh");
+
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.code().unwrap_or(-1),
+    )
+}
+
+/// Run a snippet in a new `Shell` in POSIX mode, the way `simple-shell -c` runs it.
+///
+/// ## Returns
+///
+/// What the snippet printed to standard output and its exit status.
+fn run_shell(snippet: &str, id: usize) -> (String, i32) {
+    let path = std::env::temp_dir().join(format!("conformance-{}-{}", std::process::id(), id));
+    let file = File::create(&path).expect("failed to create the output file");
+    let null = File::create("/dev/null").expect("failed to open /dev/null");
+
+    let _ = std::io::stdout().flush();
+    let pid = unsafe { fork() };
+    if pid == 0 {
+        unsafe {
+            dup2(file.as_raw_fd(), STDOUT_FILENO);
+            dup2(null.as_raw_fd(), STDERR_FILENO);
+        }
+        let mut shell = Shell::new();
+        shell.options.posix = true;
+        let status = shell.run_command_string(snippet, Vec::new());
+        shell.run_exit_trap();
+        let _ = std::io::stdout().flush();
+        exit(status);
+    }
+    assert!(pid > 0, "failed to fork");
+
+    let mut status: c_int = 0;
+    unsafe { waitpid(pid, &mut status, 0) };
+    let output = std::fs::read_to_string(&path).unwrap_or_default();
+    let _ = std::fs::remove_file(&path);
+    (output, Shell::decode_status(status))
+}
+
+#[test]
+fn test_posix_corpus() {
+    let mut failures = Vec::new();
+    for (id, snippet) in CORPUS.iter().enumerate() {
+        let expected = run_sh(snippet);
+        let actual = run_shell(snippet, id);
+        if expected != actual {
+            failures.push(format!(
+                "{:?}\n  sh:           {:?}\n  simple-shell: {:?}",
+                snippet, expected, actual
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
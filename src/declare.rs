@@ -0,0 +1,368 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::CString;
+use std::io::Write;
+
+use crate::shell::{Attributes, Shell};
+
+/// Quote a value in double quotes, as `declare -p` prints it.
+fn double_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Shell {
+    /// Get the attributes of a variable, which are all off for one that hasn't been given any.
+    pub fn var_attributes(&self, name: &str) -> Attributes {
+        self.attributes.get(name).copied().unwrap_or_default()
+    }
+
+    /// Replace the attributes of a variable, forgetting them if they are all off.
+    fn set_attributes(&mut self, name: &str, attributes: Attributes) {
+        if attributes == Attributes::default() {
+            self.attributes.remove(name);
+        } else {
+            self.attributes.insert(name.to_string(), attributes);
+        }
+    }
+
+    /// Check that a variable can be assigned or unset, which it can't once it is readonly.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the variable isn't readonly.
+    /// - `Err(String)` saying that it is.
+    pub fn check_readonly(&self, name: &str) -> Result<(), String> {
+        if self.var_attributes(name).readonly {
+            Err(format!("{}: readonly variable", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Convert a value being assigned to `name` as its attributes require, which evaluates it as
+    /// an arithmetic expression if `name` is an integer variable.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(String)` The value to store.
+    /// - `Err(String)` if the value of an integer variable isn't a valid expression.
+    pub fn attribute_value(&mut self, name: &str, value: String) -> Result<String, String> {
+        if self.var_attributes(name).integer {
+            Ok(self.arithmetic_value(&value)?.to_string())
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Check whether a variable is passed on to the commands the shell runs.
+    fn is_exported(&self, name: &str) -> bool {
+        env::var_os(name).is_some() || self.exported_unset.contains(name)
+    }
+
+    /// Get the `declare` command that would recreate a variable with its attributes and value,
+    /// or `None` if nothing is known about it.
+    fn declaration(&self, name: &str) -> Option<String> {
+        let mut flags = String::new();
+        let value = if let Some(array) = self.arrays.get(name) {
+            flags.push('a');
+            let elements: Vec<String> = array
+                .iter()
+                .map(|(index, value)| format!("[{}]={}", index, double_quote(value)))
+                .collect();
+            Some(format!("=({})", elements.join(" ")))
+        } else if let Some(array) = self.assoc_arrays.get(name) {
+            flags.push('A');
+            let elements: Vec<String> = array
+                .iter()
+                .map(|(key, value)| format!("[{}]={}", key, double_quote(value)))
+                .collect();
+            Some(format!("=({})", elements.join(" ")))
+        } else {
+            self.get_var(name)
+                .map(|value| format!("={}", double_quote(&value)))
+        };
+
+        let attributes = self.var_attributes(name);
+        if attributes.integer {
+            flags.push('i');
+        }
+        if attributes.readonly {
+            flags.push('r');
+        }
+        if self.is_exported(name) {
+            flags.push('x');
+        }
+        if value.is_none() && flags.is_empty() {
+            return None;
+        }
+
+        let flags = if flags.is_empty() {
+            String::from("--")
+        } else {
+            format!("-{}", flags)
+        };
+        Some(format!(
+            "declare {} {}{}",
+            flags,
+            name,
+            value.unwrap_or_default()
+        ))
+    }
+
+    /// Get the names of every variable the shell knows about, including those in the
+    /// environment, in order.
+    fn all_var_names(&self) -> BTreeSet<String> {
+        env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| Shell::is_valid_name(name))
+            .chain(self.vars.keys().cloned())
+            .chain(self.arrays.keys().cloned())
+            .chain(self.assoc_arrays.keys().cloned())
+            .chain(self.attributes.keys().cloned())
+            .chain(self.exported_unset.iter().cloned())
+            .collect()
+    }
+
+    /// Turn `name` into an indexed or associative array. A variable that already holds a value
+    /// keeps it as the element at index or key `0`, and arrays that are already of the right kind
+    /// are left as they are.
+    fn declare_array(&mut self, name: &str, associative: bool) -> Result<(), String> {
+        if associative {
+            if self.arrays.contains_key(name) {
+                return Err(format!(
+                    "{}: cannot convert indexed to associative array",
+                    name
+                ));
+            }
+            if !self.assoc_arrays.contains_key(name) {
+                let first = self
+                    .vars
+                    .remove(name)
+                    .map(|value| (String::from("0"), value));
+                self.assoc_arrays
+                    .insert(name.to_string(), first.into_iter().collect());
+            }
+        } else {
+            if self.assoc_arrays.contains_key(name) {
+                return Err(format!(
+                    "{}: cannot convert associative to indexed array",
+                    name
+                ));
+            }
+            if !self.arrays.contains_key(name) {
+                let first = self.vars.remove(name).map(|value| (0, value));
+                self.arrays
+                    .insert(name.to_string(), first.into_iter().collect());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `declare` builtin, also called `typeset`, as in `declare [-aAirx] [-p]
+    /// [name[=value]...]`, which sets variables along with their attributes. `-a` makes each name
+    /// an indexed array and `-A` an associative one, `-i` makes values assigned to it be evaluated
+    /// as arithmetic expressions, `-r` makes it readonly and `-x` exports it. A `+` instead of a
+    /// `-` turns `-i` or `-x` back off.
+    ///
+    /// With `-p`, or without any names, the variables are printed as the `declare` commands that
+    /// would recreate them instead, only those with the attributes given when there are no
+    /// names.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `declare`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every name was declared or printed.
+    /// - `Err(String)` if an option or name is invalid, an array would have to change kind, the
+    ///   value of an integer variable isn't a valid expression or a name to print isn't set.
+    ///   Names before it are still declared.
+    pub fn declare(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut print = false;
+        let mut array = None;
+        let mut integer = None;
+        let mut readonly = false;
+        let mut export = None;
+        let mut i = 0;
+        while let Some(arg) = args.get(i).filter(|arg| arg.len() > 1) {
+            let on = match arg.chars().next() {
+                Some('-') if arg == "--" => {
+                    i += 1;
+                    break;
+                }
+                Some('-') => true,
+                Some('+') => false,
+                _ => break,
+            };
+            for flag in arg[1..].chars() {
+                match (flag, on) {
+                    ('a', true) => array = Some(false),
+                    ('A', true) => array = Some(true),
+                    ('i', _) => integer = Some(on),
+                    ('r', true) => readonly = true,
+                    ('x', _) => export = Some(on),
+                    ('p', _) => print = true,
+                    ('a' | 'A', false) => {
+                        return Err(String::from("cannot destroy array variables"))
+                    }
+                    ('r', false) => {
+                        return Err(String::from("cannot remove the readonly attribute"))
+                    }
+                    _ => return Err(format!("{}{}: invalid option", &arg[..1], flag)),
+                }
+            }
+            i += 1;
+        }
+        let names = &args[i..];
+
+        if names.is_empty() || print {
+            let names: Vec<String> = if names.is_empty() {
+                self.all_var_names()
+                    .into_iter()
+                    .filter(|name| {
+                        let attributes = self.var_attributes(name);
+                        (array.is_none()
+                            || array == Some(true) && self.assoc_arrays.contains_key(name)
+                            || array == Some(false) && self.arrays.contains_key(name))
+                            && (integer != Some(true) || attributes.integer)
+                            && (!readonly || attributes.readonly)
+                            && (export != Some(true) || self.is_exported(name))
+                    })
+                    .collect()
+            } else {
+                names.to_vec()
+            };
+
+            let mut result = Ok(());
+            let mut out = String::new();
+            for name in names {
+                match self.declaration(&name) {
+                    Some(declaration) => out.push_str(&format!("{}\n", declaration)),
+                    None => result = Err(format!("{}: not found", name)),
+                }
+            }
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(out.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|err| format!("write error: {}", err))?;
+            return result;
+        }
+
+        for arg in names {
+            let (name, value) = match Shell::parse_assignment(arg) {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !Shell::is_valid_name(name) {
+                return Err(format!("`{}': not a valid identifier", arg));
+            }
+
+            let mut attributes = self.var_attributes(name);
+            if value.is_some() || array.is_some() || integer.is_some() || export == Some(false) {
+                self.check_readonly(name)?;
+            }
+            if let Some(associative) = array {
+                self.declare_array(name, associative)?;
+            }
+            attributes.integer = integer.unwrap_or(attributes.integer);
+            self.set_attributes(name, attributes);
+
+            match value {
+                // The elements of an array are split and expanded like those of an assignment
+                Some(value) if value.starts_with('(') && value.ends_with(')') => {
+                    self.assign(name, value)?
+                }
+                Some(value) => {
+                    let value = self.attribute_value(name, value.to_string())?;
+                    self.set_var(name, &value);
+                }
+                None => (),
+            }
+
+            match export {
+                Some(true) => {
+                    self.export(&[CString::new(name).unwrap()])?;
+                }
+                Some(false) => {
+                    self.exported_unset.remove(name);
+                    if let Ok(value) = env::var(name) {
+                        env::remove_var(name);
+                        self.vars.insert(name.to_string(), value);
+                    }
+                }
+                None => (),
+            }
+
+            // Only now that the value is set can it no longer change
+            if readonly {
+                attributes.readonly = true;
+                self.set_attributes(name, attributes);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `readonly` builtin, as in `readonly [-p] [name[=value]...]`, which makes variables
+    /// readonly after setting any values given, so that they can't be assigned or unset again.
+    /// Without names, or with `-p`, the readonly variables are printed as the `readonly`
+    /// commands that would recreate them.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `readonly`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every name was made readonly or the variables were printed.
+    /// - `Err(String)` if an option or name is invalid or a name is already readonly and given a
+    ///   value. Names before it are still made readonly.
+    pub fn readonly(&mut self, args: &[CString]) -> Result<(), String> {
+        let names = match args.first().map(|arg| arg.to_string_lossy()) {
+            Some(arg) if arg == "-p" || arg == "--" => &args[1..],
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("{}: invalid option", arg))
+            }
+            _ => args,
+        };
+        if !names.is_empty() {
+            let mut declare_args = vec![CString::new("-r").unwrap()];
+            declare_args.extend_from_slice(names);
+            return self.declare(&declare_args);
+        }
+
+        let mut out = String::new();
+        for name in self.all_var_names() {
+            if !self.var_attributes(&name).readonly {
+                continue;
+            }
+            match self.get_var(&name) {
+                Some(value) => {
+                    out.push_str(&format!("readonly {}={}\n", name, Shell::quote(&value)))
+                }
+                None => out.push_str(&format!("readonly {}\n", name)),
+            }
+        }
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+}
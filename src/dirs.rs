@@ -0,0 +1,261 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use crate::shell::Shell;
+
+/// Parse an argument such as `+2` or `-0` naming an entry of a directory stack with `len`
+/// entries, counting from the left as `dirs` lists them with `+` and from the right with `-`.
+///
+/// ## Returns
+///
+/// - `None` if the argument isn't an index.
+/// - `Some(Ok(usize))` with the index of the entry counting from the left.
+/// - `Some(Err(String))` if the stack doesn't have that many entries.
+fn stack_index(arg: &str, len: usize) -> Option<Result<usize, String>> {
+    let (from_right, digits) = if let Some(digits) = arg.strip_prefix('+') {
+        (false, digits)
+    } else {
+        (true, arg.strip_prefix('-')?)
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let index = digits.parse::<usize>().ok().filter(|&n| n < len);
+    Some(
+        index
+            .map(|n| if from_right { len - 1 - n } else { n })
+            .ok_or_else(|| format!("{}: directory stack index out of range", arg)),
+    )
+}
+
+impl Shell {
+    /// Get the directory stack as `dirs` lists it, starting with the working directory.
+    fn dir_list(&self) -> Vec<String> {
+        std::iter::once(self.working_dir())
+            .chain(self.dir_stack.iter().cloned())
+            .collect()
+    }
+
+    /// Shorten a directory within `$HOME` to start with `~`.
+    pub fn tilde_dir(&self, dir: &str) -> String {
+        match self.get_var("HOME").filter(|home| !home.is_empty()) {
+            Some(home) if dir == home => String::from("~"),
+            Some(home) => match dir.strip_prefix(&home).filter(|rest| rest.starts_with('/')) {
+                Some(rest) => format!("~{}", rest),
+                None => dir.to_string(),
+            },
+            None => dir.to_string(),
+        }
+    }
+
+    /// Print the directory stack on one line, as `pushd` and `popd` do after changing it.
+    fn print_dir_stack(&self) -> Result<(), String> {
+        let dirs: Vec<String> = self
+            .dir_list()
+            .iter()
+            .map(|dir| self.tilde_dir(dir))
+            .collect();
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{}", dirs.join(" "))
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// The `dirs` builtin, as in `dirs [-clpv] [+N | -N]`, which prints the directory stack
+    /// kept by `pushd` and `popd`, starting with the working directory. Directories within
+    /// `$HOME` are shortened to start with `~` unless `-l` is given. `-p` prints one directory per
+    /// line and `-v` numbers them, while `-c` clears the stack. `+N` or `-N` prints only the
+    /// entry at that index, counting from the left or right.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `dirs`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the stack was printed or cleared.
+    /// - `Err(String)` if an option is invalid or an index is out of range.
+    pub fn dirs(&mut self, args: &[CString]) -> Result<(), String> {
+        let list = self.dir_list();
+        let mut long = false;
+        let mut per_line = false;
+        let mut numbered = false;
+        let mut index = None;
+        for arg in args {
+            let arg = arg.to_string_lossy();
+            if let Some(entry) = stack_index(&arg, list.len()) {
+                index = Some(entry?);
+                continue;
+            }
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                return Err(format!("{}: invalid argument", arg));
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'c' => {
+                        self.dir_stack.clear();
+                        return Ok(());
+                    }
+                    'l' => long = true,
+                    'p' => per_line = true,
+                    'v' => numbered = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+        }
+
+        let show = |dir: &String| {
+            if long {
+                dir.clone()
+            } else {
+                self.tilde_dir(dir)
+            }
+        };
+        let out = match index {
+            Some(index) if numbered => format!("{:2}  {}\n", index, show(&list[index])),
+            Some(index) => format!("{}\n", show(&list[index])),
+            None if numbered => list
+                .iter()
+                .enumerate()
+                .map(|(n, dir)| format!("{:2}  {}\n", n, show(dir)))
+                .collect(),
+            None if per_line => list.iter().map(|dir| format!("{}\n", show(dir))).collect(),
+            None => format!("{}\n", list.iter().map(show).collect::<Vec<_>>().join(" ")),
+        };
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// The `pushd` builtin, as in `pushd [-n] [dir | +N | -N]`, which changes to `dir` and adds
+    /// the directory it left to the directory stack. `+N` or `-N` instead rotates the stack so
+    /// that the entry at that index is on top and changes to it, and with no arguments the top
+    /// two entries are swapped. `-n` adds `dir` below the top without changing directory. The
+    /// stack is printed afterwards.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `pushd`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the stack was changed.
+    /// - `Err(String)` if an argument is invalid, the stack is too short or the directory
+    ///   couldn't be changed.
+    pub fn pushd(&mut self, args: &[CString]) -> Result<(), String> {
+        let mut no_cd = false;
+        let mut operand = None;
+        let mut options = true;
+        for arg in args {
+            let arg = arg.to_string_lossy().into_owned();
+            if options && arg == "--" {
+                options = false;
+            } else if options && arg == "-n" {
+                no_cd = true;
+            } else if operand.is_some() {
+                return Err(String::from("too many arguments"));
+            } else if options
+                && arg.len() > 1
+                && arg.starts_with('-')
+                && !arg[1..].bytes().all(|b| b.is_ascii_digit())
+            {
+                return Err(format!("{}: invalid option", arg));
+            } else {
+                operand = Some(arg);
+            }
+        }
+
+        let mut list = self.dir_list();
+        match operand {
+            None => {
+                if list.len() < 2 {
+                    return Err(String::from("no other directory"));
+                }
+                if !no_cd {
+                    self.enter_dir(&list[1], false)?;
+                }
+                self.dir_stack[0] = list.swap_remove(0);
+            }
+            Some(arg) => match stack_index(&arg, list.len()).filter(|_| options) {
+                Some(index) => {
+                    list.rotate_left(index?);
+                    self.enter_dir(&list[0], false)?;
+                    self.dir_stack = list.split_off(1);
+                }
+                None if no_cd => self.dir_stack.insert(0, arg),
+                None => {
+                    self.enter_dir(&arg, false)?;
+                    self.dir_stack.insert(0, list.swap_remove(0));
+                }
+            },
+        }
+
+        self.print_dir_stack()
+    }
+
+    /// The `popd` builtin, as in `popd [-n] [+N | -N]`, which removes the top of the directory
+    /// stack and changes to the directory below it. `+N` or `-N` removes the entry at that index
+    /// instead, only changing directory for the top one, and `-n` removes the entry below the
+    /// top without changing directory. The stack is printed afterwards.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `popd`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if an entry was removed.
+    /// - `Err(String)` if an argument is invalid, the stack is empty or the directory couldn't
+    ///   be changed.
+    pub fn popd(&mut self, args: &[CString]) -> Result<(), String> {
+        let len = self.dir_stack.len() + 1;
+        let mut no_cd = false;
+        let mut index = 0;
+        for arg in args {
+            let arg = arg.to_string_lossy();
+            if arg == "-n" {
+                no_cd = true;
+            } else if let Some(entry) = stack_index(&arg, len) {
+                index = entry?;
+            } else {
+                return Err(format!("{}: invalid argument", arg));
+            }
+        }
+        if self.dir_stack.is_empty() {
+            return Err(String::from("directory stack empty"));
+        }
+
+        if index > 0 {
+            self.dir_stack.remove(index - 1);
+        } else if no_cd {
+            self.dir_stack.remove(0);
+        } else {
+            let dir = self.dir_stack[0].clone();
+            self.enter_dir(&dir, false)?;
+            self.dir_stack.remove(0);
+        }
+
+        self.print_dir_stack()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_index() {
+        assert_eq!(Some(Ok(0)), stack_index("+0", 3));
+        assert_eq!(Some(Ok(2)), stack_index("+2", 3));
+        assert_eq!(Some(Ok(2)), stack_index("-0", 3));
+        assert_eq!(Some(Ok(0)), stack_index("-2", 3));
+        assert!(matches!(stack_index("+3", 3), Some(Err(_))));
+        assert_eq!(None, stack_index("-n", 3));
+        assert_eq!(None, stack_index("+", 3));
+        assert_eq!(None, stack_index("/tmp", 3));
+    }
+}
@@ -0,0 +1,166 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use crate::shell::Shell;
+
+/// Append `text` to `out` with its backslash escapes replaced by the characters they stand for,
+/// as `echo -e` and `printf %b` do. A backslash before anything that isn't an escape is kept as
+/// it is.
+///
+/// ## Returns
+///
+/// `false` if a `\c` was reached, after which nothing more should be printed, otherwise `true`.
+pub fn expand_escapes(text: &[u8], out: &mut Vec<u8>) -> bool {
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] != b'\\' {
+            out.push(text[i]);
+            i += 1;
+            continue;
+        }
+        match expand_escape(&text[i + 1..], out) {
+            Some(len) => i += 1 + len,
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Append the character stood for by the escape whose backslash comes just before `text`. `\0`
+/// may be followed by up to three octal digits and `\x` by up to two hex digits.
+///
+/// ## Returns
+///
+/// The number of bytes of `text` the escape used, or `None` for `\c`.
+pub fn expand_escape(text: &[u8], out: &mut Vec<u8>) -> Option<usize> {
+    let Some(c) = text.first() else {
+        out.push(b'\\');
+        return Some(0);
+    };
+    match c {
+        b'\\' => out.push(b'\\'),
+        b'a' => out.push(0x07),
+        b'b' => out.push(0x08),
+        b'c' => return None,
+        b'e' | b'E' => out.push(0x1b),
+        b'f' => out.push(0x0c),
+        b'n' => out.push(b'\n'),
+        b'r' => out.push(b'\r'),
+        b't' => out.push(b'\t'),
+        b'v' => out.push(0x0b),
+        b'0' => {
+            let (value, len) = parse_digits(&text[1..], 8, 3);
+            out.push(value);
+            return Some(1 + len);
+        }
+        b'x' => match parse_digits(&text[1..], 16, 2) {
+            (_, 0) => out.extend_from_slice(b"\\x"),
+            (value, len) => {
+                out.push(value);
+                return Some(1 + len);
+            }
+        },
+        other => out.extend_from_slice(&[b'\\', *other]),
+    }
+
+    Some(1)
+}
+
+/// Parse up to `max` digits in `radix` from the start of `text` into a byte, wrapping if the
+/// value doesn't fit.
+///
+/// ## Returns
+///
+/// The value and the number of digits used.
+pub fn parse_digits(text: &[u8], radix: u32, max: usize) -> (u8, usize) {
+    let mut value: u32 = 0;
+    let mut len = 0;
+    while let Some(digit) = text
+        .get(len)
+        .filter(|_| len < max)
+        .and_then(|c| (*c as char).to_digit(radix))
+    {
+        value = value * radix + digit;
+        len += 1;
+    }
+
+    (value as u8, len)
+}
+
+impl Shell {
+    /// The `echo` builtin, which prints its arguments separated by spaces and followed by a
+    /// newline. Leading arguments made only of the options `-n`, `-e` and `-E` leave out the
+    /// newline, turn on backslash escapes, or turn them back off.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `echo`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the output was written.
+    /// - `Err(String)` if writing to standard output failed.
+    pub fn echo(args: &[CString]) -> Result<(), String> {
+        let mut newline = true;
+        let mut escapes = false;
+        let mut words = args.iter().map(|arg| arg.as_bytes()).peekable();
+        while let Some(flags) = words.peek().and_then(|word| word.strip_prefix(b"-")) {
+            if flags.is_empty() || !flags.iter().all(|flag| b"neE".contains(flag)) {
+                break;
+            }
+            for flag in flags {
+                match flag {
+                    b'n' => newline = false,
+                    b'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            words.next();
+        }
+
+        let mut out = Vec::new();
+        for (i, word) in words.enumerate() {
+            if i > 0 {
+                out.push(b' ');
+            }
+            if !escapes {
+                out.extend_from_slice(word);
+            } else if !expand_escapes(word, &mut out) {
+                newline = false;
+                break;
+            }
+        }
+        if newline {
+            out.push(b'\n');
+        }
+
+        // Written straight to the file descriptor so that it lands wherever standard output is
+        // currently redirected
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(&out)
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(text: &str) -> (String, bool) {
+        let mut out = Vec::new();
+        let more = expand_escapes(text.as_bytes(), &mut out);
+        (String::from_utf8(out).unwrap(), more)
+    }
+
+    #[test]
+    fn test_expand_escapes() {
+        assert_eq!((String::from("a\tb\nc\\"), true), escaped("a\\tb\\nc\\\\"));
+        assert_eq!((String::from("AB"), true), escaped("\\0101\\x42"));
+        assert_eq!((String::from("\\q\\x"), true), escaped("\\q\\x"));
+        assert_eq!((String::from("trailing\\"), true), escaped("trailing\\"));
+        assert_eq!((String::from("stop"), false), escaped("stop\\cignored"));
+    }
+}
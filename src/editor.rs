@@ -0,0 +1,600 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Bound;
+use std::path::Path;
+use std::time::SystemTime;
+
+use libc::{ioctl, winsize, STDOUT_FILENO, TIOCGWINSZ};
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, SearchDirection};
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::parser;
+use crate::shell::Shell;
+
+/// The line editor an interactive shell reads commands with.
+pub type LineEditor = Editor<ShellHelper, DefaultHistory>;
+
+/// The colors the command line is highlighted with.
+const FOUND_COLOR: &str = "\x1b[32m";
+const NOT_FOUND_COLOR: &str = "\x1b[31m";
+const KEYWORD_COLOR: &str = "\x1b[1;34m";
+const STRING_COLOR: &str = "\x1b[33m";
+const OPERATOR_COLOR: &str = "\x1b[36m";
+const COMMENT_COLOR: &str = "\x1b[90m";
+const UNMATCHED_COLOR: &str = "\x1b[1;37;41m";
+const HINT_COLOR: &str = "\x1b[2m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// The operators the highlighter knows, longest first so that `&&` isn't taken for two `&`s.
+const OPERATORS: [&str; 19] = [
+    "&>>", "&&", "||", ";;", "|&", ">>", "<<", "&>", ">&", "<&", ">|", "<>", "|", "&", ";", "(",
+    ")", "<", ">",
+];
+
+/// The characters that end a word on the command line when they aren't quoted.
+const WORD_DELIMITERS: &str = "|&;()<>";
+
+/// The programs in the directories of `$PATH`, which are kept between lines and only looked for
+/// again once `$PATH` or one of its directories changes.
+#[derive(Default)]
+pub struct PathIndex {
+    /// The `$PATH` the programs were found in.
+    path: String,
+    /// When each directory of `$PATH` was last modified, as adding or removing a file does.
+    modified: Vec<Option<SystemTime>>,
+    programs: BTreeSet<String>,
+}
+
+impl PathIndex {
+    /// Look for the programs in `$PATH` again if it or one of its directories has changed since
+    /// they were last looked for.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the index was built again.
+    pub fn refresh(&mut self, path: &str) -> bool {
+        let dirs: Vec<&str> = path.split(':').filter(|dir| !dir.is_empty()).collect();
+        let modified: Vec<Option<SystemTime>> = dirs
+            .iter()
+            .map(|dir| std::fs::metadata(dir).and_then(|meta| meta.modified()).ok())
+            .collect();
+        if path == self.path && modified == self.modified {
+            return false;
+        }
+
+        self.programs.clear();
+        for dir in dirs {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if Shell::is_executable(&entry.path()) {
+                    self.programs
+                        .insert(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        self.path = path.to_string();
+        self.modified = modified;
+        true
+    }
+
+    /// Check whether there is a program of this name on `$PATH`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.programs.contains(name)
+    }
+
+    /// Get the programs on `$PATH` whose names start with `prefix`, in order.
+    pub fn starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a String> {
+        self.programs
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |name| name.starts_with(prefix))
+    }
+}
+
+/// Check whether a word typed after `before` would name a command: it starts the line or
+/// follows an operator, a keyword such as `then`, or only assignments.
+fn is_command_position(before: &str, posix: bool) -> bool {
+    let mut before = before.trim_end();
+    loop {
+        if before.is_empty() || before.ends_with(|c| WORD_DELIMITERS.contains(c) && c != ')') {
+            return true;
+        }
+        let start = before
+            .rfind(|c: char| c.is_whitespace() || WORD_DELIMITERS.contains(c))
+            .map_or(0, |i| {
+                i + before[i..].chars().next().map_or(1, char::len_utf8)
+            });
+        let word = &before[start..];
+        if word
+            .split_once('=')
+            .is_some_and(|(name, _)| Shell::is_valid_name(name))
+        {
+            before = before[..start].trim_end();
+            continue;
+        }
+        return parser::is_reserved_word(word, posix)
+            && !matches!(word, "for" | "case" | "select" | "in");
+    }
+}
+
+/// Get how many columns wide the terminal on standard output is, or `None` if it isn't a
+/// terminal.
+fn terminal_width() -> Option<usize> {
+    let mut size: winsize = unsafe { std::mem::zeroed() };
+    match unsafe { ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => Some(size.ws_col as usize),
+        _ => None,
+    }
+}
+
+/// Get how many columns a string takes up on the terminal, leaving out the escape sequences that
+/// color it.
+pub fn display_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A control sequence ends with its first letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else if !c.is_control() {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// The line editor's view of the shell, which draws the parts of the prompt rustyline doesn't
+/// know about, colors the command line and suggests how to finish it.
+#[derive(Default)]
+pub struct ShellHelper {
+    /// The prompt drawn at the right edge of the terminal on the line the command is typed on,
+    /// which is empty unless the primary prompt is being shown.
+    pub right_prompt: String,
+    /// The aliases, functions and builtins a command can name, as they were when the line
+    /// started being read.
+    pub commands: HashSet<String>,
+    /// The programs on `$PATH`.
+    pub path_index: PathIndex,
+    /// Whether the shell is in POSIX mode, where fewer words are keywords.
+    pub posix: bool,
+}
+
+/// Find where the word starting at the beginning of `text` ends, which is at the first unquoted
+/// blank or operator.
+///
+/// ## Returns
+///
+/// The length of the word, and the offset of its opening quote if one isn't closed.
+fn word_end(text: &str) -> (usize, Option<usize>) {
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' | '"' => {
+                let mut closed = false;
+                while let Some((_, next)) = chars.next() {
+                    if next == '\\' && c == '"' {
+                        chars.next();
+                    } else if next == c {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return (text.len(), Some(i));
+                }
+            }
+            c if c.is_whitespace() || WORD_DELIMITERS.contains(c) => return (i, None),
+            _ => (),
+        }
+    }
+    (text.len(), None)
+}
+
+/// Color the quoted strings in a word, marking a quote that is never closed.
+fn paint_word(out: &mut String, word: &str, unmatched: Option<usize>) {
+    let mut chars = word.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                if let Some((_, next)) = chars.next() {
+                    out.push(next);
+                }
+            }
+            '\'' | '"' if Some(i) == unmatched => {
+                out.push_str(&format!(
+                    "{}{}{}{}{}",
+                    UNMATCHED_COLOR,
+                    c,
+                    RESET_COLOR,
+                    STRING_COLOR,
+                    &word[i + 1..]
+                ));
+                out.push_str(RESET_COLOR);
+                return;
+            }
+            '\'' | '"' => {
+                out.push_str(STRING_COLOR);
+                out.push(c);
+                while let Some((_, next)) = chars.next() {
+                    out.push(next);
+                    if next == '\\' && c == '"' {
+                        if let Some((_, escaped)) = chars.next() {
+                            out.push(escaped);
+                        }
+                    } else if next == c {
+                        break;
+                    }
+                }
+                out.push_str(RESET_COLOR);
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+impl ShellHelper {
+    /// Check whether a command name would be found as an alias, function, builtin or program.
+    fn is_command(&self, name: &str) -> bool {
+        if self.commands.contains(name) {
+            return true;
+        }
+        if name.contains('/') {
+            return Shell::is_executable(Path::new(name));
+        }
+        self.path_index.contains(name)
+    }
+
+    /// Color a command line: the name of each command is green if it would be found and red if
+    /// not, keywords are blue, strings yellow, operators cyan and comments gray. A quote that is
+    /// never closed is marked in red.
+    fn highlight_line(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len() * 2);
+        // Whether the next word names a command, or is the file of a redirection
+        let mut command_position = true;
+        let mut after_redirect = false;
+        let mut pos = 0;
+        while let Some(c) = line[pos..].chars().next() {
+            let rest = &line[pos..];
+            if c.is_whitespace() {
+                if c == '\n' {
+                    command_position = true;
+                }
+                out.push(c);
+                pos += c.len_utf8();
+                continue;
+            }
+            if c == '#' {
+                out.push_str(&format!("{}{}{}", COMMENT_COLOR, rest, RESET_COLOR));
+                break;
+            }
+
+            // A file descriptor number belongs to the redirection after it
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if let Some(op) = OPERATORS
+                .iter()
+                .find(|op| rest[digits..].starts_with(*op))
+                .filter(|op| digits == 0 || op.contains(['<', '>']))
+            {
+                let len = digits + op.len();
+                out.push_str(&format!(
+                    "{}{}{}",
+                    OPERATOR_COLOR,
+                    &rest[..len],
+                    RESET_COLOR
+                ));
+                pos += len;
+                if op.contains(['<', '>']) {
+                    after_redirect = true;
+                } else {
+                    command_position = *op != ")";
+                }
+                continue;
+            }
+
+            let (len, unmatched) = word_end(rest);
+            let word = &rest[..len];
+            pos += len;
+            if after_redirect {
+                after_redirect = false;
+            } else if command_position && unmatched.is_none() {
+                let plain = !word.contains(['\'', '"', '\\', '$', '`']);
+                if word
+                    .split_once('=')
+                    .is_some_and(|(name, _)| Shell::is_valid_name(name))
+                {
+                    // Assignments come before the command's name
+                } else if parser::is_reserved_word(word, self.posix) {
+                    out.push_str(&format!("{}{}{}", KEYWORD_COLOR, word, RESET_COLOR));
+                    // The words after these are names and patterns rather than commands
+                    command_position = !matches!(word, "for" | "case" | "select" | "in");
+                    continue;
+                } else if plain {
+                    let color = if self.is_command(word) {
+                        FOUND_COLOR
+                    } else {
+                        NOT_FOUND_COLOR
+                    };
+                    out.push_str(&format!("{}{}{}", color, word, RESET_COLOR));
+                    command_position = false;
+                    continue;
+                } else {
+                    command_position = false;
+                }
+            }
+            paint_word(&mut out, word, unmatched);
+        }
+        out
+    }
+}
+
+impl Helper for ShellHelper {}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    /// Complete the name of a command from the aliases, functions, builtins and programs on
+    /// `$PATH`, adding a space after it once there is only one that fits. Other words, and names
+    /// with a `/` or quotes in them, aren't completed.
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || WORD_DELIMITERS.contains(c))
+            .map_or(0, |i| {
+                i + line[i..].chars().next().map_or(1, char::len_utf8)
+            });
+        let word = &line[start..pos];
+        if word.contains(['/', '\'', '"', '\\', '$', '`'])
+            || !is_command_position(&line[..start], self.posix)
+        {
+            return Ok((pos, Vec::new()));
+        }
+
+        let mut names: BTreeSet<&String> = self
+            .commands
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .collect();
+        names.extend(self.path_index.starting_with(word));
+        let mut candidates: Vec<String> = names.into_iter().cloned().collect();
+        if let [name] = candidates.as_mut_slice() {
+            name.push(' ');
+        }
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    /// Suggest the rest of the most recent command in the history that starts with the line,
+    /// which the right arrow key accepts. Commands that span several lines aren't suggested.
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if line.trim().is_empty() || pos < line.len() {
+            return None;
+        }
+        let history = ctx.history();
+        (0..history.len()).rev().find_map(|index| {
+            let entry = history.get(index, SearchDirection::Reverse).ok()??.entry;
+            entry
+                .strip_prefix(line)
+                .filter(|rest| !rest.is_empty() && !entry.contains('\n'))
+                .map(str::to_string)
+        })
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned(self.highlight_line(line))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_COLOR, hint, RESET_COLOR))
+    }
+
+    /// Redraw the whole line as it changes, since one character can change the colors of the
+    /// words around it.
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+
+    /// Draw the right prompt after the prompt, then move back so that the line is drawn after the
+    /// prompt as usual. Typing over the right prompt hides it, and it is left out when there
+    /// isn't room for it.
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        _default: bool,
+    ) -> Cow<'b, str> {
+        if self.right_prompt.is_empty() {
+            return Cow::Borrowed(prompt);
+        }
+        let Some(columns) = terminal_width() else {
+            return Cow::Borrowed(prompt);
+        };
+
+        let last_line = prompt.rsplit('\n').next().unwrap_or_default();
+        let width = display_width(&self.right_prompt);
+        if display_width(last_line) + width >= columns {
+            return Cow::Borrowed(prompt);
+        }
+        // Save the cursor, draw at the right edge and restore the cursor
+        Cow::Owned(format!(
+            "{}\x1b7\x1b[{}G{}\x1b8",
+            prompt,
+            columns - width + 1,
+            self.right_prompt
+        ))
+    }
+}
+
+impl Shell {
+    /// Tell the line editor what the shell knows before a command line is read: the right
+    /// prompt to draw and the commands that can be found and completed. The programs on `$PATH`
+    /// are only looked for again if it has changed.
+    pub fn prepare_editor(&mut self, right_prompt: String) {
+        let mut commands: HashSet<String> = self
+            .aliases
+            .keys()
+            .chain(self.functions.keys())
+            .cloned()
+            .collect();
+        commands.extend(
+            self.builtins
+                .keys()
+                .filter(|name| self.is_builtin(name))
+                .cloned(),
+        );
+        let path = self.get_var("PATH").unwrap_or_default();
+        let posix = self.options.posix;
+
+        if let Some(helper) = self.editor.as_mut().and_then(|editor| editor.helper_mut()) {
+            helper.right_prompt = right_prompt;
+            helper.commands = commands;
+            helper.path_index.refresh(&path);
+            helper.posix = posix;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use rustyline::history::History;
+
+    use super::*;
+
+    /// Replace the colors in a highlighted line with markers naming them.
+    fn colors(line: &str) -> String {
+        line.replace(FOUND_COLOR, "<found>")
+            .replace(NOT_FOUND_COLOR, "<missing>")
+            .replace(KEYWORD_COLOR, "<keyword>")
+            .replace(STRING_COLOR, "<string>")
+            .replace(OPERATOR_COLOR, "<op>")
+            .replace(COMMENT_COLOR, "<comment>")
+            .replace(UNMATCHED_COLOR, "<unmatched>")
+            .replace(RESET_COLOR, "</>")
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        let mut helper = ShellHelper {
+            commands: HashSet::from([String::from("echo")]),
+            ..ShellHelper::default()
+        };
+        helper.path_index.refresh("/bin:/usr/bin");
+        let highlight = |line: &str| colors(&helper.highlight_line(line));
+
+        assert_eq!(
+            "<found>echo</> <string>'a b'</> <op>|</> <found>cat</> <op>2></> err",
+            highlight("echo 'a b' | cat 2> err")
+        );
+        assert_eq!(
+            "X=1 <missing>nope-not-here</> <op>&&</> <keyword>if</> <found>echo</> x<op>;</>",
+            highlight("X=1 nope-not-here && if echo x;")
+        );
+        assert_eq!(
+            "<found>echo</> <unmatched>\"</><string>oops</>",
+            highlight("echo \"oops")
+        );
+        assert_eq!(
+            "<found>echo</> a <comment># note</>",
+            highlight("echo a # note")
+        );
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(5, display_width("hello"));
+        assert_eq!(2, display_width("\x1b[1;32mok\x1b[0m"));
+        assert_eq!(4, display_width("✓ ab"));
+    }
+
+    #[test]
+    fn test_complete() {
+        let dir = std::env::temp_dir().join(format!("editor-test-path-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["zzprog", "zzprogram", "zzdata"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let mode = |name: &str, mode| {
+            std::fs::set_permissions(dir.join(name), std::fs::Permissions::from_mode(mode))
+        };
+        mode("zzprog", 0o755).unwrap();
+        mode("zzprogram", 0o755).unwrap();
+        mode("zzdata", 0o644).unwrap();
+
+        let mut helper = ShellHelper {
+            commands: HashSet::from([String::from("zzalias"), String::from("echo")]),
+            ..ShellHelper::default()
+        };
+        let path = dir.to_string_lossy().into_owned();
+        assert!(helper.path_index.refresh(&path));
+        assert!(!helper.path_index.refresh(&path));
+
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| helper.complete(line, line.len(), &ctx).unwrap();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        assert_eq!(
+            (0, names(&["zzalias", "zzprog", "zzprogram"])),
+            complete("zz")
+        );
+        assert_eq!((6, names(&["zzprogram "])), complete("echo; zzprogr"));
+        assert_eq!((7, names(&["zzalias "])), complete("X=1 if zza"));
+        assert_eq!((7, Vec::new()), complete("echo zz"));
+        assert_eq!((3, Vec::new()), complete("./z"));
+
+        // A new program is found once its directory changes
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(dir.join("zzprog2"), "").unwrap();
+        mode("zzprog2", 0o755).unwrap();
+        assert!(helper.path_index.refresh(&path));
+        assert!(helper.path_index.contains("zzprog2"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hint() {
+        let helper = ShellHelper::default();
+        let mut history = DefaultHistory::new();
+        for line in [
+            "echo one",
+            "ls -l",
+            "echo two\necho three",
+            "echo two",
+            "cd /",
+        ] {
+            let _ = history.add(line);
+        }
+        let ctx = Context::new(&history);
+
+        assert_eq!(Some(String::from("two")), helper.hint("echo ", 5, &ctx));
+        assert_eq!(Some(String::from("ne")), helper.hint("echo o", 6, &ctx));
+        assert_eq!(None, helper.hint("echo two", 8, &ctx));
+        assert_eq!(None, helper.hint("echo", 2, &ctx));
+        assert_eq!(None, helper.hint("", 0, &ctx));
+        assert_eq!(None, helper.hint("pwd", 3, &ctx));
+    }
+}
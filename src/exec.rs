@@ -0,0 +1,2904 @@
+use std::env;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::rc::Rc;
+
+use libc::{
+    abort, c_char, c_int, close, dup2, execvp, fork, getpid, open, pid_t, pipe, setpgid, signal,
+    tcsetpgrp, waitpid, ENOENT, O_RDONLY, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL,
+    SIG_IGN, STDIN_FILENO, STDOUT_FILENO, WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG,
+};
+
+use crate::jobs::{Job, JobState};
+use crate::parser::{
+    self, AndOrList, Command, CommandList, Connector, ParseError, ParseOptions, Pipeline, Redirect,
+    SimpleCommand,
+};
+use crate::pattern;
+use crate::shell::{CommandKind, Jump, LocalScope, Shell};
+use crate::times::Stopwatch;
+
+impl Shell {
+    /// Parse a line read from the user and run the commands on it.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `line: &str` The line to run.
+    pub fn run_line(&mut self, line: &str) {
+        self.interrupted = false;
+        self.jump = None;
+        match self.parse(line) {
+            Ok(list) => {
+                self.execute_list(&list);
+            }
+            Err(err) => {
+                eprintln!("Error parsing command: {}", err);
+                self.last_status = 2;
+            }
+        }
+    }
+
+    /// Run the commands of a script.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name of the script to report syntax errors with.
+    /// - `script: &str` The text of the script.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, or 2 if the script has a syntax error.
+    pub fn run_script(&mut self, name: &str, script: &str) -> i32 {
+        let mut lines = script.lines();
+        self.run_lines(name, |_| lines.next().map(String::from))
+    }
+
+    /// Run the commands given with `-c`. The first of the arguments after them becomes `$0` and
+    /// the rest become the positional parameters.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `command: &str` The commands to run.
+    /// - `args: Vec<String>` The arguments after the commands.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, or 2 if the commands have a syntax error.
+    pub fn run_command_string(&mut self, command: &str, args: Vec<String>) -> i32 {
+        let mut args = args.into_iter();
+        if let Some(arg0) = args.next() {
+            self.arg0 = arg0;
+        }
+        self.positional_params = args.collect();
+        self.run_script("simple-shell: -c", command)
+    }
+
+    /// Run the commands piped to the shell's standard input until it ends. Lines are read one
+    /// at a time so that commands which read standard input themselves get the lines after
+    /// their own.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, or 2 if the input has a syntax error.
+    pub fn run_input(&mut self) -> i32 {
+        let name = self.arg0.clone();
+        self.run_lines(&name, |shell| shell.read_line(""))
+    }
+
+    /// Run commands from lines of input, a complete command at a time so that each is parsed
+    /// after the ones before it have run and can use the aliases they define. Running stops at
+    /// a syntax error, when interrupted with Ctrl-C or when a builtin such as `return` jumps out
+    /// of the commands.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name of the input to report syntax errors with.
+    /// - `next_line: impl FnMut(&mut Shell) -> Option<String>` Reads the next line without its
+    ///   newline, or returns `None` at the end of the input.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, or 2 if the input has a syntax error.
+    fn run_lines(
+        &mut self,
+        name: &str,
+        mut next_line: impl FnMut(&mut Shell) -> Option<String>,
+    ) -> i32 {
+        let mut command = String::new();
+        while let Some(line) = next_line(self) {
+            command.push_str(&line);
+            command.push('\n');
+            match self.parse(&command) {
+                Ok(list) => {
+                    self.execute_list(&list);
+                }
+                Err(ParseError::Incomplete(_)) => continue,
+                Err(err) => {
+                    eprintln!("{}: {}", name, err);
+                    self.last_status = 2;
+                    return self.last_status;
+                }
+            }
+            command.clear();
+            if self.should_stop() {
+                return self.last_status;
+            }
+        }
+
+        if !command.is_empty() {
+            // A backslash at the end of the last line has no line left to join
+            let command = command.strip_suffix("\\\n").unwrap_or(&command);
+            match self.parse(command) {
+                Ok(list) => {
+                    self.execute_list(&list);
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", name, err);
+                    self.last_status = 2;
+                }
+            }
+        }
+
+        self.last_status
+    }
+
+    /// The `source` or `.` builtin, as in `source file [arg...]`, which runs the commands in a
+    /// file in the current shell so that the variables, functions and directory they set stay
+    /// set. A file name without a `/` is looked for in `$PATH` and then in the current
+    /// directory. Any arguments become the positional parameters while the file runs, and
+    /// `return` ends it early.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after the command name.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, 1 if the file couldn't be read or 2 if it has a
+    /// syntax error.
+    pub fn source(&mut self, args: &[CString]) -> i32 {
+        let Some(file) = args.first().map(|arg| arg.to_string_lossy().into_owned()) else {
+            eprintln!("source: filename argument required");
+            return 2;
+        };
+
+        let path = if file.contains('/') {
+            PathBuf::from(&file)
+        } else {
+            env::var("PATH")
+                .unwrap_or_default()
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| Path::new(dir).join(&file))
+                .find(|path| path.is_file())
+                .unwrap_or_else(|| PathBuf::from(&file))
+        };
+        let script = match std::fs::read_to_string(&path) {
+            Ok(script) => script,
+            Err(err) => {
+                eprintln!("source: {}: {}", file, err);
+                return 1;
+            }
+        };
+
+        let saved = (args.len() > 1).then(|| {
+            let args = args[1..]
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+            std::mem::replace(&mut self.positional_params, args)
+        });
+        self.source_depth += 1;
+        let status = self.run_script(&file, &script);
+        if matches!(self.jump, Some(Jump::Return(_))) {
+            self.jump = None;
+        }
+        self.source_depth -= 1;
+        if let Some(saved) = saved {
+            self.positional_params = saved;
+        }
+
+        status
+    }
+
+    /// Source the startup file of an interactive shell, so that the aliases, functions and
+    /// variables it defines are there from the first prompt. The file is `$ENV` if it is set and
+    /// `~/.simple-shellrc` otherwise, and is skipped if it doesn't exist.
+    pub fn run_startup_file(&mut self) {
+        let path = match self.get_var("ENV").filter(|path| !path.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => match self.get_var("HOME") {
+                Some(home) => Path::new(&home).join(".simple-shellrc"),
+                None => return,
+            },
+        };
+        if !path.is_file() {
+            return;
+        }
+
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        self.last_status = self.source(&[path]);
+    }
+
+    /// Find what a command name refers to, in the order the shell looks for it: aliases and
+    /// keywords when the command is parsed, then functions, builtins and programs on `$PATH`
+    /// when it is run.
+    ///
+    /// ## Returns
+    ///
+    /// What the name refers to, or `None` if it wouldn't be found.
+    pub fn lookup_command(&self, name: &str) -> Option<CommandKind> {
+        if let Some(value) = self.aliases.get(name) {
+            Some(CommandKind::Alias(value.clone()))
+        } else if parser::is_reserved_word(name, self.options.posix) {
+            Some(CommandKind::Keyword)
+        } else if self.functions.contains_key(name) {
+            Some(CommandKind::Function)
+        } else if self.is_builtin(name) {
+            Some(CommandKind::Builtin)
+        } else {
+            self.find_program(name).map(CommandKind::File)
+        }
+    }
+
+    /// Find everything a command name refers to, in the order the shell looks for it, so that
+    /// the first is what would run and the rest are hidden behind it. Every program of that
+    /// name on `$PATH` is included.
+    pub fn lookup_all(&self, name: &str) -> Vec<CommandKind> {
+        let mut kinds = Vec::new();
+        if let Some(value) = self.aliases.get(name) {
+            kinds.push(CommandKind::Alias(value.clone()));
+        }
+        if parser::is_reserved_word(name, self.options.posix) {
+            kinds.push(CommandKind::Keyword);
+        }
+        if self.functions.contains_key(name) {
+            kinds.push(CommandKind::Function);
+        }
+        if self.is_builtin(name) {
+            kinds.push(CommandKind::Builtin);
+        }
+        kinds.extend(self.find_programs(name).into_iter().map(CommandKind::File));
+
+        kinds
+    }
+
+    /// Get the paths a program could be run from: the name itself if it contains a `/` and
+    /// otherwise the file of that name in each directory in `$PATH`, whether or not it exists.
+    fn program_paths(&self, name: &str) -> Vec<PathBuf> {
+        if name.contains('/') {
+            return vec![PathBuf::from(name)];
+        }
+
+        self.get_var("PATH")
+            .unwrap_or_default()
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(name))
+            .collect()
+    }
+
+    /// Check whether `path` is a file that can be run as a program.
+    pub fn is_executable(path: &Path) -> bool {
+        std::fs::metadata(path)
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    }
+
+    /// Find the program that would be run for a command name, which is the name itself if it
+    /// contains a `/` and otherwise the first executable file of that name in `$PATH`.
+    pub fn find_program(&self, name: &str) -> Option<PathBuf> {
+        self.program_paths(name)
+            .into_iter()
+            .find(|path| Shell::is_executable(path))
+    }
+
+    /// Find every program a command name could run, in the order of `$PATH`.
+    pub fn find_programs(&self, name: &str) -> Vec<PathBuf> {
+        self.program_paths(name)
+            .into_iter()
+            .filter(|path| Shell::is_executable(path))
+            .collect()
+    }
+
+    /// Parse a line with the shell's aliases and options.
+    pub fn parse(&self, line: &str) -> Result<CommandList, ParseError> {
+        parser::parse_with(
+            line,
+            &ParseOptions {
+                aliases: &self.aliases,
+                posix: self.options.posix,
+            },
+        )
+    }
+
+    /// Run each AND-OR list of a list in order, regardless of how the previous ones exited,
+    /// stopping early if the line is interrupted with Ctrl-C or a builtin such as `return` jumps
+    /// out of it.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run.
+    pub fn execute_list(&mut self, list: &CommandList) -> i32 {
+        for and_or in &list.items {
+            self.run_pending_traps();
+            if self.should_stop() {
+                break;
+            }
+            if and_or.background {
+                self.execute_background(and_or);
+            } else {
+                self.execute_and_or(and_or);
+            }
+        }
+        self.run_pending_traps();
+
+        self.last_status
+    }
+
+    /// Start an AND-OR list in a child process without waiting for it to finish. With job control
+    /// the child gets its own process group but the terminal stays with the shell. A lone
+    /// pipeline has each of its commands started directly as a process of the job instead.
+    pub fn execute_background(&mut self, and_or: &AndOrList) {
+        self.reap_jobs();
+
+        let pipeline = &and_or.first;
+        let (pgid, pids) = match and_or.rest.as_slice() {
+            [] if pipeline.commands.len() > 1 && !pipeline.negated && !pipeline.timed => {
+                self.spawn_pipeline(&pipeline.commands, false)
+            }
+            _ => {
+                let fork_pid: pid_t = unsafe { fork() };
+                if fork_pid == 0 {
+                    self.enter_child(0, false);
+
+                    // A lone command is exec'd directly so the job's PID is the command's own
+                    let status = match pipeline.commands.as_slice() {
+                        [command]
+                            if and_or.rest.is_empty() && !pipeline.negated && !pipeline.timed =>
+                        {
+                            self.run_command(command, true)
+                        }
+                        _ => self.execute_and_or(and_or),
+                    };
+                    let _ = std::io::stdout().flush();
+                    exit(status);
+                } else if fork_pid < 0 {
+                    eprintln!("Failed to fork a new process.");
+                    unsafe { abort() };
+                }
+
+                if self.options.monitor {
+                    unsafe {
+                        setpgid(fork_pid, fork_pid);
+                    }
+                }
+                (fork_pid, vec![fork_pid])
+            }
+        };
+
+        let pid = pids.last().copied().unwrap_or(pgid);
+        self.last_background_pid = Some(pid);
+        let id = self.add_job(pgid, &pids, and_or.to_string());
+        if self.interactive {
+            eprintln!("[{}] {}", id, pid);
+        }
+        self.last_status = 0;
+    }
+
+    /// Run the commands of a command substitution in a child process and collect what they print.
+    /// The child stays in the shell's process group, so it is interrupted along with the command
+    /// being expanded.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `commands: &str` The text between `$(` and `)`.
+    ///
+    /// ## Returns
+    ///
+    /// The standard output of the commands with trailing newlines removed. Their exit status is
+    /// stored in `last_status` and `substitution_status`.
+    pub fn command_substitution(&mut self, commands: &str) -> String {
+        let mut fds: [c_int; 2] = [-1, -1];
+        if unsafe { pipe(fds.as_mut_ptr()) } == -1 {
+            eprintln!("Failed to create a pipe.");
+            return String::new();
+        }
+
+        let _ = std::io::stdout().flush();
+        let fork_pid: pid_t = unsafe { fork() };
+        if fork_pid == 0 {
+            if self.interactive {
+                Shell::reset_signals();
+            }
+            self.reset_traps();
+            self.interactive = false;
+            self.options.monitor = false;
+            self.clear_jobs();
+            unsafe {
+                close(fds[0]);
+                dup2(fds[1], STDOUT_FILENO);
+                close(fds[1]);
+            }
+
+            self.run_line(commands);
+            let _ = std::io::stdout().flush();
+            exit(self.last_status);
+        } else if fork_pid < 0 {
+            eprintln!("Failed to fork a new process.");
+            unsafe { abort() };
+        }
+
+        unsafe { close(fds[1]) };
+        let mut output = Vec::new();
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        let _ = reader.read_to_end(&mut output);
+
+        let mut status: c_int = c_int::default();
+        unsafe { waitpid(fork_pid, &mut status, 0) };
+        self.last_status = Shell::decode_status(status);
+        self.substitution_status = Some(self.last_status);
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        output
+    }
+
+    /// Run an AND-OR list. A pipeline after `&&` only runs if the status so far is zero and a
+    /// pipeline after `||` only runs if it is non-zero. Only a failure of the last pipeline
+    /// counts for `set -e`, since the others are being tested.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last pipeline run.
+    pub fn execute_and_or(&mut self, and_or: &AndOrList) -> i32 {
+        let mut status = if and_or.rest.is_empty() {
+            self.execute_checked_pipeline(&and_or.first)
+        } else {
+            self.ignoring_errexit(|shell| shell.execute_pipeline(&and_or.first))
+        };
+        for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+            let run = match connector {
+                Connector::And => status == 0,
+                Connector::Or => status != 0,
+            };
+            if !run || self.should_stop() {
+                continue;
+            }
+            status = if i + 1 == and_or.rest.len() {
+                self.execute_checked_pipeline(pipeline)
+            } else {
+                self.ignoring_errexit(|shell| shell.execute_pipeline(pipeline))
+            };
+        }
+
+        status
+    }
+
+    /// Run a pipeline whose failure makes `set -e` exit the shell. Negated pipelines are exempt,
+    /// as are compound commands other than subshells since the commands inside them have
+    /// already been checked.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the pipeline.
+    fn execute_checked_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let status = self.execute_pipeline(pipeline);
+        let compound = match pipeline.commands.as_slice() {
+            [Command::Simple(_) | Command::Subshell { .. }] => false,
+            [_] => true,
+            _ => false,
+        };
+        if status != 0
+            && self.options.errexit
+            && self.errexit_ignored == 0
+            && !pipeline.negated
+            && !compound
+            && self.jump.is_none()
+        {
+            self.jump = Some(Jump::Exit(status));
+        }
+
+        status
+    }
+
+    /// Write a command being run to standard error for `set -x`, prefixed by the expansion of
+    /// `$PS4`, which is `+ ` by default.
+    fn trace(&mut self, command: &str) {
+        let ps4 = self.get_var("PS4").unwrap_or_else(|| String::from("+ "));
+        let prefix = self.expand_string(&ps4).unwrap_or(ps4);
+        let _ = writeln!(std::io::stderr(), "{}{}", prefix, command);
+    }
+
+    /// Run commands whose failure is being tested rather than an error, so that `set -e`
+    /// doesn't exit the shell for them, such as the condition of an `if`.
+    fn ignoring_errexit(&mut self, run: impl FnOnce(&mut Shell) -> i32) -> i32 {
+        self.errexit_ignored += 1;
+        let status = run(self);
+        self.errexit_ignored -= 1;
+
+        status
+    }
+
+    /// Run a pipeline and record its exit status for `$?`. A single command runs builtins in the
+    /// shell itself while each stage of a longer pipeline runs in its own child process.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command of the pipeline, inverted if the pipeline is negated.
+    /// A timed pipeline also reports the time it took on standard error.
+    pub fn execute_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let stopwatch = pipeline.timed.then(Stopwatch::start);
+        let status = match pipeline.commands.as_slice() {
+            [command] => self.run_command(command, false),
+            commands => self.launch_pipeline(commands),
+        };
+        if let Some(stopwatch) = stopwatch {
+            stopwatch.report();
+        }
+
+        let status = if pipeline.negated {
+            (status == 0) as i32
+        } else {
+            status
+        };
+        self.last_status = status;
+        status
+    }
+
+    /// Run one stage of a pipeline.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `command: &Command` The command to run.
+    /// - `forked: bool` Whether we are already in a child process that can be used to run the
+    ///   command instead of forking a new one.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the command.
+    fn run_command(&mut self, command: &Command, forked: bool) -> i32 {
+        match command {
+            Command::Simple(command) => self.run_simple(command, forked),
+            Command::Subshell {
+                list, redirects, ..
+            } if forked => self.with_redirects(redirects, |shell| shell.execute_subshell(list)),
+            Command::Subshell {
+                list, redirects, ..
+            } => self.launch(command.to_string(), |shell| {
+                shell.with_redirects(redirects, |shell| shell.execute_subshell(list))
+            }),
+            Command::Group {
+                list, redirects, ..
+            } => self.with_redirects(redirects, |shell| shell.execute_list(list)),
+            Command::If {
+                clauses,
+                else_body,
+                redirects,
+                ..
+            } => self.with_redirects(redirects, |shell| {
+                shell.execute_if(clauses, else_body.as_ref())
+            }),
+            Command::While {
+                until,
+                condition,
+                body,
+                redirects,
+                ..
+            } => self.with_redirects(redirects, |shell| {
+                shell.in_loop(|shell| shell.execute_while(*until, condition, body))
+            }),
+            Command::For {
+                select,
+                name,
+                words,
+                body,
+                redirects,
+                ..
+            } => self.with_redirects(redirects, |shell| match shell.expand_for_words(words) {
+                Ok(values) if *select => {
+                    shell.in_loop(|shell| shell.execute_select(name, &values, body))
+                }
+                Ok(values) => shell.in_loop(|shell| shell.execute_for(name, values, body)),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    1
+                }
+            }),
+            Command::Case {
+                word,
+                arms,
+                redirects,
+                ..
+            } => self.with_redirects(redirects, |shell| shell.execute_case(word, arms)),
+            Command::Conditional {
+                expression,
+                redirects,
+                ..
+            } => self.with_redirects(redirects, |shell| {
+                match shell.evaluate_conditional(expression) {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        2
+                    }
+                }
+            }),
+            Command::Function { name, body, .. } => {
+                self.functions
+                    .insert(name.clone(), Rc::new((**body).clone()));
+                0
+            }
+        }
+    }
+
+    /// Run the body of a function with the positional parameters set to `args` in a new scope
+    /// for local variables, restoring the caller's positional parameters and variables
+    /// afterwards.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the body.
+    fn call_function(&mut self, body: &Command, args: &[CString]) -> i32 {
+        let args = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let saved = std::mem::replace(&mut self.positional_params, args);
+        let saved_loop_depth = std::mem::take(&mut self.loop_depth);
+        self.local_scopes.push(LocalScope::new());
+        let status = self.run_command(body, false);
+        if matches!(self.jump, Some(Jump::Return(_))) {
+            self.jump = None;
+        }
+        self.pop_local_scope();
+        self.loop_depth = saved_loop_depth;
+        self.positional_params = saved;
+
+        status
+    }
+
+    /// The `eval` builtin, which joins its arguments with spaces and runs the result as commands
+    /// in the current shell.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, 0 if there were none or 2 if the commands
+    /// couldn't be parsed.
+    pub fn eval(&mut self, args: &[CString]) -> i32 {
+        let line = args
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        match self.parse(&line) {
+            Ok(list) if list.items.is_empty() => 0,
+            Ok(list) => self.execute_list(&list),
+            Err(err) => {
+                eprintln!("eval: {}", err);
+                2
+            }
+        }
+    }
+
+    /// Run the commands of a subshell in the child forked for it, followed by any `EXIT` trap
+    /// they set.
+    fn execute_subshell(&mut self, list: &CommandList) -> i32 {
+        self.execute_list(list);
+        self.run_exit_trap();
+
+        self.last_status
+    }
+
+    /// Run a loop with `loop_depth` raised so that `break` and `continue` inside it can reach it.
+    fn in_loop(&mut self, run: impl FnOnce(&mut Shell) -> i32) -> i32 {
+        self.loop_depth += 1;
+        let status = run(self);
+        self.loop_depth -= 1;
+
+        status
+    }
+
+    /// Check whether a loop should stop after running part of an iteration, taking the jump
+    /// made by a `break` or `continue` that was meant for it. A jump that reaches further out
+    /// passes on to the next loop with one fewer left to leave.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if the loop should stop, which it does for `break`, a `continue` meant for an
+    /// outer loop, Ctrl-C and `return`.
+    fn loop_should_stop(&mut self) -> bool {
+        match self.jump {
+            Some(Jump::Break(1)) => {
+                self.jump = None;
+                true
+            }
+            Some(Jump::Continue(1)) => {
+                self.jump = None;
+                self.check_interrupt()
+            }
+            Some(Jump::Break(n)) => {
+                self.jump = Some(Jump::Break(n - 1));
+                true
+            }
+            Some(Jump::Continue(n)) => {
+                self.jump = Some(Jump::Continue(n - 1));
+                true
+            }
+            _ => self.should_stop(),
+        }
+    }
+
+    /// Run the body of the first arm of a `case` command with a pattern matching `word`. The
+    /// patterns are tried in order and expanded only as they are reached.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the body that ran, 0 if no pattern matched or 1 if the word or a
+    /// pattern couldn't be expanded.
+    fn execute_case(&mut self, word: &str, arms: &[(Vec<String>, CommandList)]) -> i32 {
+        let subject = match self.expand_string(word) {
+            Ok(subject) => subject,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+
+        for (patterns, body) in arms {
+            for pattern in patterns {
+                match self.expand_pattern(pattern) {
+                    // An empty body leaves nothing to set the status
+                    Ok(pattern) if pattern::matches(&pattern, &subject) => {
+                        return if body.items.is_empty() {
+                            0
+                        } else {
+                            self.execute_list(body)
+                        };
+                    }
+                    Ok(_) => (),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        return 1;
+                    }
+                }
+            }
+        }
+
+        0
+    }
+
+    /// Expand the word list of a `for` or `select` loop, which is the positional parameters if
+    /// there was no `in`.
+    fn expand_for_words(&mut self, words: &Option<Vec<String>>) -> Result<Vec<String>, String> {
+        match words {
+            Some(words) => {
+                let mut values = Vec::new();
+                for word in words {
+                    values.extend(self.expand_word(word)?);
+                }
+                Ok(values)
+            }
+            None => Ok(self.positional_params.clone()),
+        }
+    }
+
+    /// Run the body of a `for` loop once for each of `values` with the variable `name` set to
+    /// it. Ctrl-C stops the loop along with the command it interrupts.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last run of the body, or 0 if it never ran.
+    fn execute_for(&mut self, name: &str, values: Vec<String>, body: &CommandList) -> i32 {
+        if let Err(err) = self.check_readonly(name) {
+            eprintln!("{}", err);
+            return 1;
+        }
+        let mut status = 0;
+        for value in values {
+            self.set_var(name, &value);
+            status = self.execute_list(body);
+            if self.loop_should_stop() {
+                return self.last_status;
+            }
+        }
+
+        status
+    }
+
+    /// Run a `select` loop, which writes `values` to standard error as a numbered menu and reads
+    /// a choice with the `$PS3` prompt. Each line read is stored in `$REPLY` and the body is run
+    /// with `name` set to the value chosen, or to the empty string if the line wasn't one of the
+    /// numbers. An empty line shows the menu again. The loop ends at the end of the input or
+    /// when Ctrl-C is pressed.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last run of the body, or 0 if it never ran.
+    fn execute_select(&mut self, name: &str, values: &[String], body: &CommandList) -> i32 {
+        if let Err(err) = self.check_readonly(name) {
+            eprintln!("{}", err);
+            return 1;
+        }
+        let mut status = 0;
+        if values.is_empty() {
+            return status;
+        }
+
+        let mut show_menu = true;
+        loop {
+            if show_menu {
+                let width = values.len().to_string().len();
+                for (i, value) in values.iter().enumerate() {
+                    eprintln!("{:>width$}) {}", i + 1, value);
+                }
+            }
+
+            let prompt = self.get_var("PS3").unwrap_or_else(|| String::from("#? "));
+            let Some(reply) = self.read_line(&prompt) else {
+                return if self.check_interrupt() {
+                    self.last_status
+                } else {
+                    status
+                };
+            };
+            show_menu = reply.trim().is_empty();
+            if show_menu {
+                continue;
+            }
+
+            let choice = match reply.trim().parse::<usize>() {
+                Ok(n) if (1..=values.len()).contains(&n) => values[n - 1].as_str(),
+                _ => "",
+            };
+            self.set_var("REPLY", &reply);
+            self.set_var(name, choice);
+            status = self.execute_list(body);
+            if self.loop_should_stop() {
+                return self.last_status;
+            }
+        }
+    }
+
+    /// Run the body of a `while` loop for as long as its condition exits with 0, or that of an
+    /// `until` loop for as long as it doesn't. Ctrl-C stops the loop along with the command it
+    /// interrupts.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last run of the body, or 0 if it never ran.
+    fn execute_while(&mut self, until: bool, condition: &CommandList, body: &CommandList) -> i32 {
+        let mut status = 0;
+        loop {
+            let done = (self.ignoring_errexit(|shell| shell.execute_list(condition)) == 0) == until;
+            if self.loop_should_stop() {
+                return self.last_status;
+            } else if done {
+                return status;
+            }
+
+            status = self.execute_list(body);
+            if self.loop_should_stop() {
+                return self.last_status;
+            }
+        }
+    }
+
+    /// Run the body of the first clause of an `if` command whose condition exits with 0, or the
+    /// `else` body if none do.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the body that ran, or 0 if none did.
+    fn execute_if(
+        &mut self,
+        clauses: &[(CommandList, CommandList)],
+        else_body: Option<&CommandList>,
+    ) -> i32 {
+        for (condition, body) in clauses {
+            if self.ignoring_errexit(|shell| shell.execute_list(condition)) == 0 {
+                return self.execute_list(body);
+            }
+        }
+
+        match else_body {
+            Some(body) => self.execute_list(body),
+            None => 0,
+        }
+    }
+
+    /// Expand and run a single command, either as a builtin or by running a program.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `command: &SimpleCommand` The command to run.
+    /// - `forked: bool` Whether we are already in a child process, in which case programs are
+    ///   exec'd directly instead of being launched in a new process.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the command.
+    fn run_simple(&mut self, command: &SimpleCommand, forked: bool) -> i32 {
+        // A command made up only of assignments sets shell variables
+        if command
+            .words
+            .iter()
+            .all(|word| Shell::parse_assignment(word).is_some())
+        {
+            self.substitution_status = None;
+            for word in &command.words {
+                let (name, value) = Shell::parse_assignment(word).unwrap();
+                if let Err(err) = self.assign(name, value) {
+                    eprintln!("{}", err);
+                    return 1;
+                }
+                if self.options.xtrace {
+                    // Arrays and elements are traced as they were written
+                    let traced = match self.get_var(name) {
+                        Some(expanded) if Shell::is_valid_name(name) && !value.starts_with('(') => {
+                            format!("{}={}", name, Shell::quote(&expanded))
+                        }
+                        _ => word.clone(),
+                    };
+                    self.trace(&traced);
+                }
+            }
+            // The status is that of the last command substitution, if there was one
+            let status = self.substitution_status.unwrap_or(0);
+            return self.with_redirects(&command.redirects, |_| status);
+        }
+
+        // Assignments before the command's name only last while it runs
+        let (assignments, words) = command.words.split_at(
+            command
+                .words
+                .iter()
+                .take_while(|word| Shell::parse_assignment(word).is_some())
+                .count(),
+        );
+        let argv = match self.expand_words(words) {
+            Ok(argv) if argv.is_empty() => {
+                return self.with_redirects(&command.redirects, |_| 0);
+            }
+            Ok(argv) => argv,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+
+        if self.options.xtrace {
+            let words: Vec<String> = assignments
+                .iter()
+                .cloned()
+                .chain(argv.iter().map(|arg| Shell::quote(&arg.to_string_lossy())))
+                .collect();
+            self.trace(&words.join(" "));
+        }
+
+        let saved = match self.set_temporary_vars(assignments) {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+
+        // Functions are looked up first so they can wrap builtins and programs of the same name
+        let first_cmd = argv.first().unwrap().to_str().unwrap_or_default();
+        let status = if let Some(body) = self.functions.get(first_cmd).cloned() {
+            self.with_redirects(&command.redirects, |shell| {
+                shell.call_function(&body, &argv[1..])
+            })
+        } else {
+            self.run_builtin_or_program(argv, &command.redirects, forked)
+        };
+        self.restore_vars(saved);
+
+        status
+    }
+
+    /// Run a command that isn't a function, as either a builtin or a program.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `argv: Vec<CString>` The expanded words of the command.
+    /// - `redirects: &[Redirect]` The redirections of the command.
+    /// - `forked: bool` Whether this is already a child process that a program can replace.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the command.
+    fn run_builtin_or_program(
+        &mut self,
+        argv: Vec<CString>,
+        redirects: &[Redirect],
+        forked: bool,
+    ) -> i32 {
+        let first_cmd = argv.first().unwrap().to_str().unwrap_or_default();
+        let builtin = self.builtin(first_cmd);
+        if builtin.is_some() && first_cmd == "command" {
+            // These builtins need the redirections themselves rather than having them applied
+            self.command_builtin(argv, redirects, forked)
+        } else if builtin.is_some() && first_cmd == "exec" {
+            self.exec_builtin(argv, redirects)
+        } else if builtin.is_some() && first_cmd == "builtin" {
+            // `builtin name` runs the builtin even if a function has taken its name
+            match argv.get(1).and_then(|name| name.to_str().ok()) {
+                None => self.with_redirects(redirects, |_| 0),
+                Some(name) if self.is_builtin(name) => {
+                    self.run_builtin_or_program(argv[1..].to_vec(), redirects, forked)
+                }
+                Some(name) => {
+                    eprintln!("builtin: {}: not a shell builtin", name);
+                    1
+                }
+            }
+        } else if let Some(builtin) = builtin {
+            self.with_redirects(redirects, |shell| builtin.run(shell, &argv[1..]))
+        } else if let Err(err) = Shell::check_arg_max(&argv) {
+            // Report this here rather than as E2BIG from execvp, where it would be confused with
+            // the program itself being too big to run
+            eprintln!("{}", err);
+            126
+        } else if forked {
+            if let Err(err) = self.apply_redirects(redirects) {
+                eprintln!("{}", err);
+                return 1;
+            }
+            Shell::exec_program(&argv)
+        } else {
+            let command = argv
+                .iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.launch(command, |shell| {
+                if let Err(err) = shell.apply_redirects(redirects) {
+                    eprintln!("{}", err);
+                    return 1;
+                }
+                Shell::exec_program(&argv)
+            })
+        }
+    }
+
+    /// The `exec` builtin. `exec name [arg...]` replaces the shell with a program rather than
+    /// running it in a child, and `exec` with only redirections applies them to the shell itself
+    /// so that they last for the commands after it.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `argv: Vec<CString>` The expanded words of the command, starting with `exec`.
+    /// - `redirects: &[Redirect]` The redirections of the command.
+    ///
+    /// ## Returns
+    ///
+    /// 0 once the redirections are applied, or 1 if one failed. A program that can't be run
+    /// exits the shell with 127 or 126, except in an interactive shell where 127 is returned
+    /// if it isn't found.
+    pub fn exec_builtin(&mut self, argv: Vec<CString>, redirects: &[Redirect]) -> i32 {
+        let program = &argv[1..];
+        if let Some(name) = program.first().map(|name| name.to_string_lossy()) {
+            if self.interactive && self.find_program(&name).is_none() {
+                eprintln!("exec: {}: not found", name);
+                return 127;
+            }
+        }
+
+        let saved = match self.apply_redirects(redirects) {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+        if program.is_empty() {
+            Shell::keep_redirects(saved);
+            return 0;
+        }
+
+        // The program gets the default handlers of the signals an interactive shell ignores
+        if self.interactive {
+            Shell::reset_signals();
+        }
+        Shell::exec_program(program)
+    }
+
+    /// The `command` builtin, as in `command name [arg...]`, which runs a builtin or program
+    /// while skipping any function of the same name. With `-v` it instead prints how each name
+    /// would be run, as the path of a program, the definition of an alias or just the name, and
+    /// with `-V` it describes them as `type` does.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `argv: Vec<CString>` The expanded words of the command, starting with `command`.
+    /// - `redirects: &[Redirect]` The redirections of the command.
+    /// - `forked: bool` Whether this is already a child process that a program can replace.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the command that was run. With `-v` or `-V` it is 0 if every name was
+    /// found and 1 otherwise, and an invalid option gives 2.
+    pub fn command_builtin(
+        &mut self,
+        argv: Vec<CString>,
+        redirects: &[Redirect],
+        forked: bool,
+    ) -> i32 {
+        let mut describe = None;
+        let mut start = 1;
+        while let Some(arg) = argv.get(start).and_then(|arg| arg.to_str().ok()) {
+            match arg {
+                "-v" => describe = Some(false),
+                "-V" => describe = Some(true),
+                "--" => {
+                    start += 1;
+                    break;
+                }
+                arg if arg.starts_with('-') && arg.len() > 1 => {
+                    eprintln!("command: {}: invalid option", arg);
+                    return 2;
+                }
+                _ => break,
+            }
+            start += 1;
+        }
+
+        let names = &argv[start..];
+        match describe {
+            Some(true) => self.with_redirects(redirects, |shell| match shell.command_type(names) {
+                Ok(()) => 0,
+                Err(err) => {
+                    eprintln!("command: {}", err);
+                    1
+                }
+            }),
+            Some(false) => self.with_redirects(redirects, |shell| {
+                let mut status = 0;
+                for name in names {
+                    let name = name.to_string_lossy();
+                    match shell.lookup_command(&name) {
+                        Some(CommandKind::Alias(value)) => {
+                            println!("alias {}={}", name, Shell::quote(&value))
+                        }
+                        Some(CommandKind::File(path)) => println!("{}", path.display()),
+                        Some(_) => println!("{}", name),
+                        None => status = 1,
+                    }
+                }
+                status
+            }),
+            None if names.is_empty() => self.with_redirects(redirects, |_| 0),
+            None => self.run_builtin_or_program(names.to_vec(), redirects, forked),
+        }
+    }
+
+    /// Fork a child in its own process group to run `child`, giving it the terminal and waiting
+    /// for it to finish.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `command: String` The command the child runs, which is listed if it is stopped.
+    /// - `child: F` Run in the child process to produce its exit status, usually by exec'ing a
+    ///   program.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the child, 128 plus the signal number if it was killed or stopped by a
+    /// signal, or 127 if the program it tried to run couldn't be found.
+    fn launch<F: FnOnce(&mut Shell) -> i32>(&mut self, command: String, child: F) -> i32 {
+        unsafe {
+            let fork_pid: pid_t = fork();
+
+            if fork_pid == 0 {
+                // Successfully spawned a new process, give control to child
+                self.enter_child(0, true);
+
+                let status = child(self);
+                let _ = std::io::stdout().flush();
+                exit(status);
+            } else if fork_pid < 0 {
+                eprintln!("Failed to fork a new process.");
+                abort();
+            }
+
+            if self.options.monitor {
+                setpgid(fork_pid, fork_pid);
+            }
+            self.wait_foreground(Job::new(0, fork_pid, &[fork_pid], command))
+        }
+    }
+
+    /// Run a pipeline in the foreground as one job and wait for it.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command of the pipeline, or with `set -o pipefail` that of
+    /// the last command to fail.
+    fn launch_pipeline(&mut self, commands: &[Command]) -> i32 {
+        let (pgid, pids) = self.spawn_pipeline(commands, true);
+        let command = commands
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+        self.wait_foreground(Job::new(0, pgid, &pids, command))
+    }
+
+    /// Fork a child for each command of a pipeline, connecting the output of each command to the
+    /// input of the next. All of the children are put in one process group led by the first child
+    /// so that signals from the terminal reach the whole pipeline, and that group is given the
+    /// terminal if the pipeline is in the `foreground`.
+    ///
+    /// ## Returns
+    ///
+    /// The process group of the pipeline and the process IDs of its commands in order.
+    fn spawn_pipeline(&mut self, commands: &[Command], foreground: bool) -> (pid_t, Vec<pid_t>) {
+        let mut pgid: pid_t = 0;
+        let mut pids: Vec<pid_t> = Vec::new();
+        // The read end of the pipe from the previous command, if there was one
+        let mut input: c_int = -1;
+
+        for (i, command) in commands.iter().enumerate() {
+            let last = i == commands.len() - 1;
+            let mut fds: [c_int; 2] = [-1, -1];
+            unsafe {
+                if !last && pipe(fds.as_mut_ptr()) == -1 {
+                    eprintln!("Failed to create a pipe.");
+                    break;
+                }
+
+                let fork_pid: pid_t = fork();
+                if fork_pid == 0 {
+                    self.enter_child(pgid, foreground);
+
+                    if input != -1 {
+                        dup2(input, STDIN_FILENO);
+                        close(input);
+                    }
+                    if !last {
+                        close(fds[0]);
+                        dup2(fds[1], STDOUT_FILENO);
+                        close(fds[1]);
+                    }
+
+                    let status = self.run_command(command, true);
+                    let _ = std::io::stdout().flush();
+                    exit(status);
+                } else if fork_pid < 0 {
+                    eprintln!("Failed to fork a new process.");
+                    abort();
+                }
+
+                if pgid == 0 {
+                    pgid = fork_pid;
+                }
+                if self.options.monitor {
+                    setpgid(fork_pid, pgid);
+                }
+                pids.push(fork_pid);
+
+                if input != -1 {
+                    close(input);
+                }
+                if !last {
+                    close(fds[1]);
+                    input = fds[0];
+                }
+            }
+        }
+
+        (pgid, pids)
+    }
+
+    /// Set up a freshly forked child. The default signal handlers the shell ignores are restored,
+    /// and with job control the child is put in the process group `pgid` (or a new group led by
+    /// itself if `pgid` is 0) and that group is given the terminal if it's in the `foreground`.
+    /// Without job control a child in the background reads `/dev/null` and ignores Ctrl-C
+    /// instead. Job control is then turned off so anything the child runs stays in its group.
+    fn enter_child(&mut self, pgid: pid_t, foreground: bool) {
+        if self.interactive || self.options.monitor {
+            Shell::reset_signals();
+        }
+        if self.options.monitor {
+            unsafe {
+                let child_pid: pid_t = getpid();
+                let pgid = if pgid == 0 { child_pid } else { pgid };
+                setpgid(child_pid, pgid);
+                if foreground {
+                    tcsetpgrp(self.shell_terminal, pgid);
+                }
+            }
+        } else if !foreground {
+            // Without job control a background command mustn't read the terminal or be
+            // interrupted by it
+            unsafe {
+                let null = open(c"/dev/null".as_ptr(), O_RDONLY);
+                dup2(null, STDIN_FILENO);
+                close(null);
+                signal(SIGINT, SIG_IGN);
+                signal(SIGQUIT, SIG_IGN);
+            }
+        }
+        self.reset_traps();
+
+        self.interactive = false;
+        self.options.monitor = false;
+        self.clear_jobs();
+    }
+
+    /// Restore the default handlers of the signals an interactive shell ignores.
+    fn reset_signals() {
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+            signal(SIGQUIT, SIG_DFL);
+            signal(SIGTSTP, SIG_DFL);
+            signal(SIGTTIN, SIG_DFL);
+            signal(SIGTTOU, SIG_DFL);
+        }
+    }
+
+    /// Replace the current process with a program. If the program can't be run an error is
+    /// printed and the process exits with 127 if it wasn't found or 126 otherwise.
+    fn exec_program(argv: &[CString]) -> ! {
+        let name = argv.first().unwrap().to_string_lossy().into_owned();
+        let c = argv.first().unwrap().as_ptr() as *const c_char;
+        let mut ptrs: Vec<*const c_char> = argv.iter().map(|s| s.as_ptr()).collect();
+        ptrs.push(std::ptr::null());
+
+        let argv: *const *const c_char = ptrs.as_ptr();
+        unsafe {
+            execvp(c, argv);
+        }
+
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(ENOENT) {
+            eprintln!("{}: command not found", name);
+            exit(127);
+        }
+        eprintln!("{}: {}", name, err);
+        exit(126);
+    }
+
+    /// Give the terminal to the process group of a job just started in the foreground, wait for
+    /// all of its processes to finish and then take the terminal back. If any of them were killed
+    /// by Ctrl-C the line is marked as interrupted, and a job stopped by Ctrl-Z is put in the job
+    /// table instead so that the shell doesn't hang waiting for it.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last process of the job, or with `set -o pipefail` that of the
+    /// last one that didn't exit with 0.
+    fn wait_foreground(&mut self, mut job: Job) -> i32 {
+        self.give_terminal(&job);
+        let result = self.wait_job(&mut job, true);
+        self.take_terminal(&mut job, &result);
+
+        match result {
+            Ok(status) => {
+                if let Some(signal) = status.stopped {
+                    return self.stop_job(job, signal);
+                }
+                if status.interrupted {
+                    self.interrupted = true;
+                }
+            }
+            Err(err) => eprintln!("waitpid failed: {}", err),
+        }
+
+        let statuses: Vec<i32> = job
+            .processes
+            .iter()
+            .map(|process| match process.state {
+                JobState::Done(status) => status,
+                _ => 0,
+            })
+            .collect();
+        let failed = statuses.iter().rev().find(|&&status| status != 0);
+        match failed {
+            Some(&status) if self.options.pipefail => status,
+            _ => statuses.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Convert a status filled in by `waitpid` into the exit status reported by `$?`.
+    pub fn decode_status(status: c_int) -> i32 {
+        if WIFEXITED(status) {
+            WEXITSTATUS(status)
+        } else if WIFSIGNALED(status) {
+            128 + WTERMSIG(status)
+        } else {
+            status
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(shell: &mut Shell, line: &str) -> i32 {
+        shell.execute_list(&parser::parse(line).unwrap())
+    }
+
+    #[test]
+    fn test_and_or_short_circuit() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "EXEC_TEST_AND=no; EXEC_TEST_OR=no");
+        run(
+            &mut shell,
+            "EXEC_TEST_X=1 || EXEC_TEST_OR=yes && EXEC_TEST_AND=yes",
+        );
+
+        assert_eq!(Some(String::from("yes")), shell.get_var("EXEC_TEST_AND"));
+        assert_eq!(Some(String::from("no")), shell.get_var("EXEC_TEST_OR"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let mut shell = Shell::new();
+
+        assert_eq!(1, run(&mut shell, "EXEC_TEST_BAD=${EXEC_TEST_UNSET:?}"));
+        assert_eq!(Some(String::from("1")), shell.param_value("?"));
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "EXEC_TEST_BAD=${EXEC_TEST_UNSET:?} || EXEC_TEST_OK=1"
+            )
+        );
+        assert_eq!(Some(String::from("0")), shell.param_value("?"));
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut shell = Shell::new();
+
+        // `exit` would end the test process, so it runs in subshells
+        assert_eq!(3, run(&mut shell, "(exit 3)"));
+        assert_eq!(1, run(&mut shell, "(false; exit)"));
+        assert_eq!(0, run(&mut shell, "(true; exit)"));
+        assert_eq!(2, run(&mut shell, "(exit abc) 2> /dev/null"));
+    }
+
+    #[test]
+    fn test_true_false_and_colon() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-colon-{}", std::process::id()));
+
+        assert_eq!(Some(CommandKind::Builtin), shell.lookup_command("true"));
+        assert_eq!(Some(CommandKind::Builtin), shell.lookup_command(":"));
+        assert_eq!(0, run(&mut shell, "true"));
+        assert_eq!(1, run(&mut shell, "false"));
+        assert_eq!(0, run(&mut shell, "false || :"));
+
+        // `:` still expands its arguments and applies its redirections
+        let line = format!(": ${{EXEC_TEST_COLON:=set}} > {}", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(Some(String::from("set")), shell.get_var("EXEC_TEST_COLON"));
+        assert!(path.exists());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_background_does_not_wait() {
+        let mut shell = Shell::new();
+
+        let start = std::time::Instant::now();
+        assert_eq!(0, run(&mut shell, "sleep 2 &"));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!("sleep 2", shell.jobs[0].command);
+
+        unsafe {
+            libc::kill(shell.jobs[0].pgid, libc::SIGKILL);
+            waitpid(shell.jobs[0].pgid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    #[test]
+    fn test_sigchld_reaps_jobs() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "(exit 4) &");
+        let pid = shell.jobs[0].pgid;
+        // Once the handler has collected the status the process is no longer a zombie. WNOWAIT
+        // looks for it without reaping it here.
+        let gone = || unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+            libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) == -1
+        };
+        for _ in 0..100 {
+            crate::jobs::handle_sigchld(libc::SIGCHLD);
+            if gone() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(gone());
+        assert_eq!(JobState::Running, shell.jobs[0].state());
+
+        shell.reap_jobs();
+        assert_eq!(JobState::Done(4), shell.jobs[0].state());
+    }
+
+    #[test]
+    fn test_jobs() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-jobs-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        run(&mut shell, "sleep 5 & (exit 3) &");
+        for _ in 0..100 {
+            shell.reap_jobs();
+            if shell.jobs[1].state() != JobState::Running {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let pid = shell.jobs[0].pgid;
+
+        assert_eq!(0, run(&mut shell, &format!("jobs > {}", path.display())));
+        assert_eq!(
+            "[1]-  Running                 sleep 5 &\n[2]+  Exit 3                  ( exit 3 )\n",
+            read_output(&name)
+        );
+        // The finished job was reported, so it is gone
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(
+            0,
+            run(&mut shell, &format!("jobs -l %sl > {}", path.display()))
+        );
+        assert_eq!(
+            format!("[1]+ {} Running                 sleep 5 &\n", pid),
+            read_output(&name)
+        );
+        assert_eq!(
+            0,
+            run(&mut shell, &format!("jobs -p %% > {}", path.display()))
+        );
+        assert_eq!(format!("{}\n", pid), read_output(&name));
+        assert_eq!(1, run(&mut shell, "jobs %2"));
+        assert_eq!(1, run(&mut shell, "jobs -x"));
+
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    #[test]
+    fn test_fg() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-fg-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        run(&mut shell, "(exit 4) & sleep 0.2 &");
+        let pid = shell.jobs[1].pgid;
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        for _ in 0..100 {
+            shell.reap_jobs();
+            if shell.jobs[1].state() == JobState::Stopped {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(JobState::Stopped, shell.jobs[1].state());
+
+        // The stopped job is continued and waited for
+        assert_eq!(0, run(&mut shell, &format!("fg > {}", path.display())));
+        assert_eq!("sleep 0.2\n", read_output(&name));
+        assert_eq!(4, run(&mut shell, &format!("fg %1 > {}", path.display())));
+        assert_eq!("( exit 4 )\n", read_output(&name));
+        assert!(shell.jobs.is_empty());
+        assert_eq!(1, run(&mut shell, "fg"));
+    }
+
+    #[test]
+    fn test_foreground_stop() {
+        let mut shell = Shell::new();
+
+        // A stopped foreground command hands control back and becomes a job
+        assert_eq!(
+            128 + libc::SIGSTOP,
+            run(&mut shell, "sh -c 'kill -STOP $$'")
+        );
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(1, shell.jobs[0].id);
+        assert_eq!("sh -c kill -STOP $$", shell.jobs[0].command);
+        assert_eq!(JobState::Stopped, shell.jobs[0].state());
+
+        assert_eq!(0, run(&mut shell, "fg > /dev/null"));
+        assert!(shell.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_jobs() {
+        let mut shell = Shell::new();
+
+        // Every command of a background pipeline is a process of the one job
+        run(&mut shell, "exit 3 | exit 5 &");
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(2, shell.jobs[0].processes.len());
+        assert_eq!(shell.jobs[0].pgid, shell.jobs[0].processes[0].pid);
+        assert_eq!("exit 3 | exit 5", shell.jobs[0].command);
+        assert_eq!(5, run(&mut shell, "wait %1"));
+
+        // A stopped stage stops the whole pipeline, which is continued as one
+        run(&mut shell, "sh -c 'kill -STOP $$' | cat");
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(JobState::Stopped, shell.jobs[0].state());
+        assert_eq!("sh -c 'kill -STOP $$' | cat", shell.jobs[0].command);
+        assert_eq!(0, run(&mut shell, "fg > /dev/null"));
+        assert!(shell.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_notify_jobs() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "sleep 5 & (exit 2) &");
+        for _ in 0..100 {
+            shell.reap_jobs();
+            if shell.jobs[1].state() != JobState::Running {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let pid = shell.jobs[0].pgid;
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        for _ in 0..100 {
+            shell.reap_jobs();
+            if shell.jobs[0].state() == JobState::Stopped {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // The finished job is reported and forgotten while the stopped one is only reported
+        shell.notify_jobs();
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(JobState::Stopped, shell.jobs[0].reported);
+
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    #[test]
+    fn test_last_background_pid() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-last-bg-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        assert_eq!(
+            0,
+            run(&mut shell, &format!("echo \"[$!]\" > {}", path.display()))
+        );
+        assert_eq!("[]\n", read_output(&name));
+
+        // `$!` is the last command of the pipeline, which can be waited for
+        run(&mut shell, "(exit 6) | (exit 7) &");
+        let pid = shell.jobs[0].processes[1].pid;
+        run(&mut shell, &format!("echo $! ${{!}} > {}", path.display()));
+        assert_eq!(format!("{} {}\n", pid, pid), read_output(&name));
+        assert_eq!(7, run(&mut shell, "wait $!"));
+    }
+
+    #[test]
+    fn test_hangup_jobs() {
+        let mut shell = Shell::new();
+        shell.editor = Some(crate::editor::LineEditor::new().unwrap());
+
+        run(&mut shell, "sleep 5 & sleep 5 & sleep 5 &");
+        let pids: Vec<libc::pid_t> = shell.jobs.iter().map(|job| job.pgid).collect();
+        unsafe { libc::kill(pids[1], libc::SIGSTOP) };
+        run(&mut shell, "disown %3");
+
+        // The running and stopped jobs are hung up but the disowned one isn't
+        shell.hangup_jobs();
+        let mut statuses = Vec::new();
+        for &pid in &pids[..2] {
+            let mut status: c_int = c_int::default();
+            unsafe { waitpid(pid, &mut status, 0) };
+            statuses.push(WIFSIGNALED(status) && WTERMSIG(status) == libc::SIGHUP);
+        }
+        assert_eq!(vec![true, true], statuses);
+        assert_eq!(0, unsafe {
+            waitpid(pids[2], std::ptr::null_mut(), libc::WNOHANG)
+        });
+
+        // Nothing is sent once huponexit is off
+        run(&mut shell, "set +o huponexit; sleep 5 &");
+        shell.hangup_jobs();
+        let pid = shell.last_background_pid.unwrap();
+        assert_eq!(0, unsafe {
+            waitpid(pid, std::ptr::null_mut(), libc::WNOHANG)
+        });
+
+        unsafe {
+            for pid in [pids[2], pid] {
+                libc::kill(pid, libc::SIGKILL);
+                waitpid(pid, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wait_next() {
+        let mut shell = Shell::new();
+
+        // The job that finishes first is the one waited for
+        run(&mut shell, "sleep 0.3 & (exit 3) &");
+        assert_eq!(3, run(&mut shell, "wait -n"));
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!("sleep 0.3", shell.jobs[0].command);
+
+        run(&mut shell, "(exit 4) &");
+        assert_eq!(0, run(&mut shell, "wait -n %1"));
+        assert_eq!(4, run(&mut shell, "wait -n"));
+        assert_eq!(127, run(&mut shell, "wait -n"));
+        assert_eq!(127, run(&mut shell, "wait -n %5"));
+
+        // A finished process that belongs to no job is left for someone else to collect
+        run(&mut shell, "(exit 5) & disown; sleep 0.2 &");
+        assert_eq!(0, run(&mut shell, "wait -n"));
+        assert!(shell.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_bg() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-bg-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        run(&mut shell, "sleep 0.2 &");
+        let pid = shell.jobs[0].pgid;
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        for _ in 0..100 {
+            shell.reap_jobs();
+            if shell.jobs[0].state() == JobState::Stopped {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(0, run(&mut shell, &format!("bg > {}", path.display())));
+        assert_eq!("[1]+ sleep 0.2 &\n", read_output(&name));
+        assert_eq!(JobState::Running, shell.jobs[0].state());
+        // Running again, so it finishes on its own
+        unsafe { waitpid(pid, std::ptr::null_mut(), 0) };
+        assert_eq!(1, run(&mut shell, "bg %2"));
+    }
+
+    #[test]
+    fn test_disown() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "sleep 0 & sleep 0 & sleep 0 &");
+        let pids: Vec<libc::pid_t> = shell.jobs.iter().map(|job| job.pgid).collect();
+        assert_eq!(0, run(&mut shell, "disown; disown %1"));
+        assert_eq!(1, shell.jobs.len());
+        assert_eq!(2, shell.jobs[0].id);
+        assert_eq!(1, run(&mut shell, "disown %1"));
+        assert_eq!(0, run(&mut shell, "disown -a"));
+        assert!(shell.jobs.is_empty());
+
+        for pid in pids {
+            unsafe { waitpid(pid, std::ptr::null_mut(), 0) };
+        }
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "sleep 5 & sleep 5 &");
+        assert_eq!(0, run(&mut shell, "kill %1; kill -s KILL %2"));
+        assert_eq!(143, run(&mut shell, "wait %1"));
+        assert_eq!(137, run(&mut shell, "wait %2"));
+
+        assert_eq!(1, run(&mut shell, "kill -0 999999999"));
+        assert_eq!(2, run(&mut shell, "kill -NOPE 1"));
+        assert_eq!(2, run(&mut shell, "kill %3"));
+        assert_eq!(2, run(&mut shell, "kill"));
+    }
+
+    #[test]
+    fn test_enable() {
+        let mut shell = Shell::new();
+
+        assert_eq!(0, run(&mut shell, "enable -n pwd type"));
+        assert!(matches!(
+            shell.lookup_command("pwd"),
+            Some(CommandKind::File(_))
+        ));
+        assert_eq!(1, run(&mut shell, "builtin pwd"));
+        // The program is run instead
+        assert_eq!(0, run(&mut shell, "pwd > /dev/null"));
+
+        assert_eq!(0, run(&mut shell, "enable pwd"));
+        assert_eq!(Some(CommandKind::Builtin), shell.lookup_command("pwd"));
+        assert!(!shell.is_builtin("type"));
+        assert_eq!(1, run(&mut shell, "enable -n nope"));
+    }
+
+    #[test]
+    fn test_cd_previous_dir() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-cd-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        // Subshells keep the directory changes away from the other tests
+        let line = format!(
+            "(cd /usr && cd / && cd - && echo \"$PWD $OLDPWD\") > {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("/usr\n/usr /\n", read_output(&name));
+        assert_eq!(1, run(&mut shell, "(unset OLDPWD; cd -)"));
+        assert_eq!(1, run(&mut shell, "(cd /exec/missing)"));
+        assert_eq!(
+            Err(String::from("/exec/missing: No such file or directory")),
+            shell.cd(&[CString::new("/exec/missing").unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_cdpath() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-cdpath-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        let line = format!(
+            "(cd / && CDPATH=/exec/missing:/usr && cd share && echo \"$PWD\" && cd lib) > {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("/usr/share\n/usr/share\n/usr/lib\n", read_output(&name));
+        assert_eq!(
+            1,
+            run(&mut shell, "(cd / && CDPATH=/usr && cd ./bin-missing)")
+        );
+    }
+
+    #[test]
+    fn test_cd_physical() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-cd-physical-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        let link = std::env::temp_dir().join(format!("exec-test-cd-link-{}", std::process::id()));
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink("/usr/share", &link).unwrap();
+
+        let line = format!(
+            "(cd -L {0} && echo \"$PWD\" && cd .. && echo \"$PWD\" && cd -P {0} && echo \"$PWD\" \
+             && cd -P .. && echo \"$PWD\") > {1}",
+            link.display(),
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(
+            format!(
+                "{}\n{}\n/usr/share\n/usr\n",
+                link.display(),
+                std::env::temp_dir().display()
+            ),
+            read_output(&name)
+        );
+        assert_eq!(1, run(&mut shell, "(cd -x /)"));
+        let _ = std::fs::remove_file(&link);
+    }
+
+    #[test]
+    fn test_dir_stack() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-dirs-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        assert_eq!(1, run(&mut shell, "pushd"));
+        assert_eq!(1, run(&mut shell, "popd"));
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "{ pushd -n /exec/one; pushd -n /exec/two; } > /dev/null"
+            )
+        );
+        assert_eq!(vec!["/exec/two", "/exec/one"], shell.dir_stack);
+
+        let line = format!(
+            "{{ dirs -l +1; dirs -l -0; dirs -v +2; }} > {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("/exec/two\n/exec/one\n 2  /exec/one\n", read_output(&name));
+        assert_eq!(1, run(&mut shell, "dirs +3"));
+
+        assert_eq!(0, run(&mut shell, "popd -n > /dev/null"));
+        assert_eq!(vec!["/exec/one"], shell.dir_stack);
+        assert_eq!(
+            0,
+            run(&mut shell, "{ pushd -n /exec/three; popd +2; } > /dev/null")
+        );
+        assert_eq!(vec!["/exec/three"], shell.dir_stack);
+        assert_eq!(0, run(&mut shell, "dirs -c"));
+        assert!(shell.dir_stack.is_empty());
+    }
+
+    #[test]
+    fn test_history_options() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-history-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        assert_eq!(0, run(&mut shell, "history"));
+        assert_eq!(1, run(&mut shell, "history -c"));
+
+        let mut editor = crate::editor::LineEditor::new().unwrap();
+        for line in ["echo one", "echo two", "echo three", "echo four"] {
+            let _ = editor.add_history_entry(line);
+        }
+        shell.editor = Some(editor);
+
+        let line = format!("history 2 > {}", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("    3  echo three\n    4  echo four\n", read_output(&name));
+
+        assert_eq!(0, run(&mut shell, "history -d 2"));
+        assert_eq!(0, run(&mut shell, "history -d -1"));
+        let line = format!("history > {}", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("    1  echo one\n    2  echo three\n", read_output(&name));
+        assert_eq!(1, run(&mut shell, "history -d 3"));
+        assert_eq!(1, run(&mut shell, "history two"));
+
+        let file =
+            std::env::temp_dir().join(format!("exec-test-history-file-{}", std::process::id()));
+        assert_eq!(
+            0,
+            run(&mut shell, &format!("history -w {}", file.display()))
+        );
+        assert_eq!(0, run(&mut shell, "history -c"));
+        assert_eq!(
+            0,
+            rustyline::history::History::len(shell.editor.as_ref().unwrap().history())
+        );
+        assert_eq!(
+            0,
+            run(&mut shell, &format!("history -r {}", file.display()))
+        );
+        assert_eq!(
+            2,
+            rustyline::history::History::len(shell.editor.as_ref().unwrap().history())
+        );
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_fc() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-fc-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        // Lines are added to the history before they run, as the prompt does
+        let run_entered = |shell: &mut Shell, line: &str| {
+            let _ = shell.editor.as_mut().unwrap().add_history_entry(line);
+            run(shell, line)
+        };
+
+        assert_eq!(1, run(&mut shell, "fc -l"));
+        let mut editor = crate::editor::LineEditor::new().unwrap();
+        for line in ["echo one", "echo two", "echo three"] {
+            let _ = editor.add_history_entry(line);
+        }
+        shell.editor = Some(editor);
+
+        let line = format!("fc -l 1 2 > {}", path.display());
+        assert_eq!(0, run_entered(&mut shell, &line));
+        assert_eq!("    1  echo one\n    2  echo two\n", read_output(&name));
+        let line = format!("fc -lnr -2 > {}", path.display());
+        assert_eq!(0, run_entered(&mut shell, &line));
+        assert_eq!(
+            format!("\tfc -l 1 2 > {}\n\techo three\n", path.display()),
+            read_output(&name)
+        );
+
+        let line = format!("fc -s one=uno 1 > {}", path.display());
+        assert_eq!(0, run_entered(&mut shell, &line));
+        assert_eq!("echo uno\nuno\n", read_output(&name));
+        let history: Vec<String> = shell
+            .editor
+            .as_ref()
+            .unwrap()
+            .history()
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(Some(&String::from("echo uno")), history.last());
+
+        let line = format!("fc -e 'sed -i s/two/dos/' 'echo tw' > {}", path.display());
+        assert_eq!(0, run_entered(&mut shell, &line));
+        assert_eq!("echo dos\ndos\n", read_output(&name));
+        assert_eq!(1, run_entered(&mut shell, "fc -e false"));
+        assert_eq!(1, run_entered(&mut shell, "fc -s missing"));
+    }
+
+    #[test]
+    fn test_subshell_does_not_leak() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "EXEC_TEST_SUB=outer; ( EXEC_TEST_SUB=inner; EXEC_TEST_SUB_NEW=1 )"
+            )
+        );
+        assert_eq!(Some(String::from("outer")), shell.get_var("EXEC_TEST_SUB"));
+        assert_eq!(None, shell.get_var("EXEC_TEST_SUB_NEW"));
+    }
+
+    #[test]
+    fn test_subshell_status() {
+        let mut shell = Shell::new();
+
+        assert_eq!(3, run(&mut shell, "( exit 3 )"));
+        assert_eq!(0, run(&mut shell, "( exit 3 ) || ( exit 0 )"));
+    }
+
+    fn read_output(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+        contents
+    }
+
+    #[test]
+    fn test_group_shares_redirect() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-group-{}", std::process::id()));
+
+        let line = format!(
+            "{{ echo one; EXEC_TEST_GROUP=set; echo two; }} > {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(Some(String::from("set")), shell.get_var("EXEC_TEST_GROUP"));
+        assert_eq!(
+            "one\ntwo\n",
+            read_output(&format!("exec-test-group-{}", std::process::id()))
+        );
+    }
+
+    #[test]
+    fn test_redirect_append_and_dup() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-append-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        let line = format!(
+            "echo a > {0}; echo b >> {0}; ls /nonexistent 2>> {0} >&2 || true",
+            path.display()
+        );
+        run(&mut shell, &line);
+
+        let output = read_output(&name);
+        assert!(output.starts_with("a\nb\n"));
+        assert!(output.lines().count() > 2);
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-echo-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        let line = format!(
+            "echo -n a b > {0}; echo -e ' \\tc\\c' d >> {0}; echo -eE -x '\\t' >> {0}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("a b \tc-x \\t\n", read_output(&name));
+    }
+
+    #[test]
+    fn test_redirect_missing_file() {
+        let mut shell = Shell::new();
+
+        assert_eq!(1, run(&mut shell, "cat < /nonexistent/simple-shell-test"));
+    }
+
+    #[test]
+    fn test_negation() {
+        let mut shell = Shell::new();
+
+        assert_eq!(0, run(&mut shell, "! EXEC_TEST_NEG=${EXEC_TEST_UNSET:?}"));
+        assert_eq!(1, run(&mut shell, "! EXEC_TEST_NEG=1"));
+        assert_eq!(Some(String::from("1")), shell.param_value("?"));
+        assert_eq!(
+            0,
+            run(&mut shell, "! EXEC_TEST_NEG=1 || EXEC_TEST_NEG_OR=yes")
+        );
+        assert_eq!(Some(String::from("yes")), shell.get_var("EXEC_TEST_NEG_OR"));
+    }
+
+    #[test]
+    fn test_time() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-time-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        // The report goes to the shell's standard error rather than that of the pipeline
+        let line = format!(
+            "{{ time sleep 0.1 | cat 2> /dev/null; }} 2> {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        let report = read_output(&name);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[1].starts_with("real\t0m"));
+        assert!(lines[2].starts_with("user\t0m"));
+        assert!(lines[3].starts_with("sys\t0m"));
+
+        let line = format!("{{ time ! true; }} 2> {}", path.display());
+        assert_eq!(1, run(&mut shell, &line));
+        assert!(read_output(&name).contains("real\t0m"));
+    }
+
+    #[test]
+    fn test_assignment_substitution_status() {
+        let mut shell = Shell::new();
+
+        assert_eq!(1, run(&mut shell, "x=$(false)"));
+        assert_eq!(0, run(&mut shell, "false; x=$?"));
+        assert_eq!(Some(String::from("1")), shell.get_var("x"));
+    }
+
+    #[test]
+    fn test_if() {
+        let mut shell = Shell::new();
+
+        run(
+            &mut shell,
+            "if false; then x=1; elif true; then x=2; else x=3; fi",
+        );
+        assert_eq!(Some(String::from("2")), shell.get_var("x"));
+        assert_eq!(0, run(&mut shell, "if false; then x=1; fi"));
+        assert_eq!(3, run(&mut shell, "if true; then (exit 3); fi"));
+        assert_eq!(1, run(&mut shell, "if (exit 2); then :; else false; fi"));
+    }
+
+    #[test]
+    fn test_while() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(&mut shell, "x=; while [ \"$x\" != aaa ]; do x=a$x; done")
+        );
+        assert_eq!(Some(String::from("aaa")), shell.get_var("x"));
+        assert_eq!(0, run(&mut shell, "while false; do x=never; done"));
+        assert_eq!(Some(String::from("aaa")), shell.get_var("x"));
+
+        // A command killed by SIGINT stops the loop and the rest of the list
+        shell.run_line("while true; do sh -c 'kill -INT $$'; done; x=after");
+        assert_eq!(128 + SIGINT, shell.last_status);
+        assert_eq!(Some(String::from("aaa")), shell.get_var("x"));
+        shell.run_line("x=next");
+        assert_eq!(Some(String::from("next")), shell.get_var("x"));
+    }
+
+    #[test]
+    fn test_until() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(&mut shell, "x=; until [ \"$x\" = aaa ]; do x=a$x; done")
+        );
+        assert_eq!(Some(String::from("aaa")), shell.get_var("x"));
+        assert_eq!(0, run(&mut shell, "until true; do x=never; done"));
+        assert_eq!(Some(String::from("aaa")), shell.get_var("x"));
+        assert_eq!(
+            4,
+            run(
+                &mut shell,
+                "x=; until [ -n \"$x\" ]; do x=1; (exit 4); done"
+            )
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-select-{}", std::process::id()));
+        std::fs::write(&path, "2\n\n 9\n1").unwrap();
+
+        let line = format!(
+            "s=; select x in a 'b c'; do s=$s[$x:$REPLY]; done < {} 2> /dev/null",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        let _ = std::fs::remove_file(path);
+        assert_eq!(Some(String::from("[b c:2][: 9][a:1]")), shell.get_var("s"));
+
+        assert_eq!(0, run(&mut shell, "select x in $unset; do s=never; done"));
+        assert_eq!(Some(String::from("[b c:2][: 9][a:1]")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_function() {
+        let mut shell = Shell::new();
+
+        assert_eq!(0, run(&mut shell, "join() { s=$1-$2-$#; (exit $3); }; s="));
+        assert!(shell.functions.contains_key("join"));
+        assert_eq!(Some(String::from("")), shell.get_var("s"));
+        assert_eq!(4, run(&mut shell, "join a 'b c' 4"));
+        assert_eq!(Some(String::from("a-b c-3")), shell.get_var("s"));
+
+        // Arguments are only seen inside the function
+        shell.positional_params = vec![String::from("outer")];
+        run(&mut shell, "join x; t=$1");
+        assert_eq!(Some(String::from("x--1")), shell.get_var("s"));
+        assert_eq!(Some(String::from("outer")), shell.get_var("t"));
+
+        // Functions take the place of builtins and programs, and can be removed again
+        run(&mut shell, "cd() { s=wrapped; }; cd /; true() { false; }");
+        assert_eq!(Some(String::from("wrapped")), shell.get_var("s"));
+        assert_eq!(1, run(&mut shell, "true"));
+        assert_eq!(0, run(&mut shell, "unset -f true cd missing; true"));
+        assert!(!shell.functions.contains_key("cd"));
+
+        // A function can redefine itself while running
+        run(&mut shell, "f() { f() { s=second; }; s=first; }; f");
+        assert_eq!(Some(String::from("first")), shell.get_var("s"));
+        run(&mut shell, "f");
+        assert_eq!(Some(String::from("second")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut shell = Shell::new();
+
+        std::env::set_var("EXEC_TEST_UNSET_ENV", "env");
+        run(
+            &mut shell,
+            "v=1; a=(x y z); f() { :; }; unset v EXEC_TEST_UNSET_ENV 'a[1]' f",
+        );
+        assert_eq!(None, shell.get_var("v"));
+        assert_eq!(None, std::env::var_os("EXEC_TEST_UNSET_ENV"));
+        run(&mut shell, "s=\"${a[*]}\"");
+        assert_eq!(Some(String::from("x z")), shell.get_var("s"));
+        assert!(shell.functions.contains_key("f"));
+
+        assert_eq!(0, run(&mut shell, "unset -v a; unset -f f"));
+        assert!(!shell.arrays.contains_key("a"));
+        assert!(!shell.functions.contains_key("f"));
+
+        assert_eq!(1, run(&mut shell, "v=1; unset 1x v"));
+        assert_eq!(None, shell.get_var("v"));
+        assert_eq!(1, run(&mut shell, "unset -x v"));
+    }
+
+    #[test]
+    fn test_startup_file() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-rc-{}", std::process::id()));
+        std::fs::write(&path, "alias exec_test_rc='s=aliased'\n").unwrap();
+
+        shell.set_var("ENV", &path.to_string_lossy());
+        shell.run_startup_file();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            Some(&String::from("s=aliased")),
+            shell.aliases.get("exec_test_rc")
+        );
+
+        // A missing startup file is skipped
+        shell.set_var("ENV", &path.to_string_lossy());
+        shell.run_startup_file();
+        assert_eq!(0, shell.last_status);
+    }
+
+    #[test]
+    fn test_lookup_command() {
+        let mut shell = Shell::new();
+        run(&mut shell, "cd() { :; }");
+        shell
+            .aliases
+            .insert(String::from("ll"), String::from("ls -l"));
+
+        assert_eq!(
+            Some(CommandKind::Alias(String::from("ls -l"))),
+            shell.lookup_command("ll")
+        );
+        assert_eq!(Some(CommandKind::Keyword), shell.lookup_command("while"));
+        assert_eq!(Some(CommandKind::Function), shell.lookup_command("cd"));
+        assert_eq!(Some(CommandKind::Builtin), shell.lookup_command("pwd"));
+        assert!(matches!(
+            shell.lookup_command("sh"),
+            Some(CommandKind::File(path)) if path.ends_with("sh")
+        ));
+        assert_eq!(
+            Some(CommandKind::File(PathBuf::from("/bin/sh"))),
+            shell.lookup_command("/bin/sh")
+        );
+        assert_eq!(None, shell.lookup_command("exec-test-missing-command"));
+        assert_eq!(None, shell.lookup_command("/etc/passwd"));
+    }
+
+    #[test]
+    fn test_which() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-which-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        run(&mut shell, "echo() { :; }");
+        shell
+            .aliases
+            .insert(String::from("ll"), String::from("ls -l"));
+
+        let kinds = shell.lookup_all("echo");
+        assert_eq!(
+            vec![CommandKind::Function, CommandKind::Builtin],
+            kinds[..2].to_vec()
+        );
+        assert!(kinds[2..]
+            .iter()
+            .all(|kind| matches!(kind, CommandKind::File(path) if path.ends_with("echo"))));
+
+        let line = format!("which ll while echo pwd /bin/sh > {}", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(
+            "ll: aliased to ls -l\nwhile: shell reserved word\necho() { :; }\n\
+             pwd: shell built-in command\n/bin/sh\n",
+            read_output(&name)
+        );
+        let line = format!("which -a pwd > {}", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert!(read_output(&name).starts_with("pwd: shell built-in command\n/"));
+        assert_eq!(1, run(&mut shell, "which exec-test-missing-command"));
+    }
+
+    #[test]
+    fn test_command_builtin() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "true() { s=function; false; }");
+        assert_eq!(0, run(&mut shell, "command true"));
+        assert_eq!(None, shell.get_var("s"));
+        assert_eq!(0, run(&mut shell, "command eval 's=evaluated'"));
+        assert_eq!(Some(String::from("evaluated")), shell.get_var("s"));
+
+        assert_eq!(0, run(&mut shell, "command -v true sh > /dev/null"));
+        assert_eq!(1, run(&mut shell, "command -v exec-test-missing-command"));
+        assert_eq!(2, run(&mut shell, "command -x true"));
+        assert_eq!(
+            127,
+            run(&mut shell, "command exec-test-missing-command 2> /dev/null")
+        );
+    }
+
+    #[test]
+    fn test_builtin_builtin() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "set() { s=function; }; set");
+        assert_eq!(Some(String::from("function")), shell.get_var("s"));
+        assert_eq!(0, run(&mut shell, "s=; builtin set -f"));
+        assert_eq!(Some(String::new()), shell.get_var("s"));
+        assert!(shell.options.noglob);
+
+        assert_eq!(0, run(&mut shell, "builtin builtin eval 's=evaluated'"));
+        assert_eq!(Some(String::from("evaluated")), shell.get_var("s"));
+        assert_eq!(0, run(&mut shell, "builtin"));
+        assert_eq!(1, run(&mut shell, "builtin sh -c true 2> /dev/null"));
+    }
+
+    #[test]
+    fn test_exec_builtin() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-exec-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        // Redirections on their own stay in place for the commands after them
+        let line = format!(
+            "exec 8> {}; echo kept >&8; echo again >&8; exec 8>&-",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!("kept\nagain\n", read_output(&name));
+        assert_eq!(1, run(&mut shell, "echo closed >&8 2> /dev/null"));
+
+        assert_eq!(7, run(&mut shell, "(exec sh -c 'exit 7'; exit 1)"));
+        assert_eq!(0, run(&mut shell, "exec"));
+    }
+
+    #[test]
+    fn test_read() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-read-{}", std::process::id()));
+        std::fs::write(&path, "one two  three four\n a\\ b\\\nc \\d\nlast").unwrap();
+
+        let line = format!(
+            "{{ read x y z; read -r r; read p q; read; s=$?; }} < {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(Some(String::from("one")), shell.get_var("x"));
+        assert_eq!(Some(String::from("two")), shell.get_var("y"));
+        assert_eq!(Some(String::from("three four")), shell.get_var("z"));
+        assert_eq!(Some(String::from("a\\ b\\")), shell.get_var("r"));
+        assert_eq!(Some(String::from("c")), shell.get_var("p"));
+        assert_eq!(Some(String::from("d")), shell.get_var("q"));
+        // The last line has no newline, so it is read but the status is 1
+        assert_eq!(Some(String::from("last")), shell.get_var("REPLY"));
+        assert_eq!(Some(String::from("1")), shell.get_var("s"));
+
+        assert_eq!(2, run(&mut shell, "read 1x < /dev/null"));
+    }
+
+    #[test]
+    fn test_conditional_command() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "x='a b'; re='^([a-z]+)([0-9]+)$'");
+        assert_eq!(0, run(&mut shell, "[[ $x == a* && $x != \"a*\" ]]"));
+        assert_eq!(1, run(&mut shell, "[[ -z $x || ( ! -d / ) ]]"));
+        assert_eq!(0, run(&mut shell, "[[ b > a && 2 -gt 10 || -n x ]]"));
+        assert_eq!(2, run(&mut shell, "[[ 1 -eq x ]]"));
+
+        assert_eq!(
+            0,
+            run(&mut shell, "[[ abc123 =~ $re ]]; m=\"${BASH_REMATCH[*]}\"")
+        );
+        assert_eq!(Some(String::from("abc123 abc 123")), shell.get_var("m"));
+        assert_eq!(1, run(&mut shell, "[[ abc =~ \"a.c\" ]]"));
+        assert!(!shell.arrays.contains_key("BASH_REMATCH"));
+        assert_eq!(0, run(&mut shell, "[[ x =~ ^(a|x)$ ]]"));
+    }
+
+    #[test]
+    fn test_local() {
+        let mut shell = Shell::new();
+
+        run(
+            &mut shell,
+            "x=global; f() { local x=inner y; s=$x${y-unset}; x=changed; y=set; g; }",
+        );
+        run(&mut shell, "g() { local x; t=${x-unset}; x=g; }");
+        assert_eq!(0, run(&mut shell, "f"));
+        assert_eq!(Some(String::from("innerunset")), shell.get_var("s"));
+        assert_eq!(Some(String::from("unset")), shell.get_var("t"));
+        assert_eq!(Some(String::from("global")), shell.get_var("x"));
+        assert_eq!(None, shell.get_var("y"));
+        assert!(shell.local_scopes.is_empty());
+
+        // The value from before the first `local` is the one put back
+        run(&mut shell, "h() { local x=1; local x=2; }; h");
+        assert_eq!(Some(String::from("global")), shell.get_var("x"));
+
+        // Locals shadow the environment without changing it for good
+        std::env::set_var("EXEC_TEST_LOCAL", "env");
+        run(
+            &mut shell,
+            "e() { local EXEC_TEST_LOCAL=shadowed; s=$EXEC_TEST_LOCAL; }; e",
+        );
+        assert_eq!(Some(String::from("shadowed")), shell.get_var("s"));
+        assert_eq!(Ok(String::from("env")), std::env::var("EXEC_TEST_LOCAL"));
+
+        assert_eq!(1, run(&mut shell, "local x=top"));
+        assert_eq!(Some(String::from("global")), shell.get_var("x"));
+        assert_eq!(1, run(&mut shell, "b() { local 1x; }; b"));
+    }
+
+    #[test]
+    fn test_return() {
+        let mut shell = Shell::new();
+
+        run(
+            &mut shell,
+            "f() { s=before; for x in a b; do while true; do return $1; done; done; s=after; }",
+        );
+        assert_eq!(3, run(&mut shell, "f 3"));
+        assert_eq!(Some(String::from("before")), shell.get_var("s"));
+        assert_eq!(Some(String::from("a")), shell.get_var("x"));
+        assert_eq!(255, run(&mut shell, "f -1"));
+        assert_eq!(None, shell.jump);
+
+        // Without an argument the last status is kept, and only the innermost function returns
+        run(&mut shell, "g() { (exit 5); return; }; h() { g; s=$?; }");
+        assert_eq!(0, run(&mut shell, "h"));
+        assert_eq!(Some(String::from("5")), shell.get_var("s"));
+
+        // The rest of the line still runs after the function returns
+        assert_eq!(0, run(&mut shell, "f 1 || s=next"));
+        assert_eq!(Some(String::from("next")), shell.get_var("s"));
+
+        assert_eq!(1, run(&mut shell, "return 2"));
+        // A bad argument is reported without returning
+        assert_eq!(0, run(&mut shell, "e() { return x; s=$?; }; e"));
+        assert_eq!(Some(String::from("1")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_run_script() {
+        let mut shell = Shell::new();
+        shell.positional_params = vec![String::from("arg")];
+
+        let script =
+            "# a comment\nkeep() {\n  s=$s$1\n}\nkeep $1\nfor x in 1 2\ndo\n  keep $x\ndone\n(exit 3)\n";
+        assert_eq!(3, shell.run_script("test", script));
+        assert_eq!(Some(String::from("arg12")), shell.get_var("s"));
+
+        // A syntax error stops the script, as does a command left unfinished at the end
+        assert_eq!(2, shell.run_script("test", "s=before\nfi\ns=after"));
+        assert_eq!(Some(String::from("before")), shell.get_var("s"));
+        assert_eq!(2, shell.run_script("test", "if true; then\n  s=never\n"));
+        assert_eq!(Some(String::from("before")), shell.get_var("s"));
+
+        // A backslash at the end of a line continues the command on the next one
+        assert_eq!(
+            0,
+            shell.run_script("test", "s=one\\\ntwo \\\n  t=three\nkeep \\\n  four \\")
+        );
+        assert_eq!(Some(String::from("onetwofour")), shell.get_var("s"));
+        assert_eq!(Some(String::from("three")), shell.get_var("t"));
+    }
+
+    #[test]
+    fn test_run_lines() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-lines-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        // Lines arrive one at a time as they do when piped to the shell
+        let mut lines = vec![
+            format!("cat > {} <<EOF", path.display()),
+            String::from("first \"$X\""),
+            String::from("EOF"),
+            format!("cat >> {} <<'EOF'", path.display()),
+            String::from("second $X"),
+            String::from("EOF"),
+            String::from("echo one \\"),
+            format!("  two >> {}", path.display()),
+        ]
+        .into_iter();
+        shell.vars.insert(String::from("X"), String::from("x"));
+        assert_eq!(0, shell.run_lines("test", |_| lines.next()));
+        assert_eq!("first \"x\"\nsecond $X\none two\n", read_output(&name));
+    }
+
+    #[test]
+    fn test_run_command_string() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-dash-c-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        let args = vec![String::from("name"), String::from("a"), String::from("b c")];
+        let command = format!(
+            "echo \"$0\" \"$1\" $# \\\n  one \\\n two > {}",
+            path.display()
+        );
+        assert_eq!(0, shell.run_command_string(&command, args));
+        assert_eq!("name a 2 one two\n", read_output(&name));
+        assert_eq!(vec!["a", "b c"], shell.positional_params);
+
+        // Without arguments $0 stays the shell's name
+        let arg0 = shell.arg0.clone();
+        assert_eq!(3, shell.run_command_string("(exit 3)", Vec::new()));
+        assert_eq!(arg0, shell.arg0);
+        assert!(shell.positional_params.is_empty());
+    }
+
+    #[test]
+    fn test_errexit() {
+        let mut shell = Shell::new();
+        shell.options.errexit = true;
+
+        // Failures that are being tested don't exit
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "if false; then :; fi; until true; do :; done; false && true; false || ! true; s=ok"
+            )
+        );
+        assert_eq!(Some(String::from("ok")), shell.get_var("s"));
+        assert_eq!(None, shell.jump);
+
+        run(&mut shell, "f() { false; s=$s-f; }");
+        assert_eq!(0, run(&mut shell, "s=; if f; then s=$s-then; fi"));
+        assert_eq!(Some(String::from("-f-then")), shell.get_var("s"));
+
+        assert_eq!(
+            3,
+            run(&mut shell, "s=; for x in 1 2; do (exit 3); s=no; done")
+        );
+        assert_eq!(Some(Jump::Exit(3)), shell.jump);
+        assert_eq!(Some(String::from("")), shell.get_var("s"));
+        shell.jump = None;
+        assert_eq!(1, run(&mut shell, "true && f; s=no"));
+        assert_eq!(Some(Jump::Exit(1)), shell.jump);
+        assert_eq!(Some(String::from("")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_nounset_exits() {
+        let mut shell = Shell::new();
+        shell.options.nounset = true;
+
+        // A shell running a script stops at the first unset variable
+        assert_eq!(1, shell.run_script("test", "s=$EXEC_TEST_UNSET\ns=reached"));
+        assert_eq!(Some(Jump::Exit(1)), shell.jump);
+        assert_eq!(None, shell.get_var("s"));
+
+        // An interactive shell carries on with the next command
+        let mut shell = Shell::new();
+        shell.options.nounset = true;
+        shell.interactive = true;
+        assert_eq!(0, run(&mut shell, "s=$EXEC_TEST_UNSET; s=reached"));
+        assert_eq!(None, shell.jump);
+        assert_eq!(Some(String::from("reached")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_pipefail() {
+        let mut shell = Shell::new();
+
+        assert_eq!(0, run(&mut shell, "(exit 3) | (exit 4) | true"));
+        run(&mut shell, "set -o pipefail");
+        assert_eq!(4, run(&mut shell, "(exit 3) | (exit 4) | true"));
+        assert_eq!(3, run(&mut shell, "(exit 3) | true | true"));
+        assert_eq!(0, run(&mut shell, "true | true"));
+        assert_eq!(0, run(&mut shell, "! false | true"));
+    }
+
+    #[test]
+    fn test_xtrace() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-xtrace-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        let line = format!(
+            "{{ set -x; x='a b'; true $x \"$x\"; PS4='$x: '; set +x; true not traced; }} 2> {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(
+            "+ x='a b'\n+ true a b 'a b'\na b: PS4='$x: '\na b: set +x\n",
+            read_output(&name)
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("exec-test-source-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "s=$1\nf() { s=$s-f; }\n[ -n \"$2\" ] \\\n  && return 4\ns=$s-\\\nend\n",
+        )
+        .unwrap();
+
+        // Variables and functions stay defined after the file has run
+        let line = format!("source {} a; f", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(Some(String::from("a-end-f")), shell.get_var("s"));
+
+        // `return` ends the file, and the caller's positional parameters come back afterwards
+        shell.positional_params = vec![String::from("outer")];
+        let line = format!(". {} b c; t=$?", path.display());
+        assert_eq!(0, run(&mut shell, &line));
+        assert_eq!(Some(String::from("b")), shell.get_var("s"));
+        assert_eq!(Some(String::from("4")), shell.get_var("t"));
+        assert_eq!(vec![String::from("outer")], shell.positional_params);
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(1, run(&mut shell, "source /nonexistent/file"));
+        assert_eq!(2, run(&mut shell, "."));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            3,
+            run(&mut shell, "cmd='s=$x; (exit 3)'; x=1; eval \"$cmd\"")
+        );
+        assert_eq!(Some(String::from("1")), shell.get_var("s"));
+        assert_eq!(0, run(&mut shell, "false; eval"));
+        assert_eq!(2, run(&mut shell, "eval 'if true'"));
+
+        // Definitions and jumps made by the commands affect the shell running them
+        assert_eq!(4, run(&mut shell, "eval 'f() { eval return 4; s=no; }'; f"));
+        assert_eq!(Some(String::from("1")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "s=; for x in 1 2 3; do [ $x = 2 ] && continue; (exit 4); s=$s$x; done"
+            )
+        );
+        assert_eq!(Some(String::from("13")), shell.get_var("s"));
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "s=; for x in 1 2; do for y in a b; do [ $y = b ] && continue 2; s=$s$x$y; done; s=no; done"
+            )
+        );
+        assert_eq!(Some(String::from("1a2a")), shell.get_var("s"));
+
+        // Counts past the outermost loop stop there, and the status is that of `break`
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "s=; until false; do while true; do s=$s.; (exit 3); break 9; done; done"
+            )
+        );
+        assert_eq!(Some(String::from(".")), shell.get_var("s"));
+        assert_eq!(None, shell.jump);
+
+        // A function can't break out of its caller's loop
+        run(&mut shell, "f() { break; }");
+        assert_eq!(0, run(&mut shell, "s=; for x in 1 2; do f; s=$s$x; done"));
+        assert_eq!(Some(String::from("12")), shell.get_var("s"));
+
+        assert_eq!(0, run(&mut shell, "break"));
+        assert_eq!(1, run(&mut shell, "for x in 1; do continue x; done"));
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut shell = Shell::new();
+
+        run(&mut shell, "f() { shift $1; s=\"$# $1\"; }");
+        assert_eq!(0, run(&mut shell, "f 2 a b c"));
+        assert_eq!(Some(String::from("2 b")), shell.get_var("s"));
+
+        // Shifting too far or by something other than a number leaves the parameters alone
+        assert_eq!(0, run(&mut shell, "g() { shift 4 || s=\"$? $#\"; }; g a b"));
+        assert_eq!(Some(String::from("1 2")), shell.get_var("s"));
+        assert_eq!(1, run(&mut shell, "shift"));
+        assert_eq!(0, run(&mut shell, "f x a"));
+        assert_eq!(Some(String::from("2 x")), shell.get_var("s"));
+    }
+
+    #[test]
+    fn test_array_assignment() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "i=2; arr=(a\n  b) arr[$i]=c; s=${#arr[@]}${arr[2]}"
+            )
+        );
+        assert_eq!(Some(String::from("3c")), shell.get_var("s"));
+        assert_eq!(
+            0,
+            run(&mut shell, "s=; for x in \"${arr[@]}\"; do s=$s$x; done")
+        );
+        assert_eq!(Some(String::from("abc")), shell.get_var("s"));
+
+        assert_eq!(1, run(&mut shell, "arr[x]=1"));
+        assert_eq!(1, run(&mut shell, "arr=(a; b)"));
+        assert_eq!(Some(String::from("a")), shell.get_var("arr"));
+    }
+
+    #[test]
+    fn test_associative_arrays() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "declare -A map; k=two; map[one]=1 map[$k]=2; s=${map[one]}${map[two]}"
+            )
+        );
+        assert_eq!(Some(String::from("12")), shell.get_var("s"));
+        assert_eq!(
+            0,
+            run(
+                &mut shell,
+                "map=([a]=x [b]='y z'); s=; for k in \"${!map[@]}\"; do s=$s$k=${map[$k]},; done"
+            )
+        );
+        assert_eq!(Some(String::from("a=x,b=y z,")), shell.get_var("s"));
+
+        assert_eq!(1, run(&mut shell, "map=(a)"));
+        assert_eq!(1, run(&mut shell, "arr=(a); declare -A arr"));
+        assert_eq!(1, run(&mut shell, "declare -a map"));
+        assert_eq!(1, run(&mut shell, "declare +A map"));
+    }
+
+    #[test]
+    fn test_declare() {
+        let mut shell = Shell::new();
+        let name = format!("exec-test-declare-{}", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+
+        // Integer variables evaluate what is assigned to them
+        assert_eq!(
+            0,
+            run(&mut shell, "declare -i n=2+3; n=n*2; typeset -i m; m=m+1")
+        );
+        assert_eq!(Some(String::from("10")), shell.get_var("n"));
+        assert_eq!(Some(String::from("1")), shell.get_var("m"));
+        assert_eq!(0, run(&mut shell, "declare +i n; n=n+1"));
+        assert_eq!(Some(String::from("n+1")), shell.get_var("n"));
+        assert_eq!(1, run(&mut shell, "declare -i bad=1+"));
+
+        assert_eq!(0, run(&mut shell, "declare -x EXEC_TEST_DECLARE=1"));
+        assert_eq!(Ok(String::from("1")), std::env::var("EXEC_TEST_DECLARE"));
+        assert_eq!(0, run(&mut shell, "declare +x EXEC_TEST_DECLARE"));
+        assert!(std::env::var("EXEC_TEST_DECLARE").is_err());
+        assert_eq!(Some(String::from("1")), shell.get_var("EXEC_TEST_DECLARE"));
+
+        let line = format!(
+            "declare -ir r=7; declare -a arr=(a b); declare -p r arr m q > {}",
+            path.display()
+        );
+        assert_eq!(0, run(&mut shell, "q='say \"$hi\"'"));
+        assert_eq!(0, run(&mut shell, &line));
+        assert!(shell.var_attributes("r").readonly);
+        assert_eq!(
+            "declare -ir r=\"7\"\n\
+             declare -a arr=([0]=\"a\" [1]=\"b\")\n\
+             declare -i m=\"1\"\n\
+             declare -- q=\"say \\\"\\$hi\\\"\"\n",
+            read_output(&name)
+        );
+        assert_eq!(1, run(&mut shell, "declare -p exec_test_missing"));
+        assert_eq!(1, run(&mut shell, "declare -q x"));
+    }
+
+    #[test]
+    fn test_readonly() {
+        let mut shell = Shell::new();
+
+        assert_eq!(0, run(&mut shell, "readonly r=1 s; declare -ra arr=(a b)"));
+        assert_eq!(Some(String::from("1")), shell.get_var("r"));
+        for line in [
+            "r=2",
+            "arr[0]=c",
+            "unset r",
+            "let r++",
+            "for r in a; do :; done",
+            "export r=2",
+            "declare r=2",
+            "readonly r=2",
+            ": ${s:=x}",
+        ] {
+            assert_eq!(1, run(&mut shell, line), "{}", line);
+        }
+        assert_eq!(2, run(&mut shell, "read r < /dev/null"));
+        assert_eq!(Some(String::from("1")), shell.get_var("r"));
+        assert_eq!(None, shell.get_var("s"));
+        assert_eq!(vec!["a", "b"], shell.array_values("arr"));
+
+        // The attribute can be added again and other variables are left alone
+        assert_eq!(0, run(&mut shell, "readonly r; t=1; unset t"));
+    }
+}
@@ -0,0 +1,1311 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+use crate::arith;
+use crate::lexer::{self, Token};
+use crate::pattern;
+use crate::shell::{Jump, Shell};
+
+/// The field separators used when `IFS` is unset.
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// How quoted text is kept apart from unquoted text in the result of an expansion.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Literal {
+    /// Quoted text is copied as it is.
+    Plain,
+    /// Quoted pattern characters are escaped, so the result can be matched as a pattern.
+    Pattern,
+    /// Quoted regular expression characters are escaped, so the result can be used as an
+    /// extended regular expression.
+    Regex,
+}
+
+/// The fields a word is split into while it is expanded.
+struct Fields {
+    /// The fields finished so far.
+    done: Vec<String>,
+    /// The field being built.
+    current: String,
+    /// Whether the current field exists even if it ends up empty, because part of it was quoted.
+    started: bool,
+    /// Whether IFS whitespace was seen after the last text added to the current field.
+    delimited: bool,
+    /// The characters unquoted expansion results are split on, or `None` to not split them.
+    ifs: Option<String>,
+}
+
+impl Fields {
+    fn new(ifs: Option<String>) -> Fields {
+        Fields {
+            done: Vec::new(),
+            current: String::new(),
+            started: false,
+            delimited: false,
+            ifs,
+        }
+    }
+
+    /// Add an unquoted literal character to the current field.
+    fn push(&mut self, c: char) {
+        self.end_delimited();
+        self.current.push(c);
+        self.started = true;
+    }
+
+    /// Add quoted text to the current field, which makes the field exist even if it's empty.
+    fn push_quoted(&mut self, text: &str) {
+        self.end_delimited();
+        self.current.push_str(text);
+        self.started = true;
+    }
+
+    /// Add the result of an unquoted expansion, splitting it into fields on IFS characters.
+    /// Runs of IFS whitespace separate fields and are otherwise dropped, while every other IFS
+    /// character ends a field, which can leave empty fields behind.
+    fn push_expansion(&mut self, value: &str) {
+        let Some(ifs) = self.ifs.clone() else {
+            self.end_delimited();
+            self.current.push_str(value);
+            self.started |= !value.is_empty();
+            return;
+        };
+
+        for c in value.chars() {
+            if !ifs.contains(c) {
+                self.end_delimited();
+                self.current.push(c);
+                self.started = true;
+            } else if c.is_whitespace() {
+                self.delimited = true;
+            } else {
+                self.done.push(std::mem::take(&mut self.current));
+                self.started = false;
+                self.delimited = false;
+            }
+        }
+    }
+
+    /// Finish the current field, even if it is empty, and start a new one.
+    fn end_field(&mut self) {
+        self.done.push(std::mem::take(&mut self.current));
+        self.started = true;
+        self.delimited = false;
+    }
+
+    /// Finish the current field if IFS whitespace was seen since it was last added to.
+    fn end_delimited(&mut self) {
+        if self.delimited {
+            self.delimited = false;
+            if self.started {
+                self.done.push(std::mem::take(&mut self.current));
+                self.started = false;
+            }
+        }
+    }
+
+    /// Get all of the fields, including the current one if it exists.
+    fn finish(mut self) -> Vec<String> {
+        if self.started {
+            self.done.push(self.current);
+        }
+        self.done
+    }
+}
+
+impl Shell {
+    /// Expand a list of words read from the user into the argument vector handed to a builtin or
+    /// `execvp`. Words that expand to nothing (e.g. an unset, unquoted variable) are dropped.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `words: &[String]` The unexpanded words of a command.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Vec<CString>)` The expanded arguments.
+    /// - `Err(String)` if one of the words contained a bad substitution.
+    pub fn expand_words(&mut self, words: &[String]) -> Result<Vec<CString>, String> {
+        let mut argv: Vec<CString> = Vec::new();
+        for word in words {
+            for field in self.expand_word(word)? {
+                argv.push(CString::new(field).map_err(|err| err.to_string())?);
+            }
+        }
+
+        Ok(argv)
+    }
+
+    /// Expand a single word. Variables and command substitutions are expanded when unquoted or
+    /// inside double quotes, single quoted text is taken literally and quote characters are
+    /// removed. Unquoted expansion results are split into separate fields on the characters of
+    /// `$IFS`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `word: &str` The unexpanded word.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Vec<String>)` The fields the word expanded to. An unquoted word that expands to the
+    ///   empty string produces no fields, while `""` produces one empty field.
+    /// - `Err(String)` if the word contained a bad substitution.
+    pub fn expand_word(&mut self, word: &str) -> Result<Vec<String>, String> {
+        let ifs = match self.get_var("IFS") {
+            Some(ifs) if ifs.is_empty() => None,
+            Some(ifs) => Some(ifs),
+            None => Some(String::from(DEFAULT_IFS)),
+        };
+
+        // Fields are expanded as patterns so quoted characters don't match file names
+        let mut fields = Vec::new();
+        for field in self.expand_parts(word, Literal::Pattern, ifs)? {
+            let paths = if self.options.noglob || !pattern::has_meta(&field) {
+                vec![]
+            } else {
+                pattern::glob(&field)
+            };
+
+            if paths.is_empty() {
+                fields.push(pattern::unescape(&field));
+            } else {
+                fields.extend(paths);
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Expand a word into a single string, as is done for the value of an assignment or the word
+    /// in `${VAR:-word}`.
+    pub fn expand_string(&mut self, word: &str) -> Result<String, String> {
+        Ok(self.expand_parts(word, Literal::Plain, None)?.concat())
+    }
+
+    /// Expand a word that is used as a pattern. Quoted characters are escaped in the result so
+    /// that they only match themselves, e.g. `"*".rs` matches the file `*.rs` but not `main.rs`.
+    pub fn expand_pattern(&mut self, word: &str) -> Result<String, String> {
+        Ok(self.expand_parts(word, Literal::Pattern, None)?.concat())
+    }
+
+    /// Expand a word that is used as an extended regular expression, as on the right of `=~`.
+    /// Quoted characters are escaped in the result so that they only match themselves.
+    pub fn expand_regex(&mut self, word: &str) -> Result<String, String> {
+        Ok(self.expand_parts(word, Literal::Regex, None)?.concat())
+    }
+
+    /// Expand the value of an assignment and store it. The value of `name=(a b c)` is split into
+    /// words that each expand to elements of a new array, while `name[i]=value` sets a single
+    /// element. Associative arrays take `[key]=value` items and string keys instead.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name being assigned, possibly with a subscript.
+    /// - `value: &str` The unexpanded value.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the value was stored.
+    /// - `Err(String)` if the value couldn't be expanded or the subscript isn't a valid index.
+    pub fn assign(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let base = Shell::split_subscript(name).map_or(name, |(base, _)| base);
+        self.check_readonly(base)?;
+        if let Some(items) = value
+            .strip_prefix('(')
+            .and_then(|items| items.strip_suffix(')'))
+        {
+            if self.assoc_arrays.contains_key(name) {
+                return self.assign_assoc(name, items);
+            }
+            let mut elements = BTreeMap::new();
+            for token in lexer::tokenize(items).map_err(|err| err.to_string())? {
+                match token {
+                    Token::Word(word) => {
+                        for field in self.expand_word(&word)? {
+                            elements.insert(elements.len(), field);
+                        }
+                    }
+                    Token::Newline => (),
+                    token => {
+                        return Err(format!(
+                            "{}: syntax error in array assignment near `{}'",
+                            name, token
+                        ))
+                    }
+                }
+            }
+            self.vars.remove(name);
+            self.arrays.insert(name.to_string(), elements);
+            return Ok(());
+        }
+
+        let value = self.expand_string(value)?;
+        let value = self.attribute_value(base, value)?;
+        match Shell::split_subscript(name) {
+            Some((base, subscript)) if self.assoc_arrays.contains_key(base) => {
+                let key = self.expand_string(subscript)?;
+                if let Some(array) = self.assoc_arrays.get_mut(base) {
+                    array.insert(key, value);
+                }
+            }
+            Some((base, subscript)) => match self.array_index(base, subscript)? {
+                Some(index) => self.set_array_element(base, index, &value),
+                None => return Err(format!("{}: bad array subscript", name)),
+            },
+            None => self.set_var(name, &value),
+        }
+
+        Ok(())
+    }
+
+    /// Replace the contents of the associative array `name` with the `[key]=value` items of an
+    /// array assignment.
+    fn assign_assoc(&mut self, name: &str, items: &str) -> Result<(), String> {
+        let mut elements = BTreeMap::new();
+        for token in lexer::tokenize(items).map_err(|err| err.to_string())? {
+            let word = match token {
+                Token::Word(word) => word,
+                Token::Newline => continue,
+                token => {
+                    return Err(format!(
+                        "{}: syntax error in array assignment near `{}'",
+                        name, token
+                    ))
+                }
+            };
+            let Some((key, value)) = word
+                .strip_prefix('[')
+                .and_then(|item| item.split_once("]="))
+            else {
+                return Err(format!(
+                    "{}: {}: must use a subscript when assigning an associative array",
+                    name, word
+                ));
+            };
+            elements.insert(self.expand_string(key)?, self.expand_string(value)?);
+        }
+        self.assoc_arrays.insert(name.to_string(), elements);
+
+        Ok(())
+    }
+
+    /// Expand the subscript of an element of the array `name` into the index it refers to.
+    /// Negative indexes count back from one past the last element.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Some(usize))` The index.
+    /// - `Ok(None)` if a negative index reaches back past the start of the array.
+    /// - `Err(String)` if the subscript isn't a number.
+    fn array_index(&mut self, name: &str, subscript: &str) -> Result<Option<usize>, String> {
+        let expanded = self.expand_string(subscript)?;
+        let index = expanded
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| format!("{}: bad array subscript", subscript))?;
+        if index >= 0 {
+            return Ok(Some(index as usize));
+        }
+
+        let len = match self.arrays.get(name) {
+            Some(array) => array.keys().next_back().map_or(0, |last| last + 1),
+            None => usize::from(self.get_var(name).is_some()),
+        };
+        Ok(usize::try_from(len as i64 + index).ok())
+    }
+
+    /// Get the values of all of the elements of an array in order. A variable that isn't an array
+    /// acts like an array holding just its value.
+    pub fn array_values(&self, name: &str) -> Vec<String> {
+        if let Some(array) = self.assoc_arrays.get(name) {
+            return array.values().cloned().collect();
+        }
+        match self.arrays.get(name) {
+            Some(array) => array.values().cloned().collect(),
+            None => self.get_var(name).into_iter().collect(),
+        }
+    }
+
+    /// Get the indexes or keys that have been set in an array, in the same order as
+    /// `array_values`.
+    pub fn array_keys(&self, name: &str) -> Vec<String> {
+        if let Some(array) = self.assoc_arrays.get(name) {
+            return array.keys().cloned().collect();
+        }
+        match self.arrays.get(name) {
+            Some(array) => array.keys().map(|index| index.to_string()).collect(),
+            None => self
+                .get_var(name)
+                .map(|_| String::from("0"))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Look up the parameter named in a `${...}` expression, expanding the subscript first if it
+    /// names an element of an array.
+    fn braced_value(&mut self, name: &str) -> Result<Option<String>, String> {
+        match Shell::split_subscript(name) {
+            Some((base, subscript)) if subscript != "@" && subscript != "*" => {
+                if self.assoc_arrays.contains_key(base) {
+                    let key = self.expand_string(subscript)?;
+                    return Ok(self
+                        .assoc_arrays
+                        .get(base)
+                        .and_then(|array| array.get(&key).cloned()));
+                }
+                let Some(index) = self.array_index(base, subscript)? else {
+                    return Ok(None);
+                };
+                Ok(match self.arrays.get(base) {
+                    Some(array) => array.get(&index).cloned(),
+                    None if index == 0 => self.get_var(base),
+                    None => None,
+                })
+            }
+            _ => Ok(self.param_value(name)),
+        }
+    }
+
+    /// Expand `$@`, `${name[@]}` or `${!name[@]}` inside double quotes, the expansions that give
+    /// a separate field for each positional parameter or for each value or key of an array.
+    ///
+    /// ## Returns
+    ///
+    /// `Some((values, next))` if the `$` at `chars[start]` starts such an expansion, with the
+    /// values it gives and the index of the first character after the expression.
+    fn expand_quoted_array(&self, chars: &[char], start: usize) -> Option<(Vec<String>, usize)> {
+        match chars.get(start + 1) {
+            Some('@') => return Some((self.positional_params.clone(), start + 2)),
+            Some('{') => (),
+            _ => return None,
+        }
+        let end = start + 2 + chars[start + 2..].iter().position(|&c| c == '}')?;
+        let inner: String = chars[start + 2..end].iter().collect();
+        if inner == "@" {
+            return Some((self.positional_params.clone(), end + 1));
+        }
+        let name = inner.strip_suffix("[@]")?;
+
+        match name.strip_prefix('!') {
+            Some(name) => Shell::is_valid_name(name).then(|| (self.array_keys(name), end + 1)),
+            None => Shell::is_valid_name(name).then(|| (self.array_values(name), end + 1)),
+        }
+    }
+
+    /// Perform expansion and quote removal on a word.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `word: &str` The unexpanded word.
+    /// - `literal: Literal` How to escape quoted text in the result.
+    /// - `ifs: Option<String>` The characters to split unquoted expansion results on, or `None`
+    ///   to keep the word in one field.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Vec<String>)` The fields the word expanded to.
+    /// - `Err(String)` if the word contained a bad substitution.
+    fn expand_parts(
+        &mut self,
+        word: &str,
+        literal: Literal,
+        ifs: Option<String>,
+    ) -> Result<Vec<String>, String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut fields = Fields::new(ifs);
+        let mut i = 0;
+
+        // Pattern characters in unquoted expansions keep their meaning, but backslashes in them
+        // aren't escapes
+        let escape_backslashes = |value: &str| {
+            if literal == Literal::Pattern {
+                value.replace('\\', "\\\\")
+            } else {
+                value.to_string()
+            }
+        };
+
+        // Quoted text is literal, which matters when the result is used as a pattern
+        let push_quoted = |fields: &mut Fields, text: &str| match literal {
+            Literal::Plain => fields.push_quoted(text),
+            Literal::Pattern => fields.push_quoted(&pattern::escape(text)),
+            Literal::Regex => fields.push_quoted(&pattern::escape_regex(text)),
+        };
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => {
+                    i += 1;
+                    if let Some(&c) = chars.get(i) {
+                        push_quoted(&mut fields, &c.to_string());
+                    }
+                }
+                '\'' => {
+                    i += 1;
+                    let begin = i;
+                    while i < chars.len() && chars[i] != '\'' {
+                        i += 1;
+                    }
+                    push_quoted(&mut fields, &chars[begin..i].iter().collect::<String>());
+                }
+                '"' => {
+                    i += 1;
+                    // Even `""` makes a field, unless all it holds is an empty "${arr[@]}"
+                    let new_field = !fields.started || fields.delimited;
+                    let mut saw_array = false;
+                    let mut only_empty_arrays = true;
+                    push_quoted(&mut fields, "");
+                    while i < chars.len() && chars[i] != '"' {
+                        if chars[i] == '$' {
+                            if let Some((values, next)) = self.expand_quoted_array(&chars, i) {
+                                for (n, value) in values.iter().enumerate() {
+                                    if n > 0 {
+                                        fields.end_field();
+                                    }
+                                    push_quoted(&mut fields, value);
+                                }
+                                saw_array = true;
+                                only_empty_arrays &= values.is_empty();
+                                i = next;
+                                continue;
+                            }
+                        }
+                        only_empty_arrays = false;
+                        match chars[i] {
+                            // Inside double quotes a backslash only escapes characters that
+                            // would otherwise be special
+                            '\\' if matches!(chars.get(i + 1), Some('$' | '`' | '"' | '\\')) => {
+                                push_quoted(&mut fields, &chars[i + 1].to_string());
+                                i += 2;
+                            }
+                            '$' => {
+                                let (value, next) = self.expand_dollar(&chars, i)?;
+                                push_quoted(&mut fields, &value);
+                                i = next;
+                            }
+                            '`' => {
+                                let (value, next) = self.expand_backquoted(&chars, i);
+                                push_quoted(&mut fields, &value);
+                                i = next;
+                            }
+                            c => {
+                                push_quoted(&mut fields, &c.to_string());
+                                i += 1;
+                            }
+                        }
+                    }
+                    if new_field && saw_array && only_empty_arrays {
+                        fields.started = false;
+                    }
+                }
+                '$' if chars.get(i + 1) == Some(&'\'') => {
+                    let (text, end) = Shell::decode_ansi_c(&chars, i + 2);
+                    push_quoted(&mut fields, &text);
+                    i = end;
+                }
+                '$' => {
+                    let (value, next) = self.expand_dollar(&chars, i)?;
+                    fields.push_expansion(&escape_backslashes(&value));
+                    i = next;
+                    continue;
+                }
+                '`' => {
+                    let (value, next) = self.expand_backquoted(&chars, i);
+                    fields.push_expansion(&escape_backslashes(&value));
+                    i = next;
+                    continue;
+                }
+                c => fields.push(c),
+            }
+            i += 1;
+        }
+
+        Ok(fields.finish())
+    }
+
+    /// Run the `` `...` `` command substitution starting at `chars[start]`. Inside the
+    /// backquotes a backslash escapes `$`, `` ` `` and `\`.
+    ///
+    /// ## Returns
+    ///
+    /// The output of the commands and the index of the first character after the closing
+    /// backquote.
+    fn expand_backquoted(&mut self, chars: &[char], start: usize) -> (String, usize) {
+        let mut commands = String::new();
+        let mut i = start + 1;
+        while i < chars.len() && chars[i] != '`' {
+            if chars[i] == '\\' && matches!(chars.get(i + 1), Some('$' | '`' | '\\')) {
+                i += 1;
+            }
+            commands.push(chars[i]);
+            i += 1;
+        }
+
+        (self.command_substitution(&commands), i + 1)
+    }
+
+    /// Expand the `$` expression starting at `chars[start]`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok((String, usize))` The expanded value and the index of the first character after the
+    ///   expression. A `$` that doesn't start an expression expands to itself.
+    /// - `Err(String)` if the expression is malformed.
+    fn expand_dollar(&mut self, chars: &[char], start: usize) -> Result<(String, usize), String> {
+        let mut i = start + 1;
+        match chars.get(i) {
+            Some('(') => {
+                let mut text = String::new();
+                let rest: String = chars[i + 1..].iter().collect();
+                let mut rest = lexer::Cursor::new(&rest);
+                lexer::read_substitution(&mut rest, &mut text).map_err(|err| err.to_string())?;
+
+                let end = i + 1 + text.chars().count();
+                text.pop();
+                if let Some(expression) = arith::arithmetic_body(&text) {
+                    return Ok((self.arithmetic(expression)?.to_string(), end));
+                }
+                Ok((self.command_substitution(&text), end))
+            }
+            Some('{') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => (),
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(String::from("unterminated ${"));
+                }
+
+                let inner: String = chars[start + 2..i].iter().collect();
+                Ok((self.expand_braced(&inner)?, i + 1))
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+
+                let name: String = chars[start + 1..i].iter().collect();
+                let value = self.param_value(&name);
+                Ok((self.required_value(&name, value)?, i))
+            }
+            Some(&c) if Shell::is_special_param(c) => {
+                let name = c.to_string();
+                let value = self.param_value(&name);
+                Ok((self.required_value(&name, value)?, i + 1))
+            }
+            _ => Ok((String::from("$"), i)),
+        }
+    }
+
+    /// Expand the contents of a `${...}` expression, including the `${#VAR}` length form, the
+    /// `${!arr[@]}` form listing the keys of an array and the `-`, `=`, `?`, `+`, `#`, `##`, `%`
+    /// and `%%` operators.
+    fn expand_braced(&mut self, inner: &str) -> Result<String, String> {
+        let bad_substitution = || format!("${{{}}}: bad substitution", inner);
+
+        if let Some(name) = inner.strip_prefix('!').filter(|name| !name.is_empty()) {
+            return match name.strip_suffix("[@]").or(name.strip_suffix("[*]")) {
+                Some(base) if Shell::is_valid_name(base) => Ok(self.array_keys(base).join(" ")),
+                _ => Err(bad_substitution()),
+            };
+        }
+
+        if let Some(name) = inner.strip_prefix('#') {
+            if Shell::param_name_len(name) == name.len() && !name.is_empty() {
+                // The length of a whole array is its number of elements
+                if let Some(base) = name.strip_suffix("[@]").or(name.strip_suffix("[*]")) {
+                    return Ok(self.array_values(base).len().to_string());
+                }
+                let value = self.braced_value(name)?;
+                let value = self.required_value(name, value)?;
+                return Ok(value.chars().count().to_string());
+            }
+        }
+
+        let name_len = Shell::param_name_len(inner);
+        if name_len == 0 {
+            return Err(bad_substitution());
+        }
+
+        let (name, rest) = inner.split_at(name_len);
+        let value = self.braced_value(name)?;
+        if rest.is_empty() {
+            return self.required_value(name, value);
+        }
+
+        let (op, word) = [
+            ":-", ":=", ":?", ":+", "##", "%%", "-", "=", "?", "+", "#", "%",
+        ]
+        .iter()
+        .find_map(|op| rest.strip_prefix(op).map(|word| (*op, word)))
+        .ok_or_else(bad_substitution)?;
+
+        // With a colon the operator treats a variable set to the empty string as unset
+        let missing = match value.as_deref() {
+            None => true,
+            Some(value) => value.is_empty() && op.starts_with(':'),
+        };
+
+        match op.trim_start_matches(':') {
+            "-" if missing => self.expand_string(word),
+            "=" if missing => {
+                if !Shell::is_valid_name(name) {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+                self.check_readonly(name)?;
+                let value = self.expand_string(word)?;
+                self.set_var(name, &value);
+                Ok(value)
+            }
+            "?" if missing => {
+                let message = if word.is_empty() {
+                    String::from("parameter null or not set")
+                } else {
+                    self.expand_string(word)?
+                };
+                Err(format!("{}: {}", name, message))
+            }
+            "+" if missing => Ok(String::new()),
+            "+" => self.expand_string(word),
+            "-" | "=" | "?" => Ok(value.unwrap_or_default()),
+            op => {
+                let value = self.required_value(name, value)?;
+                let pattern = self.expand_pattern(word)?;
+                Ok(Shell::remove_affix(&value, &pattern, op))
+            }
+        }
+    }
+
+    /// Get the value of a parameter that is being expanded without a default. With `set -u` it
+    /// is an error for it to be unset, except for `$@`, `$*` and whole arrays, which can be
+    /// empty.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name of the parameter.
+    /// - `value: Option<String>` Its value, or `None` if it is unset.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(String)` The value, or the empty string for an unset parameter without `set -u`.
+    /// - `Err(String)` if the parameter is unset with `set -u`. A shell that isn't interactive
+    ///   exits as well.
+    fn required_value(&mut self, name: &str, value: Option<String>) -> Result<String, String> {
+        let whole = matches!(name, "@" | "*") || name.ends_with("[@]") || name.ends_with("[*]");
+        match value {
+            Some(value) => Ok(value),
+            None if self.options.nounset && !whole => {
+                if !self.interactive {
+                    self.jump = Some(Jump::Exit(1));
+                }
+                Err(format!("{}: unbound variable", name))
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Remove the shortest or longest prefix or suffix of `value` matching `pattern`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `value: &str` The value to remove from.
+    /// - `pattern: &str` The pattern to match.
+    /// - `op: &str` One of `#` (shortest prefix), `##` (longest prefix), `%` (shortest suffix) or
+    ///   `%%` (longest suffix).
+    fn remove_affix(value: &str, pattern: &str, op: &str) -> String {
+        let bounds: Vec<usize> = value
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(value.len()))
+            .collect();
+
+        let prefix = |i: &&usize| pattern::matches(pattern, &value[..**i]);
+        let suffix = |i: &&usize| pattern::matches(pattern, &value[**i..]);
+
+        match op {
+            "#" => bounds.iter().find(prefix).map(|&i| &value[i..]),
+            "##" => bounds.iter().rev().find(prefix).map(|&i| &value[i..]),
+            "%" => bounds.iter().rev().find(suffix).map(|&i| &value[..i]),
+            _ => bounds.iter().find(suffix).map(|&i| &value[..i]),
+        }
+        .unwrap_or(value)
+        .to_string()
+    }
+
+    /// Decode the backslash escapes of a `$'...'` quote whose text starts at `chars[start]`.
+    ///
+    /// Supported escapes are `\a \b \e \E \f \n \r \t \v \\ \' \" \?`, octal `\nnn`, hex
+    /// `\xHH`, Unicode `\uHHHH` and `\UHHHHHHHH`, and control characters `\cX`. Any other
+    /// escape is kept as written. A NUL character ends the string, as it can't be passed on.
+    ///
+    /// ## Returns
+    ///
+    /// The decoded text and the index of the closing `'`.
+    fn decode_ansi_c(chars: &[char], start: usize) -> (String, usize) {
+        let mut text = String::new();
+        let mut ended = false;
+        let mut i = start;
+
+        while i < chars.len() && chars[i] != '\'' {
+            let c = chars[i];
+            i += 1;
+            let decoded = if c != '\\' {
+                Some(c)
+            } else if let Some(&escape) = chars.get(i) {
+                i += 1;
+                match escape {
+                    'a' => Some('\x07'),
+                    'b' => Some('\x08'),
+                    'e' | 'E' => Some('\x1b'),
+                    'f' => Some('\x0c'),
+                    'n' => Some('\n'),
+                    'r' => Some('\r'),
+                    't' => Some('\t'),
+                    'v' => Some('\x0b'),
+                    '\\' | '\'' | '"' | '?' => Some(escape),
+                    '0'..='7' => {
+                        i -= 1;
+                        Shell::read_code(chars, &mut i, 8, 3).and_then(char::from_u32)
+                    }
+                    'x' | 'u' | 'U' => {
+                        let max = match escape {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        match Shell::read_code(chars, &mut i, 16, max) {
+                            Some(code) => char::from_u32(code),
+                            None => {
+                                text.push('\\');
+                                Some(escape)
+                            }
+                        }
+                    }
+                    'c' if i < chars.len() && chars[i] != '\'' => {
+                        i += 1;
+                        char::from_u32(chars[i - 1].to_ascii_uppercase() as u32 ^ 0x40)
+                    }
+                    other => {
+                        text.push('\\');
+                        Some(other)
+                    }
+                }
+            } else {
+                Some('\\')
+            };
+
+            match decoded {
+                Some('\0') => ended = true,
+                Some(c) if !ended => text.push(c),
+                _ => (),
+            }
+        }
+
+        (text, i)
+    }
+
+    /// Read up to `max` digits in `radix` starting at `chars[*i]` as a character code, moving `i`
+    /// past them.
+    fn read_code(chars: &[char], i: &mut usize, radix: u32, max: usize) -> Option<u32> {
+        let digits = chars[*i..]
+            .iter()
+            .take(max)
+            .take_while(|c| c.is_digit(radix))
+            .count();
+        let code: String = chars[*i..*i + digits].iter().collect();
+        *i += digits;
+        u32::from_str_radix(&code, radix).ok()
+    }
+
+    /// Look up the value of a parameter, which is either a variable, a positional parameter such
+    /// as `$1`, a special parameter such as `$$` or `$!` or all of the elements of an array.
+    /// `arr[@]` joins the elements with spaces and `arr[*]` with the first character of `$IFS`.
+    pub fn param_value(&self, name: &str) -> Option<String> {
+        if let Some((base, subscript)) = Shell::split_subscript(name) {
+            let separator = match subscript {
+                "@" => String::from(" "),
+                "*" => self.star_separator(),
+                _ => return None,
+            };
+            let values = self.array_values(base);
+            return (!values.is_empty()).then(|| values.join(&separator));
+        }
+
+        match name {
+            "$" => Some(self.shell_pgid.to_string()),
+            "?" => Some(self.last_status.to_string()),
+            "!" => self.last_background_pid.map(|pid| pid.to_string()),
+            "#" => Some(self.positional_params.len().to_string()),
+            "@" => Some(self.positional_params.join(" ")),
+            "*" => Some(self.positional_params.join(&self.star_separator())),
+            "0" => Some(self.arg0.clone()),
+            name if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                let index = name.parse::<usize>().ok()?.checked_sub(1)?;
+                self.positional_params.get(index).cloned()
+            }
+            name => self.get_var(name),
+        }
+    }
+
+    /// Get the separator that `$*` and `${arr[*]}` join values with, which is the first
+    /// character of `$IFS`, or a space if it isn't set.
+    fn star_separator(&self) -> String {
+        match self.get_var("IFS") {
+            Some(ifs) => ifs.chars().take(1).collect(),
+            None => String::from(" "),
+        }
+    }
+
+    /// Check if `c` names a special parameter or a positional parameter that can follow a `$` on
+    /// its own. Positional parameters after `$9` need braces, as in `${10}`.
+    fn is_special_param(c: char) -> bool {
+        matches!(c, '$' | '?' | '!' | '#' | '@' | '*' | '0'..='9')
+    }
+
+    /// Get the length in bytes of the parameter name at the start of `text`, including the
+    /// subscript of an array element as in `arr[1]`.
+    fn param_name_len(text: &str) -> usize {
+        match text.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = text
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(text.len());
+                match text[len..]
+                    .strip_prefix('[')
+                    .and_then(|rest| rest.find(']'))
+                {
+                    Some(close) => len + close + 2,
+                    None => len,
+                }
+            }
+            Some(c) if c.is_ascii_digit() => text
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(text.len()),
+            Some(c) if Shell::is_special_param(c) => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(shell: &mut Shell, line: &str) -> Vec<String> {
+        let words = Shell::cmd_parse(line.to_string())
+            .unwrap()
+            .into_iter()
+            .map(|word| word.into_string().unwrap())
+            .collect::<Vec<String>>();
+        shell
+            .expand_words(&words)
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_plain_and_braced() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_FOO", "bar");
+
+        assert_eq!(
+            vec!["echo", "bar", "bar", "xbarx"],
+            expand(
+                &mut shell,
+                "echo $EXPAND_TEST_FOO ${EXPAND_TEST_FOO} x${EXPAND_TEST_FOO}x"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_quoting() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_QUOTE", "a  b");
+
+        assert_eq!(
+            vec!["a  b", "$EXPAND_TEST_QUOTE", "$EXPAND_TEST_QUOTE"],
+            expand(
+                &mut shell,
+                "\"$EXPAND_TEST_QUOTE\" '$EXPAND_TEST_QUOTE' \\$EXPAND_TEST_QUOTE"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_unset_drops_word() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            vec!["echo", ""],
+            expand(&mut shell, "echo $EXPAND_TEST_UNSET \"$EXPAND_TEST_UNSET\"")
+        );
+    }
+
+    #[test]
+    fn test_expand_environment() {
+        let mut shell = Shell::new();
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(vec![home], expand(&mut shell, "$HOME"));
+    }
+
+    #[test]
+    fn test_expand_positional_params() {
+        let mut shell = Shell::new();
+        shell.positional_params = (1..=10).map(|n| format!("p{}", n)).collect();
+
+        assert_eq!(
+            vec!["p1", "p9", "p10", "p10", "10"],
+            expand(&mut shell, "$1 $9 $10 ${10} $#")
+        );
+        assert_eq!(vec!["3", "x"], expand(&mut shell, "${#10} ${11:-x}"));
+
+        // "$@" keeps each parameter in its own field, while "$*" joins them into one
+        shell.positional_params = vec![String::from("a b"), String::from("c")];
+        assert_eq!(
+            vec!["<a b", "c>", "a", "b", "c"],
+            expand(&mut shell, "\"<$@>\" $*")
+        );
+        assert_eq!(
+            vec!["a b", "c", "a b c"],
+            expand(&mut shell, "\"${@}\" \"$*\"")
+        );
+        shell.set_var("IFS", ",");
+        assert_eq!(vec!["a b,c"], expand(&mut shell, "\"$*\""));
+        shell.vars.remove("IFS");
+
+        shell.positional_params.clear();
+        shell.arg0 = String::from("script.sh");
+        assert_eq!(vec!["script.sh", "0"], expand(&mut shell, "$1 ${0} $#"));
+        assert_eq!(
+            vec!["x", "", "x"],
+            expand(&mut shell, "\"$@\" x \"$*\" ${@:-x}")
+        );
+    }
+
+    #[test]
+    fn test_expand_nounset() {
+        let mut shell = Shell::new();
+        shell.options.nounset = true;
+        shell.set_var("EXPAND_TEST_SET", "");
+
+        for word in [
+            "$EXPAND_TEST_UNSET",
+            "\"${EXPAND_TEST_UNSET}\"",
+            "${#EXPAND_TEST_UNSET}",
+            "${EXPAND_TEST_UNSET%x}",
+            "$1",
+        ] {
+            let err = shell.expand_string(word).unwrap_err();
+            assert!(err.ends_with("unbound variable"), "{}: {}", word, err);
+        }
+
+        // Defaults, empty values and empty lists are still allowed
+        assert_eq!(
+            vec!["x", "y"],
+            expand(
+                &mut shell,
+                "${EXPAND_TEST_UNSET-x}$EXPAND_TEST_SET ${EXPAND_TEST_UNSET:+z}y $@ \"$@\" $*"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_array_keys() {
+        let mut shell = Shell::new();
+        shell.assign("arr", "(a b)").unwrap();
+        shell.assign("arr[5]", "c").unwrap();
+        shell
+            .assoc_arrays
+            .insert(String::from("map"), BTreeMap::new());
+        shell.assign("map[x y]", "1").unwrap();
+        shell.assign("map[z]", "2").unwrap();
+
+        assert_eq!(vec!["0", "1", "5"], expand(&mut shell, "${!arr[@]}"));
+        assert_eq!(vec!["x y", "z"], expand(&mut shell, "\"${!map[@]}\""));
+        assert_eq!(vec!["x y z"], expand(&mut shell, "\"${!map[*]}\""));
+        assert_eq!(
+            vec!["1", "2", "2"],
+            expand(&mut shell, "\"${map[x y]}\" ${map[z]} ${#map[@]}")
+        );
+        assert!(shell.expand_string("${!arr}").is_err());
+    }
+
+    #[test]
+    fn test_expand_arrays() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_I", "1");
+        shell
+            .assign("arr", "(a 'b  c' $EXPAND_TEST_I \"\")")
+            .unwrap();
+
+        assert_eq!(
+            vec!["4", "b  c", "1", "a", "a"],
+            expand(
+                &mut shell,
+                "${#arr[@]} \"${arr[$EXPAND_TEST_I]}\" ${arr[-2]} ${arr[0]} $arr"
+            )
+        );
+        assert_eq!(
+            vec!["<a", "b  c", "1", ">"],
+            expand(&mut shell, "\"<${arr[@]}>\"")
+        );
+        assert_eq!(vec!["a", "b", "c", "1"], expand(&mut shell, "${arr[@]}"));
+        assert_eq!(vec!["a b  c 1 "], expand(&mut shell, "\"${arr[*]}\""));
+        assert_eq!(
+            vec!["4", "x"],
+            expand(&mut shell, "${#arr[1]} ${arr[9]:-x}")
+        );
+
+        // Elements can be set one at a time, leaving gaps
+        shell.assign("arr[9]", "nine").unwrap();
+        shell.set_var("arr", "first");
+        assert_eq!(
+            vec!["5", "nine", "first"],
+            expand(&mut shell, "${#arr[@]} ${arr[-1]} ${arr[0]}")
+        );
+        assert!(shell.assign("arr[-20]", "x").is_err());
+        assert!(shell.assign("arr[x]", "x").is_err());
+
+        // A plain variable acts as an array of one element
+        shell.set_var("EXPAND_TEST_S", "s");
+        assert_eq!(
+            vec!["s", "s", "1"],
+            expand(
+                &mut shell,
+                "${EXPAND_TEST_S[0]} \"${EXPAND_TEST_S[@]}\" ${#EXPAND_TEST_S[@]}"
+            )
+        );
+        shell.assign("EXPAND_TEST_S[2]", "t").unwrap();
+        assert_eq!(
+            vec!["s", "t"],
+            expand(&mut shell, "\"${EXPAND_TEST_S[@]}\"")
+        );
+
+        // An empty array makes no fields at all when quoted
+        shell.assign("empty", "()").unwrap();
+        assert_eq!(
+            vec!["0", "xy", ""],
+            expand(
+                &mut shell,
+                "${#empty[@]} x\"${empty[@]}\"y \"${empty[@]}\" \"\""
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_bad_substitution() {
+        let mut shell = Shell::new();
+
+        assert!(shell.expand_word("${not valid}").is_err());
+    }
+
+    #[test]
+    fn test_expand_default_and_alternate() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_EMPTY", "");
+        shell.set_var("EXPAND_TEST_SET", "value");
+
+        assert_eq!(
+            vec!["default", "", "value", "alt", "empty"],
+            expand(
+                &mut shell,
+                "${EXPAND_TEST_NONE:-default} \"${EXPAND_TEST_EMPTY-default}\" \
+                 ${EXPAND_TEST_SET:-default} ${EXPAND_TEST_SET:+alt} \
+                 ${EXPAND_TEST_EMPTY+empty}${EXPAND_TEST_EMPTY:+alt}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_assign_default() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            vec!["assigned"],
+            expand(&mut shell, "${EXPAND_TEST_ASSIGN:=assigned}")
+        );
+        assert_eq!(
+            Some(String::from("assigned")),
+            shell.get_var("EXPAND_TEST_ASSIGN")
+        );
+    }
+
+    #[test]
+    fn test_expand_error_if_unset() {
+        let mut shell = Shell::new();
+
+        let err = shell.expand_word("${EXPAND_TEST_ERR:?is required}");
+        assert_eq!(Err(String::from("EXPAND_TEST_ERR: is required")), err);
+    }
+
+    #[test]
+    fn test_expand_length() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_LEN", "héllo");
+
+        assert_eq!(
+            vec!["5", "0"],
+            expand(&mut shell, "${#EXPAND_TEST_LEN} ${#EXPAND_TEST_NO}")
+        );
+    }
+
+    #[test]
+    fn test_expand_remove_affix() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_PATH", "/usr/local/lib.tar.gz");
+
+        assert_eq!(
+            vec![
+                "/usr/local/lib.tar",
+                "/usr/local/lib",
+                "usr/local/lib.tar.gz",
+                "lib.tar.gz"
+            ],
+            expand(
+                &mut shell,
+                "${EXPAND_TEST_PATH%.*} ${EXPAND_TEST_PATH%%.*} \
+                 ${EXPAND_TEST_PATH#/} ${EXPAND_TEST_PATH##*/}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_quoted_pattern_is_literal() {
+        let mut shell = Shell::new();
+        shell.set_var("EXPAND_TEST_STAR", "a*b");
+
+        assert_eq!(
+            vec!["a", "a*b"],
+            expand(
+                &mut shell,
+                "${EXPAND_TEST_STAR%\\**} ${EXPAND_TEST_STAR%\"*\"}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_ansi_c_quoting() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            vec!["a\tb\nc", "A", "it's", "\x1b[0m", "é", "\x03", "\\q"],
+            expand(
+                &mut shell,
+                "$'a\\tb\\nc' $'\\x41' $'it\\'s' $'\\e[0m' $'\\u00e9' $'\\cc' $'\\q'"
+            )
+        );
+        assert_eq!(
+            vec!["x@y", "ab"],
+            expand(&mut shell, "x$'\\100'y $'a\\0z'b")
+        );
+        assert_eq!(vec!["$'\\n'"], expand(&mut shell, "\"$'\\n'\""));
+    }
+
+    #[test]
+    fn test_expand_field_splitting() {
+        let mut shell = Shell::new();
+        shell
+            .vars
+            .insert(String::from("FILES"), String::from(" a  b\tc "));
+
+        assert_eq!(vec!["a", "b", "c"], expand(&mut shell, "$FILES"));
+        assert_eq!(vec![" a  b\tc "], expand(&mut shell, "\"$FILES\""));
+        assert_eq!(
+            vec!["[", "a", "b", "c", "]"],
+            expand(&mut shell, "[$FILES]")
+        );
+        assert_eq!(vec!["x-", "a", "b", "c"], expand(&mut shell, "x-${FILES}"));
+
+        shell.vars.insert(String::from("IFS"), String::from(": "));
+        shell.vars.insert(
+            String::from("PATHS"),
+            String::from("/bin::/usr/bin : /sbin:"),
+        );
+        assert_eq!(
+            vec!["/bin", "", "/usr/bin", "/sbin"],
+            expand(&mut shell, "$PATHS")
+        );
+
+        shell.vars.insert(String::from("IFS"), String::new());
+        assert_eq!(vec![" a  b\tc "], expand(&mut shell, "$FILES"));
+    }
+
+    #[test]
+    fn test_expand_command_substitution() {
+        let mut shell = Shell::new();
+
+        assert_eq!(
+            vec!["a", "b", "a b\n\nc", "x"],
+            expand(
+                &mut shell,
+                "$(echo a b) \"$(printf 'a b\\n\\nc\\n\\n')\" `echo x`"
+            )
+        );
+        assert_eq!(
+            vec!["(nested)"],
+            expand(&mut shell, "$(echo \"($(echo nested))\")")
+        );
+        assert_eq!(vec!["$HOME"], expand(&mut shell, "`echo '\\$HOME'`"));
+
+        assert!(expand(&mut shell, "$(exit 3)").is_empty());
+        assert_eq!(3, shell.last_status);
+    }
+
+    #[test]
+    fn test_expand_pathnames() {
+        let mut shell = Shell::new();
+        let dir = std::env::temp_dir().join(format!("expand-glob-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["one.rs", "two.rs", "*.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir = dir.to_str().unwrap().to_string();
+        shell.vars.insert(String::from("DIR"), dir.clone());
+        shell
+            .vars
+            .insert(String::from("PAT"), String::from("t*.rs"));
+
+        assert_eq!(
+            vec![
+                format!("{}/*.rs", dir),
+                format!("{}/one.rs", dir),
+                format!("{}/two.rs", dir)
+            ],
+            expand(&mut shell, "$DIR/*.rs")
+        );
+        assert_eq!(
+            vec![format!("{}/*.rs", dir)],
+            expand(&mut shell, "$DIR/'*'.rs")
+        );
+        assert_eq!(
+            vec![format!("{}/two.rs", dir)],
+            expand(&mut shell, "$DIR/$PAT")
+        );
+        assert_eq!(
+            vec![format!("{}/*.md", dir)],
+            expand(&mut shell, "$DIR/*.md")
+        );
+
+        shell.set_options(&[CString::new("-f").unwrap()]).unwrap();
+        assert_eq!(
+            vec![format!("{}/*.rs", dir)],
+            expand(&mut shell, "$DIR/*.rs")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
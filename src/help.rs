@@ -0,0 +1,127 @@
+use std::ffi::CString;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::builtin::Builtin;
+use crate::pattern;
+use crate::shell::Shell;
+
+impl Shell {
+    /// The `help` builtin, as in `help [-ds] [pattern...]`, which lists the builtins with a
+    /// summary of each or, given patterns, shows the usage and options of the builtins they
+    /// match. `-d` shows only the summary and `-s` only the usage.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `help`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the help was printed.
+    /// - `Err(String)` if an option is invalid or a pattern doesn't match any builtin.
+    pub fn help(&self, args: &[CString]) -> Result<(), String> {
+        let out = self.help_text(args)?;
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// Put together what `help` prints for its arguments. No line ends in whitespace, so the
+    /// usage column is only padded when a summary follows it.
+    fn help_text(&self, args: &[CString]) -> Result<String, String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut summary_only = false;
+        let mut usage_only = false;
+        let mut i = 0;
+        while let Some(flags) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'd' => summary_only = true,
+                    's' => usage_only = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+        }
+
+        let mut out = String::new();
+        if i == args.len() {
+            let width = self
+                .builtins
+                .values()
+                .map(|builtin| builtin.usage().len())
+                .max()
+                .unwrap_or(0);
+            for builtin in self.builtins.values() {
+                let line = format!("{:<width$}  {}", builtin.usage(), builtin.summary());
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+        }
+        for pattern in &args[i..] {
+            let matched: Vec<&Rc<dyn Builtin>> = self
+                .builtins
+                .values()
+                .filter(|builtin| pattern::matches(pattern, builtin.name()))
+                .collect();
+            if matched.is_empty() {
+                return Err(format!("no help topics match `{}'", pattern));
+            }
+            for builtin in matched {
+                if summary_only {
+                    out.push_str(&format!("{} - {}\n", builtin.name(), builtin.summary()));
+                } else if usage_only {
+                    out.push_str(&format!("{}: {}\n", builtin.name(), builtin.usage()));
+                } else {
+                    out.push_str(&format!(
+                        "{}: {}\n    {}\n\n",
+                        builtin.name(),
+                        builtin.usage(),
+                        builtin.summary()
+                    ));
+                    for line in builtin.details() {
+                        if line.is_empty() {
+                            out.push('\n');
+                        } else {
+                            out.push_str(&format!("    {}\n", line));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_help_text() {
+        let shell = Shell::new();
+
+        for topics in [&[][..], &["*"], &["-d", "*"], &["-s", "*"]] {
+            let out = shell.help_text(&args(topics)).unwrap();
+            assert!(!out.is_empty());
+            for line in out.lines() {
+                assert_eq!(line.trim_end(), line);
+            }
+        }
+
+        let out = shell.help_text(&args(&["-d", "cd"])).unwrap();
+        assert_eq!("cd - Change the working directory.\n", out);
+        assert!(shell.help_text(&args(&["nope*"])).is_err());
+        assert!(shell.help_text(&args(&["-x"])).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use rustyline::history::History;
+
+use crate::editor::LineEditor;
+use crate::shell::Shell;
+
+/// The file the command history is loaded from when the shell starts and saved to after each
+/// line.
+pub const HISTORY_FILE: &str = "history.txt";
+
+/// How many commands `fc -l` lists when it isn't given a range.
+const FC_LIST_COUNT: usize = 16;
+
+/// Replace every entry in the line editor's history, which can't remove single entries itself.
+fn rebuild_history(editor: &mut LineEditor, entries: Vec<String>) -> Result<(), String> {
+    let history = editor.history_mut();
+    history.clear().map_err(|err| err.to_string())?;
+    for entry in entries {
+        history.add_owned(entry).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Find the entry `spec` refers to in `fc`'s range of the history: a positive number is an
+/// entry's number, a negative one counts back from the last entry and anything else picks the
+/// most recent entry starting with it.
+///
+/// ## Returns
+///
+/// - `Ok(usize)` The index of the entry.
+/// - `Err(String)` if no entry matches.
+fn fc_index(entries: &[String], spec: &str) -> Result<usize, String> {
+    let len = entries.len() as i64;
+    let index = match spec.parse::<i64>() {
+        // Numbers out of range are clamped to the first or last entry as in other shells
+        Ok(n) if n < 0 => Some((len + n).max(0)),
+        Ok(n) => Some((n - 1).clamp(0, len - 1)),
+        Err(_) => entries
+            .iter()
+            .rposition(|entry| entry.starts_with(spec))
+            .map(|index| index as i64),
+    };
+    index
+        .filter(|index| (0..len).contains(index))
+        .map(|index| index as usize)
+        .ok_or_else(|| format!("{}: history specification out of range", spec))
+}
+
+impl Shell {
+    /// The `history` builtin, as in `history [n]`, which lists the commands in the line editor's
+    /// history with their numbers, or only the last `n` of them. `-c` clears the history and
+    /// `-d offset` deletes one entry, counting back from the end if `offset` is negative. `-w`
+    /// writes the history to a file and `-r` reads the commands in a file into it, using the
+    /// history file unless another is given.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `history`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the history was listed or changed.
+    /// - `Err(String)` if an option or count is invalid, an offset is out of range, a file
+    ///   couldn't be read or written, or the shell isn't keeping a history.
+    pub fn history(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let Some(editor) = self.editor.as_mut() else {
+            // Only the line editor of an interactive shell keeps a history, so there is nothing
+            // to list
+            return match args.first() {
+                Some(arg) if arg.starts_with('-') => Err(String::from(
+                    "no history is kept in a non-interactive shell",
+                )),
+                _ => Ok(()),
+            };
+        };
+
+        match args.first().map(String::as_str) {
+            Some("-c") => editor.clear_history().map_err(|err| err.to_string()),
+            Some(flag) if flag.starts_with("-d") => {
+                let offset = match flag.strip_prefix("-d").filter(|offset| !offset.is_empty()) {
+                    Some(offset) => offset,
+                    None => args
+                        .get(1)
+                        .ok_or_else(|| String::from("-d: option requires an argument"))?,
+                };
+                let len = editor.history().len() as i64;
+                let index = offset
+                    .parse::<i64>()
+                    .ok()
+                    .map(|n| if n < 0 { len + n } else { n - 1 })
+                    .filter(|index| (0..len).contains(index))
+                    .ok_or_else(|| format!("{}: history position out of range", offset))?;
+
+                let mut entries: Vec<String> = editor.history().iter().cloned().collect();
+                entries.remove(index as usize);
+                rebuild_history(editor, entries)
+            }
+            Some(flag @ ("-w" | "-r")) => {
+                let path = args.get(1).map_or(HISTORY_FILE, String::as_str);
+                let result = if flag == "-w" {
+                    editor.save_history(path)
+                } else {
+                    editor.load_history(path)
+                };
+                result.map_err(|err| format!("{}: {}", path, err))
+            }
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 && !flag.starts_with("--") => {
+                Err(format!("{}: invalid option", flag))
+            }
+            count => {
+                let entries: Vec<&String> = editor.history().iter().collect();
+                let count = match count.filter(|count| *count != "--") {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("{}: numeric argument required", count))?,
+                    None => entries.len(),
+                };
+
+                let mut out = String::new();
+                let start = entries.len().saturating_sub(count);
+                for (i, entry) in entries.iter().enumerate().skip(start) {
+                    out.push_str(&format!("{:5}  {}\n", i + 1, entry));
+                }
+                let mut stdout = std::io::stdout();
+                stdout
+                    .write_all(out.as_bytes())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|err| format!("write error: {}", err))
+            }
+        }
+    }
+
+    /// The `fc` builtin, as in `fc [-e editor] [-lnr] [first [last]]` or `fc -s [old=new]
+    /// [command]`, which fixes and re-runs commands from the history. The commands from `first` to
+    /// `last`, the previous command by default, are written to a file and opened in `editor`, or
+    /// `$FCEDIT`, `$EDITOR` or `vi`, and whatever is left in the file is printed and run once the
+    /// editor exits. An editor of `-` runs the commands without editing them.
+    ///
+    /// `-l` lists the commands instead, the last 16 by default, without their numbers if `-n` is
+    /// given and in reverse with `-r`. `-s` re-runs one command after replacing every `old` in it
+    /// with `new`. Commands are picked by their number, by a negative offset from the previous
+    /// command or by the text they start with, and the `fc` command itself is replaced in the
+    /// history by the commands it runs.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `fc`.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the last command run, 0 once commands are listed, or 1 if an option is
+    /// invalid, no command matches, the editor fails or the shell isn't keeping a history.
+    pub fn fc(&mut self, args: &[CString]) -> i32 {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut editor_cmd = None;
+        let mut list = false;
+        let mut numbered = true;
+        let mut reverse = false;
+        let mut substitute = false;
+        let mut i = 0;
+        // Negative numbers are offsets into the history rather than options
+        while let Some(flags) = args
+            .get(i)
+            .filter(|arg| arg.parse::<i64>().is_err())
+            .and_then(|arg| arg.strip_prefix('-'))
+            .filter(|flags| !flags.is_empty())
+        {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for (pos, flag) in flags.char_indices() {
+                match flag {
+                    'e' => {
+                        let rest = &flags[pos + 1..];
+                        editor_cmd = if rest.is_empty() {
+                            i += 1;
+                            args.get(i - 1).cloned()
+                        } else {
+                            Some(rest.to_string())
+                        };
+                        if editor_cmd.is_none() {
+                            eprintln!("fc: -e: option requires an argument");
+                            return 1;
+                        }
+                        break;
+                    }
+                    'l' => list = true,
+                    'n' => numbered = false,
+                    'r' => reverse = true,
+                    's' => substitute = true,
+                    _ => {
+                        eprintln!("fc: -{}: invalid option", flag);
+                        return 1;
+                    }
+                }
+            }
+        }
+        let mut operands = &args[i..];
+
+        let Some(editor) = self.editor.as_ref() else {
+            eprintln!("fc: no command history");
+            return 1;
+        };
+        let mut entries: Vec<String> = editor.history().iter().cloned().collect();
+        // The last entry is this `fc` command, which isn't one it can pick
+        entries.pop();
+        if entries.is_empty() {
+            eprintln!("fc: no command history");
+            return 1;
+        }
+
+        if list {
+            let last_index = entries.len() - 1;
+            let range = match operands {
+                [] => Ok((entries.len().saturating_sub(FC_LIST_COUNT), last_index)),
+                [first] => fc_index(&entries, first).map(|first| (first, last_index)),
+                [first, last, ..] => fc_index(&entries, first)
+                    .and_then(|first| Ok((first, fc_index(&entries, last)?))),
+            };
+            let (first, last) = match range {
+                Ok(range) => range,
+                Err(err) => {
+                    eprintln!("fc: {}", err);
+                    return 1;
+                }
+            };
+
+            let mut indices: Vec<usize> = (first.min(last)..=first.max(last)).collect();
+            if reverse != (first > last) {
+                indices.reverse();
+            }
+            let mut out = String::new();
+            for index in indices {
+                if numbered {
+                    out.push_str(&format!("{:5}  {}\n", index + 1, entries[index]));
+                } else {
+                    out.push_str(&format!("\t{}\n", entries[index]));
+                }
+            }
+            let mut stdout = std::io::stdout();
+            if let Err(err) = stdout
+                .write_all(out.as_bytes())
+                .and_then(|_| stdout.flush())
+            {
+                eprintln!("fc: write error: {}", err);
+                return 1;
+            }
+            return 0;
+        }
+
+        let mut replacement = None;
+        if substitute {
+            if let Some((old, new)) = operands.first().and_then(|arg| arg.split_once('=')) {
+                replacement = Some((old.to_string(), new.to_string()));
+                operands = &operands[1..];
+            }
+            editor_cmd = Some(String::from("-"));
+        }
+        let first = match operands.first().map(|first| fc_index(&entries, first)) {
+            Some(Ok(first)) => first,
+            None => entries.len() - 1,
+            Some(Err(err)) => {
+                eprintln!("fc: {}", err);
+                return 1;
+            }
+        };
+        let last = match operands.get(1).filter(|_| !substitute) {
+            Some(last) => match fc_index(&entries, last) {
+                Ok(last) => last,
+                Err(err) => {
+                    eprintln!("fc: {}", err);
+                    return 1;
+                }
+            },
+            None => first,
+        };
+        let mut commands: Vec<&str> = entries[first.min(last)..=first.max(last)]
+            .iter()
+            .map(String::as_str)
+            .collect();
+        if reverse != (first > last) {
+            commands.reverse();
+        }
+        let mut line = commands.join("\n");
+        if let Some((old, new)) = replacement.filter(|(old, _)| !old.is_empty()) {
+            line = line.replace(&old, &new);
+        }
+
+        let editor_cmd = editor_cmd
+            .or_else(|| self.get_var("FCEDIT").filter(|cmd| !cmd.is_empty()))
+            .or_else(|| self.get_var("EDITOR").filter(|cmd| !cmd.is_empty()))
+            .unwrap_or_else(|| String::from("vi"));
+        if editor_cmd != "-" {
+            let path = std::env::temp_dir().join(format!("simple-shell-fc-{}", std::process::id()));
+            if let Err(err) = std::fs::write(&path, format!("{}\n", line)) {
+                eprintln!("fc: {}: {}", path.display(), err);
+                return 1;
+            }
+            // The editor is run as a command so that it can be given its own arguments
+            let status = self.eval(&[CString::new(format!(
+                "{} {}",
+                editor_cmd,
+                Shell::quote(&path.to_string_lossy())
+            ))
+            .unwrap()]);
+            let edited = std::fs::read_to_string(&path);
+            let _ = std::fs::remove_file(&path);
+            if status != 0 {
+                return status;
+            }
+            line = match edited {
+                Ok(edited) => edited.trim_end_matches('\n').to_string(),
+                Err(err) => {
+                    eprintln!("fc: {}: {}", path.display(), err);
+                    return 1;
+                }
+            };
+        }
+        if line.trim().is_empty() {
+            return 0;
+        }
+
+        // Commands are printed before being run, and take the place of `fc` in the history
+        let mut stdout = std::io::stdout();
+        let _ = stdout
+            .write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| stdout.flush());
+        if let Some(editor) = self.editor.as_mut() {
+            entries.push(line.clone());
+            if let Err(err) = rebuild_history(editor, entries) {
+                eprintln!("fc: {}", err);
+            }
+        }
+        match self.parse(&line) {
+            Ok(list) => self.execute_list(&list),
+            Err(err) => {
+                eprintln!("fc: {}", err);
+                2
+            }
+        }
+    }
+}
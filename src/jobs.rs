@@ -0,0 +1,1139 @@
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use libc::{
+    c_int, kill, pid_t, raise, sigaction, sigaddset, sigdelset, sigemptyset, sighandler_t,
+    siginfo_t, signal, sigprocmask, sigset_t, sigsuspend, strsignal, tcgetattr, tcsetattr,
+    tcsetpgrp, termios, waitid, waitpid, P_ALL, SA_RESTART, SIGCHLD, SIGCONT, SIGHUP, SIGINT,
+    SIGTERM, SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, TCSADRAIN, WCONTINUED, WEXITED,
+    WIFCONTINUED, WIFSIGNALED, WIFSTOPPED, WNOHANG, WNOWAIT, WSTOPSIG, WTERMSIG, WUNTRACED,
+};
+
+use crate::shell::Shell;
+use crate::trap::{parse_signal, signal_name, EXIT, SIGNALS};
+
+/// How many processes of jobs the SIGCHLD handler can keep track of at once. Any more are still
+/// reaped, but only when the shell next checks on its jobs.
+const WATCHED_PROCESSES: usize = 64;
+
+/// What `REAPED_STATUSES` holds while there is no status waiting to be taken, which no real
+/// status can be.
+const NO_STATUS: c_int = -1;
+
+/// The processes of jobs that the SIGCHLD handler reaps, with 0 marking a free slot. The
+/// handler can't reach the `Shell`, so it leaves each status it collects in the same slot of
+/// `REAPED_STATUSES` for `Shell::reap_jobs` to record in the job table.
+static WATCHED_PIDS: [AtomicI32; WATCHED_PROCESSES] =
+    [const { AtomicI32::new(0) }; WATCHED_PROCESSES];
+
+/// The status the SIGCHLD handler collected for each process in `WATCHED_PIDS` that the shell
+/// hasn't taken yet.
+static REAPED_STATUSES: [AtomicI32; WATCHED_PROCESSES] =
+    [const { AtomicI32::new(NO_STATUS) }; WATCHED_PROCESSES];
+
+/// How many jobs the SIGHUP handler can hang up as whole process groups.
+const RECORDED_JOBS: usize = 256;
+
+/// The process groups of the jobs in the job table that haven't finished, with 0 marking a free
+/// slot, kept up to date by `Shell::record_job_groups` for the SIGHUP handler.
+static JOB_GROUPS: [AtomicI32; RECORDED_JOBS] = [const { AtomicI32::new(0) }; RECORDED_JOBS];
+
+/// Whether each job in `JOB_GROUPS` is stopped, so that the SIGHUP handler continues it.
+static STOPPED_GROUPS: [AtomicBool; RECORDED_JOBS] =
+    [const { AtomicBool::new(false) }; RECORDED_JOBS];
+
+/// Whether the jobs have their own process groups. Without job control they share the shell's,
+/// so the SIGHUP handler signals their processes one at a time instead.
+static JOB_CONTROL: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGCHLD by collecting the status of every watched process that has stopped,
+/// continued or finished, so that background jobs are reaped as soon as they finish rather
+/// than the next time a command is run. A process whose last status hasn't been taken yet is
+/// left alone so that none are lost.
+pub extern "C" fn handle_sigchld(_: c_int) {
+    for (pid, reaped) in WATCHED_PIDS.iter().zip(&REAPED_STATUSES) {
+        let pid = pid.load(Ordering::SeqCst);
+        if pid == 0 || reaped.load(Ordering::SeqCst) != NO_STATUS {
+            continue;
+        }
+        let mut status: c_int = c_int::default();
+        if unsafe { waitpid(pid, &mut status, WNOHANG | WUNTRACED | WCONTINUED) } > 0 {
+            reaped.store(status, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Handle SIGHUP in an interactive shell, which arrives when its terminal goes away, by passing
+/// it on to every job, continuing the stopped ones so that they see it, and then letting it end
+/// the shell.
+pub extern "C" fn handle_sighup(hangup: c_int) {
+    if JOB_CONTROL.load(Ordering::SeqCst) {
+        for (group, stopped) in JOB_GROUPS.iter().zip(&STOPPED_GROUPS) {
+            let pgid = group.load(Ordering::SeqCst);
+            if pgid != 0 {
+                unsafe {
+                    kill(-pgid, SIGHUP);
+                    if stopped.load(Ordering::SeqCst) {
+                        kill(-pgid, SIGCONT);
+                    }
+                }
+            }
+        }
+    } else {
+        for pid in &WATCHED_PIDS {
+            let pid = pid.load(Ordering::SeqCst);
+            if pid != 0 {
+                unsafe {
+                    kill(pid, SIGHUP);
+                    kill(pid, SIGCONT);
+                }
+            }
+        }
+    }
+    unsafe {
+        signal(hangup, SIG_DFL);
+        raise(hangup);
+    }
+}
+
+/// Have the SIGCHLD handler reap a process of a job, if it has a free slot.
+fn watch(pid: pid_t) {
+    if let Some((slot, reaped)) = WATCHED_PIDS
+        .iter()
+        .zip(&REAPED_STATUSES)
+        .find(|(slot, _)| slot.load(Ordering::SeqCst) == 0)
+    {
+        // The handler skips free slots, so the status can be cleared before the pid is set
+        reaped.store(NO_STATUS, Ordering::SeqCst);
+        slot.store(pid, Ordering::SeqCst);
+    }
+}
+
+/// Stop the SIGCHLD handler from reaping a process, freeing its slot.
+fn unwatch(pid: pid_t) {
+    for slot in &WATCHED_PIDS {
+        let _ = slot.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+/// Take the status the SIGCHLD handler collected for a process, if it has one waiting. The
+/// process stops being watched once it has finished.
+fn take_reaped(pid: pid_t) -> Option<c_int> {
+    let (slot, reaped) = WATCHED_PIDS
+        .iter()
+        .zip(&REAPED_STATUSES)
+        .find(|(slot, _)| slot.load(Ordering::SeqCst) == pid)?;
+    let status = reaped.swap(NO_STATUS, Ordering::SeqCst);
+    if status == NO_STATUS {
+        return None;
+    }
+    if !WIFSTOPPED(status) && !WIFCONTINUED(status) {
+        slot.store(0, Ordering::SeqCst);
+    }
+    Some(status)
+}
+
+/// Keeps SIGCHLD blocked while it is alive, so that the handler can't reap a process the shell
+/// is about to wait for itself.
+struct SigchldBlocked(sigset_t);
+
+impl SigchldBlocked {
+    fn new() -> SigchldBlocked {
+        unsafe {
+            let mut mask: sigset_t = std::mem::zeroed();
+            let mut old: sigset_t = std::mem::zeroed();
+            sigemptyset(&mut mask);
+            sigaddset(&mut mask, SIGCHLD);
+            sigprocmask(SIG_BLOCK, &mask, &mut old);
+            SigchldBlocked(old)
+        }
+    }
+}
+
+impl SigchldBlocked {
+    /// Wait until a signal is handled, with SIGCHLD unblocked only for the wait.
+    fn suspend(&self) {
+        unsafe {
+            let mut mask = self.0;
+            sigdelset(&mut mask, SIGCHLD);
+            sigsuspend(&mask);
+        }
+    }
+}
+
+impl Drop for SigchldBlocked {
+    fn drop(&mut self) {
+        unsafe { sigprocmask(SIG_SETMASK, &self.0, std::ptr::null_mut()) };
+    }
+}
+
+/// Catches SIGCHLD with `handle_sigchld` while it is alive, if the shell wasn't already catching
+/// it, so that `SigchldBlocked::suspend` wakes up when a child changes. Only an interactive shell
+/// or a `CHLD` trap catches it otherwise.
+struct SigchldCaught(Option<sigaction>);
+
+impl SigchldCaught {
+    fn new() -> SigchldCaught {
+        unsafe {
+            let mut old: sigaction = std::mem::zeroed();
+            sigaction(SIGCHLD, std::ptr::null(), &mut old);
+            if old.sa_sigaction != SIG_DFL && old.sa_sigaction != SIG_IGN {
+                return SigchldCaught(None);
+            }
+            let mut action: sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_sigchld as extern "C" fn(c_int) as sighandler_t;
+            action.sa_flags = SA_RESTART;
+            sigemptyset(&mut action.sa_mask);
+            sigaction(SIGCHLD, &action, &mut old);
+            SigchldCaught(Some(old))
+        }
+    }
+}
+
+impl Drop for SigchldCaught {
+    fn drop(&mut self) {
+        if let Some(old) = &self.0 {
+            unsafe { sigaction(SIGCHLD, old, std::ptr::null_mut()) };
+        }
+    }
+}
+
+/// What a job, or one of its processes, was last seen doing.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JobState {
+    Running,
+    /// Stopped by a signal such as `SIGSTOP` until it is continued.
+    Stopped,
+    /// Finished with the given exit status.
+    Done(i32),
+}
+
+/// One of the processes running a job, such as a command of a pipeline.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Process {
+    pub pid: pid_t,
+    pub state: JobState,
+    /// The signal that killed the process, if one did.
+    pub signal: Option<c_int>,
+}
+
+/// How waiting for a job ended.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct JobStatus {
+    /// The exit status of the job once it has finished.
+    pub status: i32,
+    /// The signal that stopped one of the job's processes, if one did.
+    pub stopped: Option<c_int>,
+    /// Whether a process of the job was killed by Ctrl-C.
+    pub interrupted: bool,
+}
+
+/// A job in the job table: the processes started for a command line, which are listed,
+/// continued, signalled and waited for together.
+#[derive(Clone)]
+pub struct Job {
+    /// The number used to refer to the job, as in `[1]`.
+    pub id: usize,
+    /// The process group of the job, which is the process ID of its first process. Without job
+    /// control the processes stay in the shell's own group and this is only that first process.
+    pub pgid: pid_t,
+    /// The processes of the job in the order they were started.
+    pub processes: Vec<Process>,
+    /// The command line the job is running.
+    pub command: String,
+    /// The state the job was last reported in, so that each change is only reported once.
+    pub reported: JobState,
+    /// The terminal modes the job left the terminal in when it was last stopped in the
+    /// foreground, which are put back when it is continued there, e.g. the raw mode of an
+    /// editor.
+    pub tmodes: Option<termios>,
+}
+
+impl Job {
+    /// Create a job whose processes have all just been started.
+    pub fn new(id: usize, pgid: pid_t, pids: &[pid_t], command: String) -> Job {
+        Job {
+            id,
+            pgid,
+            processes: pids
+                .iter()
+                .map(|&pid| Process {
+                    pid,
+                    state: JobState::Running,
+                    signal: None,
+                })
+                .collect(),
+            command,
+            reported: JobState::Running,
+            tmodes: None,
+        }
+    }
+
+    /// Get the state of the job as a whole. It is done once every process has finished, with the
+    /// status of the last one, and stopped while any of the others is stopped.
+    pub fn state(&self) -> JobState {
+        if self
+            .processes
+            .iter()
+            .all(|process| matches!(process.state, JobState::Done(_)))
+        {
+            let status = match self.processes.last().map(|process| process.state) {
+                Some(JobState::Done(status)) => status,
+                _ => 0,
+            };
+            JobState::Done(status)
+        } else if self
+            .processes
+            .iter()
+            .any(|process| process.state == JobState::Stopped)
+        {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        }
+    }
+
+    /// Record a status reported by `waitpid` for one of the job's processes.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if the process is one of the job's.
+    pub fn record_status(&mut self, pid: pid_t, status: c_int) -> bool {
+        let Some(process) = self.processes.iter_mut().find(|process| process.pid == pid) else {
+            return false;
+        };
+        process.state = if WIFSTOPPED(status) {
+            JobState::Stopped
+        } else if WIFCONTINUED(status) {
+            JobState::Running
+        } else {
+            process.signal = WIFSIGNALED(status).then(|| WTERMSIG(status));
+            JobState::Done(Shell::decode_status(status))
+        };
+        true
+    }
+
+    /// Get the signal that killed the last process of the job, which is what its status comes
+    /// from once it is done.
+    pub fn signal(&self) -> Option<c_int> {
+        self.processes.last().and_then(|process| process.signal)
+    }
+
+    /// Mark the processes that were stopped as running again once they have been continued.
+    pub fn continued(&mut self) {
+        for process in &mut self.processes {
+            if process.state == JobState::Stopped {
+                process.state = JobState::Running;
+            }
+        }
+    }
+}
+
+impl Shell {
+    /// Add a job to the job table, numbered one past the highest job number in use.
+    ///
+    /// ## Returns
+    ///
+    /// The number of the new job.
+    pub fn add_job(&mut self, pgid: pid_t, pids: &[pid_t], command: String) -> usize {
+        let id = self.next_job_id();
+        for &pid in pids {
+            watch(pid);
+        }
+        self.jobs.push(Job::new(id, pgid, pids, command));
+        self.record_job_groups();
+        id
+    }
+
+    /// Record the process group of each job in the job table that hasn't finished, and whether
+    /// it is stopped, for the SIGHUP handler. This is done whenever jobs are added, removed or
+    /// change state.
+    fn record_job_groups(&self) {
+        JOB_CONTROL.store(self.options.monitor, Ordering::SeqCst);
+        let mut jobs = self
+            .jobs
+            .iter()
+            .filter(|job| !matches!(job.state(), JobState::Done(_)));
+        for (group, stopped) in JOB_GROUPS.iter().zip(&STOPPED_GROUPS) {
+            match jobs.next() {
+                Some(job) => {
+                    // The handler only reads the group once it is set, so the flag goes first
+                    stopped.store(job.state() == JobState::Stopped, Ordering::SeqCst);
+                    group.store(job.pgid, Ordering::SeqCst);
+                }
+                None => group.store(0, Ordering::SeqCst),
+            }
+        }
+    }
+
+    /// Get the number a new job is given, one past the highest job number in use.
+    fn next_job_id(&self) -> usize {
+        self.jobs.iter().map(|job| job.id).max().unwrap_or_default() + 1
+    }
+
+    /// Put a job that was stopped while in the foreground in the job table, numbering it if it
+    /// wasn't there before, and report it as `[1]+  Stopped  command`.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of a command stopped by `signal`, which is 128 plus its number.
+    pub fn stop_job(&mut self, mut job: Job, signal: c_int) -> i32 {
+        if job.id == 0 {
+            job.id = self.next_job_id();
+            for process in &job.processes {
+                if !matches!(process.state, JobState::Done(_)) {
+                    watch(process.pid);
+                }
+            }
+        }
+        eprintln!("\n[{}]+  Stopped                 {}", job.id, job.command);
+        job.reported = JobState::Stopped;
+        self.jobs.push(job);
+        self.record_job_groups();
+        128 + signal
+    }
+
+    /// Forget every job, as a forked child does since the jobs aren't its children.
+    pub fn clear_jobs(&mut self) {
+        for job in std::mem::take(&mut self.jobs) {
+            for process in job.processes {
+                unwatch(process.pid);
+            }
+        }
+        self.record_job_groups();
+    }
+
+    /// Update the states of jobs whose processes have stopped, continued or finished without
+    /// waiting for the rest, recording the statuses the SIGCHLD handler collected and
+    /// collecting any others so finished processes don't linger as zombies.
+    pub fn reap_jobs(&mut self) {
+        let _blocked = SigchldBlocked::new();
+        for job in &mut self.jobs {
+            for i in 0..job.processes.len() {
+                let pid = job.processes[i].pid;
+                while !matches!(job.processes[i].state, JobState::Done(_)) {
+                    let mut status: c_int = c_int::default();
+                    if let Some(reaped) = take_reaped(pid) {
+                        status = reaped;
+                    } else if unsafe { waitpid(pid, &mut status, WNOHANG | WUNTRACED | WCONTINUED) }
+                        <= 0
+                    {
+                        break;
+                    }
+                    job.record_status(pid, status);
+                }
+                if matches!(job.processes[i].state, JobState::Done(_)) {
+                    unwatch(pid);
+                }
+            }
+        }
+        self.record_job_groups();
+    }
+
+    /// Give the terminal to a job in the foreground, with the terminal modes it had when it was
+    /// stopped if it is being continued. Only a shell with job control hands the terminal over.
+    pub fn give_terminal(&self, job: &Job) {
+        if !self.options.monitor {
+            return;
+        }
+        unsafe {
+            tcsetpgrp(self.shell_terminal, job.pgid);
+            if let Some(tmodes) = &job.tmodes {
+                tcsetattr(self.shell_terminal, TCSADRAIN, tmodes);
+            }
+        }
+    }
+
+    /// Take the terminal back from a foreground job once waiting for it has ended, saving the
+    /// job's terminal modes if it stopped and putting back the shell's own, which the job may
+    /// have changed.
+    pub fn take_terminal(&self, job: &mut Job, result: &std::io::Result<JobStatus>) {
+        if !self.options.monitor {
+            return;
+        }
+        unsafe {
+            if matches!(
+                result,
+                Ok(JobStatus {
+                    stopped: Some(_),
+                    ..
+                })
+            ) {
+                let mut tmodes: termios = std::mem::zeroed();
+                job.tmodes = (tcgetattr(self.shell_terminal, &mut tmodes) == 0).then_some(tmodes);
+            }
+            tcsetpgrp(self.shell_terminal, self.shell_pgid);
+            if let Some(tmodes) = &self.shell_tmodes {
+                tcsetattr(self.shell_terminal, TCSADRAIN, tmodes);
+            }
+        }
+    }
+
+    /// Send a signal to a job, to its whole process group when it has its own.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if the signal was sent.
+    fn signal_job(&self, job: &Job, signal: c_int) -> bool {
+        if self.options.monitor {
+            unsafe { kill(-job.pgid, signal) == 0 }
+        } else {
+            // Every process is signalled even if one of them can't be
+            let mut sent = true;
+            for process in &job.processes {
+                if !matches!(process.state, JobState::Done(_)) {
+                    sent &= unsafe { kill(process.pid, signal) } == 0;
+                }
+            }
+            sent
+        }
+    }
+
+    /// The `jobs` builtin, as in `jobs [-l | -p] [%job...]`, which lists the background jobs
+    /// with their numbers, states and command lines. `-l` adds the process ID of each job and
+    /// `-p` prints only the process IDs. The current job is marked with `+` and the one before it
+    /// with `-`. Jobs that have finished are listed once and then forgotten.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `jobs`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the jobs were listed.
+    /// - `Err(String)` if an option is invalid or a job spec doesn't match a job.
+    pub fn jobs(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut long = false;
+        let mut pids_only = false;
+        let mut i = 0;
+        while let Some(flags) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+            if flags == "-" {
+                i += 1;
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'l' => long = true,
+                    'p' => pids_only = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+            i += 1;
+        }
+
+        self.reap_jobs();
+        let selected: Vec<usize> = if i == args.len() {
+            (0..self.jobs.len()).collect()
+        } else {
+            args[i..]
+                .iter()
+                .map(|spec| self.find_job(spec))
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut out = String::new();
+        for &index in &selected {
+            if pids_only {
+                out.push_str(&format!("{}\n", self.jobs[index].pgid));
+            } else {
+                out.push_str(&self.format_job(index, long));
+            }
+            self.jobs[index].reported = self.jobs[index].state();
+        }
+
+        // Finished jobs have now been reported
+        let mut index = 0;
+        self.jobs.retain(|job| {
+            let reported = selected.contains(&index) && matches!(job.state(), JobState::Done(_));
+            index += 1;
+            !reported
+        });
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// Format a job the way `jobs` lists it, as in `[1]+  Running  command &`, with its process
+    /// group after the number if `long`.
+    fn format_job(&self, index: usize, long: bool) -> String {
+        let job = &self.jobs[index];
+        let pid = if long {
+            job.pgid.to_string()
+        } else {
+            String::new()
+        };
+        let (state, suffix) = match (job.state(), job.signal()) {
+            (JobState::Running, _) => (String::from("Running"), " &"),
+            (JobState::Stopped, _) => (String::from("Stopped"), ""),
+            // A job killed by a signal is described by it, as in `Terminated`
+            (JobState::Done(_), Some(signal)) => {
+                let description = unsafe { CStr::from_ptr(strsignal(signal)) };
+                (description.to_string_lossy().into_owned(), "")
+            }
+            (JobState::Done(0), None) => (String::from("Done"), ""),
+            (JobState::Done(status), None) => (format!("Exit {}", status), ""),
+        };
+        format!(
+            "[{}]{} {} {:<24}{}{}\n",
+            job.id,
+            self.job_marker(index),
+            pid,
+            state,
+            job.command,
+            suffix
+        )
+    }
+
+    /// Send `SIGHUP` to the jobs left in the job table as an interactive shell exits, unless
+    /// `huponexit` is turned off, continuing stopped jobs so that they see it. Disowned jobs
+    /// aren't in the table, so they keep running.
+    pub fn hangup_jobs(&mut self) {
+        if self.editor.is_none() || !self.options.huponexit {
+            return;
+        }
+        self.reap_jobs();
+        for job in &self.jobs {
+            match job.state() {
+                JobState::Running => {
+                    self.signal_job(job, SIGHUP);
+                }
+                JobState::Stopped => {
+                    self.signal_job(job, SIGHUP);
+                    self.signal_job(job, SIGCONT);
+                }
+                JobState::Done(_) => (),
+            }
+        }
+    }
+
+    /// Report the jobs that have stopped or finished since they were last reported, which an
+    /// interactive shell does before showing each prompt. Finished jobs are then forgotten.
+    pub fn notify_jobs(&mut self) {
+        self.reap_jobs();
+        let mut out = String::new();
+        for index in 0..self.jobs.len() {
+            let state = self.jobs[index].state();
+            if state != self.jobs[index].reported && state != JobState::Running {
+                out.push_str(&self.format_job(index, false));
+            }
+            self.jobs[index].reported = state;
+        }
+        self.jobs
+            .retain(|job| !matches!(job.reported, JobState::Done(_)));
+
+        let _ = std::io::stderr().write_all(out.as_bytes());
+    }
+
+    /// The `fg` builtin, as in `fg [%job]`, which continues a background or stopped job in the
+    /// foreground, handing it the terminal and waiting until it finishes or stops again. Without
+    /// a job spec the current job is used.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `fg`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(i32)` The exit status of the job, or 128 plus the signal that stopped it.
+    /// - `Err(String)` if the job spec doesn't match a job.
+    pub fn fg(&mut self, args: &[CString]) -> Result<i32, String> {
+        self.reap_jobs();
+        let index = self.job_argument(args)?;
+        let mut job = self.jobs.remove(index);
+        let mut stdout = std::io::stdout();
+        let _ = writeln!(stdout, "{}", job.command).and_then(|_| stdout.flush());
+
+        // A job that already finished was collected by the reaping above
+        if let JobState::Done(status) = job.state() {
+            return Ok(status);
+        }
+
+        self.give_terminal(&job);
+        self.signal_job(&job, SIGCONT);
+        job.continued();
+        let result = self.wait_job(&mut job, true);
+        self.take_terminal(&mut job, &result);
+        let status = result.map_err(|err| format!("%{}: {}", job.id, err))?;
+
+        if let Some(signal) = status.stopped {
+            return Ok(self.stop_job(job, signal));
+        }
+        if status.interrupted {
+            self.interrupted = true;
+        }
+
+        Ok(status.status)
+    }
+
+    /// The `bg` builtin, as in `bg [%job...]`, which continues stopped jobs in the background.
+    /// Without a job spec the current job is used.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `bg`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the jobs are running.
+    /// - `Err(String)` if a job spec doesn't match a job or the job has already finished.
+    pub fn bg(&mut self, args: &[CString]) -> Result<(), String> {
+        self.reap_jobs();
+        let indices = match args {
+            [] => vec![self.job_argument(args)?],
+            specs => specs
+                .iter()
+                .map(|spec| self.find_job(&spec.to_string_lossy()))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let mut out = String::new();
+        for index in indices {
+            let marker = self.job_marker(index);
+            let job = &self.jobs[index];
+            match job.state() {
+                JobState::Running => {
+                    eprintln!("bg: job {} already in background", job.id);
+                    continue;
+                }
+                JobState::Done(_) => return Err(format!("job {} has terminated", job.id)),
+                JobState::Stopped => (),
+            }
+            self.signal_job(job, SIGCONT);
+            let job = &mut self.jobs[index];
+            job.continued();
+            job.reported = JobState::Running;
+            out.push_str(&format!("[{}]{} {} &\n", job.id, marker, job.command));
+        }
+        self.record_job_groups();
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// The `disown` builtin, as in `disown [-a] [%job...]`, which removes jobs from the job
+    /// table so they are no longer listed, waited for or signalled when the shell exits. `-a`
+    /// removes every job and without either the current job is removed.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `disown`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the jobs were removed.
+    /// - `Err(String)` if an option is invalid or a job spec doesn't match a job.
+    pub fn disown(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let (all, specs) = match args.first().map(String::as_str) {
+            Some("-a") => (true, &args[1..]),
+            Some("--") => (false, &args[1..]),
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("{}: invalid option", arg))
+            }
+            _ => (false, &args[..]),
+        };
+
+        if all {
+            self.clear_jobs();
+            return Ok(());
+        }
+        let ids = match specs {
+            [] => vec![self.job_argument(&[])?],
+            specs => specs
+                .iter()
+                .map(|spec| self.find_job(spec))
+                .collect::<Result<_, _>>()?,
+        }
+        .into_iter()
+        .map(|index| self.jobs[index].id)
+        .collect::<Vec<_>>();
+        self.jobs.retain(|job| {
+            let disowned = ids.contains(&job.id);
+            if disowned {
+                for process in &job.processes {
+                    unwatch(process.pid);
+                }
+            }
+            !disowned
+        });
+        self.record_job_groups();
+
+        Ok(())
+    }
+
+    /// The `wait` builtin, as in `wait [-n] [pid | %job...]`, which waits for the given
+    /// background jobs to finish, or for all of them if none are given. With `-n` it only waits
+    /// until the first of them finishes. Jobs waited for are removed from the job table.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `wait`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(i32)` The exit status of the last job waited for, 127 if it isn't a job of this
+    ///   shell or there are no jobs for `-n` to wait for, or 0 if all jobs were waited for.
+    /// - `Err(String)` if an argument is neither a process ID nor a job spec.
+    pub fn wait(&mut self, args: &[CString]) -> Result<i32, String> {
+        if let Some(args) = args.strip_prefix(&[CString::new("-n").unwrap()]) {
+            let mut ids = Vec::new();
+            for arg in args {
+                match self.wait_target(&arg.to_string_lossy()) {
+                    Ok(index) => ids.push(self.jobs[index].id),
+                    Err(err) => eprintln!("wait: {}", err),
+                }
+            }
+            if !args.is_empty() && ids.is_empty() {
+                return Ok(127);
+            }
+            return Ok(self.wait_next(&ids));
+        }
+
+        if args.is_empty() {
+            for mut job in std::mem::take(&mut self.jobs) {
+                let _ = self.wait_job(&mut job, false);
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for arg in args {
+            status = match self.wait_target(&arg.to_string_lossy()) {
+                Ok(index) => {
+                    let mut job = self.jobs.remove(index);
+                    self.wait_job(&mut job, false)
+                        .map_or(127, |status| status.status)
+                }
+                Err(err) => {
+                    eprintln!("wait: {}", err);
+                    127
+                }
+            };
+        }
+
+        Ok(status)
+    }
+
+    /// Find the job a `wait` argument refers to, by job spec or by the process ID of any of its
+    /// processes.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(usize)` The index of the job in the job table.
+    /// - `Err(String)` if no job matches.
+    fn wait_target(&self, arg: &str) -> Result<usize, String> {
+        if arg.starts_with('%') {
+            return self.find_job(arg);
+        }
+        let pid: pid_t = arg
+            .parse()
+            .map_err(|_| format!("`{}': not a pid or valid job spec", arg))?;
+        self.jobs
+            .iter()
+            .position(|job| job.processes.iter().any(|process| process.pid == pid))
+            .ok_or_else(|| format!("pid {} is not a child of this shell", pid))
+    }
+
+    /// Wait until the first of the jobs numbered `ids`, or of all jobs if it is empty, finishes
+    /// and remove it from the job table. Other jobs that finish meanwhile are only recorded.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of the job that finished, or 127 if there are no such jobs.
+    fn wait_next(&mut self, ids: &[usize]) -> i32 {
+        let _caught = SigchldCaught::new();
+        let blocked = SigchldBlocked::new();
+        let wanted = |job: &Job| ids.is_empty() || ids.contains(&job.id);
+        loop {
+            self.reap_jobs();
+            let finished = self
+                .jobs
+                .iter()
+                .position(|job| wanted(job) && matches!(job.state(), JobState::Done(_)));
+            if let Some(index) = finished {
+                return match self.jobs.remove(index).state() {
+                    JobState::Done(status) => status,
+                    _ => 127,
+                };
+            }
+            if !self.jobs.iter().any(wanted) {
+                return 127;
+            }
+
+            // With no children left at all nothing can finish. Otherwise sleep until SIGCHLD
+            // arrives and the handler has collected what changed, which was held back while
+            // SIGCHLD was blocked if it came before this point.
+            let mut info: siginfo_t = unsafe { std::mem::zeroed() };
+            if unsafe { waitid(P_ALL, 0, &mut info, WEXITED | WNOHANG | WNOWAIT) } == -1 {
+                return 127;
+            }
+            blocked.suspend();
+        }
+    }
+
+    /// Wait for the processes of a job that haven't finished yet, recording what each of them
+    /// did. With `untraced` the wait also ends as soon as one of them stops.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(JobStatus)` How the job ended or stopped.
+    /// - `Err(std::io::Error)` if the processes couldn't be waited for.
+    pub fn wait_job(&mut self, job: &mut Job, untraced: bool) -> std::io::Result<JobStatus> {
+        let _blocked = SigchldBlocked::new();
+        let mut result = JobStatus::default();
+        let flags = if untraced { WUNTRACED } else { 0 };
+        for i in 0..job.processes.len() {
+            let pid = job.processes[i].pid;
+            while !matches!(job.processes[i].state, JobState::Done(_)) {
+                let mut status: c_int = c_int::default();
+                if let Some(reaped) = take_reaped(pid) {
+                    status = reaped;
+                } else if unsafe { waitpid(pid, &mut status, flags) } == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                job.record_status(pid, status);
+                if matches!(job.processes[i].state, JobState::Done(_)) {
+                    unwatch(pid);
+                }
+                if WIFSTOPPED(status) {
+                    result.stopped = Some(WSTOPSIG(status));
+                    return Ok(result);
+                }
+                if WIFSIGNALED(status) && WTERMSIG(status) == SIGINT {
+                    result.interrupted = true;
+                }
+            }
+        }
+
+        if let JobState::Done(status) = job.state() {
+            result.status = status;
+        }
+        Ok(result)
+    }
+
+    /// The `kill` builtin, as in `kill [-s signal | -signal] pid | %job...`, which sends a
+    /// signal, `TERM` by default, to processes or to the process groups of jobs. Signals may be
+    /// given by name, with or without `SIG`, or by number. `kill -l` lists the signal names and
+    /// `kill -l status` names the signal that a status such as 143 came from.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `kill`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(true)` if the signal was sent to every target.
+    /// - `Ok(false)` if it couldn't be sent to some of them.
+    /// - `Err(String)` if the signal or a target isn't valid.
+    pub fn kill(&mut self, args: &[CString]) -> Result<bool, String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let invalid_signal = |name: &str| format!("{}: invalid signal specification", name);
+
+        let (signal, targets) = match args.first().map(String::as_str) {
+            Some("-l" | "-L") => return Shell::list_signals(&args[1..]).map(|_| true),
+            Some("-s" | "-n") => {
+                let name = args
+                    .get(1)
+                    .ok_or_else(|| format!("{}: option requires an argument", args[0]))?;
+                (
+                    parse_signal(name).ok_or_else(|| invalid_signal(name))?,
+                    &args[2..],
+                )
+            }
+            Some("--") => (SIGTERM, &args[1..]),
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => (
+                parse_signal(&arg[1..]).ok_or_else(|| invalid_signal(&arg[1..]))?,
+                &args[1..],
+            ),
+            _ => (SIGTERM, &args[..]),
+        };
+        if targets.is_empty() {
+            return Err(String::from(
+                "usage: kill [-s signal | -signal] pid | %job...",
+            ));
+        }
+
+        let mut sent_all = true;
+        for target in targets {
+            // A job's whole process group is signalled when it has its own
+            let pid = if target.starts_with('%') {
+                let job = &self.jobs[self.find_job(target)?];
+                if self.options.monitor {
+                    -job.pgid
+                } else {
+                    job.pgid
+                }
+            } else {
+                target
+                    .parse::<pid_t>()
+                    .map_err(|_| format!("{}: arguments must be process or job IDs", target))?
+            };
+            if unsafe { kill(pid, signal) } == -1 {
+                eprintln!("kill: ({}) - {}", target, std::io::Error::last_os_error());
+                sent_all = false;
+            }
+        }
+
+        Ok(sent_all)
+    }
+
+    /// List the signal names for `kill -l`, or with arguments print the name of each signal
+    /// number or exit status and the number of each name.
+    fn list_signals(args: &[String]) -> Result<(), String> {
+        let mut out = String::new();
+        if args.is_empty() {
+            for (name, _) in SIGNALS {
+                out.push_str(name);
+                out.push('\n');
+            }
+        }
+        for arg in args {
+            match arg.parse::<c_int>() {
+                // An exit status above 128 came from the signal it was killed by
+                Ok(status) if SIGNALS.iter().any(|(_, signal)| *signal == status % 128) => {
+                    out.push_str(&format!("{}\n", signal_name(status % 128)))
+                }
+                Ok(_) => return Err(format!("{}: invalid signal specification", arg)),
+                Err(_) => match parse_signal(arg) {
+                    Some(signal) if signal != EXIT => out.push_str(&format!("{}\n", signal)),
+                    _ => return Err(format!("{}: invalid signal specification", arg)),
+                },
+            }
+        }
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+
+    /// Find the job named by the first of a builtin's arguments, or the current job if there are
+    /// none.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(usize)` The index of the job in the job table.
+    /// - `Err(String)` if there is no such job.
+    fn job_argument(&self, args: &[CString]) -> Result<usize, String> {
+        match args.first() {
+            Some(spec) => self.find_job(&spec.to_string_lossy()),
+            None => self
+                .jobs
+                .len()
+                .checked_sub(1)
+                .ok_or_else(|| String::from("no current job")),
+        }
+    }
+
+    /// Find the background job a job spec refers to. `%%`, `%+` and `%` are the current job, the
+    /// one started most recently, `%-` is the one before it, `%n` is job number `n`, `%text` is
+    /// the job whose command starts with `text` and `%?text` the one whose command contains it.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(usize)` The index of the job in the job table.
+    /// - `Err(String)` if no job or more than one job matches.
+    pub fn find_job(&self, spec: &str) -> Result<usize, String> {
+        let no_such_job = || format!("{}: no such job", spec);
+        let jobs = &self.jobs;
+        let Some(name) = spec.strip_prefix('%') else {
+            return Err(no_such_job());
+        };
+
+        let matching: Vec<usize> = match name {
+            "" | "%" | "+" => return jobs.len().checked_sub(1).ok_or_else(no_such_job),
+            "-" => return jobs.len().checked_sub(2).ok_or_else(no_such_job),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                return jobs
+                    .iter()
+                    .position(|job| job.id.to_string() == name)
+                    .ok_or_else(no_such_job)
+            }
+            _ => match name.strip_prefix('?') {
+                Some(text) => (0..jobs.len())
+                    .filter(|&i| jobs[i].command.contains(text))
+                    .collect(),
+                None => (0..jobs.len())
+                    .filter(|&i| jobs[i].command.starts_with(name))
+                    .collect(),
+            },
+        };
+        match matching.as_slice() {
+            [index] => Ok(*index),
+            [] => Err(no_such_job()),
+            _ => Err(format!("{}: ambiguous job spec", spec)),
+        }
+    }
+
+    /// The mark shown after the number of the job at `index`: `+` for the current job, `-` for
+    /// the one before it and a space for the rest.
+    fn job_marker(&self, index: usize) -> char {
+        match self.jobs.len() - index {
+            1 => '+',
+            2 => '-',
+            _ => ' ',
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_state() {
+        let mut job = Job::new(1, 100, &[100, 101, 102], String::from("a | b | c"));
+        assert_eq!(JobState::Running, job.state());
+
+        job.processes[1].state = JobState::Stopped;
+        assert_eq!(JobState::Stopped, job.state());
+        job.continued();
+        assert_eq!(JobState::Running, job.processes[1].state);
+
+        job.processes[0].state = JobState::Done(1);
+        job.processes[1].state = JobState::Done(0);
+        assert_eq!(JobState::Running, job.state());
+        // The job's status is that of its last process
+        job.processes[2].state = JobState::Done(3);
+        assert_eq!(JobState::Done(3), job.state());
+        assert!(!job.record_status(200, 0));
+    }
+
+    #[test]
+    fn test_format_job() {
+        let mut shell = Shell::new();
+        for (id, status) in [(1, 0), (2, 3 << 8), (3, SIGTERM)] {
+            let mut job = Job::new(
+                id,
+                100 + id as pid_t,
+                &[100 + id as pid_t],
+                String::from("cmd"),
+            );
+            job.record_status(job.pgid, status);
+            shell.jobs.push(job);
+        }
+
+        assert!(shell.format_job(0, false).contains(" Done "));
+        assert!(shell.format_job(1, false).contains(" Exit 3 "));
+        assert!(shell.format_job(2, false).contains(" Terminated "));
+        assert_eq!(Some(SIGTERM), shell.jobs[2].signal());
+    }
+}
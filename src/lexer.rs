@@ -0,0 +1,806 @@
+//! Splitting of lines read from the user into the tokens the parser works with.
+
+use crate::shell::Shell;
+
+/// A token produced by splitting a line.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Token {
+    /// A word, kept verbatim with its quotes so the expansion pass can tell quoted text apart
+    /// from unquoted text.
+    Word(String),
+    /// The `;` command separator.
+    Semi,
+    /// The `;;` ending an arm of a `case` command.
+    DSemi,
+    /// An unquoted newline, which separates commands like `;`.
+    Newline,
+    /// The `&&` operator.
+    AndIf,
+    /// The `||` operator.
+    OrIf,
+    /// The `|` operator.
+    Pipe,
+    /// The `&` operator, which runs the AND-OR list before it in the background.
+    Amp,
+    /// The `(` starting a subshell.
+    LParen,
+    /// The `)` ending a subshell.
+    RParen,
+    /// A redirection operator, optionally preceded by the file descriptor it applies to.
+    Redirect(Option<i32>, RedirectOp),
+}
+
+/// The kinds of redirection operators.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RedirectOp {
+    /// `<`: read from a file.
+    Read,
+    /// `>`: write to a file, truncating it.
+    Write,
+    /// `>>`: append to a file.
+    Append,
+    /// `>|`: write to a file, truncating it.
+    Clobber,
+    /// `<>`: open a file for reading and writing.
+    ReadWrite,
+    /// `<&`: duplicate an input file descriptor.
+    DupIn,
+    /// `>&`: duplicate an output file descriptor.
+    DupOut,
+    /// `&>`: write both standard output and standard error to a file.
+    WriteBoth,
+    /// `&>>`: append both standard output and standard error to a file.
+    AppendBoth,
+    /// `<<` or `<<-`: read from the lines following the command, with expansions in them done.
+    HereDoc,
+    /// A `<<` whose delimiter was quoted, so its lines are read as they are.
+    HereDocLiteral,
+}
+
+impl RedirectOp {
+    /// The file descriptor the operator applies to when none is given.
+    pub fn default_fd(&self) -> i32 {
+        match self {
+            RedirectOp::Read
+            | RedirectOp::ReadWrite
+            | RedirectOp::DupIn
+            | RedirectOp::HereDoc
+            | RedirectOp::HereDocLiteral => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// A range of bytes in the line being parsed, used to point error messages at the text they are
+/// about.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    /// The offset of the first byte.
+    pub start: usize,
+    /// The offset just past the last byte.
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Find where the span starts in `source` for an error message.
+    pub fn position(&self, source: &str) -> Position {
+        let before = &source[..self.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// A line and column in the input, both counting from 1. Columns count characters rather than
+/// bytes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// What went wrong while splitting a line into tokens. Each of these means the input ended in the
+/// middle of something, so reading more input may fix it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LexErrorKind {
+    UnterminatedSingleQuote,
+    UnterminatedDoubleQuote,
+    /// A `$'...'` quote.
+    UnterminatedAnsiCQuote,
+    /// A `$(...)` command substitution.
+    UnterminatedSubstitution,
+    /// A `` `...` `` command substitution.
+    UnterminatedBackquote,
+    /// A `${...}` parameter expansion.
+    UnterminatedBrace,
+    /// A `name=(...)` array assignment.
+    UnterminatedArray,
+    /// A `<<` here-document without its delimiter line.
+    UnterminatedHereDoc,
+    /// A backslash at the very end of the input.
+    TrailingBackslash,
+}
+
+/// An error found while splitting a line into tokens, along with where the construct that caused
+/// it starts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub position: Position,
+}
+
+/// Reads text one character at a time, keeping track of the byte offset reached.
+pub struct Cursor<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(text: &'a str) -> Cursor<'a> {
+        Cursor { text, offset: 0 }
+    }
+
+    /// The byte offset of the next character.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Look at the next character without consuming it.
+    pub fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    /// Consume the next character if `f` returns `true` for it.
+    pub fn next_if<F: FnOnce(&char) -> bool>(&mut self, f: F) -> Option<char> {
+        let c = self.peek().filter(f)?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    /// Consume the next character if it is equal to `expected`.
+    pub fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        self.next_if(|c| c == expected)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        self.next_if(|_| true)
+    }
+}
+
+/// Copy the rest of a `$(...)` command substitution from `chars` to `word`, up to and including
+/// the `)` that closes it. Parentheses inside quotes don't count towards the nesting.
+///
+/// ## Parameter(s)
+///
+/// - `chars: &mut Cursor` The characters after the opening `$(`.
+/// - `word: &mut String` The word to add the characters to.
+///
+/// ## Returns
+///
+/// - `Ok(())` if the closing `)` was found.
+/// - `Err(LexErrorKind)` if the input ended first.
+pub fn read_substitution(chars: &mut Cursor, word: &mut String) -> Result<(), LexErrorKind> {
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        word.push(c);
+        match (c, quote) {
+            ('\\', Some('\'')) => (),
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+            }
+            (c, Some(open)) if c == open => quote = None,
+            (_, Some(_)) => (),
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            ('(', None) => depth += 1,
+            (')', None) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Err(LexErrorKind::UnterminatedSubstitution)
+}
+
+/// Check if `word` is the `name=` that starts an assignment.
+fn is_assignment_prefix(word: &str) -> bool {
+    word.strip_suffix('=').is_some_and(Shell::is_valid_name)
+}
+
+/// Copy the rest of a `` `...` `` command substitution from `chars` to `word`, up to and
+/// including the closing backquote.
+pub fn read_backquoted(chars: &mut Cursor, word: &mut String) -> Result<(), LexErrorKind> {
+    while let Some(c) = chars.next() {
+        word.push(c);
+        match c {
+            '`' => return Ok(()),
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Err(LexErrorKind::UnterminatedBackquote)
+}
+
+/// Read the lines of a here-document from `chars`, up to the line holding just its delimiter, and
+/// put them in place of the delimiter word at `index` in `tokens`. Quoting any part of the
+/// delimiter stops the lines from being expanded, and `strip` removes their leading tabs as
+/// `<<-` does.
+fn read_here_doc(
+    chars: &mut Cursor,
+    tokens: &mut [(Token, Span)],
+    index: usize,
+    strip: bool,
+) -> Result<(), LexErrorKind> {
+    // A `<<` without a word after it is left for the parser to report
+    let Some((Token::Word(word), _)) = tokens.get(index) else {
+        return Ok(());
+    };
+    let quoted = word.contains(['\'', '"', '\\']);
+    let delimiter: String = word
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+        .collect();
+
+    let mut body = String::new();
+    loop {
+        let mut line = String::new();
+        while let Some(c) = chars.next_if(|&c| c != '\n') {
+            line.push(c);
+        }
+        let ended = chars.next().is_none();
+        let line = if strip {
+            line.trim_start_matches('\t')
+        } else {
+            &line
+        };
+
+        if line == delimiter {
+            break;
+        }
+        if ended {
+            return Err(LexErrorKind::UnterminatedHereDoc);
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+
+    tokens[index].0 = Token::Word(body);
+    if let (true, Token::Redirect(_, op)) = (quoted, &mut tokens[index - 1].0) {
+        *op = RedirectOp::HereDocLiteral;
+    }
+
+    Ok(())
+}
+
+/// Split a line into words and operators. Words are separated by unquoted whitespace and
+/// operators. Quotes, `$'...'` quotes, backslash escapes, `${...}` and command substitutions are
+/// kept verbatim in the words.
+///
+/// ## Parameter(s)
+///
+/// - `line: &str` The line to split.
+///
+/// ## Returns
+///
+/// - `Ok(Vec<Token>)` The tokens of the line.
+/// - `Err(LexError)` if a quote, brace or substitution was left unterminated.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, LexError> {
+    Ok(tokenize_with_spans(line, false)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect())
+}
+
+/// Split a line into tokens like `tokenize`, along with the part of the line each token came
+/// from. In `posix` mode extensions such as `&>` aren't recognised, so `&>` is read as `&`
+/// followed by `>`.
+pub fn tokenize_with_spans(line: &str, posix: bool) -> Result<Vec<(Token, Span)>, LexError> {
+    let error = |kind: LexErrorKind, offset: usize| LexError {
+        kind,
+        position: Span::new(offset, offset).position(line),
+    };
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut word_start = 0;
+    // The delimiter words of here-documents whose lines start after the next newline, as token
+    // indexes along with whether `<<-` strips their leading tabs
+    let mut here_docs: Vec<(usize, bool)> = Vec::new();
+    let mut chars = Cursor::new(line);
+
+    while let Some(c) = chars.next() {
+        let start = chars.offset() - c.len_utf8();
+        match c {
+            '\n' => {
+                if in_word {
+                    let span = Span::new(word_start, start);
+                    tokens.push((Token::Word(std::mem::take(&mut word)), span));
+                    in_word = false;
+                }
+                tokens.push((Token::Newline, Span::new(start, chars.offset())));
+                for (index, strip) in here_docs.drain(..) {
+                    read_here_doc(&mut chars, &mut tokens, index, strip)
+                        .map_err(|kind| error(kind, tokens[index - 1].1.start))?;
+                }
+                continue;
+            }
+            // A backslash before a newline joins the two lines, so the input isn't complete until
+            // the next line has been read
+            '\\' if chars.next_if_eq(&'\n').is_some() => {
+                if chars.peek().is_none() {
+                    return Err(error(LexErrorKind::TrailingBackslash, start));
+                }
+                continue;
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    let span = Span::new(word_start, start);
+                    tokens.push((Token::Word(std::mem::take(&mut word)), span));
+                    in_word = false;
+                }
+                continue;
+            }
+            // A `#` at the start of a word comments out the rest of the line
+            '#' if !in_word => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            '<' | '>' => {
+                // A word made up only of digits directly before the operator is the file
+                // descriptor to redirect
+                let (fd, op_start) = if in_word && word.chars().all(|c| c.is_ascii_digit()) {
+                    in_word = false;
+                    (std::mem::take(&mut word).parse::<i32>().ok(), word_start)
+                } else {
+                    (None, start)
+                };
+
+                let op = match (c, chars.peek()) {
+                    ('<', Some('<')) => RedirectOp::HereDoc,
+                    ('<', Some('&')) => RedirectOp::DupIn,
+                    ('<', Some('>')) => RedirectOp::ReadWrite,
+                    ('>', Some('>')) => RedirectOp::Append,
+                    ('>', Some('&')) => RedirectOp::DupOut,
+                    ('>', Some('|')) => RedirectOp::Clobber,
+                    ('<', _) => RedirectOp::Read,
+                    _ => RedirectOp::Write,
+                };
+                if !matches!(op, RedirectOp::Read | RedirectOp::Write) {
+                    chars.next();
+                }
+                let strip = op == RedirectOp::HereDoc && chars.next_if_eq(&'-').is_some();
+
+                if in_word {
+                    let span = Span::new(word_start, start);
+                    tokens.push((Token::Word(std::mem::take(&mut word)), span));
+                    in_word = false;
+                }
+                tokens.push((Token::Redirect(fd, op), Span::new(op_start, chars.offset())));
+                if op == RedirectOp::HereDoc {
+                    here_docs.push((tokens.len(), strip));
+                }
+                continue;
+            }
+            // An array assignment is kept in one word, up to its closing `)`
+            '(' if !posix && in_word && is_assignment_prefix(&word) => {
+                word.push(c);
+                read_substitution(&mut chars, &mut word)
+                    .map_err(|_| error(LexErrorKind::UnterminatedArray, word_start))?;
+            }
+            ';' | '&' | '|' | '(' | ')' => {
+                let operator = match (c, chars.peek()) {
+                    (';', Some(';')) => Token::DSemi,
+                    (';', _) => Token::Semi,
+                    ('(', _) => Token::LParen,
+                    (')', _) => Token::RParen,
+                    ('&', Some('&')) => Token::AndIf,
+                    ('&', Some('>')) if !posix => {
+                        chars.next();
+                        if chars.next_if_eq(&'>').is_some() {
+                            Token::Redirect(None, RedirectOp::AppendBoth)
+                        } else {
+                            Token::Redirect(None, RedirectOp::WriteBoth)
+                        }
+                    }
+                    ('|', Some('|')) => Token::OrIf,
+                    ('|', _) => Token::Pipe,
+                    _ => Token::Amp,
+                };
+                if matches!(operator, Token::DSemi | Token::AndIf | Token::OrIf) {
+                    chars.next();
+                }
+
+                if in_word {
+                    let span = Span::new(word_start, start);
+                    tokens.push((Token::Word(std::mem::take(&mut word)), span));
+                    in_word = false;
+                }
+                tokens.push((operator, Span::new(start, chars.offset())));
+                continue;
+            }
+            '\\' => match chars.next() {
+                Some(next) => {
+                    word.push(c);
+                    word.push(next);
+                }
+                None => return Err(error(LexErrorKind::TrailingBackslash, start)),
+            },
+            '\'' => {
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(next) => word.push(next),
+                        None => return Err(error(LexErrorKind::UnterminatedSingleQuote, start)),
+                    }
+                }
+                word.push('\'');
+            }
+            '"' => {
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.next_if_eq(&'\n').is_some() => (),
+                        Some('$') if chars.next_if_eq(&'(').is_some() => {
+                            word.push_str("$(");
+                            let offset = chars.offset() - 2;
+                            read_substitution(&mut chars, &mut word)
+                                .map_err(|kind| error(kind, offset))?;
+                        }
+                        Some('`') => {
+                            word.push('`');
+                            let offset = chars.offset() - 1;
+                            read_backquoted(&mut chars, &mut word)
+                                .map_err(|kind| error(kind, offset))?;
+                        }
+                        Some('\\') => {
+                            word.push('\\');
+                            if let Some(next) = chars.next() {
+                                word.push(next);
+                            }
+                        }
+                        Some(next) => word.push(next),
+                        None => return Err(error(LexErrorKind::UnterminatedDoubleQuote, start)),
+                    }
+                }
+                word.push('"');
+            }
+            '$' if chars.peek() == Some('\'') => {
+                word.push(c);
+                word.push(chars.next().unwrap_or('\''));
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        // Escapes are decoded during expansion, but `\'` mustn't end the quote
+                        Some('\\') => {
+                            word.push('\\');
+                            if let Some(next) = chars.next() {
+                                word.push(next);
+                            }
+                        }
+                        Some(next) => word.push(next),
+                        None => return Err(error(LexErrorKind::UnterminatedAnsiCQuote, start)),
+                    }
+                }
+                word.push('\'');
+            }
+            '$' if chars.peek() == Some('(') => {
+                word.push(c);
+                word.push(chars.next().unwrap_or('('));
+                read_substitution(&mut chars, &mut word).map_err(|kind| error(kind, start))?;
+            }
+            '`' => {
+                word.push(c);
+                read_backquoted(&mut chars, &mut word).map_err(|kind| error(kind, start))?;
+            }
+            '$' if chars.peek() == Some('{') => {
+                word.push(c);
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        Some('{') => {
+                            depth += 1;
+                            word.push('{');
+                        }
+                        Some('}') => {
+                            depth -= 1;
+                            word.push('}');
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        Some(next) => word.push(next),
+                        None => return Err(error(LexErrorKind::UnterminatedBrace, start)),
+                    }
+                }
+            }
+            c => word.push(c),
+        }
+        if !in_word {
+            word_start = start;
+        }
+        in_word = true;
+    }
+
+    if in_word {
+        tokens.push((Token::Word(word), Span::new(word_start, line.len())));
+    }
+    if let Some(&(index, _)) = here_docs.first() {
+        return Err(error(
+            LexErrorKind::UnterminatedHereDoc,
+            tokens[index - 1].1.start,
+        ));
+    }
+
+    Ok(tokens)
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Semi => write!(f, ";"),
+            Token::DSemi => write!(f, ";;"),
+            Token::Newline => write!(f, "newline"),
+            Token::AndIf => write!(f, "&&"),
+            Token::OrIf => write!(f, "||"),
+            Token::Pipe => write!(f, "|"),
+            Token::Amp => write!(f, "&"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Redirect(fd, op) => {
+                if let Some(fd) = fd {
+                    write!(f, "{}", fd)?;
+                }
+                write!(f, "{}", op)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            RedirectOp::Read => "<",
+            RedirectOp::Write => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::DupIn => "<&",
+            RedirectOp::DupOut => ">&",
+            RedirectOp::WriteBoth => "&>",
+            RedirectOp::AppendBoth => "&>>",
+            RedirectOp::HereDoc | RedirectOp::HereDocLiteral => "<<",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 1 {
+            write!(f, "line {}, ", self.line)?;
+        }
+        write!(f, "column {}", self.column)
+    }
+}
+
+impl std::fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            LexErrorKind::UnterminatedSingleQuote => "unterminated single quote",
+            LexErrorKind::UnterminatedDoubleQuote => "unterminated double quote",
+            LexErrorKind::UnterminatedAnsiCQuote => "unterminated $' quote",
+            LexErrorKind::UnterminatedSubstitution => "unterminated $(",
+            LexErrorKind::UnterminatedBackquote => "unterminated `",
+            LexErrorKind::UnterminatedBrace => "unterminated ${",
+            LexErrorKind::UnterminatedArray => "unterminated array assignment",
+            LexErrorKind::UnterminatedHereDoc => "unterminated here-document",
+            LexErrorKind::TrailingBackslash => "unexpected end of input after \\",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} starting at {}", self.kind, self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_keeps_quotes() {
+        let tokens =
+            tokenize("echo 'a b' \"c;d\" e\\ f ${X:-g h} $'i\\' j' $(k \")\" (l)) \"`m n`\"")
+                .unwrap();
+
+        assert_eq!(
+            vec![
+                Token::Word(String::from("echo")),
+                Token::Word(String::from("'a b'")),
+                Token::Word(String::from("\"c;d\"")),
+                Token::Word(String::from("e\\ f")),
+                Token::Word(String::from("${X:-g h}")),
+                Token::Word(String::from("$'i\\' j'")),
+                Token::Word(String::from("$(k \")\" (l))")),
+                Token::Word(String::from("\"`m n`\"")),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let tokens = tokenize("ls # list files 'unterminated").unwrap();
+        assert_eq!(vec![Token::Word(String::from("ls"))], tokens);
+
+        let tokens = tokenize("echo a#b '#c' \\#d;# e").unwrap();
+        assert_eq!(
+            vec![
+                Token::Word(String::from("echo")),
+                Token::Word(String::from("a#b")),
+                Token::Word(String::from("'#c'")),
+                Token::Word(String::from("\\#d")),
+                Token::Semi,
+            ],
+            tokens
+        );
+
+        assert!(tokenize("# just a comment").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tokenize_case_separator() {
+        let tokens = tokenize("a) b;; c;;;d ';;'").unwrap();
+        assert_eq!(
+            vec![
+                Token::Word(String::from("a")),
+                Token::RParen,
+                Token::Word(String::from("b")),
+                Token::DSemi,
+                Token::Word(String::from("c")),
+                Token::DSemi,
+                Token::Semi,
+                Token::Word(String::from("d")),
+                Token::Word(String::from("';;'")),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_tokenize_array_assignment() {
+        let tokens = tokenize("arr=(a 'b )' $(c)) x=(y) =(z)").unwrap();
+        assert_eq!(
+            vec![
+                Token::Word(String::from("arr=(a 'b )' $(c))")),
+                Token::Word(String::from("x=(y)")),
+                Token::Word(String::from("=")),
+                Token::LParen,
+                Token::Word(String::from("z")),
+                Token::RParen,
+            ],
+            tokens
+        );
+
+        // Arrays aren't part of POSIX sh
+        let tokens = tokenize_with_spans("x=(y)", true).unwrap();
+        assert_eq!(Token::LParen, tokens[1].0);
+
+        assert_eq!(
+            LexErrorKind::UnterminatedArray,
+            tokenize("echo; arr=(a b").unwrap_err().kind
+        );
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_quote() {
+        assert!(tokenize("echo 'foo").is_err());
+        assert!(tokenize("echo \"foo").is_err());
+        assert!(tokenize("echo $'foo\\'").is_err());
+        assert!(tokenize("echo $(ls").is_err());
+        assert!(tokenize("echo `ls").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_error_positions() {
+        let error = |line: &str| tokenize(line).unwrap_err().to_string();
+
+        assert_eq!(
+            "unterminated single quote starting at column 12",
+            error("echo hello 'world")
+        );
+        assert_eq!(
+            "unterminated $( starting at line 2, column 7",
+            error("ls\necho \"$(date\"")
+        );
+        assert_eq!("unterminated ${ starting at column 3", error("é ${X"));
+        assert_eq!(
+            LexErrorKind::TrailingBackslash,
+            tokenize("ls \\").unwrap_err().kind
+        );
+        assert_eq!(
+            LexErrorKind::TrailingBackslash,
+            tokenize("ls \\\n").unwrap_err().kind
+        );
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let line = "cat 2>&1 <x <<-y\n\tbody\n\ty";
+        let spans: Vec<&str> = tokenize_with_spans(line, false)
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| &line[span.start..span.end])
+            .collect();
+        assert_eq!(vec!["cat", "2>&", "1", "<", "x", "<<-", "y", "\n"], spans);
+
+        let tokens: Vec<Token> = tokenize_with_spans("a &>b", true)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(
+            vec![
+                Token::Word(String::from("a")),
+                Token::Amp,
+                Token::Redirect(None, RedirectOp::Write),
+                Token::Word(String::from("b")),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_tokenize_here_doc() {
+        assert_eq!(
+            vec![
+                Token::Word(String::from("cat")),
+                Token::Redirect(None, RedirectOp::HereDoc),
+                Token::Word(String::from("$a\n  b\n")),
+                Token::Redirect(None, RedirectOp::HereDocLiteral),
+                Token::Word(String::from("c\n")),
+                Token::Newline,
+                Token::Word(String::from("ls")),
+            ],
+            tokenize("cat <<EOF <<-'END'\n$a\n  b\nEOF\n\tc\n\tEND\nls").unwrap()
+        );
+
+        assert_eq!(
+            LexErrorKind::UnterminatedHereDoc,
+            tokenize("cat <<EOF\nbody\n").unwrap_err().kind
+        );
+        assert_eq!(
+            LexErrorKind::UnterminatedHereDoc,
+            tokenize("cat <<EOF").unwrap_err().kind
+        );
+    }
+}
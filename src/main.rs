@@ -1,91 +1,143 @@
-use std::process::exit;
-
-use libc::{
-    abort, c_char, c_int, execvp, fork, getpid, pid_t, setpgid, signal, tcsetpgrp, waitpid, SIGINT,
-    SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL,
-};
+use editor::{LineEditor, ShellHelper};
+use history::HISTORY_FILE;
+use parser::ParseError;
 use rustyline::error::ReadlineError;
-use rustyline::{DefaultEditor, Result};
-use shell::Shell;
+use rustyline::{CompletionType, Config, Result};
+use shell::{Jump, Shell, ShellOptions};
+use std::io::IsTerminal;
+use std::process::exit;
 
+pub mod arith;
+pub mod builtin;
+pub mod conditional;
+#[cfg(test)]
+mod conformance;
+pub mod declare;
+pub mod dirs;
+pub mod echo;
+pub mod editor;
+pub mod exec;
+pub mod expand;
+pub mod help;
+pub mod history;
+pub mod jobs;
+pub mod lexer;
+pub mod parser;
+pub mod pattern;
+pub mod printf;
+pub mod prompt;
+pub mod read;
+pub mod redirect;
 pub mod shell;
+#[cfg(test)]
+mod test_support;
+pub mod times;
+pub mod trap;
+pub mod ulimit;
+pub mod umask;
 
 fn main() -> Result<()> {
-    Shell::parse_args();
-
-    let mut rl = DefaultEditor::new()?;
-    if rl.load_history("history.txt").is_err() {
-        eprintln!("No previous history.");
+    let invocation = Shell::parse_args();
+
+    // A command string or script runs on its own without the terminal or line editor
+    if let Some(command) = invocation.command {
+        let mut shell = Shell::new();
+        shell.options = invocation.options;
+        let status = shell.run_command_string(&command, invocation.args);
+        shell.run_exit_trap();
+        exit(status);
+    }
+    if let Some(script) = invocation.script {
+        let contents = match std::fs::read_to_string(&script) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("simple-shell: {}: {}", script, err);
+                exit(if err.kind() == std::io::ErrorKind::NotFound {
+                    127
+                } else {
+                    126
+                });
+            }
+        };
+
+        let mut shell = Shell::new();
+        shell.options = invocation.options;
+        shell.arg0 = script.clone();
+        shell.positional_params = invocation.args;
+        let status = shell.run_script(&script, &contents);
+        shell.run_exit_trap();
+        exit(status);
     }
 
-    let shell: Shell = Shell::init();
+    // Commands piped in are run as they are read, without the line editor or job control
+    if !std::io::stdin().is_terminal() {
+        let mut shell = Shell::new();
+        shell.options = invocation.options;
+        let status = shell.run_input();
+        shell.run_exit_trap();
+        exit(status);
+    }
 
-    let builtin_cmds = ["cd", "exit", "history"];
+    // Like other shells, Tab lists the commands that fit when it can't finish the name
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = LineEditor::with_config(config)?;
+    rl.set_helper(Some(ShellHelper::default()));
+    if rl.load_history(HISTORY_FILE).is_err() {
+        eprintln!("No previous history.");
+    }
 
-    loop {
-        let readline = rl.readline(&shell.prompt);
+    let mut shell: Shell = Shell::init();
+    shell.options = ShellOptions {
+        monitor: true,
+        ..invocation.options
+    };
+    shell.editor = Some(rl);
+    shell.run_startup_file();
+
+    'repl: loop {
+        shell.notify_jobs();
+        let prompt = shell.primary_prompt();
+        let right_prompt = shell.right_prompt();
+        shell.prepare_editor(right_prompt);
+        let rl = shell.editor.as_mut().expect("the shell's line editor");
+        let readline = rl.readline(&prompt);
+        // Only the primary prompt has a right prompt
+        if let Some(helper) = rl.helper_mut() {
+            helper.right_prompt.clear();
+        }
         match readline {
-            Ok(line) => {
+            Ok(mut line) => {
                 if line.trim().is_empty() {
                     continue;
                 }
 
-                let _ = rl.add_history_entry(line.as_str());
-                match Shell::cmd_parse(line) {
-                    Ok(cmd) => {
-                        let c_cstr = cmd.first().unwrap();
-                        let first_cmd = c_cstr.to_str().unwrap();
-                        if builtin_cmds.contains(&first_cmd) {
-                            if first_cmd == "exit" || first_cmd == "history" {
-                                let _ = rl.save_history("history.txt");
-                            }
-
-                            let _ = shell.do_builtin(cmd);
-                        } else {
-                            let c = c_cstr.as_ptr() as *const c_char;
-                            let mut ptrs: Vec<*const c_char> =
-                                cmd.iter().map(|s| s.as_ptr()).collect();
-                            ptrs.push(std::ptr::null());
-
-                            let argv: *const *const c_char = ptrs.as_ptr();
-                            unsafe {
-                                let fork_pid: pid_t = fork();
-
-                                if fork_pid == 0 {
-                                    // Successfully spawned a new process, give control to child
-                                    let child_pid: pid_t = getpid();
-                                    setpgid(child_pid, child_pid);
-                                    tcsetpgrp(shell.shell_terminal, child_pid);
-
-                                    // Set signals
-                                    signal(SIGINT, SIG_DFL);
-                                    signal(SIGQUIT, SIG_DFL);
-                                    signal(SIGTSTP, SIG_DFL);
-                                    signal(SIGTTIN, SIG_DFL);
-                                    signal(SIGTTOU, SIG_DFL);
-
-                                    // Tell it to execute the non-builtin command
-                                    execvp(c, argv);
-                                    exit(1);
-                                } else if fork_pid < 0 {
-                                    eprintln!("Failed to fork a new process.");
-                                    abort();
-                                }
+                // Keep reading lines until the command is complete, e.g. the closing quote
+                // has been typed
+                while let Err(ParseError::Incomplete(_)) = shell.parse(&line) {
+                    let prompt = shell.secondary_prompt();
+                    let rl = shell.editor.as_mut().expect("the shell's line editor");
+                    match rl.readline(&prompt) {
+                        Ok(next) => {
+                            line.push('\n');
+                            line.push_str(&next);
+                        }
+                        Err(ReadlineError::Interrupted) => continue 'repl,
+                        Err(_) => break,
+                    }
+                }
 
-                                setpgid(fork_pid, fork_pid);
-                                tcsetpgrp(shell.shell_terminal, fork_pid);
+                // Save right away so `history` and `exit` anywhere on the line see this entry
+                let rl = shell.editor.as_mut().expect("the shell's line editor");
+                let _ = rl.add_history_entry(line.as_str());
+                let _ = rl.save_history(HISTORY_FILE);
 
-                                let status: c_int = c_int::default();
-                                let wait = waitpid(fork_pid, status as *mut c_int, 0);
-                                if wait == -1 {
-                                    eprintln!("waidpid failed with -1 code");
-                                }
+                shell.run_line(&line);
 
-                                tcsetpgrp(shell.shell_terminal, shell.shell_pgid);
-                            }
-                        }
-                    }
-                    Err(err) => eprintln!("Error parsing command: {:?}", err),
+                // `set -e` ends the shell once a command fails
+                if let Some(Jump::Exit(status)) = shell.jump {
+                    shell.exit_shell(status);
                 }
             }
             Err(ReadlineError::Interrupted) => {
@@ -105,8 +157,9 @@ fn main() -> Result<()> {
         println!();
     }
 
-    let _ = rl.save_history("history.txt");
-    shell.destroy();
-
-    Ok(())
+    if let Some(rl) = shell.editor.as_mut() {
+        let _ = rl.save_history(HISTORY_FILE);
+    }
+    let status = shell.last_status;
+    shell.exit_shell(status)
 }
@@ -0,0 +1,1658 @@
+//! Parsing of lines read from the user into the command structures run by the executor.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::conditional::{BINARY_OPS, UNARY_OPS};
+use crate::lexer::{tokenize_with_spans, LexError, RedirectOp, Span, Token};
+use crate::shell::Shell;
+
+/// An error found while parsing a line.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    /// The input ended before the command was complete, e.g. inside quotes or after `&&`, so
+    /// reading more input may complete it.
+    Incomplete(String),
+    /// The input isn't valid no matter what follows it.
+    Syntax(String),
+}
+
+/// A redirection of one of a command's file descriptors.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Redirect {
+    /// The file descriptor being redirected.
+    pub fd: i32,
+    pub op: RedirectOp,
+    /// The unexpanded file name or file descriptor the redirection refers to.
+    pub target: String,
+    pub span: Span,
+}
+
+/// A single command: the unexpanded words making up its name and arguments, and the
+/// redirections to apply while it runs.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SimpleCommand {
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// A command that can make up one stage of a pipeline.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Command {
+    /// A program or builtin with its arguments.
+    Simple(SimpleCommand),
+    /// A list of commands in parentheses, run in a child process so changes they make to the
+    /// shell's state don't affect the parent.
+    Subshell {
+        list: CommandList,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// A list of commands in braces, run in the shell itself with shared redirections.
+    Group {
+        list: CommandList,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `if condition; then body; elif condition; then body; else body; fi`, which runs the body
+    /// of the first clause whose condition exits with 0.
+    If {
+        /// The condition and body of the `if` and each `elif`, in order.
+        clauses: Vec<(CommandList, CommandList)>,
+        else_body: Option<CommandList>,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `while condition; do body; done`, which runs the body for as long as the condition exits
+    /// with 0, or `until condition; do body; done`, which runs it until the condition does.
+    While {
+        /// Whether this is an `until` loop, which inverts the condition.
+        until: bool,
+        condition: CommandList,
+        body: CommandList,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `for name in words; do body; done`, which runs the body once for each field the words
+    /// expand to with the variable `name` set to it. Without `in` the positional parameters are
+    /// used. `select name in words; do body; done` instead offers the fields as a numbered menu
+    /// and runs the body each time one is chosen.
+    For {
+        /// Whether this is a `select` loop.
+        select: bool,
+        name: String,
+        /// The unexpanded words after `in`, or `None` if there was no `in`.
+        words: Option<Vec<String>>,
+        body: CommandList,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `case word in pattern | pattern) body;; esac`, which runs the body of the first arm with
+    /// a pattern matching the expanded word.
+    Case {
+        word: String,
+        /// The unexpanded patterns and the body of each arm, in order.
+        arms: Vec<(Vec<String>, CommandList)>,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `[[ expression ]]`, which exits with 0 if the expression is true. Its words are
+    /// expanded without field splitting or pathname expansion.
+    Conditional {
+        expression: Conditional,
+        redirects: Vec<Redirect>,
+        span: Span,
+    },
+    /// `name() body`, which defines a function that runs the compound command `body`, along
+    /// with its redirections, when `name` is used as a command.
+    Function {
+        name: String,
+        body: Box<Command>,
+        span: Span,
+    },
+}
+
+impl Command {
+    /// The part of the line the command was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Command::Simple(command) => command.span,
+            Command::Subshell { span, .. }
+            | Command::Group { span, .. }
+            | Command::If { span, .. }
+            | Command::While { span, .. }
+            | Command::For { span, .. }
+            | Command::Case { span, .. }
+            | Command::Conditional { span, .. }
+            | Command::Function { span, .. } => *span,
+        }
+    }
+}
+
+/// An expression inside `[[ ... ]]`, with its words left unexpanded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Conditional {
+    /// A word on its own, which is true if it expands to a non-empty string.
+    Word(String),
+    /// A test of one word, such as `-f file`.
+    Unary { op: String, word: String },
+    /// A comparison of two words, such as `word == pattern`.
+    Binary {
+        left: String,
+        op: String,
+        right: String,
+    },
+    /// `! expression`
+    Not(Box<Conditional>),
+    /// `expression && expression`
+    And(Box<Conditional>, Box<Conditional>),
+    /// `expression || expression`
+    Or(Box<Conditional>, Box<Conditional>),
+    /// `( expression )`
+    Group(Box<Conditional>),
+}
+
+/// Commands joined by `|`, each reading the output of the one before it. A pipeline starting
+/// with `!` has its exit status inverted, and one starting with `time` reports how long it took
+/// to run.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Pipeline {
+    pub timed: bool,
+    pub negated: bool,
+    pub commands: Vec<Command>,
+    pub span: Span,
+}
+
+/// How a command in an AND-OR list is connected to the one before it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Connector {
+    /// `&&`: only run if the previous command succeeded.
+    And,
+    /// `||`: only run if the previous command failed.
+    Or,
+}
+
+/// Pipelines joined by `&&` and `||`, evaluated left to right with short-circuiting. A list
+/// followed by `&` runs in the background.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    pub background: bool,
+    /// The pipelines of the list, not including a trailing `;` or `&`.
+    pub span: Span,
+}
+
+/// A sequence of AND-OR lists that are run one after the other.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CommandList {
+    pub items: Vec<AndOrList>,
+    pub span: Span,
+}
+/// Parse a line into a list of commands.
+///
+/// ## Parameter(s)
+///
+/// - `line: &str` The line to parse.
+///
+/// ## Returns
+///
+/// - `Ok(CommandList)` The commands on the line, in the order they should be run.
+/// - `Err(String)` if the line is not a valid command list.
+pub fn parse(line: &str) -> Result<CommandList, ParseError> {
+    parse_with(
+        line,
+        &ParseOptions {
+            aliases: &HashMap::new(),
+            posix: false,
+        },
+    )
+}
+
+/// Settings that change how a line is parsed.
+pub struct ParseOptions<'a> {
+    /// The alias table, mapping names to the text that replaces them in the first word of a
+    /// command.
+    pub aliases: &'a HashMap<String, String>,
+    /// Only accept the syntax of POSIX sh.
+    pub posix: bool,
+}
+
+/// Parse a line into a list of commands, expanding aliases in the first word of each command.
+///
+/// ## Parameter(s)
+///
+/// - `line: &str` The line to parse.
+/// - `options: &ParseOptions` The aliases to expand and the syntax to accept.
+///
+/// ## Returns
+///
+/// - `Ok(CommandList)` The commands on the line, in the order they should be run.
+/// - `Err(String)` if the line, after alias expansion, is not a valid command list.
+pub fn parse_with(line: &str, options: &ParseOptions) -> Result<CommandList, ParseError> {
+    let mut parser = Parser {
+        source: line,
+        tokens: tokenize_with_spans(line, options.posix)?.into(),
+        last_end: 0,
+        aliases: options.aliases,
+        posix: options.posix,
+    };
+    let list = parser.parse_list()?;
+    match parser.peek() {
+        None => Ok(list),
+        Some(_) => Err(parser.unexpected()),
+    }
+}
+
+/// The state of a parse: the tokens of a line that are yet to be parsed and the aliases to
+/// expand in them.
+struct Parser<'a> {
+    /// The line being parsed, for error messages.
+    source: &'a str,
+    tokens: VecDeque<(Token, Span)>,
+    /// The end of the last token consumed, which is where the node being parsed ends so far.
+    last_end: usize,
+    aliases: &'a HashMap<String, String>,
+    posix: bool,
+}
+
+impl Parser<'_> {
+    /// Look at the next token without consuming it.
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.front().map(|(token, _)| token)
+    }
+
+    /// Consume the next token.
+    fn advance(&mut self) -> Option<Token> {
+        let (token, span) = self.tokens.pop_front()?;
+        self.last_end = span.end;
+        Some(token)
+    }
+
+    /// Get the offset the next token starts at, which is where a node parsed from it starts.
+    fn start(&self) -> usize {
+        self.tokens
+            .front()
+            .map_or(self.last_end, |(_, span)| span.start)
+    }
+
+    /// Get the span from `start` to the end of the last token consumed.
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.last_end.max(start))
+    }
+
+    /// Consume the next token if it is equal to `expected`.
+    fn next_if_eq(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the next token if it is a word, returning the word.
+    fn next_word(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(Token::Word(_)) => match self.advance() {
+                Some(Token::Word(word)) => Some(word),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Build the error for the next token, which isn't allowed where it was found. If there are
+    /// no tokens left the input ended too early.
+    fn unexpected(&self) -> ParseError {
+        match self.tokens.front() {
+            Some((token, span)) => ParseError::Syntax(format!(
+                "syntax error near unexpected token `{}' at {}",
+                token,
+                span.position(self.source)
+            )),
+            None => ParseError::Incomplete(String::from("syntax error: unexpected end of input")),
+        }
+    }
+
+    /// Parse AND-OR lists separated by `;` or `&` up to the end of the line, a closing `)` or
+    /// a closing `}`.
+    fn parse_list(&mut self) -> Result<CommandList, ParseError> {
+        let mut list = CommandList::default();
+
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none_or(is_list_end) {
+                break;
+            }
+
+            let mut and_or = self.parse_and_or()?;
+            match self.peek() {
+                Some(Token::Semi | Token::Newline) => {
+                    self.advance();
+                }
+                Some(Token::Amp) => {
+                    self.advance();
+                    and_or.background = true;
+                }
+                token if token.is_none_or(is_list_end) => (),
+                _ => return Err(self.unexpected()),
+            }
+            list.items.push(and_or);
+        }
+
+        if let (Some(first), Some(last)) = (list.items.first(), list.items.last()) {
+            list.span = Span::new(first.span.start, last.span.end);
+        }
+        Ok(list)
+    }
+
+    /// Skip over newlines, which are allowed before a command and after `&&`, `||` and `|`.
+    fn skip_newlines(&mut self) {
+        while self.next_if_eq(&Token::Newline) {}
+    }
+
+    /// Parse pipelines joined by `&&` and `||`.
+    fn parse_and_or(&mut self) -> Result<AndOrList, ParseError> {
+        let start = self.start();
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.peek() {
+                Some(Token::AndIf) => Connector::And,
+                Some(Token::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.advance();
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+
+        Ok(AndOrList {
+            first,
+            rest,
+            background: false,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse commands joined by `|`, optionally preceded by `time` and `!`.
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let start = self.start();
+        // `time` isn't part of POSIX sh
+        let timed = !self.posix && self.next_if_eq(&Token::Word(String::from("time")));
+        let negated = self.next_if_eq(&Token::Word(String::from("!")));
+        let mut commands = vec![self.parse_command()?];
+        while self.next_if_eq(&Token::Pipe) {
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+
+        Ok(Pipeline {
+            timed,
+            negated,
+            commands,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse one stage of a pipeline.
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias();
+        let start = self.start();
+        match self.peek() {
+            Some(Token::LParen) => {
+                self.advance();
+                let list = self.parse_compound_list(&Token::RParen)?;
+                Ok(Command::Subshell {
+                    list,
+                    redirects: self.parse_redirects()?,
+                    span: self.span_from(start),
+                })
+            }
+            Some(token) if is_reserved(token, "{") => {
+                self.advance();
+                let list = self.parse_compound_list(&reserved("}"))?;
+                Ok(Command::Group {
+                    list,
+                    redirects: self.parse_redirects()?,
+                    span: self.span_from(start),
+                })
+            }
+            Some(token) if is_reserved(token, "if") => self.parse_if(start),
+            Some(token) if is_reserved(token, "while") || is_reserved(token, "until") => {
+                let until = is_reserved(token, "until");
+                self.advance();
+                let condition = self.parse_compound_list(&reserved("do"))?;
+                let body = self.parse_compound_list(&reserved("done"))?;
+                Ok(Command::While {
+                    until,
+                    condition,
+                    body,
+                    redirects: self.parse_redirects()?,
+                    span: self.span_from(start),
+                })
+            }
+            Some(token) if is_reserved(token, "for") => self.parse_for(start, false),
+            Some(token) if is_reserved(token, "select") && !self.posix => {
+                self.parse_for(start, true)
+            }
+            Some(token) if is_reserved(token, "case") => self.parse_case(start),
+            Some(token) if is_reserved(token, "[[") && !self.posix => self.parse_conditional(start),
+            Some(Token::Word(word))
+                if Shell::is_valid_name(word)
+                    && matches!(self.tokens.get(1), Some((Token::LParen, _))) =>
+            {
+                self.parse_function(start)
+            }
+            _ => Ok(Command::Simple(self.parse_simple()?)),
+        }
+    }
+
+    /// Parse a non-empty list of commands followed by the token that closes it, such as the `)`
+    /// of a subshell or the `then` after the condition of an `if`.
+    fn parse_compound_list(&mut self, close: &Token) -> Result<CommandList, ParseError> {
+        let list = self.parse_list()?;
+        if list.items.is_empty() || !self.next_if_eq(close) {
+            return Err(self.unexpected());
+        }
+
+        Ok(list)
+    }
+
+    /// Parse an `if` command from the `if` at `start` through to its `fi` and any redirections
+    /// after it.
+    fn parse_if(&mut self, start: usize) -> Result<Command, ParseError> {
+        let mut clauses = Vec::new();
+        let mut else_body = None;
+
+        loop {
+            // The `if` or `elif`
+            self.advance();
+            let condition = self.parse_compound_list(&reserved("then"))?;
+            let body = self.parse_list()?;
+            if body.items.is_empty() {
+                return Err(self.unexpected());
+            }
+            clauses.push((condition, body));
+
+            match self.peek() {
+                Some(token) if is_reserved(token, "elif") => continue,
+                Some(token) if is_reserved(token, "else") => {
+                    self.advance();
+                    else_body = Some(self.parse_compound_list(&reserved("fi"))?);
+                    break;
+                }
+                Some(token) if is_reserved(token, "fi") => {
+                    self.advance();
+                    break;
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+
+        Ok(Command::If {
+            clauses,
+            else_body,
+            redirects: self.parse_redirects()?,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse a `for` or `select` loop from the keyword at `start` through to its `done` and any
+    /// redirections after it.
+    fn parse_for(&mut self, start: usize, select: bool) -> Result<Command, ParseError> {
+        self.advance();
+        let name = match self.peek() {
+            Some(Token::Word(word)) if Shell::is_valid_name(word) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.advance();
+        self.skip_newlines();
+
+        // The word list ends at a `;` or newline, which is only optional without `in`
+        let words = if self.next_if_eq(&reserved("in")) {
+            let mut words = Vec::new();
+            while let Some(word) = self.next_word() {
+                words.push(word);
+            }
+            if !self.next_if_eq(&Token::Semi) && !self.next_if_eq(&Token::Newline) {
+                return Err(self.unexpected());
+            }
+            Some(words)
+        } else {
+            self.next_if_eq(&Token::Semi);
+            None
+        };
+
+        self.skip_newlines();
+        if !self.next_if_eq(&reserved("do")) {
+            return Err(self.unexpected());
+        }
+        let body = self.parse_compound_list(&reserved("done"))?;
+
+        Ok(Command::For {
+            select,
+            name,
+            words,
+            body,
+            redirects: self.parse_redirects()?,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse a `case` command from the `case` at `start` through to its `esac` and any
+    /// redirections after it. The `;;` after the last arm is optional.
+    fn parse_case(&mut self, start: usize) -> Result<Command, ParseError> {
+        self.advance();
+        let Some(word) = self.next_word() else {
+            return Err(self.unexpected());
+        };
+        self.skip_newlines();
+        if !self.next_if_eq(&reserved("in")) {
+            return Err(self.unexpected());
+        }
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.next_if_eq(&reserved("esac")) {
+                break;
+            }
+
+            self.next_if_eq(&Token::LParen);
+            let mut patterns = Vec::new();
+            loop {
+                match self.next_word() {
+                    Some(pattern) => patterns.push(pattern),
+                    None => return Err(self.unexpected()),
+                }
+                if !self.next_if_eq(&Token::Pipe) {
+                    break;
+                }
+            }
+            if !self.next_if_eq(&Token::RParen) {
+                return Err(self.unexpected());
+            }
+
+            // Unlike other compound commands an arm's body may be empty
+            let body = self.parse_list()?;
+            arms.push((patterns, body));
+            if !self.next_if_eq(&Token::DSemi) {
+                if self.next_if_eq(&reserved("esac")) {
+                    break;
+                }
+                return Err(self.unexpected());
+            }
+        }
+
+        Ok(Command::Case {
+            word,
+            arms,
+            redirects: self.parse_redirects()?,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse a `[[ ... ]]` command from the `[[` at `start` through to its `]]` and any
+    /// redirections after it.
+    fn parse_conditional(&mut self, start: usize) -> Result<Command, ParseError> {
+        self.advance();
+        let expression = self.parse_conditional_or()?;
+        self.skip_newlines();
+        if !self.next_if_eq(&reserved("]]")) {
+            return Err(self.unexpected());
+        }
+
+        Ok(Command::Conditional {
+            expression,
+            redirects: self.parse_redirects()?,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parse conditional expressions joined by `||`, which binds more loosely than `&&`.
+    fn parse_conditional_or(&mut self) -> Result<Conditional, ParseError> {
+        let mut expression = self.parse_conditional_and()?;
+        while self.next_if_eq(&Token::OrIf) {
+            let right = self.parse_conditional_and()?;
+            expression = Conditional::Or(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    /// Parse conditional expressions joined by `&&`.
+    fn parse_conditional_and(&mut self) -> Result<Conditional, ParseError> {
+        let mut expression = self.parse_conditional_not()?;
+        while self.next_if_eq(&Token::AndIf) {
+            let right = self.parse_conditional_not()?;
+            expression = Conditional::And(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    /// Parse a conditional expression that may be negated with `!`.
+    fn parse_conditional_not(&mut self) -> Result<Conditional, ParseError> {
+        self.skip_newlines();
+        if self.next_if_eq(&reserved("!")) {
+            return Ok(Conditional::Not(Box::new(self.parse_conditional_not()?)));
+        }
+
+        self.parse_conditional_primary()
+    }
+
+    /// Parse a parenthesised conditional expression, a unary or binary test, or a lone word.
+    fn parse_conditional_primary(&mut self) -> Result<Conditional, ParseError> {
+        if self.next_if_eq(&Token::LParen) {
+            let expression = self.parse_conditional_or()?;
+            self.skip_newlines();
+            if !self.next_if_eq(&Token::RParen) {
+                return Err(self.unexpected());
+            }
+            return Ok(Conditional::Group(Box::new(expression)));
+        }
+
+        let word = match self.peek() {
+            Some(Token::Word(word)) if word != "]]" => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        // An operator with nothing after it is just a word, as in `[[ -f ]]`
+        if UNARY_OPS.contains(&word.as_str()) {
+            if let Some((Token::Word(next), _)) = self.tokens.get(1) {
+                if next != "]]" {
+                    self.advance();
+                    let operand = self.next_word().unwrap_or_default();
+                    return Ok(Conditional::Unary {
+                        op: word,
+                        word: operand,
+                    });
+                }
+            }
+        }
+        self.advance();
+
+        let op = match self.peek() {
+            Some(Token::Word(op)) if op == "=~" || BINARY_OPS.contains(&op.as_str()) => op.clone(),
+            Some(Token::Redirect(None, RedirectOp::Read)) => String::from("<"),
+            Some(Token::Redirect(None, RedirectOp::Write)) => String::from(">"),
+            _ => return Ok(Conditional::Word(word)),
+        };
+        self.advance();
+        let right = if op == "=~" {
+            self.parse_regex()?
+        } else {
+            match self.peek() {
+                Some(Token::Word(right)) if right != "]]" => self.next_word().unwrap_or_default(),
+                _ => return Err(self.unexpected()),
+            }
+        };
+
+        Ok(Conditional::Binary {
+            left: word,
+            op,
+            right,
+        })
+    }
+
+    /// Parse the regular expression after `=~`, which runs up to the next unquoted whitespace so
+    /// that it may contain `(`, `)` and `|` without quoting them.
+    fn parse_regex(&mut self) -> Result<String, ParseError> {
+        let start = self.start();
+        match self.peek() {
+            Some(Token::Word(word)) if word == "]]" => return Err(self.unexpected()),
+            Some(Token::Word(_) | Token::LParen | Token::RParen | Token::Pipe) => {
+                self.advance();
+            }
+            _ => return Err(self.unexpected()),
+        }
+        while let Some((token, span)) = self.tokens.front() {
+            let joined = span.start == self.last_end
+                && matches!(
+                    token,
+                    Token::Word(_) | Token::LParen | Token::RParen | Token::Pipe
+                );
+            if !joined || is_reserved(token, "]]") {
+                break;
+            }
+            self.advance();
+        }
+
+        Ok(self.source[start..self.last_end].to_string())
+    }
+
+    /// Parse a function definition from the name at `start` through to the end of its body.
+    fn parse_function(&mut self, start: usize) -> Result<Command, ParseError> {
+        let Some(name) = self.next_word() else {
+            return Err(self.unexpected());
+        };
+        self.advance();
+        if !self.next_if_eq(&Token::RParen) {
+            return Err(self.unexpected());
+        }
+
+        // The body must be a compound command, which may be on the next line
+        self.skip_newlines();
+        if !self.peek().is_some_and(|token| self.starts_compound(token)) {
+            return Err(self.unexpected());
+        }
+        let body = self.parse_command()?;
+
+        Ok(Command::Function {
+            name,
+            body: Box::new(body),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Check if a token starts a compound command such as a subshell or an `if`.
+    fn starts_compound(&self, token: &Token) -> bool {
+        match token {
+            Token::LParen => true,
+            Token::Word(word) if word == "select" || word == "[[" => !self.posix,
+            Token::Word(word) => COMPOUND_WORDS.contains(&word.as_str()),
+            _ => false,
+        }
+    }
+
+    /// Parse the redirections following a compound command.
+    fn parse_redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+
+        Ok(redirects)
+    }
+
+    /// Replace the word at the start of a command with the tokens of its alias, repeating for
+    /// the first word of the replacement. An alias is never expanded inside its own expansion,
+    /// so `alias ls='ls -F'` and aliases that refer to each other can't loop forever.
+    fn expand_alias(&mut self) {
+        let mut expanded: HashSet<String> = HashSet::new();
+
+        while let Some((Token::Word(word), span)) = self.tokens.front() {
+            // Quoting any part of the word suppresses alias expansion
+            if expanded.contains(word) || word.contains(['\'', '"', '\\', '$']) {
+                break;
+            }
+            let Some(value) = self.aliases.get(word) else {
+                break;
+            };
+            // An alias whose text can't be split is left for the command lookup to reject
+            let Ok(replacement) = tokenize_with_spans(value, self.posix) else {
+                break;
+            };
+
+            // The replacement tokens are reported as coming from the alias name
+            let span = *span;
+            expanded.insert(word.clone());
+            self.tokens.pop_front();
+            for (token, _) in replacement.into_iter().rev() {
+                self.tokens.push_front((token, span));
+            }
+        }
+    }
+
+    /// Parse the words and redirections of a single command.
+    fn parse_simple(&mut self) -> Result<SimpleCommand, ParseError> {
+        let start = self.start();
+        let mut command = SimpleCommand::default();
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                command.redirects.push(redirect);
+            } else if let Some(word) = self.next_word() {
+                command.words.push(word);
+            } else {
+                break;
+            }
+        }
+
+        if command.words.is_empty() && command.redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        command.span = self.span_from(start);
+        Ok(command)
+    }
+
+    /// Parse a redirection operator and the word following it.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Some(Redirect))` if the next token was a redirection operator.
+    /// - `Ok(None)` if the next token wasn't a redirection operator.
+    /// - `Err(String)` if the operator wasn't followed by a word.
+    fn parse_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let Some(&Token::Redirect(fd, op)) = self.peek() else {
+            return Ok(None);
+        };
+        let start = self.start();
+        self.advance();
+
+        match self.next_word() {
+            Some(target) => Ok(Some(Redirect {
+                fd: fd.unwrap_or(op.default_fd()),
+                op,
+                target,
+                span: self.span_from(start),
+            })),
+            None => Err(self.unexpected()),
+        }
+    }
+}
+
+/// Reserved words that end the list of commands before them, such as the `fi` of an `if`.
+const LIST_END_WORDS: [&str; 8] = ["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+/// Reserved words that start a compound command, apart from the non-POSIX `select`.
+const COMPOUND_WORDS: [&str; 6] = ["{", "if", "while", "until", "for", "case"];
+
+/// Check if `word` is a reserved word, which is only a keyword at the start of a command.
+/// `select`, `time`, `[[` and `]]` are only reserved outside of POSIX mode.
+pub fn is_reserved_word(word: &str, posix: bool) -> bool {
+    LIST_END_WORDS.contains(&word)
+        || COMPOUND_WORDS.contains(&word)
+        || word == "!"
+        || word == "in"
+        || (matches!(word, "select" | "time" | "[[" | "]]") && !posix)
+}
+
+/// Check if a token ends the list of a subshell or compound command. This is only asked of the
+/// token where a command would start, so `echo fi` is still a simple command.
+fn is_list_end(token: &Token) -> bool {
+    match token {
+        Token::RParen | Token::DSemi => true,
+        Token::Word(word) => LIST_END_WORDS.contains(&word.as_str()),
+        _ => false,
+    }
+}
+
+/// Check if a token is the reserved word `word`. Reserved words are only recognised when
+/// unquoted, which is why the token is compared verbatim.
+fn is_reserved(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(token) if token == word)
+}
+
+/// Build the token for the reserved word `word`.
+fn reserved(word: &str) -> Token {
+    Token::Word(String::from(word))
+}
+
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> ParseError {
+        ParseError::Incomplete(err.to_string())
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Incomplete(message) | ParseError::Syntax(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.fd != self.op.default_fd() {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{}{}", self.op, self.target)
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redirects = match self {
+            Command::Simple(command) => return write!(f, "{}", command),
+            Command::Subshell {
+                list, redirects, ..
+            } => {
+                write!(f, "( {} )", list)?;
+                redirects
+            }
+            Command::Group {
+                list, redirects, ..
+            } => {
+                write!(f, "{{ {}; }}", list)?;
+                redirects
+            }
+            Command::If {
+                clauses,
+                else_body,
+                redirects,
+                ..
+            } => {
+                for (i, (condition, body)) in clauses.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    write!(f, "{} {}; then {}; ", keyword, condition, body)?;
+                }
+                if let Some(body) = else_body {
+                    write!(f, "else {}; ", body)?;
+                }
+                write!(f, "fi")?;
+                redirects
+            }
+            Command::While {
+                until,
+                condition,
+                body,
+                redirects,
+                ..
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{} {}; do {}; done", keyword, condition, body)?;
+                redirects
+            }
+            Command::For {
+                select,
+                name,
+                words,
+                body,
+                redirects,
+                ..
+            } => {
+                let keyword = if *select { "select" } else { "for" };
+                write!(f, "{} {}", keyword, name)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {}", word)?;
+                    }
+                }
+                write!(f, "; do {}; done", body)?;
+                redirects
+            }
+            Command::Case {
+                word,
+                arms,
+                redirects,
+                ..
+            } => {
+                write!(f, "case {} in ", word)?;
+                for (patterns, body) in arms {
+                    write!(f, "{}) {};; ", patterns.join(" | "), body)?;
+                }
+                write!(f, "esac")?;
+                redirects
+            }
+            Command::Conditional {
+                expression,
+                redirects,
+                ..
+            } => {
+                write!(f, "[[ {} ]]", expression)?;
+                redirects
+            }
+            // The body prints its own redirections
+            Command::Function { name, body, .. } => return write!(f, "{}() {}", name, body),
+        };
+        for redirect in redirects {
+            write!(f, " {}", redirect)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Conditional {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conditional::Word(word) => write!(f, "{}", word),
+            Conditional::Unary { op, word } => write!(f, "{} {}", op, word),
+            Conditional::Binary { left, op, right } => write!(f, "{} {} {}", left, op, right),
+            Conditional::Not(expression) => write!(f, "! {}", expression),
+            Conditional::And(left, right) => write!(f, "{} && {}", left, right),
+            Conditional::Or(left, right) => write!(f, "{} || {}", left, right),
+            Conditional::Group(expression) => write!(f, "( {} )", expression),
+        }
+    }
+}
+
+impl std::fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redirects = self.redirects.iter().map(ToString::to_string);
+        let parts: Vec<String> = self.words.iter().cloned().chain(redirects).collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.timed {
+            write!(f, "time ")?;
+        }
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        let commands: Vec<String> = self.commands.iter().map(ToString::to_string).collect();
+        write!(f, "{}", commands.join(" | "))
+    }
+}
+
+impl std::fmt::Display for CommandList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", and_or)?;
+            if and_or.background {
+                write!(f, " &")?;
+            } else if i + 1 < self.items.len() {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for AndOrList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            match connector {
+                Connector::And => write!(f, " && {}", pipeline)?,
+                Connector::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn words(pipeline: &Pipeline) -> Vec<&str> {
+        match &pipeline.commands[0] {
+            Command::Simple(command) => command.words.iter().map(String::as_str).collect(),
+            other => panic!("expected a simple command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_newlines() {
+        assert!(parse("#!/bin/simple-shell").unwrap().items.is_empty());
+        let list = parse("ls\n\npwd &&\n echo a |\n\n wc\n( echo\n b )\n").unwrap();
+
+        assert_eq!(3, list.items.len());
+        assert_eq!("pwd && echo a | wc", list.items[1].to_string());
+        assert_eq!("( echo; b )", list.items[2].to_string());
+        assert_eq!(
+            vec![
+                Token::Word(String::from("echo")),
+                Token::Word(String::from("ab"))
+            ],
+            tokenize("echo a\\\nb").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        for line in [
+            "echo 'a", "echo \"a", "ls |", "ls &&", "ls ||", "echo \\", "( ls", "{ ls;", "{ ls }",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Incomplete(_))),
+                "{} should be incomplete",
+                line
+            );
+        }
+        for line in ["ls )", "; ls", "ls && ;", "ls;;"] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{} should be a syntax error",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let list = parse("cd /tmp; ls -a;pwd;").unwrap();
+
+        assert_eq!(3, list.items.len());
+        assert_eq!(vec!["cd", "/tmp"], words(&list.items[0].first));
+        assert_eq!(vec!["ls", "-a"], words(&list.items[1].first));
+        assert_eq!(vec!["pwd"], words(&list.items[2].first));
+    }
+
+    #[test]
+    fn test_parse_empty_command_is_error() {
+        assert!(parse("; ls").is_err());
+        assert!(parse("ls;; pwd").is_err());
+        assert!(parse("ls && || pwd").is_err());
+        assert!(parse("ls &&").is_err());
+    }
+
+    #[test]
+    fn test_parse_and_or() {
+        let list = parse("make && ./run || echo failed; ls").unwrap();
+
+        assert_eq!(2, list.items.len());
+        let and_or = &list.items[0];
+        assert_eq!(vec!["make"], words(&and_or.first));
+        assert_eq!(Connector::And, and_or.rest[0].0);
+        assert_eq!(vec!["./run"], words(&and_or.rest[0].1));
+        assert_eq!(Connector::Or, and_or.rest[1].0);
+        assert_eq!(vec!["echo", "failed"], words(&and_or.rest[1].1));
+        assert!(list.items[1].rest.is_empty());
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let list = parse("ls -l | grep rs|wc -l").unwrap();
+        let pipeline = &list.items[0].first;
+
+        assert!(!pipeline.negated);
+        assert_eq!(3, pipeline.commands.len());
+        assert_eq!("grep rs", pipeline.commands[1].to_string());
+        assert_eq!("wc -l", pipeline.commands[2].to_string());
+        assert!(parse("ls |").is_err());
+        assert!(parse("| ls").is_err());
+    }
+
+    #[test]
+    fn test_parse_negation() {
+        let list = parse("! grep -q foo file && echo absent; '!' x").unwrap();
+
+        assert!(list.items[0].first.negated);
+        assert_eq!(
+            vec!["grep", "-q", "foo", "file"],
+            words(&list.items[0].first)
+        );
+        assert!(!list.items[0].rest[0].1.negated);
+        assert!(!list.items[1].first.negated);
+        assert!(parse("!").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let list = parse("time ! sleep 1 | wc -l; echo time").unwrap();
+
+        assert!(list.items[0].first.timed);
+        assert!(list.items[0].first.negated);
+        assert_eq!(2, list.items[0].first.commands.len());
+        assert!(!list.items[1].first.timed);
+        assert_eq!("time ! sleep 1 | wc -l; echo time", list.to_string());
+
+        // `time` isn't part of POSIX sh, where it is the name of a program
+        let posix = ParseOptions {
+            aliases: &HashMap::new(),
+            posix: true,
+        };
+        let list = parse_with("time sleep 1", &posix).unwrap();
+        assert!(!list.items[0].first.timed);
+        assert_eq!(vec!["time", "sleep", "1"], words(&list.items[0].first));
+    }
+
+    #[test]
+    fn test_parse_background() {
+        let list = parse("sleep 1 && echo   done & ls&").unwrap();
+
+        assert_eq!(2, list.items.len());
+        assert!(list.items[0].background);
+        assert_eq!("sleep 1 && echo done", list.items[0].to_string());
+        assert!(list.items[1].background);
+        assert!(parse("& ls").is_err());
+        assert!(parse("ls & & pwd").is_err());
+    }
+
+    #[test]
+    fn test_parse_subshell() {
+        let list = parse("( cd /tmp && tar xf x.tar ) | (ls;pwd)").unwrap();
+        let pipeline = &list.items[0].first;
+
+        match &pipeline.commands[0] {
+            Command::Subshell { list: inner, .. } => {
+                assert_eq!(1, inner.items.len());
+                assert_eq!("cd /tmp && tar xf x.tar", inner.items[0].to_string());
+            }
+            other => panic!("expected a subshell, got {:?}", other),
+        }
+        assert_eq!("( ls; pwd )", pipeline.commands[1].to_string());
+        assert!(parse("( ls").is_err());
+        assert!(parse("ls )").is_err());
+        assert!(parse("( )").is_err());
+    }
+
+    #[test]
+    fn test_parse_group() {
+        let list = parse("{ cmd1; cmd2 a}; } > out.txt; echo }").unwrap();
+
+        match &list.items[0].first.commands[0] {
+            Command::Group {
+                list, redirects, ..
+            } => {
+                assert_eq!("cmd1; cmd2 a}", list.to_string());
+                assert_eq!(
+                    vec![Redirect {
+                        fd: 1,
+                        op: RedirectOp::Write,
+                        target: String::from("out.txt"),
+                        span: Span::new(19, 28),
+                    }],
+                    *redirects
+                );
+            }
+            other => panic!("expected a group, got {:?}", other),
+        }
+        assert_eq!(vec!["echo", "}"], words(&list.items[1].first));
+        assert!(parse("{ ls }").is_err());
+        assert!(parse("{ ls; ").is_err());
+        assert!(parse("ls; }").is_err());
+    }
+
+    #[test]
+    fn test_parse_redirects() {
+        let list = parse("cmd <in 2>>err >&2 3<>rw a2>x &>both 2>&-").unwrap();
+        let Command::Simple(command) = &list.items[0].first.commands[0] else {
+            panic!("expected a simple command");
+        };
+
+        assert_eq!(vec!["cmd", "a2"], command.words);
+        let redirects: Vec<(i32, RedirectOp, &str)> = command
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, RedirectOp::Read, "in"),
+                (2, RedirectOp::Append, "err"),
+                (1, RedirectOp::DupOut, "2"),
+                (3, RedirectOp::ReadWrite, "rw"),
+                (1, RedirectOp::Write, "x"),
+                (1, RedirectOp::WriteBoth, "both"),
+                (2, RedirectOp::DupOut, "-"),
+            ],
+            redirects
+        );
+        assert!(parse("ls >").is_err());
+        assert!(parse("ls > ;").is_err());
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let aliases: HashMap<String, String> = [
+            ("ll", "ls -l"),
+            ("ls", "ls -F"),
+            ("a", "b x"),
+            ("b", "a y"),
+            ("both", "echo 1; echo 2"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+        let parse = |line: &str| {
+            parse_with(
+                line,
+                &ParseOptions {
+                    aliases: &aliases,
+                    posix: false,
+                },
+            )
+        };
+
+        let list = parse("ll src | ls && ! ll; 'll'").unwrap();
+        assert_eq!("ls -F -l src | ls -F && ! ls -F -l; 'll'", list.to_string());
+
+        // Aliases referring to each other stop once one would be expanded again
+        let list = parse("a z").unwrap();
+        assert_eq!("a y x z", list.to_string());
+
+        // Only the first word of a command is expanded, but the replacement can hold operators
+        let list = parse("echo ll; both > out").unwrap();
+        assert_eq!("echo ll; echo 1; echo 2 >out", list.to_string());
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let line = "ls -l 2>err | wc && { echo é; }";
+        let list = parse(line).unwrap();
+        let and_or = &list.items[0];
+        let text = |span: Span| &line[span.start..span.end];
+
+        assert_eq!(line, text(list.span));
+        assert_eq!(line, text(and_or.span));
+        assert_eq!("ls -l 2>err | wc", text(and_or.first.span));
+        assert_eq!("ls -l 2>err", text(and_or.first.commands[0].span()));
+        let Command::Simple(command) = &and_or.first.commands[0] else {
+            panic!("expected a simple command");
+        };
+        assert_eq!("2>err", text(command.redirects[0].span));
+        assert_eq!("{ echo é; }", text(and_or.rest[0].1.commands[0].span()));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        assert_eq!(
+            Err(ParseError::Syntax(String::from(
+                "syntax error near unexpected token `)' at column 9"
+            ))),
+            parse("echo é; )")
+        );
+        assert_eq!(
+            Err(ParseError::Syntax(String::from(
+                "syntax error near unexpected token `;;' at line 2, column 5"
+            ))),
+            parse("ls\npwd ;; x")
+        );
+    }
+
+    #[test]
+    fn test_parse_if() {
+        let list = parse("if a; then b; elif c\nthen d; e; else f; fi > out; echo fi").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::If {
+                clauses,
+                else_body,
+                redirects,
+                ..
+            } => {
+                assert_eq!(2, clauses.len());
+                assert_eq!("c", clauses[1].0.to_string());
+                assert_eq!("d; e", clauses[1].1.to_string());
+                assert_eq!(
+                    Some(String::from("f")),
+                    else_body.as_ref().map(|b| b.to_string())
+                );
+                assert_eq!(1, redirects.len());
+            }
+            other => panic!("expected an if, got {:?}", other),
+        }
+        assert_eq!(vec!["echo", "fi"], words(&list.items[1].first));
+        assert_eq!(
+            "if a; then if b; then c; fi; fi",
+            parse("if a; then if b; then c; fi; fi")
+                .unwrap()
+                .to_string()
+        );
+
+        for line in ["if a; then b;", "if a", "if a; then b; else"] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Incomplete(_))),
+                "{}",
+                line
+            );
+        }
+        for line in ["if; then b; fi", "if a; then fi", "then", "if a; fi", "fi"] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_while() {
+        let list = parse("while a; b\ndo c; done < in; echo done").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::While {
+                until,
+                condition,
+                body,
+                redirects,
+                ..
+            } => {
+                assert!(!until);
+                assert_eq!("a; b", condition.to_string());
+                assert_eq!("c", body.to_string());
+                assert_eq!(1, redirects.len());
+            }
+            other => panic!("expected a while, got {:?}", other),
+        }
+        assert_eq!(vec!["echo", "done"], words(&list.items[1].first));
+        assert_eq!(
+            "while a; do while b; do c; done; done",
+            parse("while a; do while b; do c; done; done")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "until a; do b; done >out",
+            parse("until a\ndo b\ndone > out").unwrap().to_string()
+        );
+
+        assert!(matches!(
+            parse("while a; do b;"),
+            Err(ParseError::Incomplete(_))
+        ));
+        for line in [
+            "while a; done",
+            "while a; do done",
+            "while; do b; done",
+            "until a; done",
+            "do",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_for() {
+        let list = parse("for f in *.rs \"$x\"\ndo wc -l $f; done > out; echo for").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::For {
+                select,
+                name,
+                words,
+                body,
+                redirects,
+                ..
+            } => {
+                assert!(!select);
+                assert_eq!("f", name);
+                assert_eq!(
+                    Some(vec![String::from("*.rs"), String::from("\"$x\"")]),
+                    *words
+                );
+                assert_eq!("wc -l $f", body.to_string());
+                assert_eq!(1, redirects.len());
+            }
+            other => panic!("expected a for, got {:?}", other),
+        }
+        assert_eq!(vec!["echo", "for"], words(&list.items[1].first));
+
+        for (line, expected) in [
+            ("for f; do a; done", "for f; do a; done"),
+            ("for f do a; done", "for f; do a; done"),
+            ("for f\ndo a; done", "for f; do a; done"),
+            ("for f in; do a; done", "for f in; do a; done"),
+            ("for f\nin a b\ndo c\ndone", "for f in a b; do c; done"),
+            ("select f in a b; do c; done", "select f in a b; do c; done"),
+            ("select f\ndo c; done", "select f; do c; done"),
+        ] {
+            assert_eq!(expected, parse(line).unwrap().to_string());
+        }
+
+        assert!(matches!(
+            parse("for f in a b"),
+            Err(ParseError::Incomplete(_))
+        ));
+        // `select` isn't part of POSIX sh
+        let posix = ParseOptions {
+            aliases: &HashMap::new(),
+            posix: true,
+        };
+        assert!(matches!(
+            parse_with("select f in a; do b; done", &posix),
+            Err(ParseError::Syntax(_))
+        ));
+        for line in [
+            "for; do a; done",
+            "for 1x in a; do b; done",
+            "for f in a b do c; done",
+            "for f in a; b; do c; done",
+            "for f in a; do done",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_case() {
+        let list =
+            parse("case $x\nin\n(a*) one;;\n b | \"c\") two; three\n;;\nesac > out; echo esac")
+                .unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::Case {
+                word,
+                arms,
+                redirects,
+                ..
+            } => {
+                assert_eq!("$x", word);
+                assert_eq!(2, arms.len());
+                assert_eq!(vec!["a*"], arms[0].0);
+                assert_eq!("one", arms[0].1.to_string());
+                assert_eq!(vec!["b", "\"c\""], arms[1].0);
+                assert_eq!("two; three", arms[1].1.to_string());
+                assert_eq!(1, redirects.len());
+            }
+            other => panic!("expected a case, got {:?}", other),
+        }
+        assert_eq!(vec!["echo", "esac"], words(&list.items[1].first));
+
+        for (line, expected) in [
+            ("case a in esac", "case a in esac"),
+            ("case a in a) esac", "case a in a) ;; esac"),
+            ("case a in a) b; esac", "case a in a) b;; esac"),
+            ("case a in a) ;; *) b;; esac", "case a in a) ;; *) b;; esac"),
+            (
+                "case a in (a) case b in b) c;; esac;; esac",
+                "case a in a) case b in b) c;; esac;; esac",
+            ),
+        ] {
+            assert_eq!(expected, parse(line).unwrap().to_string());
+        }
+
+        assert!(matches!(
+            parse("case a in a) b;;"),
+            Err(ParseError::Incomplete(_))
+        ));
+        for line in [
+            "case; esac",
+            "case a esac",
+            "case a in a b) c;; esac",
+            "case a in a) b c) d;; esac",
+            "case a in |a) b;; esac",
+            "case a in a) b;; c;",
+            "echo a;; echo b",
+            "esac",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let list = parse("[[ -n $x && ( a < b || ! $y =~ ^(a|b) ) ]] > out").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::Conditional {
+                expression,
+                redirects,
+                ..
+            } => {
+                assert!(matches!(expression, Conditional::And(..)));
+                assert_eq!(
+                    "-n $x && ( a < b || ! $y =~ ^(a|b) )",
+                    expression.to_string()
+                );
+                assert_eq!(1, redirects.len());
+            }
+            other => panic!("expected a conditional, got {:?}", other),
+        }
+
+        for (line, expected) in [
+            ("[[ -f ]]", "[[ -f ]]"),
+            ("[[ ! ! x ]]", "[[ ! ! x ]]"),
+            ("[[ a &&\n b ]]", "[[ a && b ]]"),
+            ("f() [[ $1 == y* ]]", "f() [[ $1 == y* ]]"),
+            ("echo [[ a", "echo [[ a"),
+        ] {
+            assert_eq!(expected, parse(line).unwrap().to_string());
+        }
+
+        assert!(matches!(parse("[[ a"), Err(ParseError::Incomplete(_))));
+        for line in [
+            "[[ ]]",
+            "[[ a == ]]",
+            "[[ a b ]]",
+            "[[ ( a ]]",
+            "[[ a =~ ]]",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+        let posix = ParseOptions {
+            aliases: &HashMap::new(),
+            posix: true,
+        };
+        assert!(matches!(
+            parse_with("[[ a ]]", &posix),
+            Ok(list) if list.to_string() == "[[ a ]]"
+        ));
+    }
+
+    #[test]
+    fn test_parse_function() {
+        let list = parse("greet()\n{ echo hi $1; } > out; greet").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::Function { name, body, .. } => {
+                assert_eq!("greet", name);
+                assert!(matches!(**body, Command::Group { .. }));
+            }
+            other => panic!("expected a function, got {:?}", other),
+        }
+        assert_eq!(vec!["greet"], words(&list.items[1].first));
+
+        for (line, expected) in [
+            ("f() { a; }", "f() { a; }"),
+            ("f ( ) (a)", "f() ( a )"),
+            ("f() if a; then b; fi 2>&1", "f() if a; then b; fi 2>&1"),
+            ("f() for x in a; do b; done", "f() for x in a; do b; done"),
+        ] {
+            assert_eq!(expected, parse(line).unwrap().to_string());
+        }
+
+        assert!(matches!(parse("f() {"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(parse("f()"), Err(ParseError::Incomplete(_))));
+        for line in [
+            "f() echo a",
+            "f(a) { b; }",
+            "1f() { a; }",
+            "f() }",
+            "'f'() { a; }",
+        ] {
+            assert!(
+                matches!(parse(line), Err(ParseError::Syntax(_))),
+                "{}",
+                line
+            );
+        }
+    }
+}
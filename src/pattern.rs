@@ -0,0 +1,343 @@
+//! Shell pattern matching as used by parameter expansion, `case` and pathname expansion.
+//!
+//! Patterns support `*` (any string), `?` (any character), bracket expressions such as `[a-z]`,
+//! `[!0-9]` and `[[:alpha:]]`, and backslash escapes which make the next character literal.
+
+/// Check if `text` matches `pattern` in its entirety.
+///
+/// ## Parameter(s)
+///
+/// - `pattern: &str` The shell pattern.
+/// - `text: &str` The text to match against.
+///
+/// ## Returns
+///
+/// `true` if the whole of `text` is matched by `pattern`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut pi, mut ti) = (0, 0);
+    // Where to resume if the current attempt fails: the index after the last `*` and the text
+    // index it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < text.len() {
+        if pi < pattern.len() && pattern[pi] == '*' {
+            pi += 1;
+            backtrack = Some((pi, ti));
+            continue;
+        }
+
+        if let Some(next) = match_one(&pattern, pi, text[ti]) {
+            pi = next;
+            ti += 1;
+            continue;
+        }
+
+        match backtrack {
+            Some((star_pi, star_ti)) => {
+                pi = star_pi;
+                ti = star_ti + 1;
+                backtrack = Some((star_pi, star_ti + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[pi..].iter().all(|&c| c == '*')
+}
+
+/// Escape all characters with a special meaning in patterns so `text` only matches itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Escape all characters with a special meaning in extended regular expressions so `text` only
+/// matches itself.
+pub fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Check if `pattern` contains an unescaped `*`, `?` or `[`, which makes it worth matching
+/// against file names.
+pub fn has_meta(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+
+    false
+}
+
+/// Remove the backslash escapes from a pattern, giving the text it matches literally.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+
+    text
+}
+
+/// Expand a pattern into the paths of existing files it matches, as done for unquoted words
+/// containing `*`, `?` or `[`. Each `/`-separated component is matched against the entries of
+/// the directories matched so far. Names starting with `.` are only matched by a component that
+/// starts with a literal `.`.
+///
+/// ## Parameter(s)
+///
+/// - `pattern: &str` The pattern, with quoted characters escaped.
+///
+/// ## Returns
+///
+/// The matching paths in sorted order, which is empty if nothing matched.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+
+        for path in &paths {
+            if component.is_empty() {
+                // Keep repeated and trailing slashes
+                next.push(format!("{}/", path.trim_end_matches('/')));
+                continue;
+            }
+
+            let join = |name: &str| match path.as_str() {
+                "" => name.to_string(),
+                path if path.ends_with('/') => format!("{}{}", path, name),
+                path => format!("{}/{}", path, name),
+            };
+
+            if !has_meta(component) {
+                let candidate = join(&unescape(component));
+                if last || std::path::Path::new(&candidate).is_dir() {
+                    next.push(candidate);
+                }
+                continue;
+            }
+
+            let dir = if path.is_empty() { "." } else { path.as_str() };
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            let mut matched: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| last || entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| matches(component, name))
+                .map(|name| join(&name))
+                .collect();
+            matched.sort();
+            next.extend(matched);
+        }
+
+        paths = next;
+    }
+
+    // A pattern without metacharacters in its last component may name a file that doesn't exist
+    paths.retain(|path| {
+        std::fs::symlink_metadata(path.trim_end_matches('/')).is_ok() || path == "/"
+    });
+    paths
+}
+
+/// Try to match the single pattern element at `pattern[pi]` against `c`.
+///
+/// ## Returns
+///
+/// The index of the next pattern element if `c` matched, otherwise `None`.
+fn match_one(pattern: &[char], pi: usize, c: char) -> Option<usize> {
+    match pattern.get(pi)? {
+        '?' => Some(pi + 1),
+        '[' => match match_bracket(pattern, pi, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            // Without a closing `]` the `[` is an ordinary character
+            None => (c == '[').then_some(pi + 1),
+        },
+        '\\' if pi + 1 < pattern.len() => (pattern[pi + 1] == c).then_some(pi + 2),
+        &literal => (literal == c).then_some(pi + 1),
+    }
+}
+
+/// Match `c` against the bracket expression starting at `pattern[start]`.
+///
+/// ## Returns
+///
+/// - `Some((bool, usize))` Whether `c` matched and the index after the closing `]`.
+/// - `None` if the bracket expression is never closed.
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+
+        // Character classes such as [:alpha:]
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = &rest[..end];
+                matched |= match_class(class, c);
+                i += 2 + class.chars().count() + 2;
+                continue;
+            }
+        }
+
+        let low = if current == '\\' {
+            i += 1;
+            *pattern.get(i)?
+        } else {
+            current
+        };
+        i += 1;
+
+        // Ranges such as a-z, where a trailing `-` is literal
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|&next| next != ']') {
+            let mut high = pattern[i + 1];
+            i += 2;
+            if high == '\\' {
+                high = *pattern.get(i)?;
+                i += 1;
+            }
+            matched |= low <= c && c <= high;
+        } else {
+            matched |= low == c;
+        }
+    }
+}
+
+/// Check if `c` is a member of the named POSIX character class.
+fn match_class(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_lowercase(),
+        "print" => c.is_ascii_graphic() || c == ' ',
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal() {
+        assert!(matches("foo", "foo"));
+        assert!(!matches("foo", "foobar"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn test_star_and_question() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(matches("?ain.r?", "main.rs"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(!matches("[!abc]x", "bx"));
+        assert!(matches("[a-z][0-9]", "q7"));
+        assert!(matches("[[:upper:]]*", "Hello"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches(&escape("a*[b]"), "a*[b]"));
+    }
+
+    #[test]
+    fn test_has_meta_and_unescape() {
+        assert!(has_meta("*.rs"));
+        assert!(has_meta("src/[a-z]"));
+        assert!(!has_meta("\\*.rs"));
+        assert!(!has_meta("plain"));
+        assert_eq!("*.rs", unescape("\\*.rs"));
+        assert_eq!("a\\b", unescape("a\\\\b"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = std::env::temp_dir().join(format!("glob-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.rs", "a.rs", ".hidden.rs", "c.txt", "sub/d.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let base = escape(dir.to_str().unwrap());
+        let found = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", base, pattern))
+                .into_iter()
+                .map(|path| path[base.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(vec!["a.rs", "b.rs"], found("*.rs"));
+        assert_eq!(vec![".hidden.rs"], found(".*.rs"));
+        assert_eq!(vec!["sub/d.rs"], found("s*/*.rs"));
+        assert_eq!(vec!["sub/"], found("*/"));
+        assert!(found("*.md").is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,478 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use crate::echo::{expand_escape, expand_escapes, parse_digits};
+use crate::shell::Shell;
+
+/// The largest width or precision a conversion can have, which keeps a typo such as
+/// `%99999999999d` from using up all memory.
+const MAX_FIELD_WIDTH: usize = 1 << 20;
+
+/// The flags, width and precision of a `printf` conversion such as `%-08.3d`.
+#[derive(Debug, Default)]
+struct Spec {
+    /// `-`, pad on the right instead of the left.
+    left: bool,
+    /// `+`, always print the sign of a number.
+    plus: bool,
+    /// ` `, print a space in place of a `+` sign.
+    space: bool,
+    /// `#`, prefix octal with `0` and hex with `0x`.
+    alternate: bool,
+    /// `0`, pad numbers with zeros rather than spaces.
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad `body` with spaces to the width on the side given by the flags.
+    fn pad(&self, body: &[u8], out: &mut Vec<u8>) {
+        let fill = self.width.saturating_sub(body.len());
+        if !self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+        out.extend_from_slice(body);
+        if self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+    }
+
+    /// Pad a number made of a `sign`, a `prefix` such as `0x` and its `digits` to the width,
+    /// with zeros between the prefix and the digits if the `0` flag allows it.
+    fn pad_number(
+        &self,
+        sign: &str,
+        prefix: &str,
+        digits: &str,
+        zeros_allowed: bool,
+        out: &mut Vec<u8>,
+    ) {
+        let len = sign.len() + prefix.len() + digits.len();
+        if self.zero && !self.left && zeros_allowed && len < self.width {
+            let zeros = "0".repeat(self.width - len);
+            out.extend_from_slice(format!("{}{}{}{}", sign, prefix, zeros, digits).as_bytes());
+        } else {
+            self.pad(format!("{}{}{}", sign, prefix, digits).as_bytes(), out);
+        }
+    }
+
+    /// The sign to print before a number.
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            (false, false, false) => "",
+        }
+    }
+}
+
+/// The arguments left to be converted, with the first error found while converting them.
+struct Args<'a> {
+    args: std::slice::Iter<'a, CString>,
+    error: Option<String>,
+}
+
+impl Args<'_> {
+    /// Take the next argument, or an empty string once they have run out.
+    fn next_bytes(&mut self) -> &[u8] {
+        self.args.next().map_or(b"", |arg| arg.as_bytes())
+    }
+
+    /// Take the next argument as an integer. A leading quote gives the code of the character
+    /// after it, and `0x` or `0` mark hex or octal numbers.
+    fn next_integer(&mut self) -> i64 {
+        let arg = String::from_utf8_lossy(self.next_bytes()).into_owned();
+        if let Some(value) = Args::character_code(&arg) {
+            return value;
+        }
+
+        let text = arg.trim_start();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (radix, digits) =
+            if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                (16, hex)
+            } else if text.len() > 1 && text.starts_with('0') {
+                (8, &text[1..])
+            } else {
+                (10, text)
+            };
+
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if len < digits.len() || (len == 0 && !text.is_empty()) {
+            self.fail(format!("{}: invalid number", arg));
+        }
+        let value = i64::from_str_radix(&digits[..len], radix).unwrap_or_default();
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }
+    }
+
+    /// Take the next argument as a floating point number.
+    fn next_float(&mut self) -> f64 {
+        let arg = String::from_utf8_lossy(self.next_bytes()).into_owned();
+        if let Some(value) = Args::character_code(&arg) {
+            return value as f64;
+        }
+        let text = arg.trim();
+        if text.is_empty() {
+            return 0.0;
+        }
+
+        text.parse().unwrap_or_else(|_| {
+            self.fail(format!("{}: invalid number", arg));
+            0.0
+        })
+    }
+
+    /// Get the code of the character after a leading quote, as in `'A`.
+    fn character_code(arg: &str) -> Option<i64> {
+        let rest = arg.strip_prefix(['\'', '"'])?;
+        Some(rest.chars().next().map_or(0, |c| c as i64))
+    }
+
+    /// Keep the first error, which is reported once everything has been printed.
+    fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+}
+
+impl Shell {
+    /// The `printf` builtin, as in `printf format [argument...]`. The format is printed with its
+    /// backslash escapes replaced and each `%` conversion replaced by the next argument, and is
+    /// used again for as long as there are arguments left. The conversions are `%s` and `%b` for
+    /// strings, the latter with escapes in the argument replaced, `%c` for a character, `%d`,
+    /// `%i`, `%o`, `%u`, `%x` and `%X` for integers, `%f`, `%e` and `%E` for floating point
+    /// numbers and `%%` for a `%`. Each may have flags, a width and a precision, where a `*`
+    /// takes them from the arguments.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `printf`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if everything was printed.
+    /// - `Err(String)` if there was no format, the format has an invalid conversion, or an
+    ///   argument wasn't a valid number. Everything that could be converted is still printed.
+    pub fn printf(args: &[CString]) -> Result<(), String> {
+        let Some((format, args)) = args.split_first() else {
+            return Err(String::from("usage: printf format [argument...]"));
+        };
+        let mut args = Args {
+            args: args.iter(),
+            error: None,
+        };
+
+        let mut out = Vec::new();
+        loop {
+            let remaining = args.args.len();
+            if !Shell::format_once(format.as_bytes(), &mut args, &mut out) {
+                break;
+            }
+            if args.args.len() == 0 || args.args.len() == remaining {
+                break;
+            }
+        }
+
+        // Written straight to the file descriptor so that it lands wherever standard output is
+        // currently redirected
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(&out)
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))?;
+
+        match args.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Print the format once, converting arguments as its conversions need them.
+    ///
+    /// ## Returns
+    ///
+    /// `false` if printing should stop, because of a `\c` or an invalid conversion.
+    fn format_once(format: &[u8], args: &mut Args, out: &mut Vec<u8>) -> bool {
+        let mut i = 0;
+        while i < format.len() {
+            match format[i] {
+                // Octal escapes in the format don't need a leading 0
+                b'\\' if format.get(i + 1).is_some_and(|c| (b'1'..=b'7').contains(c)) => {
+                    let (value, len) = parse_digits(&format[i + 1..], 8, 3);
+                    out.push(value);
+                    i += 1 + len;
+                }
+                b'\\' => match expand_escape(&format[i + 1..], out) {
+                    Some(len) => i += 1 + len,
+                    None => return false,
+                },
+                b'%' => match Shell::convert(&format[i + 1..], args, out) {
+                    Some(len) => i += 1 + len,
+                    None => return false,
+                },
+                c => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Convert the next argument as the conversion whose `%` comes just before `format`.
+    ///
+    /// ## Returns
+    ///
+    /// The number of bytes of `format` the conversion used, or `None` if printing should stop.
+    fn convert(format: &[u8], args: &mut Args, out: &mut Vec<u8>) -> Option<usize> {
+        let mut spec = Spec::default();
+        let mut i = 0;
+        while let Some(flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        if format.get(i) == Some(&b'*') {
+            let width = args.next_integer();
+            spec.left |= width < 0;
+            spec.width = usize::try_from(width.unsigned_abs()).unwrap_or(usize::MAX);
+            i += 1;
+        } else {
+            let len = format[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+            // A width too big to parse is as invalid as one that is merely too big
+            spec.width = std::str::from_utf8(&format[i..i + len])
+                .unwrap()
+                .parse()
+                .unwrap_or(if len == 0 { 0 } else { usize::MAX });
+            i += len;
+        }
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            if format.get(i) == Some(&b'*') {
+                spec.precision = usize::try_from(args.next_integer()).ok();
+                i += 1;
+            } else {
+                let len = format[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+                spec.precision = Some(
+                    std::str::from_utf8(&format[i..i + len])
+                        .unwrap()
+                        .parse()
+                        .unwrap_or(if len == 0 { 0 } else { usize::MAX }),
+                );
+                i += len;
+            }
+        }
+        if spec.width > MAX_FIELD_WIDTH {
+            args.fail(String::from("invalid field width"));
+            return None;
+        }
+        if spec
+            .precision
+            .is_some_and(|precision| precision > MAX_FIELD_WIDTH)
+        {
+            args.fail(String::from("invalid precision"));
+            return None;
+        }
+
+        let Some(conversion) = format.get(i) else {
+            args.fail(String::from("%: missing conversion character"));
+            return None;
+        };
+        match conversion {
+            b'%' => out.push(b'%'),
+            b's' => {
+                let arg = args.next_bytes();
+                let len = spec.precision.map_or(arg.len(), |max| max.min(arg.len()));
+                spec.pad(&arg[..len], out);
+            }
+            b'b' => {
+                let mut text = Vec::new();
+                let more = expand_escapes(args.next_bytes(), &mut text);
+                if let Some(max) = spec.precision {
+                    text.truncate(max);
+                }
+                spec.pad(&text, out);
+                if !more {
+                    return None;
+                }
+            }
+            b'c' => {
+                let arg = args.next_bytes();
+                spec.pad(&arg[..arg.len().min(1)], out);
+            }
+            b'd' | b'i' => {
+                let value = args.next_integer();
+                let digits = Shell::integer_digits(value.unsigned_abs().to_string(), &spec);
+                spec.pad_number(
+                    spec.sign(value < 0),
+                    "",
+                    &digits,
+                    spec.precision.is_none(),
+                    out,
+                );
+            }
+            b'o' | b'u' | b'x' | b'X' => {
+                let value = args.next_integer() as u64;
+                let digits = match conversion {
+                    b'o' => format!("{:o}", value),
+                    b'u' => value.to_string(),
+                    b'x' => format!("{:x}", value),
+                    _ => format!("{:X}", value),
+                };
+                let digits = Shell::integer_digits(digits, &spec);
+                let prefix = match conversion {
+                    b'o' if spec.alternate && !digits.starts_with('0') => "0",
+                    b'x' if spec.alternate && value != 0 => "0x",
+                    b'X' if spec.alternate && value != 0 => "0X",
+                    _ => "",
+                };
+                spec.pad_number("", prefix, &digits, spec.precision.is_none(), out);
+            }
+            b'f' | b'F' | b'e' | b'E' => {
+                let value = args.next_float();
+                let precision = spec.precision.unwrap_or(6);
+                let digits = match conversion {
+                    b'f' | b'F' if value.is_finite() => format!("{:.*}", precision, value.abs()),
+                    b'e' | b'E' if value.is_finite() => {
+                        // Rust writes `1.5e2` where C writes `1.5e+02`
+                        let formatted = format!("{:.*e}", precision, value.abs());
+                        let (mantissa, exponent) = formatted.split_once('e').unwrap();
+                        let exponent: i32 = exponent.parse().unwrap();
+                        let sign = if exponent < 0 { '-' } else { '+' };
+                        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+                    }
+                    _ if value.is_nan() => String::from("nan"),
+                    _ => String::from("inf"),
+                };
+                let digits = if conversion.is_ascii_uppercase() {
+                    digits.to_ascii_uppercase()
+                } else {
+                    digits
+                };
+                let sign = spec.sign(value.is_sign_negative() && !value.is_nan());
+                spec.pad_number(sign, "", &digits, value.is_finite(), out);
+            }
+            other => {
+                args.fail(format!("%{}: invalid conversion", *other as char));
+                return None;
+            }
+        }
+
+        Some(i + 1)
+    }
+
+    /// Pad the digits of an integer with zeros to the precision of a conversion, where a
+    /// precision of 0 prints nothing for 0.
+    fn integer_digits(digits: String, spec: &Spec) -> String {
+        match spec.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(precision) if digits.len() < precision => {
+                format!("{}{}", "0".repeat(precision - digits.len()), digits)
+            }
+            _ => digits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Format the arguments without printing them.
+    fn format(format: &str, args: &[&str]) -> (String, Option<String>) {
+        let args = crate::test_support::args(args);
+        let mut args = Args {
+            args: args.iter(),
+            error: None,
+        };
+        let mut out = Vec::new();
+        while Shell::format_once(format.as_bytes(), &mut args, &mut out) && args.args.len() > 0 {}
+        (String::from_utf8(out).unwrap(), args.error)
+    }
+
+    #[test]
+    fn test_printf_strings() {
+        assert_eq!(
+            (String::from("<a> <b c>\n"), None),
+            format("<%s> <%s>\\n", &["a", "b c"])
+        );
+        assert_eq!(
+            (String::from("[  ab][x   ][h]"), None),
+            format("[%4.2s][%-4c][%.1s]", &["abc", "xyz", "hi"])
+        );
+        assert_eq!((String::from("1\t2\n"), None), format("%b", &["1\\t2\\n"]));
+        assert_eq!((String::from("a"), None), format("%b%s", &["a\\cb", "c"]));
+        assert_eq!((String::from("%A\n"), None), format("%%\\101\\n", &[]));
+        assert_eq!(
+            (String::from("a,b,c,"), None),
+            format("%s,", &["a", "b", "c"])
+        );
+        assert_eq!((String::from("<>"), None), format("<%s>", &[]));
+    }
+
+    #[test]
+    fn test_printf_numbers() {
+        assert_eq!(
+            (String::from("42 -0042 +7  ff 0X1F 017"), None),
+            format(
+                "%d %05d %+d %3x %#X %#o",
+                &["42", "-42", "7", "255", "31", "15"]
+            )
+        );
+        assert_eq!(
+            (String::from("65 16 8 3.142 -1.50e+02 4294967295"), None),
+            format(
+                "%d %i %d %.3f %.2e %u",
+                &["'A", "0x10", "010", "3.14159", "-150", "4294967295"]
+            )
+        );
+        assert_eq!(
+            (String::from("   07|2   |"), None),
+            format("%*.*d|%-*d|", &["5", "2", "7", "4", "2"])
+        );
+
+        let (out, error) = format("%d %d", &["12abc", "x"]);
+        assert_eq!("12 0", out);
+        assert_eq!(Some(String::from("12abc: invalid number")), error);
+
+        let (out, error) = format("a%qb", &["1"]);
+        assert_eq!("a", out);
+        assert_eq!(Some(String::from("%q: invalid conversion")), error);
+
+        // Widths and precisions too big to print stop the output rather than the shell
+        for spec in [
+            "a%99999999999d",
+            "a%999999999999999999999d",
+            "a%*d",
+            "a%.99999999999d",
+        ] {
+            let (out, error) = format(spec, &["99999999999", "1"]);
+            assert_eq!("a", out);
+            assert!(error.is_some(), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use libc::{c_char, geteuid, gethostname, getpwuid, localtime_r, time, tm};
+
+use crate::shell::Shell;
+
+/// Get the name of the machine the shell is running on, or an empty string if it can't be found.
+fn host_name() -> String {
+    let mut buf = [0 as c_char; 256];
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return String::new();
+    }
+    // The name may not be terminated if it filled the buffer
+    buf[buf.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The colors the `\?` prompt escape shows a command succeeding or failing in.
+const SUCCESS_COLOR: &str = "\x1b[32m";
+const FAILURE_COLOR: &str = "\x1b[31m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// The colors a theme's segments can be drawn in, with their escape sequences.
+const THEME_COLORS: [(&str, &str); 8] = [
+    ("black", "\x1b[30m"),
+    ("red", "\x1b[31m"),
+    ("green", "\x1b[32m"),
+    ("yellow", "\x1b[33m"),
+    ("blue", "\x1b[34m"),
+    ("magenta", "\x1b[35m"),
+    ("cyan", "\x1b[36m"),
+    ("white", "\x1b[37m"),
+];
+
+/// A prompt theme defined with `theme -d`, which draws the primary prompt as a row of segments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    /// The segments as they were given, each a prompt string optionally starting with a color
+    /// and a `:`, as in `blue:\w`.
+    pub segments: Vec<String>,
+    /// What is put between two segments.
+    pub separator: String,
+}
+
+/// Split a theme segment into the escape sequence for its color, if it starts with one, and the
+/// prompt string to draw. A color can be made bold by starting it with `bold-`.
+fn segment_color(segment: &str) -> (String, &str) {
+    if let Some((color, text)) = segment.split_once(':') {
+        let (bold, name) = match color.strip_prefix("bold-") {
+            Some(name) => ("\x1b[1m", name),
+            None => ("", color),
+        };
+        if let Some((_, code)) = THEME_COLORS.iter().find(|(color, _)| *color == name) {
+            return (format!("{}{}", bold, code), text);
+        }
+    }
+    (String::new(), segment)
+}
+
+/// How long whether a git repository has uncommitted changes is remembered for, so that showing
+/// the prompt doesn't run `git status` every time.
+const GIT_STATUS_TTL: Duration = Duration::from_secs(5);
+
+/// What the prompt last found out about a git repository.
+pub struct GitStatus {
+    /// Whether the work tree or index had changes that aren't committed.
+    dirty: bool,
+    /// When the index was last modified at the time, as staging or committing changes it.
+    index_modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// Find the git repository a directory is in.
+///
+/// ## Returns
+///
+/// - `Some((PathBuf, PathBuf))` The top of the work tree and the repository's git directory.
+/// - `None` if the directory isn't in a repository.
+fn find_git_dir(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    let top = dir.ancestors().find(|dir| dir.join(".git").exists())?;
+    let dot_git = top.join(".git");
+    if dot_git.is_dir() {
+        return Some((top.to_path_buf(), dot_git));
+    }
+    // Work trees and submodules have a file pointing at the git directory instead
+    let contents = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = contents.strip_prefix("gitdir:")?.trim();
+    Some((top.to_path_buf(), top.join(git_dir)))
+}
+
+/// Get the branch checked out in a git directory, or the start of the commit's hash if none is.
+fn git_branch(git_dir: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+            )
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// Get the current local time as `HH:MM:SS`.
+fn local_time() -> String {
+    let mut now: tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let secs = time(std::ptr::null_mut());
+        localtime_r(&secs, &mut now);
+    }
+    format!("{:02}:{:02}:{:02}", now.tm_hour, now.tm_min, now.tm_sec)
+}
+
+impl Shell {
+    /// Get the name of the user running the shell, from `$USER` or the password database.
+    fn user_name(&self) -> String {
+        if let Some(user) = self.get_var("USER").filter(|user| !user.is_empty()) {
+            return user;
+        }
+        let passwd = unsafe { getpwuid(geteuid()) };
+        if passwd.is_null() {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr((*passwd).pw_name) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Get the git segment of the prompt for the working directory, which is the branch checked
+    /// out followed by a `*` if there are uncommitted changes, or an empty string outside of a
+    /// repository. Whether there are changes is cached for each repository until its index
+    /// changes or a few seconds have passed.
+    fn git_prompt(&mut self) -> String {
+        let Some((top, git_dir)) = find_git_dir(Path::new(&self.working_dir())) else {
+            return String::new();
+        };
+        let Some(branch) = git_branch(&git_dir) else {
+            return String::new();
+        };
+
+        let index_modified = std::fs::metadata(git_dir.join("index"))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let key = top.to_string_lossy().into_owned();
+        let cached = self.git_statuses.get(&key).filter(|status| {
+            status.index_modified == index_modified && status.checked.elapsed() < GIT_STATUS_TTL
+        });
+        let dirty = match cached {
+            Some(status) => status.dirty,
+            None => {
+                // Without a working `git` the repository is shown as clean
+                let dirty = Command::new("git")
+                    .args(["status", "--porcelain"])
+                    .current_dir(&top)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output()
+                    .is_ok_and(|output| output.status.success() && !output.stdout.is_empty());
+                let status = GitStatus {
+                    dirty,
+                    index_modified,
+                    checked: Instant::now(),
+                };
+                self.git_statuses.insert(key, status);
+                dirty
+            }
+        };
+
+        if dirty {
+            format!("{}*", branch)
+        } else {
+            branch
+        }
+    }
+
+    /// Get the primary prompt as it should be shown right now. The prompt is drawn by the theme
+    /// picked with `theme` if there is one, otherwise it is `$PS1` if it is set or the one the
+    /// shell started with. Its escapes are expanded each time so that the working directory and
+    /// time are up to date.
+    pub fn primary_prompt(&mut self) -> String {
+        if let Some(theme) = self.theme.as_ref().and_then(|name| self.themes.get(name)) {
+            return self.render_theme(&theme.clone());
+        }
+        let prompt = self.get_var("PS1").unwrap_or_else(|| self.prompt.clone());
+        self.expand_prompt(&prompt)
+    }
+
+    /// Draw a theme's segments in their colors with the separator between them, leaving out those
+    /// that expand to nothing, such as a git segment outside of a repository.
+    fn render_theme(&mut self, theme: &Theme) -> String {
+        let mut parts = Vec::new();
+        for segment in &theme.segments {
+            let (color, text) = segment_color(segment);
+            let text = self.expand_prompt(text);
+            if text.is_empty() {
+                continue;
+            }
+            if color.is_empty() {
+                parts.push(text);
+            } else {
+                parts.push(format!("{}{}{}", color, text, RESET_COLOR));
+            }
+        }
+        format!("{} ", parts.join(&theme.separator))
+    }
+
+    /// The `theme` builtin, as in `theme [name]`, `theme -d name [-s separator] segment...` or
+    /// `theme -u`, which manages the themes that draw the primary prompt. `-d` defines a theme
+    /// from its segments, each a prompt string that can start with a color such as `blue:` or
+    /// `bold-red:`, joined by the separator, a space by default. Themes are usually defined in
+    /// the startup file. Giving a name makes that theme draw the prompt in place of `$PS1` and
+    /// `-u` goes back to `$PS1`, while on its own `theme` prints the commands that would define
+    /// the themes again and pick the current one.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `theme`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the themes were listed or changed.
+    /// - `Err(String)` if an option is invalid, a theme has no segments or no theme has the name.
+    pub fn theme_builtin(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        match args.first().map(String::as_str) {
+            None => {
+                let mut out = String::new();
+                for (name, theme) in &self.themes {
+                    out.push_str(&format!(
+                        "theme -d {} -s {}",
+                        Shell::quote(name),
+                        Shell::quote(&theme.separator)
+                    ));
+                    for segment in &theme.segments {
+                        out.push_str(&format!(" {}", Shell::quote(segment)));
+                    }
+                    out.push('\n');
+                }
+                if let Some(name) = &self.theme {
+                    out.push_str(&format!("theme {}\n", Shell::quote(name)));
+                }
+                let mut stdout = std::io::stdout();
+                stdout
+                    .write_all(out.as_bytes())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|err| format!("write error: {}", err))
+            }
+            Some("-u") => {
+                self.theme = None;
+                Ok(())
+            }
+            Some("-d") => {
+                let name = args
+                    .get(1)
+                    .ok_or_else(|| String::from("-d: option requires an argument"))?;
+                let mut rest = &args[2..];
+                let mut separator = String::from(" ");
+                if rest.first().is_some_and(|arg| arg == "-s") {
+                    separator = rest
+                        .get(1)
+                        .cloned()
+                        .ok_or_else(|| String::from("-s: option requires an argument"))?;
+                    rest = &rest[2..];
+                }
+                if rest.is_empty() {
+                    return Err(format!("{}: a theme needs at least one segment", name));
+                }
+                let theme = Theme {
+                    segments: rest.to_vec(),
+                    separator,
+                };
+                self.themes.insert(name.clone(), theme);
+                Ok(())
+            }
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                Err(format!("{}: invalid option", flag))
+            }
+            Some(name) => {
+                if !self.themes.contains_key(name) {
+                    return Err(format!("{}: no such theme", name));
+                }
+                self.theme = Some(name.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the prompt drawn at the right edge of the terminal next to the primary prompt, which is
+    /// `$RPROMPT` with its escapes expanded, or an empty string if it isn't set.
+    pub fn right_prompt(&mut self) -> String {
+        match self.get_var("RPROMPT") {
+            Some(prompt) => self.expand_prompt(&prompt),
+            None => String::new(),
+        }
+    }
+
+    /// Get the prompt shown while the rest of an unfinished command is read, which is `$PS2`
+    /// if it is set and `> ` otherwise, with its escapes expanded as in the primary prompt.
+    pub fn secondary_prompt(&mut self) -> String {
+        let prompt = self.get_var("PS2").unwrap_or_else(|| String::from("> "));
+        self.expand_prompt(&prompt)
+    }
+
+    /// Expand the backslash escapes in a prompt string: `\u` is the user name, `\h` the host name
+    /// up to the first `.` and `\H` all of it, `\w` the working directory with `$HOME` shortened
+    /// to `~` and `\W` only its last part, `\t` the time as `HH:MM:SS`, `\$` a `#` for root and a
+    /// `$` for anyone else, `\g` the git branch and whether it has changes, `\?` a green `✓` if
+    /// the last command succeeded and a red `✗` with its status if it failed, `\e` an escape
+    /// character for writing colors, `\n` a newline and `\\` a backslash. `\[` and `\]` are
+    /// dropped and other escapes are left as they are.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `prompt: &str` The prompt string to expand.
+    ///
+    /// ## Returns
+    ///
+    /// The prompt with its escapes replaced.
+    pub fn expand_prompt(&mut self, prompt: &str) -> String {
+        let mut out = String::new();
+        let mut chars = prompt.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => out.push_str(&self.user_name()),
+                Some('h') => {
+                    let host = host_name();
+                    out.push_str(host.split('.').next().unwrap_or_default());
+                }
+                Some('H') => out.push_str(&host_name()),
+                Some('w') => out.push_str(&self.tilde_dir(&self.working_dir())),
+                Some('W') => {
+                    let dir = self.tilde_dir(&self.working_dir());
+                    match dir.rsplit_once('/') {
+                        Some((_, base)) if !base.is_empty() => out.push_str(base),
+                        _ => out.push_str(&dir),
+                    }
+                }
+                Some('t') => out.push_str(&local_time()),
+                Some('g') => out.push_str(&self.git_prompt()),
+                Some('?') => match self.last_status {
+                    0 => out.push_str(&format!("{}✓{}", SUCCESS_COLOR, RESET_COLOR)),
+                    status => {
+                        out.push_str(&format!("{}✗ {}{}", FAILURE_COLOR, status, RESET_COLOR))
+                    }
+                },
+                Some('e') => out.push('\x1b'),
+                // Bash marks the parts of a prompt that take up no room with these, which the
+                // line editor works out by itself
+                Some('[' | ']') => (),
+                Some('$') => out.push(if unsafe { geteuid() } == 0 { '#' } else { '$' }),
+                Some('n') => out.push('\n'),
+                Some('\\') => out.push('\\'),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push('\\'),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_expand_prompt() {
+        let mut shell = Shell::new();
+        shell
+            .vars
+            .insert(String::from("USER"), String::from("dylan"));
+        shell.vars.insert(String::from("HOME"), shell.working_dir());
+
+        assert_eq!("dylan@~ ", shell.expand_prompt("\\u@\\w "));
+        assert_eq!("~", shell.expand_prompt("\\W"));
+        assert_eq!("a\nb\\c\\q", shell.expand_prompt("a\\nb\\\\c\\q"));
+
+        let sign = if unsafe { geteuid() } == 0 { "#" } else { "$" };
+        assert_eq!(format!("{} ", sign), shell.expand_prompt("\\$ "));
+
+        assert_eq!("\x1b[32m✓\x1b[0m", shell.expand_prompt("\\?"));
+        shell.last_status = 127;
+        assert_eq!("\x1b[31m✗ 127\x1b[0m", shell.expand_prompt("\\?"));
+        assert_eq!(
+            "\x1b[1mbold\x1b[0m",
+            shell.expand_prompt("\\[\\e[1m\\]bold\\[\\e[0m\\]")
+        );
+
+        let time = shell.expand_prompt("\\t");
+        assert_eq!(8, time.len());
+        assert_eq!(Some(':'), time.chars().nth(2));
+
+        shell
+            .vars
+            .insert(String::from("PS1"), String::from("[\\u]"));
+        assert_eq!("[dylan]", shell.primary_prompt());
+
+        assert_eq!("", shell.right_prompt());
+        shell
+            .vars
+            .insert(String::from("RPROMPT"), String::from("[\\W]"));
+        assert_eq!("[~]", shell.right_prompt());
+
+        assert_eq!("> ", shell.secondary_prompt());
+        shell
+            .vars
+            .insert(String::from("PS2"), String::from("\\u... "));
+        assert_eq!("dylan... ", shell.secondary_prompt());
+    }
+
+    #[test]
+    fn test_theme() {
+        let mut shell = Shell::new();
+        shell
+            .vars
+            .insert(String::from("USER"), String::from("dylan"));
+        shell
+            .vars
+            .insert(String::from("PS1"), String::from("plain "));
+
+        assert!(shell
+            .theme_builtin(&args(&[
+                "-d",
+                "duo",
+                "-s",
+                " | ",
+                "bold-blue:\\u",
+                "x:y",
+                "\\e"
+            ]))
+            .is_ok());
+        assert_eq!("plain ", shell.primary_prompt());
+        assert!(shell.theme_builtin(&args(&["duo"])).is_ok());
+        assert_eq!(
+            "\x1b[1m\x1b[34mdylan\x1b[0m | x:y | \x1b ",
+            shell.primary_prompt()
+        );
+
+        // Segments that expand to nothing are left out
+        assert!(shell
+            .theme_builtin(&args(&["-d", "duo", "red:", "\\u"]))
+            .is_ok());
+        assert_eq!("dylan ", shell.primary_prompt());
+
+        assert!(shell.theme_builtin(&args(&["-u"])).is_ok());
+        assert_eq!("plain ", shell.primary_prompt());
+
+        assert!(shell.theme_builtin(&args(&["nope"])).is_err());
+        assert!(shell.theme_builtin(&args(&["-d", "empty"])).is_err());
+        assert!(shell.theme_builtin(&args(&["-q"])).is_err());
+    }
+
+    #[test]
+    fn test_git_branch() {
+        let dir = std::env::temp_dir().join(format!("prompt-test-git-{}", std::process::id()));
+        let git_dir = dir.join(".git");
+        std::fs::create_dir_all(git_dir.join("refs")).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(
+            Some((dir.clone(), git_dir.clone())),
+            find_git_dir(&dir.join("sub"))
+        );
+        assert_eq!(Some(String::from("feature/x")), git_branch(&git_dir));
+
+        std::fs::write(git_dir.join("HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(Some(String::from("0123456")), git_branch(&git_dir));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use std::ffi::CString;
+
+use libc::{isatty, STDIN_FILENO};
+
+use crate::expand::DEFAULT_IFS;
+use crate::shell::Shell;
+
+impl Shell {
+    /// The `read` builtin, as in `read [-r] [-p prompt] [name...]`, which reads a line from
+    /// standard input and splits it into fields on the characters of `$IFS`. Each name is
+    /// assigned a field, with the last getting the rest of the line, and without any names the
+    /// whole line is assigned to `REPLY`. Unless `-r` is given a backslash keeps the character
+    /// after it from separating fields, and one at the end of the line joins the next line on.
+    /// The prompt is only shown when reading from the terminal, where the line editor is used.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `read`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(true)` if a whole line was read.
+    /// - `Ok(false)` at the end of the input. Anything read before it is still assigned.
+    /// - `Err(String)` if an option or name is invalid.
+    pub fn read(&mut self, args: &[CString]) -> Result<bool, String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut raw = false;
+        let mut prompt = String::new();
+        let mut i = 0;
+        while let Some(arg) = args.get(i) {
+            match arg.as_str() {
+                "-r" => raw = true,
+                "-p" => {
+                    i += 1;
+                    prompt = args
+                        .get(i)
+                        .cloned()
+                        .ok_or_else(|| String::from("-p: option requires an argument"))?;
+                }
+                "--" => {
+                    i += 1;
+                    break;
+                }
+                arg if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("{}: invalid option", arg))
+                }
+                _ => break,
+            }
+            i += 1;
+        }
+        let names = &args[i..];
+        if let Some(name) = names.iter().find(|name| !Shell::is_valid_name(name)) {
+            return Err(format!("`{}': not a valid identifier", name));
+        }
+        for name in names {
+            self.check_readonly(name)?;
+        }
+
+        // Each character is kept with whether a backslash escaped it
+        let mut chars: Vec<(char, bool)> = Vec::new();
+        let mut complete = false;
+        let mut line_prompt = prompt;
+        while let Some((line, newline)) = self.read_input_line(&line_prompt) {
+            complete = newline;
+            let mut escaped = false;
+            for c in line.chars() {
+                if escaped {
+                    chars.push((c, true));
+                    escaped = false;
+                } else if c == '\\' && !raw {
+                    escaped = true;
+                } else {
+                    chars.push((c, false));
+                }
+            }
+            // A trailing backslash continues the line
+            if !escaped || !newline {
+                break;
+            }
+            line_prompt = self.secondary_prompt();
+        }
+
+        let ifs = self
+            .get_var("IFS")
+            .unwrap_or_else(|| String::from(DEFAULT_IFS));
+        if names.is_empty() {
+            let line: String = chars.iter().map(|(c, _)| c).collect();
+            self.set_var("REPLY", &line);
+        } else {
+            for (name, value) in
+                names
+                    .iter()
+                    .zip(Shell::split_read_fields(&chars, &ifs, names.len()))
+            {
+                self.set_var(name, &value);
+            }
+        }
+
+        Ok(complete)
+    }
+
+    /// Read a line for `read`, using the line editor and showing the prompt when standard input
+    /// is the terminal.
+    ///
+    /// ## Returns
+    ///
+    /// The line and whether it ended with a newline, or `None` at the end of the input.
+    fn read_input_line(&mut self, prompt: &str) -> Option<(String, bool)> {
+        if unsafe { isatty(STDIN_FILENO) } == 1 {
+            self.read_line(prompt).map(|line| (line, true))
+        } else {
+            Shell::read_stdin_line()
+        }
+    }
+
+    /// Split a line read by `read` into `count` fields. Whitespace in `ifs` around the fields is
+    /// dropped and each other character of `ifs` ends one field, while escaped characters never
+    /// separate fields. The last field is the rest of the line after the fields before it.
+    ///
+    /// ## Returns
+    ///
+    /// The fields, with empty strings for any the line didn't have.
+    fn split_read_fields(chars: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+        let is_ifs = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+        let is_space =
+            |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c) && c.is_whitespace();
+
+        let mut fields = Vec::new();
+        let mut i = 0;
+        while i < chars.len() && is_space(&chars[i]) {
+            i += 1;
+        }
+        for _ in 1..count {
+            let start = i;
+            while i < chars.len() && !is_ifs(&chars[i]) {
+                i += 1;
+            }
+            fields.push(chars[start..i].iter().map(|(c, _)| c).collect());
+
+            // One field separator, with any whitespace around it
+            while i < chars.len() && is_space(&chars[i]) {
+                i += 1;
+            }
+            if i < chars.len() && is_ifs(&chars[i]) {
+                i += 1;
+                while i < chars.len() && is_space(&chars[i]) {
+                    i += 1;
+                }
+            }
+        }
+
+        let mut end = chars.len();
+        while end > i && is_space(&chars[end - 1]) {
+            end -= 1;
+        }
+        fields.push(chars[i..end].iter().map(|(c, _)| c).collect());
+
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str, ifs: &str, count: usize) -> Vec<String> {
+        let chars: Vec<(char, bool)> = line.chars().map(|c| (c, false)).collect();
+        Shell::split_read_fields(&chars, ifs, count)
+    }
+
+    #[test]
+    fn test_split_read_fields() {
+        assert_eq!(
+            vec!["a", "b", "c  d"],
+            split("  a  b c  d  ", DEFAULT_IFS, 3)
+        );
+        assert_eq!(vec!["a", "", ""], split("a", DEFAULT_IFS, 3));
+        assert_eq!(vec!["a", "", "c:d"], split("a::c:d", ":", 3));
+        assert_eq!(vec!["a", "b c"], split(" a : b c ", ": ", 2));
+        assert_eq!(vec!["a b"], split("a b", "", 1));
+
+        let escaped = vec![
+            ('a', false),
+            (' ', true),
+            ('b', false),
+            (' ', false),
+            ('c', false),
+        ];
+        assert_eq!(
+            vec!["a b", "c"],
+            Shell::split_read_fields(&escaped, DEFAULT_IFS, 2)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::{Seek, Write};
+use std::os::fd::IntoRawFd;
+
+use libc::{
+    c_int, close, dup2, fcntl, open, F_DUPFD_CLOEXEC, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC,
+    O_WRONLY,
+};
+
+use crate::lexer::{read_backquoted, read_substitution, Cursor, RedirectOp};
+use crate::parser::Redirect;
+use crate::shell::Shell;
+
+/// File descriptors moved out of the way by `apply_redirects`, as pairs of the redirected file
+/// descriptor and the copy of its original, or -1 if it wasn't open.
+pub type SavedFds = Vec<(c_int, c_int)>;
+
+/// The lowest file descriptor used for saved copies, above the ones users redirect by hand.
+const SAVED_FD_MIN: c_int = 10;
+
+impl Shell {
+    /// Apply `redirects`, run `f` and then put the file descriptors back the way they were.
+    ///
+    /// ## Returns
+    ///
+    /// The exit status of `f`, or 1 if a redirection failed in which case `f` isn't run.
+    pub fn with_redirects<F: FnOnce(&mut Shell) -> i32>(
+        &mut self,
+        redirects: &[Redirect],
+        f: F,
+    ) -> i32 {
+        if redirects.is_empty() {
+            return f(self);
+        }
+
+        let saved = match self.apply_redirects(redirects) {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+        let status = f(self);
+        Shell::restore_redirects(saved);
+
+        status
+    }
+
+    /// Apply redirections to the shell's own file descriptors in order, saving the originals so
+    /// they can be restored with `restore_redirects`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `redirects: &[Redirect]` The redirections to apply.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(SavedFds)` The saved file descriptors.
+    /// - `Err(String)` if a redirection failed. Any redirections already applied are undone.
+    pub fn apply_redirects(&mut self, redirects: &[Redirect]) -> Result<SavedFds, String> {
+        // Anything already printed belongs to the old standard output
+        let _ = std::io::stdout().flush();
+
+        let mut saved: SavedFds = Vec::new();
+        for redirect in redirects {
+            if let Err(err) = self.apply_redirect(redirect, &mut saved) {
+                Shell::restore_redirects(saved);
+                return Err(err);
+            }
+        }
+
+        Ok(saved)
+    }
+
+    /// Undo redirections applied by `apply_redirects`.
+    pub fn restore_redirects(saved: SavedFds) {
+        let _ = std::io::stdout().flush();
+
+        for (fd, copy) in saved.into_iter().rev() {
+            unsafe {
+                if copy == -1 {
+                    close(fd);
+                } else {
+                    dup2(copy, fd);
+                    close(copy);
+                }
+            }
+        }
+    }
+
+    /// Keep redirections applied by `apply_redirects` in place for good, closing the copies of
+    /// the file descriptors they replaced.
+    pub fn keep_redirects(saved: SavedFds) {
+        for (_, copy) in saved {
+            if copy != -1 {
+                unsafe {
+                    close(copy);
+                }
+            }
+        }
+    }
+
+    /// Apply a single redirection, recording the file descriptors it replaces in `saved`.
+    fn apply_redirect(&mut self, redirect: &Redirect, saved: &mut SavedFds) -> Result<(), String> {
+        match redirect.op {
+            RedirectOp::HereDoc => {
+                let body = self.expand_here_doc(&redirect.target)?;
+                return Shell::redirect_here_doc(redirect.fd, &body, saved);
+            }
+            RedirectOp::HereDocLiteral => {
+                return Shell::redirect_here_doc(redirect.fd, &redirect.target, saved);
+            }
+            _ => (),
+        }
+
+        let target = match self.expand_word(&redirect.target)?.as_slice() {
+            [target] => target.clone(),
+            _ => return Err(format!("{}: ambiguous redirect", redirect.target)),
+        };
+
+        let mut op = redirect.op;
+        let dup_source = match op {
+            RedirectOp::DupIn | RedirectOp::DupOut if target == "-" => None,
+            RedirectOp::DupIn | RedirectOp::DupOut => match target.parse::<c_int>() {
+                Ok(source) => Some(source),
+                // `>&file` is another way to write `&>file`
+                Err(_) if op == RedirectOp::DupOut && redirect.fd == 1 && !self.options.posix => {
+                    op = RedirectOp::WriteBoth;
+                    None
+                }
+                Err(_) => return Err(format!("{}: ambiguous redirect", target)),
+            },
+            _ => None,
+        };
+
+        let fds: &[c_int] = match op {
+            RedirectOp::WriteBoth | RedirectOp::AppendBoth => &[1, 2],
+            _ => &[redirect.fd],
+        };
+        for &fd in fds {
+            saved.push((fd, unsafe { fcntl(fd, F_DUPFD_CLOEXEC, SAVED_FD_MIN) }));
+        }
+
+        let flags = match op {
+            RedirectOp::DupIn | RedirectOp::DupOut => {
+                unsafe {
+                    match dup_source {
+                        Some(source) => {
+                            if dup2(source, redirect.fd) == -1 {
+                                return Err(format!("{}: bad file descriptor", source));
+                            }
+                        }
+                        None => {
+                            close(redirect.fd);
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            RedirectOp::Read => O_RDONLY,
+            RedirectOp::Write | RedirectOp::Clobber | RedirectOp::WriteBoth => {
+                O_WRONLY | O_CREAT | O_TRUNC
+            }
+            RedirectOp::Append | RedirectOp::AppendBoth => O_WRONLY | O_CREAT | O_APPEND,
+            RedirectOp::ReadWrite => O_RDWR | O_CREAT,
+            RedirectOp::HereDoc | RedirectOp::HereDocLiteral => unreachable!(),
+        };
+
+        let path = CString::new(target.clone()).map_err(|err| err.to_string())?;
+        unsafe {
+            let file = open(path.as_ptr(), flags, 0o666);
+            if file == -1 {
+                return Err(format!("{}: {}", target, std::io::Error::last_os_error()));
+            }
+            for &fd in fds {
+                dup2(file, fd);
+            }
+            if !fds.contains(&file) {
+                close(file);
+            }
+        }
+
+        Ok(())
+    }
+    /// Expand the lines of a here-document as if they were in double quotes, except that a `"`
+    /// is an ordinary character and a backslash before a newline joins two lines.
+    fn expand_here_doc(&mut self, body: &str) -> Result<String, String> {
+        let mut word = String::from("\"");
+        let mut chars = Cursor::new(body);
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('\\', Some('\n')) => {
+                    chars.next();
+                }
+                ('\\', Some(next @ ('$' | '`' | '\\'))) => {
+                    chars.next();
+                    word.push('\\');
+                    word.push(next);
+                }
+                // A backslash before anything else is an ordinary character
+                ('\\', _) => word.push_str("\\\\"),
+                ('"', _) => word.push_str("\\\""),
+                // Command substitutions are copied whole, as the quotes in them are their own
+                ('$', Some('(')) => {
+                    chars.next();
+                    word.push_str("$(");
+                    let _ = read_substitution(&mut chars, &mut word);
+                }
+                ('`', _) => {
+                    word.push('`');
+                    let _ = read_backquoted(&mut chars, &mut word);
+                }
+                (c, _) => word.push(c),
+            }
+        }
+        word.push('"');
+
+        self.expand_string(&word)
+    }
+
+    /// Point `fd` at an unlinked temporary file holding `body`, so that reading it gives the
+    /// lines of a here-document.
+    fn redirect_here_doc(fd: c_int, body: &str, saved: &mut SavedFds) -> Result<(), String> {
+        let error = |err: std::io::Error| format!("here-document: {}", err);
+        let dir = std::env::temp_dir();
+        let mut attempt = 0;
+        let (path, mut file) = loop {
+            let path = dir.join(format!("simple-shell-{}-{}", std::process::id(), attempt));
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => break (path, file),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(err) => return Err(error(err)),
+            }
+        };
+        let _ = std::fs::remove_file(&path);
+        file.write_all(body.as_bytes()).map_err(error)?;
+        file.rewind().map_err(error)?;
+
+        saved.push((fd, unsafe { fcntl(fd, F_DUPFD_CLOEXEC, SAVED_FD_MIN) }));
+        let file = file.into_raw_fd();
+        unsafe {
+            dup2(file, fd);
+            if file != fd {
+                close(file);
+            }
+        }
+
+        Ok(())
+    }
+}
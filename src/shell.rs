@@ -1,213 +1,1832 @@
 use libc::{
-    c_char, chdir, getpid, getpwuid, getuid, isatty, kill, pid_t, setpgid, signal, tcsetattr,
-    tcsetpgrp, termios, SIGINT, SIGQUIT, SIGTERM, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL, SIG_IGN,
+    c_char, c_int, c_void, chdir, getpid, isatty, pid_t, read, setpgid, sigaction, sigemptyset,
+    sighandler_t, signal, strerror, sysconf, tcgetattr, tcsetattr, tcsetpgrp, termios, _SC_ARG_MAX,
+    SA_RESTART, SIGCHLD, SIGHUP, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL, SIG_IGN,
     STDIN_FILENO, TCSADRAIN,
 };
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rustyline::error::ReadlineError;
+
+use crate::builtin::{self, Builtin};
+use crate::editor::LineEditor;
+use crate::jobs::{self, Job};
+use crate::lexer::{self, Token};
+use crate::parser::Command;
+use crate::prompt::{GitStatus, Theme};
+
+/// Set by the SIGINT handler of an interactive shell. The handler can't reach the `Shell`, so
+/// `Shell::check_interrupt` moves this into `Shell::interrupted`.
+static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGINT in an interactive shell, which arrives when Ctrl-C is pressed while the shell
+/// itself is running commands, e.g. a loop made only of builtins.
+extern "C" fn handle_sigint(_: c_int) {
+    SIGINT_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// The variables a function made local, mapping each name to the values it had as a shell
+/// variable and in the environment before the function shadowed it. They are put back when the
+/// function returns.
+pub type LocalScope = HashMap<String, (Option<String>, Option<String>)>;
 
 pub struct Shell {
-    pub shell_is_interactive: bool,
     pub shell_pgid: pid_t,
-    pub shell_tmodes: termios,
+    /// The terminal modes of an interactive shell, saved when it starts and put back whenever it
+    /// takes the terminal back from a job.
+    pub shell_tmodes: Option<termios>,
     pub shell_terminal: i32,
+    /// The prompt shown when `$PS1` isn't set, taken from `$MY_PROMPT` when the shell starts.
+    /// Its escapes are expanded each time it is shown.
     pub prompt: String,
+    /// Variables assigned in the shell with `NAME=value`. These shadow the environment when
+    /// expanded but are not passed on to child processes.
+    pub vars: HashMap<String, String>,
+    /// Names given to `export` before they had a value. They are moved into the environment as
+    /// soon as they are assigned.
+    pub exported_unset: HashSet<String>,
+    /// Indexed arrays assigned with `name=(a b c)` or `name[i]=value`, mapping each index that
+    /// has been set to its value.
+    pub arrays: HashMap<String, BTreeMap<usize, String>>,
+    /// Associative arrays declared with `declare -A name`, mapping each key that has been set to
+    /// its value.
+    pub assoc_arrays: HashMap<String, BTreeMap<String, String>>,
+    /// Aliases, mapping a name to the text that replaces it when it's the first word of a
+    /// command.
+    pub aliases: HashMap<String, String>,
+    /// Functions defined with `name() body`, mapping each name to its body. Bodies are shared
+    /// so a function can be redefined while it is running.
+    pub functions: HashMap<String, Rc<Command>>,
+    /// The attributes given to variables with `declare`. Variables without any aren't listed.
+    pub attributes: HashMap<String, Attributes>,
+    /// The commands run by the shell itself rather than as programs, keyed by name.
+    pub builtins: BTreeMap<String, Rc<dyn Builtin>>,
+    /// Builtins turned off with `enable -n`, which are looked up as programs instead.
+    pub disabled_builtins: HashSet<String>,
+    /// The directory stack kept by `pushd` and `popd`, not counting the working directory on
+    /// top of it. The entry `pushd` added last comes first.
+    pub dir_stack: Vec<String>,
+    /// The positional parameters `$1`, `$2`, ..., which are the arguments of the function being
+    /// run. A `for` loop without `in` runs over them.
+    pub positional_params: Vec<String>,
+    /// The name of the shell or of the script it is running, reported by `$0`.
+    pub arg0: String,
+    /// A scope for each function being run, innermost last, holding the variables it made local
+    /// with `local`.
+    pub local_scopes: Vec<LocalScope>,
+    /// The number of files being run with `source`, which `return` can also end.
+    pub source_depth: usize,
+    /// Where `getopts` is within a group of options such as `-abc`, as the value of `OPTIND` it
+    /// left and the index of the next letter in that argument. This is forgotten when `OPTIND`
+    /// is changed by something else.
+    pub getopts_pos: Option<(usize, usize)>,
+    /// The exit status of the most recently run command, reported by `$?`.
+    pub last_status: i32,
+    /// The exit status of the last command substitution run while expanding the current
+    /// command, which becomes the status of a command made up only of assignments.
+    pub substitution_status: Option<i32>,
+    /// The options changed with `set`.
+    pub options: ShellOptions,
+    /// Whether this is an interactive shell that `init` set up to catch or ignore the signals
+    /// sent from the terminal, which forked children put back. This is turned off in forked
+    /// children.
+    pub interactive: bool,
+    /// The job table: jobs started in the background with `&` or stopped, that haven't been
+    /// reported as finished yet, in the order they were added.
+    pub jobs: Vec<Job>,
+    /// The process ID of the last command of the most recently started background job, which
+    /// `$!` expands to.
+    pub last_background_pid: Option<pid_t>,
+    /// Whether Ctrl-C was pressed while the current line was running, either killing a
+    /// foreground command or interrupting the shell. Lists and loops stop once this is set.
+    pub interrupted: bool,
+    /// Set by a builtin such as `return` that stops the commands around it, which are skipped
+    /// until whatever it jumps to is reached.
+    pub jump: Option<Jump>,
+    /// The number of loops around the command being run within the current function, which is
+    /// how far `break` and `continue` can reach.
+    pub loop_depth: usize,
+    /// The number of conditions being run, such as that of an `if`, in which a failing command
+    /// doesn't make `set -e` exit the shell.
+    pub errexit_ignored: usize,
+    /// The actions set with `trap`, keyed by signal number with 0 for `EXIT`. An empty action
+    /// means the signal is ignored.
+    pub traps: BTreeMap<c_int, String>,
+    /// The line editor used to read commands, which commands that prompt for input such as
+    /// `select` also use when reading from the terminal. `None` when not reading from a user.
+    pub editor: Option<LineEditor>,
+    /// What the prompt last found out about each git repository, keyed by the top of its work
+    /// tree.
+    pub git_statuses: HashMap<String, GitStatus>,
+    /// The prompt themes defined with `theme -d`, by name.
+    pub themes: BTreeMap<String, Theme>,
+    /// The theme drawing the primary prompt, or `None` to use `$PS1`.
+    pub theme: Option<String>,
+}
+
+/// A jump out of the commands being run, made by a builtin.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Jump {
+    /// `return`, which ends the function being run with the given exit status.
+    Return(i32),
+    /// `break`, which ends the given number of enclosing loops.
+    Break(usize),
+    /// `continue`, which ends the given number of enclosing loops but starts the next iteration
+    /// of the outermost of them.
+    Continue(usize),
+    /// A failing command with `set -e`, which exits the shell with the given status.
+    Exit(i32),
+}
+
+/// What a command name refers to, as found by `Shell::lookup_command`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CommandKind {
+    /// An alias, with the text it stands for.
+    Alias(String),
+    /// A reserved word such as `if`.
+    Keyword,
+    /// A function defined in the shell.
+    Function,
+    /// A command built into the shell.
+    Builtin,
+    /// A program, with the path it was found at.
+    File(PathBuf),
+}
+
+/// How the shell was started, from its command line arguments.
+#[derive(Debug, Default)]
+pub struct Invocation {
+    /// The options to start the shell with.
+    pub options: ShellOptions,
+    /// Commands given with `-c` to run instead of reading them from the user.
+    pub command: Option<String>,
+    /// The path of a script to run instead of reading commands from the user.
+    pub script: Option<String>,
+    /// The arguments after the script, which become its positional parameters. After a `-c`
+    /// command the first of them is used for `$0` instead.
+    pub args: Vec<String>,
+}
+
+/// The attributes of a variable that change how it can be assigned, set with `declare`. Whether
+/// it is exported or an array is kept by where its value is stored instead.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Attributes {
+    /// `-i`: values assigned to the variable are evaluated as arithmetic expressions.
+    pub integer: bool,
+    /// `-r`: the variable can't be assigned or unset.
+    pub readonly: bool,
+}
+
+/// The options that change how the shell behaves, set with `set -<letter>` or `set -o <name>`
+/// and cleared with `+` instead of `-`.
+#[derive(Debug, Clone)]
+pub struct ShellOptions {
+    /// `-e`: exit as soon as a command fails, unless its status is being tested as the
+    /// condition of an `if`, `while` or `until`, by `!` or by an AND-OR list.
+    pub errexit: bool,
+    /// `-o huponexit`: send `SIGHUP` to the jobs left in the job table when an interactive
+    /// shell exits. This is on unless turned off.
+    pub huponexit: bool,
+    /// `-m`: job control, where commands are put in their own process groups and handed the
+    /// terminal. This is on in an interactive shell and off in forked children so that the
+    /// commands they run stay in the child's process group.
+    pub monitor: bool,
+    /// `-f`: don't expand pathname patterns.
+    pub noglob: bool,
+    /// `-u`: make expanding an unset parameter an error instead of giving the empty string.
+    pub nounset: bool,
+    /// `-o pipefail`: give a pipeline the status of the last command in it that failed instead
+    /// of that of its last command.
+    pub pipefail: bool,
+    /// `--posix`: only accept POSIX sh syntax and behaviour where the shell would otherwise
+    /// extend it.
+    pub posix: bool,
+    /// `-x`: write each simple command to standard error after it has been expanded, prefixed
+    /// by `$PS4`.
+    pub xtrace: bool,
+}
+
+impl Default for ShellOptions {
+    fn default() -> Self {
+        ShellOptions {
+            errexit: false,
+            huponexit: true,
+            monitor: false,
+            noglob: false,
+            nounset: false,
+            pipefail: false,
+            posix: false,
+            xtrace: false,
+        }
+    }
+}
+
+impl ShellOptions {
+    /// The long names of all options with their single letter forms, if they have one.
+    pub const NAMES: [(&'static str, Option<char>); 8] = [
+        ("errexit", Some('e')),
+        ("huponexit", None),
+        ("monitor", Some('m')),
+        ("noglob", Some('f')),
+        ("nounset", Some('u')),
+        ("pipefail", None),
+        ("posix", None),
+        ("xtrace", Some('x')),
+    ];
+
+    /// Look up an option by its long name.
+    ///
+    /// ## Returns
+    ///
+    /// The option's value, or `None` if there is no option with that name.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "huponexit" => Some(&mut self.huponexit),
+            "monitor" => Some(&mut self.monitor),
+            "noglob" => Some(&mut self.noglob),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "posix" => Some(&mut self.posix),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// Get the long name of the option with a single letter form.
+    pub fn name_of(letter: char) -> Option<&'static str> {
+        ShellOptions::NAMES
+            .iter()
+            .find(|(_, c)| *c == Some(letter))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
 }
 
 impl Shell {
     /// Initialize the shell for use. Allocate all datastructures, grab control of the terminal and
     /// put the shell in its own process group.
     ///
-    /// NOTE: This function will block until the shell is in its own program group. Attaching a
-    /// debugger will always cause this function to fail because the debugger maintains control of
-    /// he subprocess it is debugging.
-    pub fn init() -> Self {
-        let shell_terminal = STDIN_FILENO;
-        let shell_is_interactive = unsafe { isatty(shell_terminal) } == 1;
-        let shell_pgid: pid_t = unsafe { getpid() };
-        let shell_tmodes: termios = unsafe { std::mem::zeroed() };
-        let prompt = Shell::get_prompt(String::from("MY_PROMPT"));
+    /// NOTE: This function will block until the shell is in its own program group. Attaching a
+    /// debugger will always cause this function to fail because the debugger maintains control of
+    /// he subprocess it is debugging.
+    pub fn init() -> Self {
+        let mut shell = Shell::new();
+        shell.interactive = true;
+        shell.options.monitor = true;
+        shell.start_job_control();
+
+        unsafe {
+            // Catch rather than ignore SIGINT so that it can interrupt loops. SA_RESTART keeps
+            // it from failing the read or wait that's in progress.
+            let mut action: sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_sigint as extern "C" fn(c_int) as sighandler_t;
+            action.sa_flags = SA_RESTART;
+            sigemptyset(&mut action.sa_mask);
+            sigaction(SIGINT, &action, std::ptr::null_mut());
+            // Reap background jobs as they finish, even while waiting for a line to be typed
+            action.sa_sigaction = jobs::handle_sigchld as extern "C" fn(c_int) as sighandler_t;
+            sigaction(SIGCHLD, &action, std::ptr::null_mut());
+            action.sa_sigaction = jobs::handle_sighup as extern "C" fn(c_int) as sighandler_t;
+            sigaction(SIGHUP, &action, std::ptr::null_mut());
+            let _ = signal(SIGQUIT, SIG_IGN);
+        }
+
+        shell
+    }
+
+    /// Take control of the terminal for job control, which `init` does for an interactive shell
+    /// and `set -m` for any other. The shell is put in its own process group in the foreground of
+    /// the terminal, the terminal's modes are saved and the signals that would stop the shell
+    /// while a job has the terminal are ignored.
+    pub fn start_job_control(&mut self) {
+        unsafe {
+            let _ = signal(SIGTSTP, SIG_IGN);
+            let _ = signal(SIGTTIN, SIG_IGN);
+            let _ = signal(SIGTTOU, SIG_IGN);
+
+            setpgid(self.shell_pgid, self.shell_pgid);
+            if isatty(self.shell_terminal) == 1 {
+                tcsetpgrp(self.shell_terminal, self.shell_pgid);
+                let mut tmodes: termios = std::mem::zeroed();
+                if tcgetattr(self.shell_terminal, &mut tmodes) == 0 {
+                    self.shell_tmodes = Some(tmodes);
+                }
+            }
+        }
+    }
+
+    /// Get the handler `init` or `start_job_control` gives a signal, which `trap` puts back when
+    /// a trap is removed.
+    pub fn default_disposition(&self, signal: c_int) -> sighandler_t {
+        match signal {
+            SIGINT if self.interactive => handle_sigint as extern "C" fn(c_int) as sighandler_t,
+            SIGCHLD if self.interactive => {
+                jobs::handle_sigchld as extern "C" fn(c_int) as sighandler_t
+            }
+            SIGHUP if self.interactive => {
+                jobs::handle_sighup as extern "C" fn(c_int) as sighandler_t
+            }
+            SIGQUIT if self.interactive => SIG_IGN,
+            SIGTSTP | SIGTTIN | SIGTTOU if self.interactive || self.options.monitor => SIG_IGN,
+            _ => SIG_DFL,
+        }
+    }
+
+    /// Allocate the shell's datastructures without touching the terminal, process group, or
+    /// signal dispositions. `init` builds on this; tests use it directly so they don't steal the
+    /// terminal from the test harness.
+    pub fn new() -> Self {
+        let shell_terminal = STDIN_FILENO;
+        let shell_pgid: pid_t = unsafe { getpid() };
+        let prompt = Shell::get_prompt(String::from("MY_PROMPT"));
+
+        Self {
+            shell_pgid,
+            shell_tmodes: None,
+            shell_terminal,
+            prompt,
+            vars: HashMap::new(),
+            exported_unset: HashSet::new(),
+            arrays: HashMap::new(),
+            assoc_arrays: HashMap::new(),
+            aliases: HashMap::new(),
+            functions: HashMap::new(),
+            attributes: HashMap::new(),
+            builtins: builtin::registry(),
+            disabled_builtins: HashSet::new(),
+            dir_stack: Vec::new(),
+            positional_params: Vec::new(),
+            arg0: String::from("simple-shell"),
+            local_scopes: Vec::new(),
+            source_depth: 0,
+            getopts_pos: None,
+            last_status: 0,
+            substitution_status: None,
+            options: ShellOptions::default(),
+            interactive: false,
+            jobs: Vec::new(),
+            last_background_pid: None,
+            interrupted: false,
+            jump: None,
+            loop_depth: 0,
+            errexit_ignored: 0,
+            traps: BTreeMap::new(),
+            editor: None,
+            git_statuses: HashMap::new(),
+            themes: BTreeMap::new(),
+            theme: None,
+        }
+    }
+
+    /// Read a line of input for a command such as `select`. The line editor is used when
+    /// standard input is the terminal, otherwise the prompt goes to standard error and the line
+    /// is read directly from standard input.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `prompt: &str` The prompt to show before reading.
+    ///
+    /// ## Returns
+    ///
+    /// The line without its newline, or `None` at the end of the input or if Ctrl-C was pressed.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        if unsafe { isatty(STDIN_FILENO) } == 1 {
+            if let Some(editor) = self.editor.as_mut() {
+                return match editor.readline(prompt) {
+                    Ok(line) => Some(line),
+                    Err(ReadlineError::Interrupted) => {
+                        self.interrupted = true;
+                        self.last_status = 128 + SIGINT;
+                        None
+                    }
+                    Err(_) => None,
+                };
+            }
+        }
+
+        eprint!("{}", prompt);
+        Shell::read_stdin_line().map(|(line, _)| line)
+    }
+
+    /// Read a line directly from standard input. A byte is read at a time so nothing past the
+    /// line is taken from input shared with the commands that run next.
+    ///
+    /// ## Returns
+    ///
+    /// The line without its newline and whether it ended with one, which the last line of the
+    /// input may not, or `None` at the end of the input.
+    pub fn read_stdin_line() -> Option<(String, bool)> {
+        let mut line: Vec<u8> = Vec::new();
+        let mut byte: u8 = 0;
+        let newline = loop {
+            match unsafe { read(STDIN_FILENO, &mut byte as *mut u8 as *mut c_void, 1) } {
+                1 if byte == b'\n' => break true,
+                1 => line.push(byte),
+                0 if !line.is_empty() => break false,
+                _ => return None,
+            }
+        };
+
+        Some((String::from_utf8_lossy(&line).into_owned(), newline))
+    }
+
+    /// Check whether the line being run has been interrupted with Ctrl-C.
+    ///
+    /// ## Returns
+    ///
+    /// `true` if it has, in which case `last_status` is that of a command killed by SIGINT.
+    pub fn check_interrupt(&mut self) -> bool {
+        if SIGINT_RECEIVED.swap(false, Ordering::SeqCst) {
+            self.interrupted = true;
+            self.last_status = 128 + SIGINT;
+        }
+        self.interrupted
+    }
+
+    /// Check whether the commands being run should stop early, either because the line has been
+    /// interrupted or because a builtin such as `return` is jumping out of them.
+    pub fn should_stop(&mut self) -> bool {
+        self.check_interrupt() || self.jump.is_some()
+    }
+
+    /// Give the terminal back to the shell's process group and put back its modes and the
+    /// default handlers of the signals job control ignores, leaving things as they were before
+    /// the shell started.
+    pub fn release_terminal(&self) {
+        unsafe {
+            if self.options.monitor {
+                tcsetpgrp(self.shell_terminal, self.shell_pgid);
+                if let Some(tmodes) = &self.shell_tmodes {
+                    tcsetattr(self.shell_terminal, TCSADRAIN, tmodes);
+                }
+            }
+
+            signal(SIGINT, SIG_DFL);
+            signal(SIGQUIT, SIG_DFL);
+            signal(SIGTSTP, SIG_DFL);
+            signal(SIGTTIN, SIG_DFL);
+            signal(SIGTTOU, SIG_DFL);
+        }
+    }
+
+    /// Exit the shell with `status`, after running the `EXIT` trap, hanging up the remaining
+    /// jobs and releasing the terminal.
+    pub fn exit_shell(&mut self, status: i32) -> ! {
+        self.run_exit_trap();
+        self.hangup_jobs();
+        self.release_terminal();
+        let _ = std::io::stdout().flush();
+        exit(status);
+    }
+
+    /// Set the shell prompt. This function will attempt to load a prompt from the requested
+    /// environment variable, if the environment variable is not set, a default prompt of "shell>"
+    /// is returned.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `env: String` The environment variable
+    ///
+    /// ## Return(s)
+    ///
+    /// The prompt from the environment variable or the default prompt.
+    pub fn get_prompt(env: String) -> String {
+        match env::var(env) {
+            Ok(prompt) => prompt,
+            Err(_) => String::from("shell>"),
+        }
+    }
+
+    /// The `cd` builtin, as in `cd [-L | -P] [dir]`, which changes to `dir`, or to `$HOME`
+    /// without one. `cd -` changes back to `$OLDPWD` and prints it. A relative `dir` that isn't
+    /// in the current directory is looked for in each directory in `$CDPATH`, and printed if it
+    /// is found there.
+    ///
+    /// By default, or with `-L`, `dir` is followed logically from `$PWD` so that `..` undoes the
+    /// symlinks taken to get there. `-P` follows the physical directory structure instead, and
+    /// sets `$PWD` to the directory with every symlink resolved.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `cd`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the directory was successfully changed.
+    /// - `Err(String)` if an option is invalid, the directory couldn't be changed to or `$HOME` or
+    ///   `$OLDPWD` isn't set.
+    pub fn cd(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut physical = false;
+        let mut i = 0;
+        while let Some(flags) = args
+            .get(i)
+            .and_then(|arg| arg.strip_prefix('-'))
+            .filter(|flags| !flags.is_empty())
+        {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'L' => physical = false,
+                    'P' => physical = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+        }
+
+        let (dir, print) = match args.get(i).map(String::as_str) {
+            Some("-") => {
+                let dir = self
+                    .get_var("OLDPWD")
+                    .ok_or_else(|| String::from("OLDPWD not set"))?;
+                (dir, true)
+            }
+            // A directory found through `$CDPATH` is printed since it isn't where `dir` is
+            Some(dir) => match self.search_cdpath(dir) {
+                Some(found) => (found, true),
+                None => (dir.to_string(), false),
+            },
+            None => {
+                let home = self
+                    .get_var("HOME")
+                    .ok_or_else(|| String::from("HOME not set"))?;
+                (home, false)
+            }
+        };
+
+        self.enter_dir(&dir, physical)?;
+        if print {
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(format!("{}\n", self.working_dir()).as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|err| format!("write error: {}", err))?;
+        }
+        Ok(())
+    }
+
+    /// Find a relative directory that isn't in the current directory in one of the
+    /// colon-separated directories in `$CDPATH`. Directories starting with `.` or `..` are only
+    /// ever followed from the current directory.
+    ///
+    /// ## Returns
+    ///
+    /// The path of the directory found, or `None` if it should be followed from the current
+    /// directory.
+    fn search_cdpath(&self, dir: &str) -> Option<String> {
+        let first = dir.split('/').next().unwrap_or_default();
+        if dir.is_empty() || dir.starts_with('/') || first == "." || first == ".." {
+            return None;
+        }
+        let cwd = self.working_dir();
+        if Path::new(&Shell::logical_path(&cwd, dir)).is_dir() {
+            return None;
+        }
+
+        self.get_var("CDPATH")?
+            .split(':')
+            .filter(|base| !base.is_empty())
+            .map(|base| Shell::logical_path(&Shell::logical_path(&cwd, base), dir))
+            .find(|path| Path::new(path).is_dir())
+    }
+
+    /// Change the working directory to `dir`. A logical change follows `dir` from `$PWD` with
+    /// `..` removing the last part of the path rather than going to the parent of wherever a
+    /// symlink led, while a physical one leaves that to the system and sets `$PWD` with every
+    /// symlink resolved. `$PWD` and `$OLDPWD` are updated once the directory has changed.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the directory was successfully changed.
+    /// - `Err(String)` saying why the directory couldn't be changed to.
+    pub fn enter_dir(&mut self, dir: &str, physical: bool) -> Result<(), String> {
+        let old = self.working_dir();
+        let new = if physical {
+            dir.to_string()
+        } else {
+            Shell::logical_path(&old, dir)
+        };
+        let path = CString::new(new.as_str()).map_err(|err| format!("{}: {}", dir, err))?;
+        if unsafe { chdir(path.as_ptr()) } != 0 {
+            return Err(format!("{}: {}", dir, Shell::last_os_error()));
+        }
+
+        let pwd = if !physical && Shell::is_current_dir(&new) {
+            new
+        } else {
+            Shell::physical_dir()
+        };
+        self.set_var("OLDPWD", &old);
+        self.set_var("PWD", &pwd);
+
+        Ok(())
+    }
+
+    /// Describe the error of the last failed system call as `strerror` does, leaving out the
+    /// "(os error N)" that `io::Error` adds.
+    pub fn last_os_error() -> String {
+        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        unsafe { CStr::from_ptr(strerror(errno)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The `pwd` builtin, which prints the logical working directory kept in `$PWD`, or with
+    /// `-P` the physical one with every symlink resolved. `-L` goes back to the logical one.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `pwd`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the directory was printed.
+    /// - `Err(String)` if an option isn't valid.
+    pub fn pwd(&self, args: &[CString]) -> Result<(), String> {
+        let mut physical = false;
+        for arg in args {
+            let arg = arg.to_string_lossy();
+            if arg == "--" {
+                break;
+            }
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                break;
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'L' => physical = false,
+                    'P' => physical = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+        }
+
+        if physical {
+            println!("{}", Shell::physical_dir());
+        } else {
+            println!("{}", self.working_dir());
+        }
+        Ok(())
+    }
+
+    /// Get the logical working directory, which is `$PWD` as long as it is an absolute path
+    /// without any `.` or `..` that still names the current directory. Otherwise the physical
+    /// working directory is used.
+    pub fn working_dir(&self) -> String {
+        match self.get_var("PWD") {
+            Some(pwd)
+                if pwd.starts_with('/')
+                    && !pwd.split('/').any(|part| part == "." || part == "..")
+                    && Shell::is_current_dir(&pwd) =>
+            {
+                pwd
+            }
+            _ => Shell::physical_dir(),
+        }
+    }
+
+    /// Get the working directory with every symlink resolved, or an empty string if it no
+    /// longer exists.
+    fn physical_dir() -> String {
+        env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Check whether `path` names the current directory, following any symlinks.
+    fn is_current_dir(path: &str) -> bool {
+        match (std::fs::metadata(path), std::fs::metadata(".")) {
+            (Ok(path), Ok(current)) => path.dev() == current.dev() && path.ino() == current.ino(),
+            _ => false,
+        }
+    }
+
+    /// Follow `dir` from the directory `base` without looking at the filesystem, so that `.` is
+    /// dropped and `..` removes the part of the path before it. An absolute `dir` ignores `base`.
+    pub fn logical_path(base: &str, dir: &str) -> String {
+        let mut parts: Vec<&str> = Vec::new();
+        let start = if dir.starts_with('/') { "" } else { base };
+        for part in start.split('/').chain(dir.split('/')) {
+            match part {
+                "" | "." => (),
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
+        }
+
+        format!("/{}", parts.join("/"))
+    }
+
+    /// Convert line read from the user into format that will work with `execvp`. We limit the
+    /// number of arguments to `ARG_MAX` loaded from sysconf.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `line: String` The line to process.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(Vec<CString>)` if the line was parsed without issue.
+    /// - `Err(String)` if there was an issue parsing the line or the arguments are too long.
+    pub fn cmd_parse(line: String) -> Result<Vec<CString>, String> {
+        // Parse the line into a vector of CStrings
+        let argv = lexer::tokenize(&line)
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .map(|token| match token {
+                Token::Word(word) => CString::new(word).map_err(|err| err.to_string()),
+                other => Err(format!("unexpected token {:?}", other)),
+            })
+            .collect::<Result<Vec<CString>, String>>()?;
+
+        Shell::check_arg_max(&argv)?;
+        Ok(argv)
+    }
+
+    /// Check that `argv` and the environment fit in the space the kernel allows for a new
+    /// program, given by `sysconf(_SC_ARG_MAX)`. Each string counts its bytes, its terminating
+    /// NUL and the pointer to it.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `argv: &[CString]` The arguments a program is going to be run with.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the arguments fit or there is no limit.
+    /// - `Err(String)` describing how far over the limit they are.
+    pub fn check_arg_max(argv: &[CString]) -> Result<(), String> {
+        let limit = unsafe { sysconf(_SC_ARG_MAX) };
+        if limit <= 0 {
+            return Ok(());
+        }
+
+        let pointer = std::mem::size_of::<*const c_char>();
+        let args: usize = argv
+            .iter()
+            .map(|arg| arg.as_bytes_with_nul().len() + pointer)
+            .sum();
+        let environment: usize = env::vars_os()
+            .map(|(name, value)| name.len() + value.len() + 2 + pointer)
+            .sum();
+        // Both lists end with a null pointer
+        let total = args + environment + 2 * pointer;
+
+        if total > limit as usize {
+            let name = argv
+                .first()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            return Err(format!(
+                "{}: argument list too long ({} bytes of arguments and environment, the limit is {})",
+                name, total, limit
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check if a word has the form `NAME=value` where `NAME` is a valid variable name, optionally
+    /// followed by a subscript as in `arr[1]=value`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `word: &str` The unexpanded word to check.
+    ///
+    /// ## Returns
+    ///
+    /// - `Some((name, value))` if the word is an assignment. The value is still unexpanded.
+    /// - `None` otherwise.
+    pub fn parse_assignment(word: &str) -> Option<(&str, &str)> {
+        let (name, value) = word.split_once('=')?;
+        let base = match Shell::split_subscript(name) {
+            Some((base, _)) => base,
+            None => name,
+        };
+        if Shell::is_valid_name(base) {
+            Some((name, value))
+        } else {
+            None
+        }
+    }
+
+    /// Split a name like `arr[1]` that refers to an element of an array into the array's name
+    /// and the subscript.
+    ///
+    /// ## Returns
+    ///
+    /// - `Some((name, subscript))` if `name` ends with a subscript in brackets.
+    /// - `None` otherwise.
+    pub fn split_subscript(name: &str) -> Option<(&str, &str)> {
+        let (base, rest) = name.split_once('[')?;
+        Some((base, rest.strip_suffix(']')?))
+    }
+
+    /// Check if `name` can be used as a variable name: a letter or underscore followed by
+    /// letters, digits and underscores.
+    pub fn is_valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            _ => false,
+        }
+    }
+
+    /// Quote text so that the shell would read it back as a single word with the same value. Text
+    /// made only of characters that are never special is left as it is, and anything else is put
+    /// in single quotes.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `text: &str` The text to quote.
+    ///
+    /// ## Returns
+    ///
+    /// The quoted text.
+    pub fn quote(text: &str) -> String {
+        let plain = !text.is_empty()
+            && text
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-+=,./:@%^".contains(c));
+        if plain {
+            text.to_string()
+        } else {
+            format!("'{}'", text.replace('\'', "'\\''"))
+        }
+    }
+
+    /// Look up a variable, checking the shell's own variables before the environment. The value of
+    /// an array is its element at index 0, or with the key `0` for an associative array.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name of the variable.
+    ///
+    /// ## Returns
+    ///
+    /// The value of the variable or `None` if it is unset.
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        if let Some(array) = self.arrays.get(name) {
+            return array.get(&0).cloned();
+        }
+        if let Some(array) = self.assoc_arrays.get(name) {
+            return array.get("0").cloned();
+        }
+        match self.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// Set the element at `index` of an array, turning a shell variable of the same name into
+    /// the element at index 0 if the array doesn't exist yet.
+    pub fn set_array_element(&mut self, name: &str, index: usize, value: &str) {
+        if !self.arrays.contains_key(name) {
+            let first = self.vars.remove(name).into_iter().map(|value| (0, value));
+            self.arrays.insert(name.to_string(), first.collect());
+        }
+        if let Some(array) = self.arrays.get_mut(name) {
+            array.insert(index, value.to_string());
+        }
+    }
+
+    /// Set a shell variable. If the variable is already part of the environment the environment
+    /// is updated instead so child processes see the new value, and if it is an array its element
+    /// at index 0, or with the key `0`, is set.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `name: &str` The name of the variable.
+    /// - `value: &str` The new value.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        if let Some(array) = self.arrays.get_mut(name) {
+            array.insert(0, value.to_string());
+        } else if let Some(array) = self.assoc_arrays.get_mut(name) {
+            array.insert(String::from("0"), value.to_string());
+        } else if env::var_os(name).is_some() || self.exported_unset.remove(name) {
+            env::set_var(name, value);
+        } else {
+            self.vars.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// The `set` builtin. `-f` or `-o noglob` turns an option on and `+f` or `+o noglob` turns it
+    /// off. Several letters can be given at once, as in `-fx`. On its own `-o` lists the options
+    /// and `+o` prints the commands that would restore them, while `set` with no arguments lists
+    /// the shell's variables, including those in the environment, and functions. Any arguments
+    /// after the options, or after `--` or `-`, replace the positional parameters, and a `--`
+    /// with nothing after it clears them. Turning on `-m` puts the shell in its own process
+    /// group and takes the terminal so that jobs can be stopped and moved between the foreground
+    /// and background.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `set`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if all of the options were valid.
+    /// - `Err(String)` naming the first invalid option. Options before it are still applied.
+    pub fn set_options(&mut self, args: &[CString]) -> Result<(), String> {
+        if args.is_empty() {
+            // Shell variables shadow the environment
+            let mut vars: BTreeMap<String, String> = env::vars_os()
+                .filter_map(|(name, value)| {
+                    let name = name.into_string().ok()?;
+                    Some((name, value.to_string_lossy().into_owned()))
+                })
+                .filter(|(name, _)| Shell::is_valid_name(name))
+                .collect();
+            vars.extend(self.vars.clone());
+            for (name, value) in vars {
+                println!("{}={}", name, Shell::quote(&value));
+            }
+            let mut arrays: Vec<(&String, &BTreeMap<usize, String>)> = self.arrays.iter().collect();
+            arrays.sort();
+            for (name, array) in arrays {
+                let elements: Vec<String> = array
+                    .iter()
+                    .map(|(index, value)| format!("[{}]=\"{}\"", index, value))
+                    .collect();
+                println!("{}=({})", name, elements.join(" "));
+            }
+            let mut assoc_arrays: Vec<(&String, &BTreeMap<String, String>)> =
+                self.assoc_arrays.iter().collect();
+            assoc_arrays.sort();
+            for (name, array) in assoc_arrays {
+                let elements: Vec<String> = array
+                    .iter()
+                    .map(|(key, value)| format!("[{}]=\"{}\"", key, value))
+                    .collect();
+                println!("{}=({})", name, elements.join(" "));
+            }
+            let mut functions: Vec<&Rc<Command>> = self.functions.values().collect();
+            functions.sort_by_key(|function| function.to_string());
+            for function in functions {
+                println!("{}", function);
+            }
+            return Ok(());
+        }
+
+        let monitor = self.options.monitor;
+        let result = self.apply_options(args);
+        // Turning on job control takes the terminal, as `init` does for an interactive shell
+        if self.options.monitor && !monitor {
+            self.start_job_control();
+        }
+        result
+    }
+
+    /// Apply the options given to `set`, and set the positional parameters to any arguments
+    /// after them.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if all of the options were valid.
+    /// - `Err(String)` naming the first invalid option. Options before it are still applied.
+    fn apply_options(&mut self, args: &[CString]) -> Result<(), String> {
+        let mut args = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .peekable();
+        while let Some(arg) = args.peek() {
+            let enable = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => break,
+            };
+            let arg = args.next().unwrap();
+            if arg == "--" {
+                self.positional_params = args.collect();
+                return Ok(());
+            }
+            if arg == "-" {
+                break;
+            }
+
+            let letters = &arg[1..];
+            if letters == "o" {
+                match args.next() {
+                    Some(name) => match self.options.get_mut(&name) {
+                        Some(option) => *option = enable,
+                        None => return Err(format!("{}: invalid option name", name)),
+                    },
+                    None => self.print_options(enable),
+                }
+                continue;
+            }
+
+            for letter in letters.chars() {
+                let option =
+                    ShellOptions::name_of(letter).and_then(|name| self.options.get_mut(name));
+                match option {
+                    Some(option) => *option = enable,
+                    None => return Err(format!("{}{}: invalid option", &arg[..1], letter)),
+                }
+            }
+        }
+
+        if args.peek().is_some() {
+            self.positional_params = args.collect();
+        }
+        Ok(())
+    }
+
+    /// Print the state of every option, either as a table for `set -o` or as the `set` commands
+    /// that would restore it for `set +o`.
+    fn print_options(&mut self, table: bool) {
+        for (name, _) in ShellOptions::NAMES {
+            let enabled = *self.options.get_mut(name).unwrap();
+            match (table, enabled) {
+                (true, true) => println!("{:<15}on", name),
+                (true, false) => println!("{:<15}off", name),
+                (false, true) => println!("set -o {}", name),
+                (false, false) => println!("set +o {}", name),
+            }
+        }
+    }
+
+    /// The `break` and `continue` builtins, which jump out of the `n` innermost loops, or just the
+    /// innermost if no count is given. A count past the outermost loop stops at it. Outside of a
+    /// loop they only print a warning, as bash does.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `is_continue: bool` Whether this is `continue` rather than `break`.
+    /// - `args: &[CString]` The arguments after the command name.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the loops will be left or there were none.
+    /// - `Err(String)` if the count isn't a positive number.
+    pub fn loop_jump(&mut self, is_continue: bool, args: &[CString]) -> Result<(), String> {
+        let count = match args {
+            [] => 1,
+            [arg] => {
+                let arg = arg.to_string_lossy();
+                match arg.parse::<i64>() {
+                    Ok(count) if count > 0 => count as usize,
+                    Ok(_) => return Err(format!("{}: loop count out of range", arg)),
+                    Err(_) => return Err(format!("{}: numeric argument required", arg)),
+                }
+            }
+            _ => return Err(String::from("too many arguments")),
+        };
+
+        if self.loop_depth == 0 {
+            let name = if is_continue { "continue" } else { "break" };
+            eprintln!(
+                "{}: only meaningful in a `for', `while', `until' or `select' loop",
+                name
+            );
+        } else if is_continue {
+            self.jump = Some(Jump::Continue(count.min(self.loop_depth)));
+        } else {
+            self.jump = Some(Jump::Break(count.min(self.loop_depth)));
+        }
+
+        Ok(())
+    }
+
+    /// The `getopts` builtin, as in `getopts optstring name [arg...]`, which parses the next
+    /// option in the arguments, or the positional parameters if there are none. The option
+    /// letter is stored in `name` and its argument, for letters followed by `:` in `optstring`,
+    /// in `OPTARG`. `OPTIND` holds the index of the next argument to look at, starting from 1.
+    ///
+    /// An unknown option or a missing argument sets `name` to `?` and prints an error, unless
+    /// `optstring` starts with `:`. In that case the letter is put in `OPTARG` instead and `name`
+    /// is set to `?` for an unknown option or `:` for a missing argument.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `getopts`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(true)` if an option was found.
+    /// - `Ok(false)` once the options have run out, at the first argument that isn't an option
+    ///   or after `--`.
+    /// - `Err(String)` if `optstring` or `name` is missing, or `name` is invalid.
+    pub fn getopts(&mut self, args: &[CString]) -> Result<bool, String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let [optstring, name, rest @ ..] = args.as_slice() else {
+            return Err(String::from("usage: getopts optstring name [arg ...]"));
+        };
+        if !Shell::is_valid_name(name) {
+            return Err(format!("`{}': not a valid identifier", name));
+        }
+        self.check_readonly(name)?;
+        let params = if rest.is_empty() {
+            self.positional_params.clone()
+        } else {
+            rest.to_vec()
+        };
+        let (silent, optstring) = match optstring.strip_prefix(':') {
+            Some(optstring) => (true, optstring),
+            None => (false, optstring.as_str()),
+        };
+
+        let mut optind = self
+            .get_var("OPTIND")
+            .and_then(|optind| optind.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+        let mut pos = match self.getopts_pos {
+            Some((last_optind, pos)) if last_optind == optind => pos,
+            _ => 1,
+        };
+
+        let arg: Vec<char> = match params.get(optind - 1) {
+            Some(arg) if arg == "--" => {
+                optind += 1;
+                Vec::new()
+            }
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => arg.chars().collect(),
+            _ => Vec::new(),
+        };
+        let Some(&letter) = arg.get(pos) else {
+            self.getopts_pos = None;
+            self.set_var("OPTIND", &optind.to_string());
+            self.set_var(name, "?");
+            self.vars.remove("OPTARG");
+            return Ok(false);
+        };
+
+        pos += 1;
+        let mut optarg = None;
+        let found = optstring.find(letter).filter(|_| letter != ':');
+        let takes_arg = found.is_some_and(|i| optstring[i + letter.len_utf8()..].starts_with(':'));
+        if takes_arg {
+            if pos < arg.len() {
+                optarg = Some(arg[pos..].iter().collect());
+                optind += 1;
+            } else {
+                optarg = params.get(optind).cloned();
+                optind += 1 + usize::from(optarg.is_some());
+            }
+            pos = 1;
+        } else if pos >= arg.len() {
+            optind += 1;
+            pos = 1;
+        }
+        self.getopts_pos = Some((optind, pos));
+        self.set_var("OPTIND", &optind.to_string());
+
+        let (value, optarg) = match (found, optarg) {
+            (None, _) if silent => (String::from("?"), Some(letter.to_string())),
+            (None, _) => {
+                eprintln!("getopts: illegal option -- {}", letter);
+                (String::from("?"), None)
+            }
+            (Some(_), None) if takes_arg && silent => (String::from(":"), Some(letter.to_string())),
+            (Some(_), None) if takes_arg => {
+                eprintln!("getopts: option requires an argument -- {}", letter);
+                (String::from("?"), None)
+            }
+            (Some(_), optarg) => (letter.to_string(), optarg),
+        };
+        self.set_var(name, &value);
+        match optarg {
+            Some(optarg) => self.set_var("OPTARG", &optarg),
+            None => {
+                self.vars.remove("OPTARG");
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The `local` builtin, which makes variables local to the function being run. Each argument
+    /// is either an assignment or a name on its own, which leaves the variable unset. The
+    /// variables get their old values back when the function returns.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `local`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every argument was a valid name or assignment.
+    /// - `Err(String)` if an argument wasn't, or if no function is running. Arguments before an
+    ///   invalid one are still applied.
+    pub fn declare_local(&mut self, args: &[CString]) -> Result<(), String> {
+        if self.local_scopes.is_empty() {
+            return Err(String::from("can only be used in a function"));
+        }
 
-        unsafe {
-            setpgid(shell_pgid, shell_pgid);
-            tcsetpgrp(shell_terminal, shell_pgid);
+        for arg in args {
+            let arg = arg.to_string_lossy();
+            let (name, value) = match Shell::parse_assignment(&arg) {
+                Some((name, value)) if Shell::is_valid_name(name) => (name, Some(value)),
+                None if Shell::is_valid_name(&arg) => (arg.as_ref(), None),
+                _ => return Err(format!("`{}': not a valid identifier", arg)),
+            };
+            self.check_readonly(name)?;
 
-            let _ = signal(SIGINT, SIG_IGN);
-            let _ = signal(SIGQUIT, SIG_IGN);
-            let _ = signal(SIGTSTP, SIG_IGN);
-            let _ = signal(SIGTTIN, SIG_IGN);
-            let _ = signal(SIGTTOU, SIG_IGN);
+            // Only the value from before the function's first `local` of the name is kept
+            let saved = (self.vars.get(name).cloned(), env::var(name).ok());
+            let scope = self.local_scopes.last_mut().unwrap();
+            scope.entry(name.to_string()).or_insert(saved);
+
+            match value {
+                Some(value) => self.set_var(name, value),
+                None => {
+                    self.vars.remove(name);
+                    env::remove_var(name);
+                }
+            }
         }
 
-        Self {
-            shell_is_interactive,
-            shell_pgid,
-            shell_tmodes,
-            shell_terminal,
-            prompt,
+        Ok(())
+    }
+
+    /// Put back the variables made local by the function that is returning.
+    pub fn pop_local_scope(&mut self) {
+        if let Some(scope) = self.local_scopes.pop() {
+            self.restore_vars(scope);
         }
     }
 
-    pub fn destroy(&self) {
-        unsafe {
-            tcsetpgrp(self.shell_terminal, self.shell_pgid);
-            tcsetattr(self.shell_terminal, TCSADRAIN, &self.shell_tmodes);
+    /// Give variables values that only last while a single command runs, as the assignments in
+    /// `X=1 IFS=: command` do. The values go in the environment so that a program run by the
+    /// command sees them too.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `assignments: &[String]` The unexpanded `name=value` words before the command's name.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(LocalScope)` The values the variables had before, for `restore_vars`.
+    /// - `Err(String)` if a name isn't valid, is readonly or its value had a bad substitution.
+    ///   The assignments before it are undone.
+    pub fn set_temporary_vars(&mut self, assignments: &[String]) -> Result<LocalScope, String> {
+        let mut saved = LocalScope::new();
+        for word in assignments {
+            let Some((name, value)) = Shell::parse_assignment(word) else {
+                continue;
+            };
+            let value = if Shell::is_valid_name(name) {
+                self.check_readonly(name)
+                    .and_then(|_| self.expand_string(value))
+            } else {
+                Err(format!("`{}': not a valid identifier", name))
+            };
+            let value = match value {
+                Ok(value) => value,
+                Err(err) => {
+                    self.restore_vars(saved);
+                    return Err(err);
+                }
+            };
 
-            signal(SIGINT, SIG_DFL);
-            signal(SIGQUIT, SIG_DFL);
-            signal(SIGTSTP, SIG_DFL);
-            signal(SIGTTIN, SIG_DFL);
-            signal(SIGTTOU, SIG_DFL);
+            // Only the value from before the first assignment of the name is kept
+            let shell_value = self.vars.remove(name);
+            let env_value = env::var(name).ok();
+            saved
+                .entry(name.to_string())
+                .or_insert((shell_value, env_value));
+            env::set_var(name, value);
+        }
+
+        Ok(saved)
+    }
 
-            kill(getpid(), SIGTERM);
+    /// Put back the values variables had before a function made them local or a command was
+    /// given temporary values for them.
+    pub fn restore_vars(&mut self, saved: LocalScope) {
+        for (name, (shell_value, env_value)) in saved {
+            match shell_value {
+                Some(value) => self.vars.insert(name.clone(), value),
+                None => self.vars.remove(&name),
+            };
+            match env_value {
+                Some(value) => env::set_var(&name, value),
+                None => env::remove_var(&name),
+            }
         }
     }
 
-    /// Set the shell prompt. This function will attempt to load a prompt from the requested
-    /// environment variable, if the environment variable is not set, a default prompt of "shell>"
-    /// is returned.
+    /// The `return` builtin, which ends the function or sourced file being run. The exit status
+    /// is the argument if there is one, or else that of the last command run.
     ///
     /// ## Parameter(s)
     ///
-    /// - `env: String` The environment variable
+    /// - `args: &[CString]` The arguments after `return`.
     ///
-    /// ## Return(s)
+    /// ## Returns
     ///
-    /// The prompt from the environment variable or the default prompt.
-    pub fn get_prompt(env: String) -> String {
-        match env::var(env) {
-            Ok(prompt) => prompt,
-            Err(_) => String::from("shell>"),
+    /// - `Ok(())` if the function will return.
+    /// - `Err(String)` if no function or sourced file is running or the argument isn't a number.
+    pub fn return_from(&mut self, args: &[CString]) -> Result<(), String> {
+        if self.local_scopes.is_empty() && self.source_depth == 0 {
+            return Err(String::from(
+                "can only `return' from a function or sourced script",
+            ));
         }
+
+        let status = match args {
+            [] => self.last_status,
+            [arg] => {
+                let arg = arg.to_string_lossy();
+                match arg.parse::<i32>() {
+                    Ok(status) => status & 0xff,
+                    Err(_) => return Err(format!("{}: numeric argument required", arg)),
+                }
+            }
+            _ => return Err(String::from("too many arguments")),
+        };
+        self.jump = Some(Jump::Return(status));
+
+        Ok(())
     }
 
-    /// Changes the current working directory of the shell. Uses the Linux system call `chdir`.
-    /// With no arguments, the users home directory is used as the directory to change to.
+    /// The `shift` builtin, which drops the first `n` positional parameters, or just the first if
+    /// no count is given, so that `$n+1` becomes `$1`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `shift`.
     ///
     /// ## Returns
     ///
-    /// - `Ok(())` if the directory was successfully changed.
-    /// - `Err(isize)` if the directory failed to change.
-    pub fn change_dir(dir: Vec<CString>) -> Result<(), isize> {
-        // If we weren' passsed a directory to go to, use libc to navigate to the
-        // user's home directory
-        if dir.len() <= 1 {
-            match env::var("HOME") {
-                // If the HOME environment variable is set, use it
-                Ok(home_dir) => {
-                    let cstring = CString::new(home_dir).unwrap();
-                    return match unsafe { chdir(cstring.as_ptr() as *const c_char) } {
-                        0 => Ok(()),
-                        other => Err(other.try_into().unwrap()),
-                    };
+    /// - `Ok(())` if the parameters were shifted.
+    /// - `Err(String)` if the count isn't a number or is more than the number of parameters, in
+    ///   which case nothing is shifted.
+    pub fn shift(&mut self, args: &[CString]) -> Result<(), String> {
+        let count = match args {
+            [] => 1,
+            [arg] => {
+                let arg = arg.to_string_lossy();
+                arg.parse::<usize>()
+                    .map_err(|_| format!("{}: numeric argument required", arg))?
+            }
+            _ => return Err(String::from("too many arguments")),
+        };
+        if count > self.positional_params.len() {
+            return Err(format!("{}: shift count out of range", count));
+        }
+        self.positional_params.drain(..count);
+
+        Ok(())
+    }
+
+    /// The `unset` builtin, as in `unset [-v] name...` or `unset -f name...`, which removes
+    /// variables from the shell and the environment, or with `-f` removes functions. A name like
+    /// `arr[1]` removes one element of an array.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `unset`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the arguments were valid. Removing something that doesn't exist is not an
+    ///   error.
+    /// - `Err(String)` if an option is invalid, or naming the last argument that isn't a valid
+    ///   name or is readonly. The others are still removed.
+    pub fn unset(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut functions = false;
+        let mut names = &args[..];
+        while let Some((flag, rest)) = names.split_first() {
+            match flag.as_str() {
+                "-f" => functions = true,
+                "-v" => functions = false,
+                "--" => {
+                    names = rest;
+                    break;
                 }
-                // If it's not set, get it from the UID
-                Err(_) => unsafe {
-                    let uid = getuid();
-                    let passwd = getpwuid(uid);
-
-                    return match chdir((*passwd).pw_dir as *const c_char) {
-                        0 => Ok(()),
-                        other => Err(other.try_into().unwrap()),
-                    };
-                },
+                flag if flag.starts_with('-') => return Err(format!("{}: invalid option", flag)),
+                _ => break,
             }
+            names = rest;
         }
 
-        match unsafe { chdir(dir.get(1).unwrap().as_ptr() as *const c_char) } {
-            0 => Ok(()),
-            other => Err(other.try_into().unwrap()),
+        let mut result = Ok(());
+        for name in names {
+            let base = Shell::split_subscript(name).map_or(name.as_str(), |(base, _)| base);
+            if functions {
+                self.functions.remove(name);
+            } else if self.var_attributes(base).readonly {
+                result = Err(format!("{}: cannot unset: readonly variable", base));
+            } else if let Some((array, subscript)) = Shell::split_subscript(name) {
+                if let Some(array) = self.arrays.get_mut(array) {
+                    if let Ok(index) = subscript.parse::<usize>() {
+                        array.remove(&index);
+                    }
+                } else if let Some(array) = self.assoc_arrays.get_mut(array) {
+                    array.remove(subscript);
+                }
+            } else if Shell::is_valid_name(name) {
+                self.vars.remove(name);
+                self.arrays.remove(name);
+                self.assoc_arrays.remove(name);
+                self.exported_unset.remove(name);
+                self.attributes.remove(name);
+                env::remove_var(name);
+            } else {
+                result = Err(format!("`{}': not a valid identifier", name));
+            }
         }
+
+        result
     }
 
-    /// Convert line read from the user into format that will work with `execvp`. We limit the
-    /// number of arguments to `ARG_MAX` loaded from sysconf.
+    /// The `alias` builtin, as in `alias name=value...`, which makes `name` stand for `value`
+    /// when it is the first word of a command. A name on its own prints its alias, and with no
+    /// arguments, or with `-p`, every alias is printed as the command that would define it again.
     ///
     /// ## Parameter(s)
     ///
-    /// - `line: String` The line to process.
+    /// - `args: &[CString]` The arguments after `alias`.
     ///
     /// ## Returns
     ///
-    /// - `Ok(Vec<*mut c_char>)` if the line was parsed without issue.
-    /// - `Err(String)` if there was an issue parsing the line.
-    pub fn cmd_parse(line: String) -> Result<Vec<CString>, String> {
-        // Parse the line into a vector of CStrings
-        let line = Shell::trim_white(line);
-        Ok(line.split(" ").map(|s| CString::new(s).unwrap()).collect())
+    /// - `Ok(())` if every alias was defined or printed.
+    /// - `Err(String)` naming the last alias that wasn't found or couldn't be defined. The others
+    ///   are still defined or printed.
+    pub fn alias(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let names = match args.first().map(String::as_str) {
+            Some("-p") | Some("--") => &args[1..],
+            _ => &args[..],
+        };
+        if names.is_empty() {
+            let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+            aliases.sort();
+            for (name, value) in aliases {
+                println!("alias {}={}", name, Shell::quote(value));
+            }
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        for arg in names {
+            match arg.split_once('=') {
+                Some((name, _)) if !Shell::is_valid_alias_name(name) => {
+                    result = Err(format!("`{}': invalid alias name", name));
+                }
+                Some((name, value)) => {
+                    self.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match self.aliases.get(arg) {
+                    Some(value) => println!("alias {}={}", arg, Shell::quote(value)),
+                    None => result = Err(format!("{}: not found", arg)),
+                },
+            }
+        }
+
+        result
     }
 
-    /// Trim the whitespace from the start and end of a string. For example "   ls -a   " becomes
-    /// "ls -a". This function modifies the argument `line` so that all printable chars are moved
-    /// to the front of the string.
+    /// The `type` builtin, as in `type name...`, which prints what each name would run as a
+    /// command: an alias, a keyword, a function with its definition, a builtin, or the path of a
+    /// program.
     ///
     /// ## Parameter(s)
     ///
-    /// - `line: &mut String` A reference to the `String` to trim.
-    pub fn trim_white(line: String) -> String {
-        line.split_whitespace().collect::<Vec<&str>>().join(" ")
+    /// - `args: &[CString]` The arguments after `type`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every name was found.
+    /// - `Err(String)` naming the last name that wasn't found. The others are still printed.
+    pub fn command_type(&self, args: &[CString]) -> Result<(), String> {
+        let mut result = Ok(());
+        for name in args {
+            let name = name.to_string_lossy();
+            match self.lookup_command(&name) {
+                Some(CommandKind::Alias(value)) => println!("{} is aliased to `{}'", name, value),
+                Some(CommandKind::Keyword) => println!("{} is a shell keyword", name),
+                Some(CommandKind::Function) => {
+                    println!("{} is a function", name);
+                    println!("{}() {}", name, self.functions[name.as_ref()]);
+                }
+                Some(CommandKind::Builtin) => println!("{} is a shell builtin", name),
+                Some(CommandKind::File(path)) => println!("{} is {}", name, path.display()),
+                None => result = Err(format!("{}: not found", name)),
+            }
+        }
+
+        result
     }
 
-    /// Takes an argument list and checks if the first argument is a built in command such as exit,
-    /// cd, jobs, etc. If the command is a built in command this function will handle the command.
-    /// If the first argument is *NOT* a built in command, this function will exit immediately.
+    /// The `which` builtin, as in `which [-a] name...`, which prints what each name would run
+    /// as a command using the same lookup the shell does: an alias, a keyword, a function with
+    /// its definition, a builtin, or the path of a program. `-a` prints everything each name
+    /// refers to in the order it is looked up, including every program of that name on `$PATH`.
     ///
     /// ## Parameter(s)
     ///
-    /// - `argv: Vec<String>` The argument list to check
+    /// - `args: &[CString]` The arguments after `which`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every name was found.
+    /// - `Err(String)` if an option is invalid or naming the last name that wasn't found. The
+    ///   others are still printed.
+    pub fn which(&self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut all = false;
+        let mut i = 0;
+        while let Some(flags) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'a' => all = true,
+                    _ => return Err(format!("-{}: invalid option", flag)),
+                }
+            }
+        }
+
+        let mut result = Ok(());
+        let mut out = String::new();
+        for name in &args[i..] {
+            let kinds = if all {
+                self.lookup_all(name)
+            } else {
+                self.lookup_command(name).into_iter().collect()
+            };
+            if kinds.is_empty() {
+                result = Err(format!("{}: not found", name));
+            }
+            for kind in kinds {
+                match kind {
+                    CommandKind::Alias(value) => {
+                        out.push_str(&format!("{}: aliased to {}\n", name, value))
+                    }
+                    CommandKind::Keyword => {
+                        out.push_str(&format!("{}: shell reserved word\n", name))
+                    }
+                    CommandKind::Function => {
+                        out.push_str(&format!("{}() {}\n", name, self.functions[name]))
+                    }
+                    CommandKind::Builtin => {
+                        out.push_str(&format!("{}: shell built-in command\n", name))
+                    }
+                    CommandKind::File(path) => out.push_str(&format!("{}\n", path.display())),
+                }
+            }
+        }
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))?;
+        result
+    }
+
+    /// The `enable` builtin, as in `enable [-n] name...`, which turns builtins back on, or off
+    /// with `-n` so that a program of the same name on `$PATH` is run instead. Without names the
+    /// enabled builtins are listed, the disabled ones with `-n` and all of them with `-a`.
+    ///
+    /// ## Parameter(s)
     ///
+    /// - `args: &[CString]` The arguments after `enable`.
     ///
     /// ## Returns
     ///
-    /// - `Ok(())` if this function handled the command as a built in.
-    /// - `Err(isize)` if the command wasn't a built in and was *NOT* handled or the command failed
-    ///   to execute and returned a non-zero status code..
-    pub fn do_builtin(&self, argv: Vec<CString>) -> Result<(), isize> {
-        if argv.is_empty() {
-            Err(0)
-        } else {
-            let c_cstr = argv.first().unwrap();
-            let builtin_cmd = c_cstr.to_str().unwrap();
-            if builtin_cmd == "exit" {
-                if argv.len() > 1 {
-                    exit(
-                        argv.get(1)
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .parse::<i32>()
-                            .unwrap(),
-                    );
+    /// - `Ok(())` if the builtins were changed or listed.
+    /// - `Err(String)` if an option is invalid or a name isn't a builtin. The other names are
+    ///   still changed.
+    pub fn enable(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut disable = false;
+        let mut all = false;
+        let mut i = 0;
+        while let Some(flags) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'n' => disable = true,
+                    'a' => all = true,
+                    'p' => (),
+                    _ => return Err(format!("-{}: invalid option", flag)),
                 }
-                exit(0);
-            } else if builtin_cmd == "cd" {
-                Shell::change_dir(argv)
-            } else if builtin_cmd == "history" {
-                let mut history_file_contents: String =
-                    std::fs::read_to_string("history.txt").unwrap_or_default();
-
-                // Remove the leading "#V2" from the history file if it's not the default
-                if !history_file_contents.is_empty() {
-                    history_file_contents = history_file_contents
-                        [history_file_contents.char_indices().nth(4).unwrap().0..]
-                        .to_string();
+            }
+        }
+
+        if i == args.len() {
+            let mut out = String::new();
+            for name in self.builtins.keys() {
+                let disabled = self.disabled_builtins.contains(name);
+                if disabled {
+                    if all || disable {
+                        out.push_str(&format!("enable -n {}\n", name));
+                    }
+                } else if all || !disable {
+                    out.push_str(&format!("enable {}\n", name));
                 }
+            }
 
-                println!("{}", history_file_contents);
-                Ok(())
+            let mut stdout = std::io::stdout();
+            return stdout
+                .write_all(out.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|err| format!("write error: {}", err));
+        }
+
+        let mut result = Ok(());
+        for name in &args[i..] {
+            if !self.builtins.contains_key(name) {
+                result = Err(format!("{}: not a shell builtin", name));
+            } else if disable {
+                self.disabled_builtins.insert(name.clone());
             } else {
-                Err(-1)
+                self.disabled_builtins.remove(name);
+            }
+        }
+
+        result
+    }
+
+    /// The `unalias` builtin, as in `unalias name...`, which removes aliases, or all of them with
+    /// `-a`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `unalias`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every alias was removed.
+    /// - `Err(String)` naming the last alias that didn't exist. The others are still removed.
+    pub fn unalias(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let names = match args.first().map(String::as_str) {
+            Some("-a") => {
+                self.aliases.clear();
+                return Ok(());
+            }
+            Some("--") => &args[1..],
+            None => return Err(String::from("usage: unalias [-a] name...")),
+            _ => &args[..],
+        };
+
+        let mut result = Ok(());
+        for name in names {
+            if self.aliases.remove(name).is_none() {
+                result = Err(format!("{}: not found", name));
+            }
+        }
+
+        result
+    }
+
+    /// Check whether `name` can be an alias: it must be one word without any quotes or
+    /// characters that are special to the shell.
+    fn is_valid_alias_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| !c.is_whitespace() && !"|&;<>()$`\\\"'=/".contains(c))
+    }
+
+    /// The `export` builtin, as in `export name[=value]...`, which moves shell variables into
+    /// the environment so that they are passed on to the programs the shell runs. A name without
+    /// a value is exported as soon as it is assigned. With no names, or with `-p`, the exported
+    /// variables are printed as the commands that would export them again.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `export`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every name was exported.
+    /// - `Err(String)` naming the last argument that isn't a valid name. The others are still
+    ///   exported.
+    pub fn export(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let names = match args.first().map(String::as_str) {
+            Some("-p") | Some("--") => &args[1..],
+            _ => &args[..],
+        };
+        if names.is_empty() {
+            self.print_exports();
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !Shell::is_valid_name(name) {
+                result = Err(format!("`{}': not a valid identifier", arg));
+                continue;
+            }
+            if value.is_some() {
+                if let Err(err) = self.check_readonly(name) {
+                    result = Err(err);
+                    continue;
+                }
+            }
+
+            match value.or_else(|| self.vars.remove(name)) {
+                Some(value) => {
+                    self.vars.remove(name);
+                    self.exported_unset.remove(name);
+                    env::set_var(name, value);
+                }
+                None if env::var_os(name).is_none() => {
+                    self.exported_unset.insert(name.to_string());
+                }
+                None => (),
+            }
+        }
+
+        result
+    }
+
+    /// Print every exported variable as the `export` command that would export it again.
+    fn print_exports(&self) {
+        let mut exports: Vec<(String, Option<String>)> = env::vars_os()
+            .filter_map(|(name, value)| {
+                let name = name.into_string().ok()?;
+                Some((name, Some(value.to_string_lossy().into_owned())))
+            })
+            .filter(|(name, _)| Shell::is_valid_name(name))
+            .chain(self.exported_unset.iter().map(|name| (name.clone(), None)))
+            .collect();
+        exports.sort();
+
+        for (name, value) in exports {
+            match value {
+                Some(value) => println!("export {}={}", name, Shell::quote(&value)),
+                None => println!("export {}", name),
             }
         }
     }
 
-    /// Parse command line args from the user when the shell was launched.
-    pub fn parse_args() {
-        let mut args = std::env::args();
-        if args.len() > 1 {
-            let arg = args.nth(1).unwrap();
-            if arg == "-v" {
+    /// Parse command line args from the user when the shell was launched. Options come first,
+    /// and the first argument that isn't one is a script to run with the rest as its arguments.
+    /// With `-c` the commands to run are given instead, followed by `$0` and the arguments.
+    ///
+    /// ## Returns
+    ///
+    /// How the shell should start.
+    pub fn parse_args() -> Invocation {
+        let mut invocation = Invocation::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--posix" {
+                invocation.options.posix = true;
+            } else if arg == "-v" {
                 println!(
                     "Simple Shell v{}.{} written by Dylan Gresham",
                     env!("CARGO_PKG_VERSION_MAJOR"),
@@ -216,13 +1835,42 @@ impl Shell {
 
                 exit(0);
             } else if arg == "-h" {
-                println!("Usage: simple-shell [-v | -h]\n");
-                println!("\t-v\t\tPrints the major and minor version of this program.");
-                println!("\t-h\t\tPrints this usage message.");
+                Shell::print_usage();
 
                 exit(0);
+            } else if arg == "-c" {
+                let Some(command) = args.next() else {
+                    eprintln!("simple-shell: -c: option requires an argument");
+                    exit(2);
+                };
+                invocation.command = Some(command);
+                break;
+            } else if arg == "--" {
+                invocation.script = args.next();
+                break;
+            } else if arg.starts_with('-') && arg != "-" {
+                eprintln!("simple-shell: {}: invalid option", arg);
+                Shell::print_usage();
+
+                exit(2);
+            } else {
+                invocation.script = Some(arg);
+                break;
             }
         }
+        invocation.args = args.collect();
+
+        invocation
+    }
+
+    /// Print the command line usage message.
+    fn print_usage() {
+        println!("Usage: simple-shell [-v | -h | --posix] [-c command [name [arg ...]] | script [arg ...]]\n");
+        println!("\t-v\t\tPrints the major and minor version of this program.");
+        println!("\t-h\t\tPrints this usage message.");
+        println!("\t--posix\t\tOnly accept POSIX sh syntax.");
+        println!("\t-c command\tRuns the given commands and exits.");
+        println!("\tscript\t\tRuns the commands in a file instead of reading them.");
     }
 }
 
@@ -231,6 +1879,7 @@ mod tests {
     use rustyline::{history::History, DefaultEditor};
 
     use super::*;
+    use crate::test_support::args;
 
     #[test]
     fn test_cmd_parse_two() {
@@ -259,41 +1908,6 @@ mod tests {
         assert_eq!(expected, rval);
     }
 
-    #[test]
-    fn test_trim_white_no_whitespace() {
-        let rval = Shell::trim_white(String::from("ls -a"));
-
-        assert_eq!("ls -a", rval);
-    }
-
-    #[test]
-    fn test_trim_white_start_whitespace() {
-        let rval = Shell::trim_white(String::from("  ls -a"));
-
-        assert_eq!("ls -a", rval);
-    }
-
-    #[test]
-    fn test_trim_white_end_whitespace() {
-        let rval = Shell::trim_white(String::from("ls -a  "));
-
-        assert_eq!("ls -a", rval);
-    }
-
-    #[test]
-    fn test_trim_white_both_whitespace() {
-        let rval = Shell::trim_white(String::from(" ls -a "));
-
-        assert_eq!("ls -a", rval);
-    }
-
-    #[test]
-    fn test_trim_white_all_whitespace() {
-        let rval = Shell::trim_white(String::from(" "));
-
-        assert_eq!("", rval);
-    }
-
     #[test]
     fn test_get_prompt_default() {
         if env::var("MY_PROMPT").is_ok() {
@@ -315,43 +1929,15 @@ mod tests {
     }
 
     #[test]
-    fn test_ch_dir_home() {
-        let cmd = Shell::cmd_parse(String::from("cd")).unwrap();
-        let expected = env::var("HOME").unwrap();
-        let _ = Shell::change_dir(cmd).unwrap();
-
-        let actual = env::current_dir().unwrap().to_str().unwrap().to_string();
-
-        assert_eq!(expected, actual);
-    }
-
-    #[test]
-    fn test_ch_dir_root() {
-        let cmd = Shell::cmd_parse(String::from("cd /")).unwrap();
-        let expected = String::from("/");
-        let _ = Shell::change_dir(cmd).unwrap();
-
-        let actual = env::current_dir().unwrap().to_str().unwrap().to_string();
-
-        assert_eq!(expected, actual);
+    fn test_logical_path() {
+        assert_eq!("/home/user/dir", Shell::logical_path("/home/user", "dir"));
+        assert_eq!("/home/dir", Shell::logical_path("/home/user", "./../dir/."));
+        assert_eq!("/tmp", Shell::logical_path("/home/user", "/tmp//"));
+        assert_eq!("/", Shell::logical_path("/home", "../../.."));
     }
 
     // My tests
 
-    #[test]
-    fn test_whitespace_between_params() {
-        let rval = Shell::trim_white(String::from("ls      -a     -l"));
-
-        assert_eq!("ls -a -l", rval);
-    }
-
-    #[test]
-    fn test_whitespace_between_params_two() {
-        let rval = Shell::trim_white(String::from("mkdir    -p    foo"));
-
-        assert_eq!("mkdir -p foo", rval);
-    }
-
     #[test]
     fn test_add_history() {
         let mut rl = DefaultEditor::new().unwrap();
@@ -374,4 +1960,196 @@ mod tests {
             assert_eq!(String::from("exit"), cmd);
         }
     }
+
+    #[test]
+    fn test_set_options() {
+        let mut shell = Shell::new();
+
+        assert!(shell.set_options(&args(&["-f"])).is_ok());
+        assert!(shell.options.noglob);
+        assert!(shell.set_options(&args(&["+o", "noglob"])).is_ok());
+        assert!(!shell.options.noglob);
+        assert!(shell.set_options(&args(&["-o", "noglob"])).is_ok());
+        assert!(shell.options.noglob);
+
+        assert!(shell.set_options(&args(&["-q"])).is_err());
+        assert!(shell.set_options(&args(&["-o", "nope"])).is_err());
+
+        // Arguments after the options replace the positional parameters
+        assert!(shell.set_options(&args(&["+f", "a", "-b"])).is_ok());
+        assert!(!shell.options.noglob);
+        assert_eq!(vec!["a", "-b"], shell.positional_params);
+        assert!(shell.set_options(&args(&["-f", "--", "-c"])).is_ok());
+        assert!(shell.options.noglob);
+        assert_eq!(vec!["-c"], shell.positional_params);
+        assert!(shell.set_options(&args(&["-f"])).is_ok());
+        assert_eq!(vec!["-c"], shell.positional_params);
+        assert!(shell.set_options(&args(&["--"])).is_ok());
+        assert!(shell.positional_params.is_empty());
+
+        // Turning job control off leaves the terminal alone
+        shell.options.monitor = true;
+        assert!(shell.set_options(&args(&["+m"])).is_ok());
+        assert!(!shell.options.monitor);
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut shell = Shell::new();
+
+        assert!(shell.alias(&args(&["greet=s=hello", "two=s=2"])).is_ok());
+        let list = shell.parse("greet").unwrap();
+        shell.execute_list(&list);
+        assert_eq!(Some(String::from("hello")), shell.get_var("s"));
+
+        assert!(shell.alias(&args(&["greet", "missing"])).is_err());
+        assert!(shell.alias(&args(&["bad name=x"])).is_err());
+        assert!(!shell.aliases.contains_key("bad name"));
+
+        assert!(shell.unalias(&args(&["greet", "missing"])).is_err());
+        assert!(!shell.aliases.contains_key("greet"));
+        assert!(shell.unalias(&args(&["-a"])).is_ok());
+        assert!(shell.aliases.is_empty());
+        assert!(shell.unalias(&[]).is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!("ls", Shell::quote("ls"));
+        assert_eq!("--color=auto", Shell::quote("--color=auto"));
+        assert_eq!("''", Shell::quote(""));
+        assert_eq!("'a b'", Shell::quote("a b"));
+        assert_eq!("'*.rs'", Shell::quote("*.rs"));
+        assert_eq!("'it'\\''s'", Shell::quote("it's"));
+    }
+
+    #[test]
+    fn test_export() {
+        let mut shell = Shell::new();
+
+        shell.set_var("SHELL_TEST_EXPORT_VAR", "shell");
+        assert_eq!(None, env::var_os("SHELL_TEST_EXPORT_VAR"));
+        assert!(shell
+            .export(&args(&[
+                "SHELL_TEST_EXPORT_VAR",
+                "SHELL_TEST_EXPORT_NEW=new",
+                "SHELL_TEST_EXPORT_LATER"
+            ]))
+            .is_ok());
+        assert_eq!(Ok(String::from("shell")), env::var("SHELL_TEST_EXPORT_VAR"));
+        assert_eq!(Ok(String::from("new")), env::var("SHELL_TEST_EXPORT_NEW"));
+        assert!(!shell.vars.contains_key("SHELL_TEST_EXPORT_VAR"));
+
+        // Assigning a name exported without a value puts it in the environment
+        assert_eq!(None, env::var_os("SHELL_TEST_EXPORT_LATER"));
+        shell.set_var("SHELL_TEST_EXPORT_LATER", "later");
+        assert_eq!(
+            Ok(String::from("later")),
+            env::var("SHELL_TEST_EXPORT_LATER")
+        );
+
+        assert!(shell
+            .export(&args(&["1x=1", "SHELL_TEST_EXPORT_VAR=again"]))
+            .is_err());
+        assert_eq!(Ok(String::from("again")), env::var("SHELL_TEST_EXPORT_VAR"));
+
+        for name in ["VAR", "NEW", "LATER"] {
+            env::remove_var(format!("SHELL_TEST_EXPORT_{}", name));
+        }
+    }
+
+    #[test]
+    fn test_getopts() {
+        let mut shell = Shell::new();
+        let next = |shell: &mut Shell, optstring: &str| {
+            let found = shell.getopts(&args(&[optstring, "opt"])).unwrap();
+            (
+                found,
+                shell.get_var("opt").unwrap_or_default(),
+                shell.get_var("OPTARG"),
+                shell.get_var("OPTIND").unwrap_or_default(),
+            )
+        };
+        let none = None::<String>;
+
+        shell.positional_params = ["-ab", "x", "-cy", "-d", "--", "-a"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            (true, "a".into(), none.clone(), "1".into()),
+            next(&mut shell, "ab:c:")
+        );
+        assert_eq!(
+            (true, "b".into(), Some("x".into()), "3".into()),
+            next(&mut shell, "ab:c:")
+        );
+        assert_eq!(
+            (true, "c".into(), Some("y".into()), "4".into()),
+            next(&mut shell, "ab:c:")
+        );
+        assert_eq!(
+            (true, "?".into(), none.clone(), "5".into()),
+            next(&mut shell, "ab:c:")
+        );
+        assert_eq!(
+            (false, "?".into(), none.clone(), "6".into()),
+            next(&mut shell, "ab:c:")
+        );
+
+        // Silent reporting puts the letter in OPTARG
+        shell.set_var("OPTIND", "1");
+        shell.positional_params = vec![String::from("-x"), String::from("-b")];
+        assert_eq!(
+            (true, "?".into(), Some("x".into()), "2".into()),
+            next(&mut shell, ":b:")
+        );
+        assert_eq!(
+            (true, ":".into(), Some("b".into()), "3".into()),
+            next(&mut shell, ":b:")
+        );
+        assert_eq!(
+            (false, "?".into(), none, "3".into()),
+            next(&mut shell, ":b:")
+        );
+
+        assert!(shell.getopts(&args(&["a"])).is_err());
+        assert!(shell.getopts(&args(&["a", "1x"])).is_err());
+    }
+
+    #[test]
+    fn test_check_arg_max() {
+        assert!(Shell::cmd_parse(String::from("ls -la /tmp")).is_ok());
+
+        // Skip the check on systems where the limit is too big to reach cheaply
+        let limit = unsafe { sysconf(_SC_ARG_MAX) };
+        if limit <= 0 || limit > 64 * 1024 * 1024 {
+            return;
+        }
+        let line = format!("echo {}", "a".repeat(limit as usize));
+        let err = Shell::cmd_parse(line).unwrap_err();
+        assert!(err.starts_with("echo: argument list too long"), "{}", err);
+    }
+
+    #[test]
+    fn test_exit_shell() {
+        // `exit` and the end of the shell by `set -e` leave the status of the command, not a
+        // signal. Each runs in a child process so the status can be checked.
+        for (line, expected) in [("set -e; (exit 3); echo never", 3), ("false; exit", 1)] {
+            let pid = unsafe { libc::fork() };
+            if pid == 0 {
+                let mut shell = Shell::new();
+                shell.run_line(line);
+                match shell.jump {
+                    Some(Jump::Exit(status)) => shell.exit_shell(status),
+                    _ => exit(0),
+                }
+            }
+            assert!(pid > 0);
+
+            let mut status: c_int = 0;
+            unsafe { libc::waitpid(pid, &mut status, 0) };
+            assert_eq!(expected, Shell::decode_status(status), "{}", line);
+        }
+    }
 }
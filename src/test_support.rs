@@ -0,0 +1,8 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::ffi::CString;
+
+/// Convert arguments to the form builtins take them in.
+pub fn args(args: &[&str]) -> Vec<CString> {
+    args.iter().map(|arg| CString::new(*arg).unwrap()).collect()
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+use std::time::Instant;
+
+use libc::{getrusage, rusage, timeval, RUSAGE_CHILDREN, RUSAGE_SELF};
+
+use crate::shell::Shell;
+
+/// Format a CPU time as minutes and seconds, as in `1m2.345s`.
+fn format_time(time: timeval) -> String {
+    let millis = time.tv_sec * 1000 + time.tv_usec / 1000;
+    format!(
+        "{}m{}.{:03}s",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Get the user and system CPU time used by the shell and the commands it has waited for
+/// together, in microseconds.
+fn cpu_times() -> (i64, i64) {
+    let mut times = (0, 0);
+    for who in [RUSAGE_SELF, RUSAGE_CHILDREN] {
+        let mut usage: rusage = unsafe { std::mem::zeroed() };
+        if unsafe { getrusage(who, &mut usage) } == 0 {
+            times.0 += usage.ru_utime.tv_sec * 1_000_000 + usage.ru_utime.tv_usec;
+            times.1 += usage.ru_stime.tv_sec * 1_000_000 + usage.ru_stime.tv_usec;
+        }
+    }
+    times
+}
+
+/// Turn a number of microseconds back into a `timeval` to be formatted.
+fn to_timeval(micros: i64) -> timeval {
+    timeval {
+        tv_sec: micros / 1_000_000,
+        tv_usec: micros % 1_000_000,
+    }
+}
+
+/// Measures the real and CPU time taken by a pipeline prefixed with `time`.
+pub struct Stopwatch {
+    started: Instant,
+    user: i64,
+    sys: i64,
+}
+
+impl Stopwatch {
+    /// Start measuring from now.
+    pub fn start() -> Stopwatch {
+        let (user, sys) = cpu_times();
+        Stopwatch {
+            started: Instant::now(),
+            user,
+            sys,
+        }
+    }
+
+    /// Write the real, user and system time taken since the stopwatch was started to standard
+    /// error, as in `real\t0m1.002s` followed by the `user` and `sys` lines.
+    pub fn report(&self) {
+        let real = self.started.elapsed().as_micros() as i64;
+        let (user, sys) = cpu_times();
+        let _ = write!(
+            std::io::stderr(),
+            "\nreal\t{}\nuser\t{}\nsys\t{}\n",
+            format_time(to_timeval(real)),
+            format_time(to_timeval(user - self.user)),
+            format_time(to_timeval(sys - self.sys))
+        );
+    }
+}
+
+impl Shell {
+    /// The `times` builtin, which prints the user and system CPU time used by the shell on one
+    /// line and by the commands it has waited for on the next.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the times were printed.
+    /// - `Err(String)` if they couldn't be read or written.
+    pub fn times() -> Result<(), String> {
+        let mut out = String::new();
+        for who in [RUSAGE_SELF, RUSAGE_CHILDREN] {
+            let mut usage: rusage = unsafe { std::mem::zeroed() };
+            if unsafe { getrusage(who, &mut usage) } == -1 {
+                return Err(std::io::Error::last_os_error().to_string());
+            }
+            out.push_str(&format!(
+                "{} {}\n",
+                format_time(usage.ru_utime),
+                format_time(usage.ru_stime)
+            ));
+        }
+
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| format!("write error: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        let time = |tv_sec, tv_usec| timeval { tv_sec, tv_usec };
+        assert_eq!("0m0.000s", format_time(time(0, 0)));
+        assert_eq!("0m0.004s", format_time(time(0, 4_999)));
+        assert_eq!("1m15.500s", format_time(time(75, 500_000)));
+        assert_eq!("61m1.001s", format_time(time(3661, 1_000)));
+    }
+}
@@ -0,0 +1,290 @@
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use libc::{
+    c_int, sigaction, sigemptyset, sighandler_t, SA_RESTART, SIGABRT, SIGALRM, SIGBUS, SIGCHLD,
+    SIGCONT, SIGFPE, SIGHUP, SIGILL, SIGINT, SIGKILL, SIGPIPE, SIGPROF, SIGQUIT, SIGSEGV, SIGSTOP,
+    SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2, SIGVTALRM,
+    SIGWINCH, SIGXCPU, SIGXFSZ, SIG_DFL, SIG_IGN,
+};
+
+use crate::jobs;
+use crate::shell::{Jump, Shell};
+
+/// The signals that can be named, without their `SIG` prefix, with their numbers.
+pub const SIGNALS: [(&str, c_int); 28] = [
+    ("HUP", SIGHUP),
+    ("INT", SIGINT),
+    ("QUIT", SIGQUIT),
+    ("ILL", SIGILL),
+    ("TRAP", SIGTRAP),
+    ("ABRT", SIGABRT),
+    ("BUS", SIGBUS),
+    ("FPE", SIGFPE),
+    ("KILL", SIGKILL),
+    ("USR1", SIGUSR1),
+    ("SEGV", SIGSEGV),
+    ("USR2", SIGUSR2),
+    ("PIPE", SIGPIPE),
+    ("ALRM", SIGALRM),
+    ("TERM", SIGTERM),
+    ("CHLD", SIGCHLD),
+    ("CONT", SIGCONT),
+    ("STOP", SIGSTOP),
+    ("TSTP", SIGTSTP),
+    ("TTIN", SIGTTIN),
+    ("TTOU", SIGTTOU),
+    ("URG", SIGURG),
+    ("XCPU", SIGXCPU),
+    ("XFSZ", SIGXFSZ),
+    ("VTALRM", SIGVTALRM),
+    ("PROF", SIGPROF),
+    ("WINCH", SIGWINCH),
+    ("SYS", SIGSYS),
+];
+
+/// The number `trap` uses for the shell exiting, which isn't a real signal.
+pub const EXIT: c_int = 0;
+
+/// A bit for each trapped signal that has arrived but whose trap hasn't run yet. Handlers only
+/// set bits here, and the traps are run later by `run_pending_traps` where it is safe to run
+/// commands.
+static PENDING_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+/// Handle a signal that has a trap set by marking it as pending. A trap on `CHLD` doesn't stop
+/// background jobs from being reaped.
+extern "C" fn handle_trapped_signal(signal: c_int) {
+    if signal == SIGCHLD {
+        jobs::handle_sigchld(signal);
+    }
+    PENDING_SIGNALS.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Look up a signal by name or number, as given to `trap` or `kill`. Names don't need the `SIG`
+/// prefix and may be in any case, and `EXIT` or `0` names the shell exiting.
+///
+/// ## Returns
+///
+/// The signal's number, or `None` if there is no such signal.
+pub fn parse_signal(spec: &str) -> Option<c_int> {
+    if let Ok(number) = spec.parse::<c_int>() {
+        return (number == EXIT || SIGNALS.iter().any(|(_, signal)| *signal == number))
+            .then_some(number);
+    }
+
+    let name = spec.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    if name == "EXIT" {
+        return Some(EXIT);
+    }
+    SIGNALS
+        .iter()
+        .find(|(signal_name, _)| *signal_name == name)
+        .map(|(_, signal)| *signal)
+}
+
+/// Get the name of a signal without its `SIG` prefix, or `EXIT` for the shell exiting.
+pub fn signal_name(signal: c_int) -> &'static str {
+    SIGNALS
+        .iter()
+        .find(|(_, number)| *number == signal)
+        .map_or("EXIT", |(name, _)| name)
+}
+
+/// Set what happens when `signal` arrives.
+fn set_disposition(signal: c_int, handler: sighandler_t) {
+    unsafe {
+        let mut action: sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = SA_RESTART;
+        sigemptyset(&mut action.sa_mask);
+        sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+impl Shell {
+    /// The `trap` builtin. `trap action signal...` runs `action` when any of the signals arrive,
+    /// or when the shell exits for `EXIT`. An empty action ignores the signals and an action of
+    /// `-`, or a first argument that is a signal number, puts back their default handling. On
+    /// its own, or with `-p`, `trap` prints the traps that are set as commands that would set
+    /// them again.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `trap`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if every signal was valid.
+    /// - `Err(String)` naming the first signal that doesn't exist or can't be trapped. Signals
+    ///   before it are still changed.
+    pub fn trap(&mut self, args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let args = match args.first().map(String::as_str) {
+            Some("--") => &args[1..],
+            _ => &args[..],
+        };
+
+        let (action, signals) = match args {
+            [] => {
+                self.print_traps();
+                return Ok(());
+            }
+            [flag] if flag == "-p" => {
+                self.print_traps();
+                return Ok(());
+            }
+            [first, ..] if first.parse::<u32>().is_ok() => (None, args),
+            [action, signals @ ..] if action == "-" => (None, signals),
+            [action, signals @ ..] => (Some(action.as_str()), signals),
+        };
+
+        for spec in signals {
+            let signal = match parse_signal(spec) {
+                Some(signal) if signal != SIGKILL && signal != SIGSTOP => signal,
+                Some(_) => return Err(format!("{}: signal can't be trapped", spec)),
+                None => return Err(format!("{}: invalid signal specification", spec)),
+            };
+
+            match action {
+                Some(action) => {
+                    self.traps.insert(signal, action.to_string());
+                }
+                None => {
+                    self.traps.remove(&signal);
+                }
+            }
+            if signal == EXIT {
+                continue;
+            }
+            let handler = match action {
+                Some("") => SIG_IGN,
+                Some(_) => handle_trapped_signal as extern "C" fn(c_int) as sighandler_t,
+                None => self.default_disposition(signal),
+            };
+            set_disposition(signal, handler);
+        }
+
+        Ok(())
+    }
+
+    /// Print every trap that is set as the `trap` command that would set it.
+    fn print_traps(&self) {
+        for (signal, action) in &self.traps {
+            println!("trap -- {} {}", Shell::quote(action), signal_name(*signal));
+        }
+    }
+
+    /// Run the traps of any signals that have arrived since this was last called. The status of
+    /// the last command is kept, so that `$?` after a trap is the same as before it.
+    pub fn run_pending_traps(&mut self) {
+        if PENDING_SIGNALS.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        // Only the signals this shell traps are taken, leaving any others pending
+        let trapped = self.traps.keys().fold(0, |mask, signal| mask | 1 << signal);
+        let pending = PENDING_SIGNALS.fetch_and(!trapped, Ordering::SeqCst) & trapped;
+
+        for (_, signal) in SIGNALS {
+            if pending & (1 << signal) != 0 {
+                if let Some(action) = self.traps.get(&signal).cloned() {
+                    self.run_trap(&action);
+                }
+            }
+        }
+    }
+
+    /// Run the `EXIT` trap, if there is one, before the shell exits. It is removed first so
+    /// that an `exit` inside it doesn't run it again.
+    pub fn run_exit_trap(&mut self) {
+        if let Some(action) = self.traps.remove(&EXIT) {
+            self.run_trap(&action);
+        }
+    }
+
+    /// Run the action of a trap without disturbing the status or jump of the commands it
+    /// interrupted, unless the action makes the shell exit.
+    fn run_trap(&mut self, action: &str) {
+        let status = self.last_status;
+        let jump = self.jump.take();
+        match self.parse(action) {
+            Ok(list) => {
+                self.execute_list(&list);
+            }
+            Err(err) => eprintln!("trap: {}", err),
+        }
+
+        self.last_status = status;
+        if !matches!(self.jump, Some(Jump::Exit(_))) {
+            self.jump = jump;
+        }
+    }
+
+    /// Forget the traps with actions in a newly forked child, which goes back to the default
+    /// handling of their signals. Ignored signals stay ignored.
+    pub fn reset_traps(&mut self) {
+        let trapped: Vec<c_int> = self
+            .traps
+            .iter()
+            .filter(|(_, action)| !action.is_empty())
+            .map(|(signal, _)| *signal)
+            .collect();
+        for signal in trapped {
+            self.traps.remove(&signal);
+            if signal != EXIT {
+                set_disposition(signal, SIG_DFL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(Some(SIGINT), parse_signal("INT"));
+        assert_eq!(Some(SIGINT), parse_signal("sigint"));
+        assert_eq!(Some(SIGTERM), parse_signal("15"));
+        assert_eq!(Some(EXIT), parse_signal("EXIT"));
+        assert_eq!(Some(EXIT), parse_signal("0"));
+        assert_eq!(None, parse_signal("NOPE"));
+        assert_eq!(None, parse_signal("99"));
+        assert_eq!("USR1", signal_name(SIGUSR1));
+    }
+
+    #[test]
+    fn test_trap() {
+        let mut shell = Shell::new();
+
+        assert!(shell.trap(&args(&["s=$s-usr1", "USR1", "SIGUSR2"])).is_ok());
+        assert!(shell.trap(&args(&["s=$s-exit", "EXIT"])).is_ok());
+        assert_eq!(Some(&String::from("s=$s-usr1")), shell.traps.get(&SIGUSR2));
+
+        // Traps run once the signal has arrived and the shell gets to them, keeping `$?`
+        shell.last_status = 3;
+        unsafe {
+            libc::raise(SIGUSR1);
+        }
+        shell.run_pending_traps();
+        shell.run_pending_traps();
+        assert_eq!(Some(String::from("-usr1")), shell.get_var("s"));
+        assert_eq!(3, shell.last_status);
+
+        shell.run_exit_trap();
+        shell.run_exit_trap();
+        assert_eq!(Some(String::from("-usr1-exit")), shell.get_var("s"));
+
+        assert!(shell.trap(&args(&["-", "USR1"])).is_ok());
+        assert!(shell.trap(&args(&["12"])).is_ok());
+        assert!(shell.traps.is_empty());
+
+        assert!(shell.trap(&args(&["echo", "KILL"])).is_err());
+        assert!(shell.trap(&args(&["echo", "NOPE"])).is_err());
+    }
+}
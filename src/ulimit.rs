@@ -0,0 +1,202 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use libc::{
+    c_int, getrlimit, rlim_t, rlimit, setrlimit, RLIMIT_AS, RLIMIT_CORE, RLIMIT_CPU, RLIMIT_DATA,
+    RLIMIT_FSIZE, RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_STACK, RLIM_INFINITY,
+};
+
+use crate::shell::Shell;
+
+/// A resource limit `ulimit` can show or change: its option letter, its description, the
+/// resource and the number of bytes in each unit it is given in.
+struct Limit {
+    flag: char,
+    description: &'static str,
+    resource: c_int,
+    unit: rlim_t,
+}
+
+/// The limits `ulimit` knows about, in the order `ulimit -a` lists them.
+const LIMITS: [Limit; 8] = [
+    Limit {
+        flag: 'c',
+        description: "core file size (blocks)",
+        resource: RLIMIT_CORE as c_int,
+        unit: 512,
+    },
+    Limit {
+        flag: 'd',
+        description: "data seg size (kbytes)",
+        resource: RLIMIT_DATA as c_int,
+        unit: 1024,
+    },
+    Limit {
+        flag: 'f',
+        description: "file size (blocks)",
+        resource: RLIMIT_FSIZE as c_int,
+        unit: 512,
+    },
+    Limit {
+        flag: 'n',
+        description: "open files",
+        resource: RLIMIT_NOFILE as c_int,
+        unit: 1,
+    },
+    Limit {
+        flag: 's',
+        description: "stack size (kbytes)",
+        resource: RLIMIT_STACK as c_int,
+        unit: 1024,
+    },
+    Limit {
+        flag: 't',
+        description: "cpu time (seconds)",
+        resource: RLIMIT_CPU as c_int,
+        unit: 1,
+    },
+    Limit {
+        flag: 'u',
+        description: "max user processes",
+        resource: RLIMIT_NPROC as c_int,
+        unit: 1,
+    },
+    Limit {
+        flag: 'v',
+        description: "virtual memory (kbytes)",
+        resource: RLIMIT_AS as c_int,
+        unit: 1024,
+    },
+];
+
+impl Limit {
+    /// Get the current soft and hard values of the limit.
+    fn get(&self) -> Result<rlimit, String> {
+        let mut limit = rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { getrlimit(self.resource as _, &mut limit) } == -1 {
+            return Err(format!(
+                "{}: cannot get limit: {}",
+                self.description,
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        Ok(limit)
+    }
+
+    /// Format a value of the limit in its units.
+    fn format(&self, value: rlim_t) -> String {
+        if value == RLIM_INFINITY {
+            String::from("unlimited")
+        } else {
+            (value / self.unit).to_string()
+        }
+    }
+}
+
+impl Shell {
+    /// The `ulimit` builtin, as in `ulimit [-H | -S] [-a | -cdfnstuv] [limit]`, which shows or
+    /// sets a resource limit of the shell and the programs it runs, the maximum file size by
+    /// default. `-S` works with the soft limit that is enforced and `-H` with the hard limit the
+    /// soft one can be raised to. Setting a limit sets both unless one is chosen, while showing
+    /// one shows the soft limit unless `-H` is given. `-a` shows every limit.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `ulimit`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the limit was shown or set.
+    /// - `Err(String)` if an option or the new limit isn't valid or the limit couldn't be
+    ///   changed.
+    pub fn ulimit(args: &[CString]) -> Result<(), String> {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut soft = false;
+        let mut hard = false;
+        let mut all = false;
+        let mut limit = &LIMITS[2];
+        let mut i = 0;
+        while let Some(flags) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+            i += 1;
+            if flags == "-" {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'S' => soft = true,
+                    'H' => hard = true,
+                    'a' => all = true,
+                    _ => {
+                        limit = LIMITS
+                            .iter()
+                            .find(|limit| limit.flag == flag)
+                            .ok_or_else(|| format!("-{}: invalid option", flag))?
+                    }
+                }
+            }
+        }
+
+        let Some(value) = args.get(i) else {
+            let show = |limit: &Limit| -> Result<String, String> {
+                let values = limit.get()?;
+                Ok(limit.format(if hard && !soft {
+                    values.rlim_max
+                } else {
+                    values.rlim_cur
+                }))
+            };
+            let mut out = String::new();
+            if all {
+                for limit in &LIMITS {
+                    out.push_str(&format!(
+                        "{:<28}(-{}) {}\n",
+                        limit.description,
+                        limit.flag,
+                        show(limit)?
+                    ));
+                }
+            } else {
+                out.push_str(&format!("{}\n", show(limit)?));
+            }
+
+            let mut stdout = std::io::stdout();
+            return stdout
+                .write_all(out.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|err| format!("write error: {}", err));
+        };
+
+        let new_value = if value == "unlimited" {
+            RLIM_INFINITY
+        } else {
+            value
+                .parse::<rlim_t>()
+                .ok()
+                .and_then(|value| value.checked_mul(limit.unit))
+                .ok_or_else(|| format!("{}: invalid number", value))?
+        };
+        let mut values = limit.get()?;
+        if soft || !hard {
+            values.rlim_cur = new_value;
+        }
+        if hard || !soft {
+            values.rlim_max = new_value;
+        }
+        if unsafe { setrlimit(limit.resource as _, &values) } == -1 {
+            return Err(format!(
+                "{}: cannot modify limit: {}",
+                limit.description,
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        Ok(())
+    }
+}
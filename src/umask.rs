@@ -0,0 +1,145 @@
+use std::ffi::CString;
+use std::io::Write;
+
+use libc::{mode_t, umask};
+
+use crate::shell::Shell;
+
+/// The permission bits of each class of user, in the order `umask -S` lists them.
+const CLASSES: [(char, mode_t); 3] = [('u', 0o700), ('g', 0o070), ('o', 0o007)];
+
+/// Get the file mode creation mask without changing it.
+fn current_mask() -> mode_t {
+    unsafe {
+        let mask = umask(0);
+        umask(mask);
+        mask
+    }
+}
+
+/// Apply a symbolic mode such as `u=rwx,g+r,o-w` to the permissions a mask allows. A clause
+/// without any of `u`, `g`, `o` or `a` applies to everyone.
+///
+/// ## Returns
+///
+/// The new allowed permissions, or `None` if the mode isn't valid.
+fn apply_symbolic(mode: &str, allowed: mode_t) -> Option<mode_t> {
+    let mut allowed = allowed;
+    for clause in mode.split(',') {
+        let mut chars = clause.chars().peekable();
+        let mut who: mode_t = 0;
+        while let Some(c) = chars.next_if(|c| "ugoa".contains(*c)) {
+            who |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                _ => 0o777,
+            };
+        }
+        if who == 0 {
+            who = 0o777;
+        }
+
+        // Each operator is followed by the permissions it applies
+        chars.peek()?;
+        while let Some(op) = chars.next() {
+            let mut perms: mode_t = 0;
+            while let Some(c) = chars.next_if(|c| "rwx".contains(*c)) {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    _ => 0o111,
+                };
+            }
+            match op {
+                '+' => allowed |= perms & who,
+                '-' => allowed &= !(perms & who),
+                '=' => allowed = (allowed & !who) | (perms & who),
+                _ => return None,
+            }
+        }
+    }
+
+    Some(allowed)
+}
+
+impl Shell {
+    /// The `umask` builtin, as in `umask [-S] [mask]`, which sets the file mode creation mask of
+    /// the shell and the programs it runs. The mask may be given in octal or symbolically, as in
+    /// `u=rwx,g=rx,o=`, which names the permissions to allow rather than those to mask. Without a
+    /// mask the current one is printed, symbolically with `-S`.
+    ///
+    /// ## Parameter(s)
+    ///
+    /// - `args: &[CString]` The arguments after `umask`.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(())` if the mask was printed or set.
+    /// - `Err(String)` if the mask or an option isn't valid.
+    pub fn umask(args: &[CString]) -> Result<(), String> {
+        let mut args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let symbolic = args.first().is_some_and(|arg| arg == "-S");
+        if symbolic || args.first().is_some_and(|arg| arg == "--") {
+            args.remove(0);
+        }
+
+        let Some(mode) = args.first() else {
+            let mask = current_mask();
+            let text = if symbolic {
+                let classes: Vec<String> = CLASSES
+                    .iter()
+                    .map(|&(class, bits)| {
+                        let allowed = !mask & bits;
+                        let perms: String = [('r', 0o444), ('w', 0o222), ('x', 0o111)]
+                            .iter()
+                            .filter(|&&(_, perm)| allowed & perm != 0)
+                            .map(|&(perm, _)| perm)
+                            .collect();
+                        format!("{}={}", class, perms)
+                    })
+                    .collect();
+                classes.join(",")
+            } else {
+                format!("{:04o}", mask)
+            };
+
+            let mut stdout = std::io::stdout();
+            return writeln!(stdout, "{}", text)
+                .and_then(|_| stdout.flush())
+                .map_err(|err| format!("write error: {}", err));
+        };
+
+        let mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+            mode_t::from_str_radix(mode, 8)
+                .ok()
+                .filter(|mask| *mask <= 0o777)
+                .ok_or_else(|| format!("{}: octal number out of range", mode))?
+        } else {
+            let allowed = apply_symbolic(mode, !current_mask() & 0o777)
+                .ok_or_else(|| format!("{}: invalid symbolic mode", mode))?;
+            !allowed & 0o777
+        };
+        unsafe { umask(mask) };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_symbolic() {
+        assert_eq!(Some(0o750), apply_symbolic("u=rwx,g=rx,o=", 0o000));
+        assert_eq!(Some(0o772), apply_symbolic("a+w", 0o750));
+        assert_eq!(Some(0o644), apply_symbolic("go-wx", 0o677));
+        assert_eq!(Some(0o555), apply_symbolic("=rx", 0o777));
+        assert_eq!(None, apply_symbolic("g+z", 0o777));
+        assert_eq!(None, apply_symbolic("u", 0o777));
+    }
+}